@@ -0,0 +1,46 @@
+//! Sources for the tokenizer benchmark. `tests/bench_fixtures.rs` checks that
+//! they tokenize correctly, so a fast tokenizer is also a right one.
+
+const TARGET_SIZE: usize = 100 * 1024;
+
+/// A few lines, like a snippet typed into a prompt.
+pub const SMALL: &str = include_str!("small.lua");
+
+/// About 100 KB of a typical module, made from copies of `module.lua` that
+/// each sit in a block of their own.
+pub fn module() -> String {
+    let mut source = String::new();
+
+    while source.len() < TARGET_SIZE {
+        source.push_str("do\n");
+        source.push_str(include_str!("module.lua"));
+        source.push_str("end\n");
+    }
+
+    source
+}
+
+/// About 100 KB of names, half of them keywords and half of them names that
+/// are nearly keywords, to time telling them apart.
+pub fn keywords() -> String {
+    let mut source = String::new();
+
+    while source.len() < TARGET_SIZE {
+        source.push_str("and break do else elseif end false for function goto if in local nil not or repeat return then true until while\n");
+        source.push_str("andy breaks done elsewhere elseifs ending falsey fork functions gotos iff inner locale nilx note order repeats returned thence trues untill whiles\n");
+    }
+
+    source
+}
+
+/// About 100 KB on a single line, made of every operator over and over.
+pub fn pathological() -> String {
+    let mut source = String::from("local x = ");
+
+    while source.len() < TARGET_SIZE {
+        source.push_str("a + b * c - d / e % f ^ -#g .. h == i ~= j <= k >= l < m > n and not o or ");
+    }
+
+    source.push('z');
+    source
+}
@@ -0,0 +1,199 @@
+--[[
+	A small class library, the kind of module most Lua projects carry a copy
+	of somewhere.
+]]
+
+local setmetatable = setmetatable
+local getmetatable = getmetatable
+local rawget, rawset = rawget, rawset
+local insert, remove, concat = table.insert, table.remove, table.concat
+local format = string.format
+
+local Class = {}
+Class.__index = Class
+
+-- Every class remembers its parent, so that `is` can walk up the chain.
+local registry = setmetatable({}, { __mode = "k" })
+
+local function copy(source, target)
+	target = target or {}
+
+	for key, value in pairs(source) do
+		if type(value) == "table" and getmetatable(value) == nil then
+			target[key] = copy(value)
+		else
+			target[key] = value
+		end
+	end
+
+	return target
+end
+
+function Class:extend(name, definition)
+	local class = copy(definition or {})
+	class.__index = class
+	class.__name = name or "Anonymous"
+	class.super = self
+
+	registry[class] = self
+
+	return setmetatable(class, {
+		__index = self,
+		__call = function(cls, ...)
+			return cls.new(...)
+		end,
+		__tostring = function(cls)
+			return format("<class %s>", cls.__name)
+		end,
+	})
+end
+
+function Class.new(...)
+	error("Class.new must be overridden", 2)
+end
+
+function Class:is(other)
+	local current = getmetatable(self)
+
+	while current do
+		if current == other then
+			return true
+		end
+
+		current = registry[current]
+	end
+
+	return false
+end
+
+local Vector = Class:extend("Vector")
+
+function Vector.new(x, y)
+	return setmetatable({ x = x or 0, y = y or 0 }, Vector)
+end
+
+function Vector.__add(a, b)
+	return Vector.new(a.x + b.x, a.y + b.y)
+end
+
+function Vector.__sub(a, b)
+	return Vector.new(a.x - b.x, a.y - b.y)
+end
+
+function Vector.__mul(a, b)
+	if type(a) == "number" then
+		return Vector.new(a * b.x, a * b.y)
+	elseif type(b) == "number" then
+		return Vector.new(a.x * b, a.y * b)
+	end
+
+	return a.x * b.x + a.y * b.y
+end
+
+function Vector.__eq(a, b)
+	return a.x == b.x and a.y == b.y
+end
+
+function Vector.__tostring(v)
+	return format("(%.2f, %.2f)", v.x, v.y)
+end
+
+function Vector:length()
+	return math.sqrt(self.x ^ 2 + self.y ^ 2)
+end
+
+function Vector:normalized()
+	local length = self:length()
+
+	if length < 1e-9 then
+		return Vector.new(0, 0)
+	end
+
+	return Vector.new(self.x / length, self.y / length)
+end
+
+local Queue = Class:extend("Queue", {
+	capacity = 0x100,
+})
+
+function Queue.new()
+	return setmetatable({ first = 1, last = 0, items = {} }, Queue)
+end
+
+function Queue:push(value)
+	local last = self.last + 1
+
+	if last - self.first >= self.capacity then
+		error(format("queue is full (%d items)", self.capacity))
+	end
+
+	self.last = last
+	self.items[last] = value
+end
+
+function Queue:pop()
+	local first = self.first
+
+	if first > self.last then
+		return nil
+	end
+
+	local value = self.items[first]
+	self.items[first] = nil
+	self.first = first + 1
+
+	return value
+end
+
+function Queue:__len()
+	return self.last - self.first + 1
+end
+
+local Logger = Class:extend("Logger")
+
+Logger.levels = { "debug", "info", "warn", "error" }
+
+function Logger.new(level, sink)
+	local self = setmetatable({}, Logger)
+	self.level = level or 2
+	self.sink = sink or print
+	self.lines = {}
+
+	return self
+end
+
+function Logger:log(level, message, ...)
+	if level < self.level then
+		return
+	end
+
+	local line = format("[%s] %s", self.levels[level], format(message, ...))
+	insert(self.lines, line)
+
+	if #self.lines > 64 then
+		remove(self.lines, 1)
+	end
+
+	self.sink(line)
+end
+
+function Logger:dump()
+	return concat(self.lines, "\n")
+end
+
+local usage = [==[
+Usage:
+	local Vector = require("class").Vector
+	local v = Vector(3, 4)
+	print(v:length()) --> 5
+]==]
+
+return {
+	Class = Class,
+	Vector = Vector,
+	Queue = Queue,
+	Logger = Logger,
+	usage = usage,
+	version = '1.4.2',
+	_private = { copy = copy, rawget = rawget, rawset = rawset },
+}
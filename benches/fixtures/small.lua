@@ -0,0 +1,9 @@
+local function greet(name)
+	print("Hello, " .. name .. "!")
+end
+
+for i = 1, 10 do
+	if i % 2 == 0 then
+		greet("even " .. i)
+	end
+end
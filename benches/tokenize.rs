@@ -0,0 +1,201 @@
+//! Times the tokenizer over a couple megabytes of Lua built from the parse
+//! examples and over each of the fixtures, reporting throughput in MB/s.
+//! Also counts how many allocations tokenizing many small sources takes with
+//! and without a reused buffer, and times a pass that looks up every name,
+//! over plain and interned tokens. Run with `cargo bench --bench tokenize`.
+
+#[macro_use]
+extern crate criterion;
+extern crate mab;
+
+mod fixtures;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+use mab::interner::{tokenize_interned, Interner};
+use mab::{tokenize, tokenize_into};
+
+const TARGET_SIZE: usize = 2 * 1024 * 1024;
+
+/// Counts allocations, including reallocations, so that buffer growth shows
+/// up.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout)
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(pointer, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The number of allocations `f` makes.
+fn count_allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+/// Every parse example that tokenizes, in a stable order.
+fn example_sources() -> Vec<String> {
+    let mut sources: Vec<String> = fs::read_dir("parse_examples/source")
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .filter(|source| tokenize(source).is_ok())
+        .collect();
+
+    sources.sort();
+    sources
+}
+
+/// The parse examples repeated until they add up to about `TARGET_SIZE`.
+fn bundle() -> String {
+    let sources = example_sources();
+    let mut bundle = String::new();
+
+    while bundle.len() < TARGET_SIZE {
+        for source in &sources {
+            bundle.push_str(source);
+            bundle.push('\n');
+        }
+    }
+
+    bundle
+}
+
+fn tokenize_sources(c: &mut Criterion) {
+    let sources = vec![
+        ("bundle", bundle()),
+        ("small", fixtures::SMALL.to_string()),
+        ("module", fixtures::module()),
+        ("keywords", fixtures::keywords()),
+        ("pathological", fixtures::pathological()),
+    ];
+
+    let mut group = c.benchmark_group("tokenize");
+
+    for (name, source) in &sources {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| tokenize(source).unwrap().len())
+        });
+    }
+
+    group.finish();
+}
+
+fn tokenize_small_sources(c: &mut Criterion) {
+    let sources = example_sources();
+    let mut group = c.benchmark_group("small sources");
+
+    group.bench_function("tokenize", |b| {
+        b.iter(|| {
+            for source in &sources {
+                tokenize(source).unwrap();
+            }
+        })
+    });
+
+    let mut buffer = Vec::new();
+
+    group.bench_function("tokenize_into", |b| {
+        b.iter(|| {
+            for source in &sources {
+                tokenize_into(source, &mut buffer).unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
+// A stand-in for name resolution: count the uses of each name, keyed by the
+// name itself or by its interned id.
+fn resolve_names(c: &mut Criterion) {
+    let bundle = bundle();
+    let tokens = tokenize(&bundle).unwrap();
+    let mut interner = Interner::new();
+    let interned = tokenize_interned(&bundle, &mut interner).unwrap();
+
+    let mut group = c.benchmark_group("resolve names");
+
+    group.bench_function("tokens", |b| {
+        b.iter(|| {
+            let mut uses: HashMap<&str, usize> = HashMap::new();
+
+            for name in tokens.iter().filter_map(|token| token.as_identifier()) {
+                *uses.entry(name).or_insert(0) += 1;
+            }
+
+            uses.len()
+        })
+    });
+
+    group.bench_function("interned tokens", |b| {
+        b.iter(|| {
+            let mut uses = vec![0usize; interner.len()];
+
+            for symbol in interned.iter().filter_map(|token| token.symbol) {
+                uses[symbol.index()] += 1;
+            }
+
+            uses.iter().filter(|&&count| count > 0).count()
+        })
+    });
+
+    group.finish();
+}
+
+/// Criterion only times things, so allocations are counted once up front.
+fn report_allocations() {
+    let sources = example_sources();
+
+    let fresh = count_allocations(|| {
+        for source in &sources {
+            tokenize(source).unwrap();
+        }
+    });
+
+    let mut buffer = Vec::new();
+    let reused = count_allocations(|| {
+        for source in &sources {
+            tokenize_into(source, &mut buffer).unwrap();
+        }
+    });
+
+    println!(
+        "{} small sources: {} allocations with tokenize, {} with tokenize_into and one buffer",
+        sources.len(),
+        fresh,
+        reused,
+    );
+}
+
+criterion_group!(benches, tokenize_sources, tokenize_small_sources, resolve_names);
+
+fn main() {
+    report_allocations();
+    benches();
+
+    Criterion::default()
+        .configure_from_args()
+        .final_summary();
+}
@@ -0,0 +1,3 @@
+local count = 0
+local name, greeting = "mab", 'hello'
+print(greeting .. " " .. name)
@@ -0,0 +1,7 @@
+if a then
+	f()
+elseif b then
+	g()
+else
+	h()
+end
@@ -0,0 +1,2 @@
+-- Expressions can't stand alone as statements.
+x + 1
@@ -0,0 +1,9 @@
+function greet(name)
+	print("hi", name)
+end
+
+local function helper() end
+
+function M.util:run(a, b)
+	helper()
+end
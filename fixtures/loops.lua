@@ -0,0 +1,15 @@
+for i = 1, 10 do
+	print(i)
+end
+
+for key, value in pairs(t) do
+	print(key, value)
+end
+
+while running do
+	step()
+end
+
+repeat
+	wait(1)
+until done
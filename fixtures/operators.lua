@@ -0,0 +1,4 @@
+local total = 1 + 2 * 3 ^ 2 - -4 / 2
+local joined = "a" .. "b" .. "c"
+local negated = not true
+local size = #{1, 2, 3}
@@ -0,0 +1,6 @@
+local t = {
+	"positional",
+	key = true,
+	[1 + 1] = nil,
+	nested = {x = 1},
+}
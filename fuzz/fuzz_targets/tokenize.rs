@@ -0,0 +1,59 @@
+//! Feeds arbitrary bytes to the tokenizer, which should never panic and
+//! should only report errors at places inside the source. Run with
+//! `cargo fuzz run tokenize` from the repository root.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate mab;
+
+use mab::version::LuaVersion;
+use mab::{tokenize_recovering, tokenize_with_options, OnError, TokenizeError, TokenizerOptions};
+
+fn check_error(source: &str, err: &TokenizeError) {
+    if let Some(position) = err.position() {
+        assert!(source.is_char_boundary(position.bytes), "{:?} is outside of the source", err);
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+
+    // The first byte picks the options, so that every combination is tried.
+    let flags = data.first().cloned().unwrap_or(0);
+    let options = TokenizerOptions {
+        unicode_identifiers: flags & 1 != 0,
+        interpolated_strings: flags & 2 != 0,
+        luajit_number_suffixes: flags & 64 != 0,
+        emit_trivia: flags & 128 != 0,
+        tab_width: if flags & 4 != 0 { 4 } else { 0 },
+        version: match flags >> 3 & 7 {
+            0 => Some(LuaVersion::Lua51),
+            1 => Some(LuaVersion::Lua52),
+            2 => Some(LuaVersion::Lua53),
+            3 => Some(LuaVersion::Lua54),
+            _ => None,
+        },
+        on_error: match data.get(1).cloned().unwrap_or(0) % 3 {
+            0 => OnError::Stop,
+            1 => OnError::SkipChar,
+            _ => OnError::SkipToWhitespace,
+        },
+        ..TokenizerOptions::default()
+    };
+
+    match tokenize_with_options(&source, &options) {
+        Ok(tokens) => {
+            let reproduced: String = tokens.iter().map(|token| token.to_source()).collect();
+            assert_eq!(reproduced, source);
+        },
+        Err(err) => check_error(&source, &err),
+    }
+
+    let (_, errors) = tokenize_recovering(&source);
+
+    for err in &errors {
+        check_error(&source, err);
+    }
+});
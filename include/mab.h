@@ -0,0 +1,62 @@
+/*
+ * C interface to the mab Lua parser.
+ *
+ * Build the library with:
+ *
+ *     cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * Ownership rules:
+ *
+ * - mab_parse returns a handle owned by the caller, which must be released
+ *   with mab_result_free exactly once.
+ * - Strings returned by the mab_result_* accessors are owned by the handle.
+ *   They stay valid until the handle is freed and must not be freed by the
+ *   caller. They are UTF-8 and NOT NUL-terminated; their length in bytes is
+ *   written to the `length` out parameter.
+ * - No function keeps a reference to the source buffer after it returns.
+ *
+ * No function lets a Rust panic unwind into the caller.
+ */
+
+#ifndef MAB_H
+#define MAB_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct MabParseResult MabParseResult;
+
+/*
+ * Parses `length` bytes of UTF-8 source. Never returns NULL. `source` may only
+ * be NULL if `length` is 0.
+ */
+MabParseResult *mab_parse(const uint8_t *source, size_t length);
+
+/* Returns whether the source parsed successfully. False for NULL. */
+bool mab_result_success(const MabParseResult *result);
+
+/*
+ * Returns the error message if parsing failed, or NULL if it succeeded.
+ * `length` may be NULL.
+ */
+const uint8_t *mab_result_error(const MabParseResult *result, size_t *length);
+
+/*
+ * Returns the AST as JSON if parsing succeeded, or NULL if it failed.
+ * `length` may be NULL.
+ */
+const uint8_t *mab_result_ast_json(const MabParseResult *result, size_t *length);
+
+/* Frees a handle returned by mab_parse. Does nothing for NULL. */
+void mab_result_free(MabParseResult *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,2 @@
+do
+	print(1)
\ No newline at end of file
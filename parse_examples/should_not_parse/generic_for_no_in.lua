@@ -0,0 +1,3 @@
+for k, v inn pairs(t) do
+	print(k, v)
+end
\ No newline at end of file
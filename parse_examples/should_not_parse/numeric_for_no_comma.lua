@@ -0,0 +1,3 @@
+for k = 1 do
+	print(k)
+end
\ No newline at end of file
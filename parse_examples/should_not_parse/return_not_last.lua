@@ -0,0 +1,2 @@
+return 1
+print(1)
\ No newline at end of file
@@ -0,0 +1,3 @@
+function f()
+	return 1,
+end
\ No newline at end of file
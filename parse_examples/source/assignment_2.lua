@@ -0,0 +1 @@
+x, y = f(), 2
\ No newline at end of file
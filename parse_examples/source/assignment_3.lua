@@ -0,0 +1,3 @@
+a, b = b, a
+local c = a
+c = a + b
\ No newline at end of file
@@ -0,0 +1,3 @@
+t.x = 1
+t[k] = v
+a.b[c].d = e
\ No newline at end of file
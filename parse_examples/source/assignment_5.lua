@@ -0,0 +1 @@
+x, t.y, t[ [[z]]] = 1, 2, 3
\ No newline at end of file
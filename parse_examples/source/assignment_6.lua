@@ -0,0 +1 @@
+f().x, (t)[1] = 1, 2
\ No newline at end of file
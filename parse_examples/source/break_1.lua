@@ -0,0 +1,3 @@
+while true do
+	break
+end
\ No newline at end of file
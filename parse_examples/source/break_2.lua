@@ -0,0 +1,3 @@
+repeat
+	break
+until done
\ No newline at end of file
@@ -0,0 +1,5 @@
+for i = 1, 10 do
+	if i then
+		break
+	end
+end
\ No newline at end of file
@@ -0,0 +1 @@
+do end
\ No newline at end of file
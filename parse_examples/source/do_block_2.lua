@@ -0,0 +1,4 @@
+do
+	do
+	end
+end
\ No newline at end of file
@@ -0,0 +1,8 @@
+local a = 1
+
+do
+	local a = 2
+	print(a)
+end
+
+print(a)
\ No newline at end of file
@@ -0,0 +1 @@
+local x = t.x
\ No newline at end of file
@@ -0,0 +1 @@
+print(t.x, t["y"], t.list[1].z)
\ No newline at end of file
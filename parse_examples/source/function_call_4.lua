@@ -0,0 +1,2 @@
+os.execute("ls")
+t[1].f()
\ No newline at end of file
@@ -0,0 +1,2 @@
+io:write(1, 2)
+io.stdout:write(x)
\ No newline at end of file
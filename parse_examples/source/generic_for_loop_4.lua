@@ -0,0 +1,3 @@
+for k, v, extra in next, t, nil do
+	print(k, v, extra)
+end
\ No newline at end of file
@@ -0,0 +1 @@
+return 1
\ No newline at end of file
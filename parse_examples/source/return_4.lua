@@ -0,0 +1,7 @@
+function check(a)
+	if a then
+		return a
+	end
+
+	return
+end
\ No newline at end of file
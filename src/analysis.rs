@@ -0,0 +1,671 @@
+//! Source-level facts about a chunk that tools like coverage reporters and
+//! editors need.
+//!
+//! The AST doesn't record where nodes came from, so these functions work from
+//! the token stream and recover statement boundaries by parsing each block
+//! statement by statement.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+use ast::Statement;
+use parser::{expect_end_of_stream, parse_from_tokens, parse_statement_at};
+use tokenizer::{StringLiteral, Symbol, Token, TokenKind};
+use version::LuaVersion;
+
+/// A range of bytes in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Span {
+    /// The byte offset of the first token, inclusive.
+    pub start: usize,
+
+    /// The byte offset of the end of the last token, exclusive.
+    pub end: usize,
+}
+
+/// A statement and the line it's attributed to for coverage purposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatementLine {
+    /// The source of the whole statement, including any nested blocks.
+    pub span: Span,
+
+    /// The line the statement starts on, starting at 1.
+    pub line: usize,
+}
+
+/// Lists every statement in the chunk along with the line it starts on, in
+/// source order.
+///
+/// A statement that spans several lines is attributed to its first line. A
+/// statement with a body, like a function declaration or a loop, is listed
+/// itself and then each statement in its body is listed separately. Branches
+/// introduced by `elseif` are listed as well, since their condition is
+/// evaluated on that line.
+pub fn statement_lines(tokens: &[Token]) -> Result<Vec<StatementLine>, String> {
+    Ok(collect(tokens)?.lines)
+}
+
+/// Returns the set of lines that contain the start of an executable statement.
+///
+/// Lines that only contain keywords closing a block, like `end` and `else`,
+/// are never included.
+pub fn executable_lines(tokens: &[Token]) -> Result<BTreeSet<usize>, String> {
+    Ok(statement_lines(tokens)?
+        .into_iter()
+        .map(|statement| statement.line)
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymbolKind {
+    /// A function declared with `function name()`, including dotted names.
+    Function,
+
+    /// A function declared with `function name:method()`.
+    Method,
+
+    /// A function declared with `local function`.
+    LocalFunction,
+
+    /// A local variable declared at the top level of the chunk.
+    Local,
+}
+
+/// A named item in a chunk, for building an outline of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolInfo {
+    /// The full name of the symbol, like `M.foo` or `Class:method`.
+    pub name: String,
+
+    pub kind: SymbolKind,
+
+    /// The source of the whole declaration.
+    pub span: Span,
+
+    /// The source of just the name.
+    pub selection_span: Span,
+
+    /// Symbols nested in this one. Functions declared inside of a function are
+    /// its children, and functions declared on a top-level local table, like
+    /// `function M.foo()` after `local M = {}`, are children of that local.
+    pub children: Vec<SymbolInfo>,
+}
+
+/// Lists the symbols declared in a chunk as a tree, in source order.
+pub fn symbols(tokens: &[Token]) -> Result<Vec<SymbolInfo>, String> {
+    let collected = collect(tokens)?;
+
+    let mut statements: Vec<&SpannedStatement> = collected.statements.iter().collect();
+    statements.sort_by_key(|statement| statement.tokens.start);
+
+    // Symbols are gathered flat with the index of their parent, then turned
+    // into a tree once they're all known.
+    let mut flat: Vec<(SymbolInfo, Option<usize>)> = Vec::new();
+    let mut top_level_locals: HashMap<String, usize> = HashMap::new();
+
+    // The functions that enclose the current statement, by token range and
+    // index into `flat`.
+    let mut enclosing: Vec<(Range<usize>, usize)> = Vec::new();
+
+    let span_of = |start: usize, end: usize| Span {
+        start: tokens[start].start_position.bytes,
+        end: tokens[end - 1].end_position.bytes,
+    };
+
+    for statement in statements {
+        while let Some((range, _)) = enclosing.last() {
+            if statement.tokens.start < range.end {
+                break;
+            }
+
+            enclosing.pop();
+        }
+
+        match &statement.statement {
+            Statement::FunctionDeclaration(declaration) => {
+                let name_start = statement.tokens.start + if declaration.local { 2 } else { 1 };
+                let name_end = name_start + tokens[name_start..]
+                    .iter()
+                    .position(|token| token.kind == TokenKind::Symbol(Symbol::LeftParen))
+                    .expect("Function declaration is missing its parameters");
+
+                let kind = if declaration.local {
+                    SymbolKind::LocalFunction
+                } else if declaration.name.method.is_some() {
+                    SymbolKind::Method
+                } else {
+                    SymbolKind::Function
+                };
+
+                let parent = match enclosing.last() {
+                    Some(&(_, parent)) => Some(parent),
+                    None if !declaration.local && statement.block == 0 => {
+                        declaration.name.segments
+                            .first()
+                            .filter(|_| declaration.name.segments.len() > 1 || declaration.name.method.is_some())
+                            .and_then(|table| top_level_locals.get(table.as_ref()).cloned())
+                    },
+                    None => None,
+                };
+
+                flat.push((SymbolInfo {
+                    name: declaration.name.to_string(),
+                    kind,
+                    span: statement.span,
+                    selection_span: span_of(name_start, name_end),
+                    children: Vec::new(),
+                }, parent));
+
+                enclosing.push((statement.tokens.clone(), flat.len() - 1));
+            },
+            Statement::LocalAssignment(assignment) if statement.block == 0 => {
+                for (index, name) in assignment.names.iter().enumerate() {
+                    // Names are separated by commas after the `local` keyword.
+                    let name_token = statement.tokens.start + 1 + index * 2;
+
+                    flat.push((SymbolInfo {
+                        name: name.to_string(),
+                        kind: SymbolKind::Local,
+                        span: statement.span,
+                        selection_span: span_of(name_token, name_token + 1),
+                        children: Vec::new(),
+                    }, None));
+
+                    top_level_locals.insert(name.to_string(), flat.len() - 1);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Parents always come before their children, so attaching children from
+    // the back keeps every subtree complete by the time it's moved.
+    let mut roots = Vec::new();
+
+    while let Some((symbol, parent)) = flat.pop() {
+        match parent {
+            Some(parent) => flat[parent].0.children.insert(0, symbol),
+            None => roots.insert(0, symbol),
+        }
+    }
+
+    Ok(roots)
+}
+
+/// A construct that only some versions of Lua accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VersionFeature {
+    /// A `\xXX` escape in a string.
+    HexEscape,
+
+    /// A `\z` escape, which skips the whitespace after it.
+    SkipWhitespaceEscape,
+
+    /// A `\u{XXX}` escape.
+    UnicodeEscape,
+
+    /// An escape like `\q` that 5.1 reads as the character itself and later
+    /// versions reject.
+    UnknownEscape,
+
+    /// `goto` used as a name, which later versions reserve as a keyword.
+    GotoAsName,
+}
+
+impl VersionFeature {
+    /// Whether `version` accepts this construct.
+    pub fn is_supported_by(&self, version: LuaVersion) -> bool {
+        match *self {
+            VersionFeature::HexEscape | VersionFeature::SkipWhitespaceEscape => version >= LuaVersion::Lua52,
+            VersionFeature::UnicodeEscape => version >= LuaVersion::Lua53,
+            VersionFeature::UnknownEscape | VersionFeature::GotoAsName => version == LuaVersion::Lua51,
+        }
+    }
+}
+
+impl fmt::Display for VersionFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            VersionFeature::HexEscape => "hex escapes (5.2+)",
+            VersionFeature::SkipWhitespaceEscape => "\\z escapes (5.2+)",
+            VersionFeature::UnicodeEscape => "unicode escapes (5.3+)",
+            VersionFeature::UnknownEscape => "unknown escapes (5.1 only)",
+            VersionFeature::GotoAsName => "goto as a name (5.1 only)",
+        };
+
+        f.write_str(description)
+    }
+}
+
+/// One use of a version-specific construct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionFinding {
+    pub feature: VersionFeature,
+    pub span: Span,
+}
+
+/// Which versions of Lua accept a chunk, and why the others don't.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompatReport {
+    /// Every version-specific construct in the chunk, in source order.
+    pub findings: Vec<VersionFinding>,
+
+    /// The versions that accept every construct in the chunk, oldest first.
+    pub compatible: Vec<LuaVersion>,
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut features: Vec<VersionFeature> = Vec::new();
+
+        for finding in &self.findings {
+            if !features.contains(&finding.feature) {
+                features.push(finding.feature);
+            }
+        }
+
+        if !features.is_empty() {
+            let uses: Vec<String> = features.iter().map(ToString::to_string).collect();
+            write!(f, "uses {} \u{2014} ", uses.join(", "))?;
+        }
+
+        if self.compatible.len() == LuaVersion::ALL.len() {
+            write!(f, "compatible with all versions")
+        } else if self.compatible.is_empty() {
+            write!(f, "compatible with no version")
+        } else {
+            let versions: Vec<String> = self.compatible.iter().map(ToString::to_string).collect();
+            write!(f, "compatible with {}", versions.join(", "))
+        }
+    }
+}
+
+/// Reports which versions of Lua accept a chunk, along with every construct
+/// that rules out the others.
+///
+/// The tokenizer and parser accept the union of what the versions allow, so
+/// the constructs checked here are the ones that can make it through them:
+/// escapes in quoted strings, and names that later became keywords.
+pub fn compatibility(tokens: &[Token]) -> Result<CompatReport, String> {
+    parse_from_tokens(tokens)?;
+
+    let mut findings = Vec::new();
+
+    for token in tokens {
+        let start = token.start_position.bytes;
+
+        match &token.kind {
+            TokenKind::Identifier(name) if name == "goto" => {
+                findings.push(VersionFinding {
+                    feature: VersionFeature::GotoAsName,
+                    span: Span {
+                        start,
+                        end: token.end_position.bytes,
+                    },
+                });
+            },
+            TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content })
+            | TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content }) => {
+                // The content starts after the opening quote.
+                escape_findings(raw_content, start + 1, &mut findings);
+            },
+            _ => {},
+        }
+    }
+
+    let compatible = LuaVersion::ALL
+        .iter()
+        .cloned()
+        .filter(|&version| findings.iter().all(|finding| finding.feature.is_supported_by(version)))
+        .collect();
+
+    Ok(CompatReport {
+        findings,
+        compatible,
+    })
+}
+
+/// Finds version-specific escapes in the raw content of a quoted string that
+/// starts at byte `offset` of the source.
+fn escape_findings(content: &str, offset: usize, findings: &mut Vec<VersionFinding>) {
+    let mut chars = content.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+
+        let (escaped_index, escaped) = match chars.next() {
+            Some(next) => next,
+            None => break,
+        };
+
+        let feature = match escaped {
+            'x' => VersionFeature::HexEscape,
+            'z' => VersionFeature::SkipWhitespaceEscape,
+            'u' => VersionFeature::UnicodeEscape,
+            'a' | 'b' | 'f' | 'n' | 'r' | 't' | 'v' | '\\' | '"' | '\'' | '\n' | '\r' => continue,
+            c if c.is_ascii_digit() => continue,
+            _ => VersionFeature::UnknownEscape,
+        };
+
+        let end = match feature {
+            VersionFeature::HexEscape => escaped_index + 1 + content[escaped_index + 1..]
+                .chars()
+                .take(2)
+                .take_while(|c| c.is_ascii_hexdigit())
+                .count(),
+            VersionFeature::UnicodeEscape => match content[escaped_index..].find('}') {
+                Some(close) => escaped_index + close + 1,
+                None => escaped_index + 1,
+            },
+            _ => escaped_index + escaped.len_utf8(),
+        };
+
+        findings.push(VersionFinding {
+            feature,
+            span: Span {
+                start: offset + index,
+                end: offset + end,
+            },
+        });
+    }
+}
+
+/// A statement at any depth of a chunk, along with where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SpannedStatement<'a> {
+    pub statement: Statement<'a>,
+
+    /// The indices of the statement's tokens.
+    pub tokens: Range<usize>,
+
+    pub span: Span,
+
+    /// Identifies the block the statement is directly in. Statements in the
+    /// same block have the same value and are listed in order.
+    pub block: usize,
+}
+
+pub(crate) struct Collected<'a> {
+    pub lines: Vec<StatementLine>,
+    pub statements: Vec<SpannedStatement<'a>>,
+}
+
+/// Finds every statement in a chunk at any depth.
+pub(crate) fn collect<'a>(tokens: &'a [Token<'a>]) -> Result<Collected<'a>, String> {
+    let mut collector = Collector {
+        tokens,
+        blocks: 0,
+        collected: Collected {
+            lines: Vec::new(),
+            statements: Vec::new(),
+        },
+    };
+
+    let position = collector.block(0)?;
+    expect_end_of_stream(tokens, position)?;
+
+    Ok(collector.collected)
+}
+
+struct Collector<'a> {
+    tokens: &'a [Token<'a>],
+    blocks: usize,
+    collected: Collected<'a>,
+}
+
+impl<'a> Collector<'a> {
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span {
+            start: self.tokens[start].start_position.bytes,
+            end: self.tokens[end - 1].end_position.bytes,
+        }
+    }
+
+    fn record(&mut self, start: usize, end: usize) {
+        let span = self.span(start, end);
+
+        self.collected.lines.push(StatementLine {
+            span,
+            line: self.tokens[start].start_position.line,
+        });
+    }
+
+    /// Collects every statement of the block starting at the token at
+    /// `position` and returns the position of the token that ended it.
+    fn block(&mut self, position: usize) -> Result<usize, String> {
+        let block = self.blocks;
+        self.blocks += 1;
+
+        let mut position = position;
+
+        while let Some((next, statement)) = parse_statement_at(self.tokens, position)? {
+            self.record(position, next);
+            self.statement(position, &statement)?;
+
+            let span = self.span(position, next);
+            self.collected.statements.push(SpannedStatement {
+                statement,
+                tokens: position..next,
+                span,
+                block,
+            });
+
+            position = next;
+        }
+
+        Ok(position)
+    }
+
+    fn statement(&mut self, start: usize, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::FunctionDeclaration(_) => {
+                let body = self.after(start, Symbol::RightParen);
+                self.block(body)?;
+            },
+            Statement::NumericFor(_) | Statement::GenericFor(_) | Statement::WhileLoop(_) => {
+                let body = self.after(start, Symbol::Do);
+                self.block(body)?;
+            },
+            Statement::RepeatLoop(_) | Statement::Do(_) => {
+                self.block(start + 1)?;
+            },
+            Statement::IfStatement(_) => {
+                let body = self.after(start, Symbol::Then);
+                let mut position = self.block(body)?;
+
+                loop {
+                    match self.tokens[position].kind {
+                        TokenKind::Symbol(Symbol::ElseIf) => {
+                            let body = self.after(position, Symbol::Then);
+                            self.record(position, body - 1);
+                            position = self.block(body)?;
+                        },
+                        TokenKind::Symbol(Symbol::Else) => {
+                            position = self.block(position + 1)?;
+                        },
+                        _ => break,
+                    }
+                }
+            },
+            Statement::Assignment(_)
+            | Statement::LocalAssignment(_)
+            | Statement::FunctionCall(_)
+            | Statement::Return(_)
+            | Statement::Break => {},
+        }
+
+        Ok(())
+    }
+
+    /// Finds the first `symbol` at or after `position` and returns the position
+    /// of the token after it. Statements were already parsed successfully, so
+    /// the symbol is known to be there.
+    fn after(&self, position: usize, symbol: Symbol) -> usize {
+        let offset = self.tokens[position..]
+            .iter()
+            .position(|token| token.kind == TokenKind::Symbol(symbol))
+            .expect("Statement is missing an expected symbol");
+
+        position + offset + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokenizer::tokenize;
+
+    const FIXTURE: &str = r#"local a = compute(
+	1,
+	2
+)
+
+function f(x)
+	if big(x) then
+		print("big")
+	elseif x
+		+ 1 then
+		print("small")
+	elseif zero(x) then
+	else
+		print(
+			"negative"
+		)
+	end
+
+	repeat
+		local y = x - 1
+	until negative(y)
+end
+
+f(a)
+"#;
+
+    #[test]
+    fn fixture_lines() {
+        let tokens = tokenize(FIXTURE).unwrap();
+        let lines = executable_lines(&tokens).unwrap();
+
+        let expected: BTreeSet<usize> = vec![1, 6, 7, 8, 9, 11, 12, 14, 19, 20, 24].into_iter().collect();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn statement_spans() {
+        let source = "local a = 1\nwhile a do\n\tlocal b = 2\nend";
+        let tokens = tokenize(source).unwrap();
+        let statements = statement_lines(&tokens).unwrap();
+
+        let spans: Vec<_> = statements
+            .iter()
+            .map(|statement| (&source[statement.span.start..statement.span.end], statement.line))
+            .collect();
+
+        assert_eq!(spans, vec![
+            ("local a = 1", 1),
+            ("while a do\n\tlocal b = 2\nend", 2),
+            ("local b = 2", 3),
+        ]);
+    }
+
+    const MODULE: &str = "local M = {}
+local cache, count = {}, 0
+
+local function helper(x)
+	print(x)
+end
+
+function M.foo()
+	local function inner() end
+	if count then
+		function M.nested() end
+	end
+end
+
+function M.sub.bar(a, b) end
+
+function M:method() end
+
+function global() end
+";
+
+    fn outline(symbols: &[SymbolInfo], depth: usize, lines: &mut Vec<String>) {
+        for symbol in symbols {
+            lines.push(format!("{}{:?} {} [{}]", "  ".repeat(depth), symbol.kind, symbol.name, &MODULE[symbol.selection_span.start..symbol.selection_span.end]));
+            outline(&symbol.children, depth + 1, lines);
+        }
+    }
+
+    #[test]
+    fn module_outline() {
+        let tokens = tokenize(MODULE).unwrap();
+        let symbols = symbols(&tokens).unwrap();
+
+        let mut lines = Vec::new();
+        outline(&symbols, 0, &mut lines);
+
+        assert_eq!(lines, vec![
+            "Local M [M]",
+            "  Function M.foo [M.foo]",
+            "    LocalFunction inner [inner]",
+            "    Function M.nested [M.nested]",
+            "  Function M.sub.bar [M.sub.bar]",
+            "  Method M:method [M:method]",
+            "Local cache [cache]",
+            "Local count [count]",
+            "LocalFunction helper [helper]",
+            "Function global [global]",
+        ]);
+
+        let foo = &symbols[0].children[0];
+        assert!(MODULE[foo.span.start..foo.span.end].starts_with("function M.foo()"));
+        assert!(MODULE[foo.span.start..foo.span.end].ends_with("\tend\nend"));
+    }
+
+    #[test]
+    fn invalid_source() {
+        let tokens = tokenize("while true do").unwrap();
+        assert!(executable_lines(&tokens).is_err());
+    }
+
+    #[test]
+    fn plain_source_is_compatible_with_everything() {
+        let tokens = tokenize(FIXTURE).unwrap();
+        let report = compatibility(&tokens).unwrap();
+
+        assert!(report.findings.is_empty());
+        assert_eq!(report.compatible, LuaVersion::ALL.to_vec());
+        assert_eq!(report.to_string(), "compatible with all versions");
+    }
+
+    #[test]
+    fn mixed_features() {
+        let source = "local a = \"\\65\\n\\x41\"\nprint('\\u{1F600}', a, \"\\x4\")";
+        let tokens = tokenize(source).unwrap();
+        let report = compatibility(&tokens).unwrap();
+
+        let findings: Vec<_> = report.findings
+            .iter()
+            .map(|finding| (finding.feature, &source[finding.span.start..finding.span.end]))
+            .collect();
+
+        assert_eq!(findings, vec![
+            (VersionFeature::HexEscape, "\\x41"),
+            (VersionFeature::UnicodeEscape, "\\u{1F600}"),
+            (VersionFeature::HexEscape, "\\x4"),
+        ]);
+        assert_eq!(report.compatible, vec![LuaVersion::Lua53, LuaVersion::Lua54]);
+        assert_eq!(report.to_string(), "uses hex escapes (5.2+), unicode escapes (5.3+) \u{2014} compatible with 5.3, 5.4");
+
+        let tokens = tokenize("goto(\"\\q\", \"\\z\")").unwrap();
+        let report = compatibility(&tokens).unwrap();
+
+        let features: Vec<_> = report.findings.iter().map(|finding| finding.feature).collect();
+        assert_eq!(features, vec![VersionFeature::GotoAsName, VersionFeature::UnknownEscape, VersionFeature::SkipWhitespaceEscape]);
+        assert!(report.compatible.is_empty());
+    }
+}
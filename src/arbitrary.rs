@@ -0,0 +1,318 @@
+//! proptest strategies that generate ASTs the parser can produce.
+//!
+//! Every generated tree survives a trip through the emitter and back through
+//! the parser unchanged, so the strategies only produce what the parser
+//! understands today: no varargs, calls on plain names, assignment targets
+//! without calls in them, and parentheses wherever operator precedence would
+//! otherwise regroup an expression.
+//!
+//! Trees are built from small pieces with `prop_recursive`, so shrinking a
+//! failing case tends to end at a handful of nodes.
+
+use std::borrow::Cow;
+
+use proptest::prelude::*;
+use proptest::arbitrary::Arbitrary;
+use proptest::collection::vec;
+use proptest::option;
+
+use ast::*;
+use tokenizer::StringLiteral;
+
+const KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function",
+    "goto", "if", "in", "local", "nil", "not", "or", "repeat", "return", "then",
+    "true", "until", "while",
+];
+
+/// Generates identifiers that aren't keywords in any Lua version.
+pub fn identifier() -> BoxedStrategy<Cow<'static, str>> {
+    "[a-z][a-z0-9_]{0,5}"
+        .prop_filter("identifiers can't be keywords", |name| !KEYWORDS.contains(&name.as_str()))
+        .prop_map(Cow::Owned)
+        .boxed()
+}
+
+/// Generates decimal, fractional, exponent and hexadecimal number literals.
+pub fn number() -> BoxedStrategy<Cow<'static, str>> {
+    prop_oneof![
+        "[0-9]{1,4}",
+        "[0-9]{1,3}\\.[0-9]{1,3}",
+        "[0-9]{1,2}e-?[0-9]{1,2}",
+        "0x[0-9A-F]{1,4}",
+    ].prop_map(Cow::Owned).boxed()
+}
+
+/// Generates string literals in all three quoting styles.
+pub fn string_literal() -> BoxedStrategy<StringLiteral<'static>> {
+    let content = || "[a-zA-Z0-9 ]{0,8}".prop_map(Cow::Owned);
+
+    prop_oneof![
+        content().prop_map(|raw_content| StringLiteral::DoubleQuote { raw_content }),
+        content().prop_map(|raw_content| StringLiteral::SingleQuote { raw_content }),
+        (content(), 0..3u32).prop_map(|(raw_content, depth)| StringLiteral::LongForm { raw_content, depth }),
+    ].boxed()
+}
+
+fn names(max: usize) -> BoxedStrategy<Vec<Cow<'static, str>>> {
+    vec(identifier(), 1..max + 1).boxed()
+}
+
+fn parenthesize(expression: Expression<'static>, needed: bool) -> Box<Expression<'static>> {
+    if needed {
+        Box::new(Expression::ParenExpression(Box::new(expression)))
+    } else {
+        Box::new(expression)
+    }
+}
+
+/// Builds a unary operation, adding parentheses around the argument if the
+/// operator would otherwise bind tighter than it.
+fn unary(operator: UnaryOpKind, argument: Expression<'static>) -> Expression<'static> {
+    let needed = match &argument {
+        Expression::BinaryOp(op) => op.operator.precedence() < 12,
+        _ => false,
+    };
+
+    Expression::UnaryOp(UnaryOp {
+        operator,
+        argument: parenthesize(argument, needed),
+    })
+}
+
+/// Builds a binary operation, adding parentheses around either operand if it
+/// would otherwise be regrouped by precedence or associativity.
+fn binary(operator: BinaryOpKind, left: Expression<'static>, right: Expression<'static>) -> Expression<'static> {
+    let precedence = operator.precedence();
+
+    let left_needed = match &left {
+        Expression::BinaryOp(op) => {
+            let inner = op.operator.precedence();
+            inner < precedence || (inner == precedence && operator.is_right_associative())
+        },
+        Expression::UnaryOp(op) => op.operator.precedence() < precedence,
+        _ => false,
+    };
+
+    let right_needed = match &right {
+        Expression::BinaryOp(op) => {
+            let inner = op.operator.precedence();
+            inner < precedence || (inner == precedence && !operator.is_right_associative())
+        },
+        _ => false,
+    };
+
+    Expression::BinaryOp(BinaryOp {
+        operator,
+        left: parenthesize(left, left_needed),
+        right: parenthesize(right, right_needed),
+    })
+}
+
+fn call(name: Cow<'static, str>, arguments: Vec<Expression<'static>>) -> FunctionCall<'static> {
+    FunctionCall {
+        name_expression: Box::new(Expression::Name(name)),
+        method: None,
+        arguments,
+    }
+}
+
+fn unary_operator() -> BoxedStrategy<UnaryOpKind> {
+    prop_oneof![
+        Just(UnaryOpKind::Negate),
+        Just(UnaryOpKind::BooleanNot),
+        Just(UnaryOpKind::Length),
+    ].boxed()
+}
+
+fn binary_operator() -> BoxedStrategy<BinaryOpKind> {
+    prop_oneof![
+        Just(BinaryOpKind::Add),
+        Just(BinaryOpKind::Subtract),
+        Just(BinaryOpKind::Multiply),
+        Just(BinaryOpKind::Divide),
+        Just(BinaryOpKind::Exponent),
+        Just(BinaryOpKind::Concat),
+    ].boxed()
+}
+
+/// Generates expressions up to a few levels deep.
+pub fn expression() -> BoxedStrategy<Expression<'static>> {
+    let leaf = prop_oneof![
+        Just(Expression::Nil),
+        any::<bool>().prop_map(Expression::Bool),
+        number().prop_map(Expression::Number),
+        string_literal().prop_map(Expression::String),
+        identifier().prop_map(Expression::Name),
+    ];
+
+    leaf.prop_recursive(4, 32, 4, |inner| {
+        let key = prop_oneof![
+            identifier().prop_map(TableKey::Name),
+            inner.clone().prop_map(TableKey::Expression),
+        ];
+
+        prop_oneof![
+            vec((option::of(key), inner.clone()), 0..4)
+                .prop_map(|items| Expression::Table(TableLiteral { items })),
+            (identifier(), vec(inner.clone(), 0..3))
+                .prop_map(|(name, arguments)| Expression::FunctionCall(call(name, arguments))),
+            inner.clone().prop_map(|inner| Expression::ParenExpression(Box::new(inner))),
+            (unary_operator(), inner.clone())
+                .prop_map(|(operator, argument)| unary(operator, argument)),
+            (binary_operator(), inner.clone(), inner)
+                .prop_map(|(operator, left, right)| binary(operator, left, right)),
+        ]
+    }).boxed()
+}
+
+fn assignment_target() -> BoxedStrategy<AssignmentTarget<'static>> {
+    identifier().prop_map(AssignmentTarget::Name).prop_recursive(3, 8, 1, |inner| {
+        // Bases are prefix expressions, so they can be calls and
+        // parenthesized expressions as well as other targets.
+        let base = prop_oneof![
+            inner.prop_map(|target| match target {
+                AssignmentTarget::Name(name) => Expression::Name(name),
+                target => Expression::Var(Box::new(target)),
+            }),
+            (identifier(), vec(expression(), 0..2))
+                .prop_map(|(name, arguments)| Expression::FunctionCall(call(name, arguments))),
+            expression().prop_map(|inner| Expression::ParenExpression(Box::new(inner))),
+        ];
+
+        prop_oneof![
+            (base.clone(), identifier())
+                .prop_map(|(base, name)| AssignmentTarget::FieldAccess { base: Box::new(base), name }),
+            (base, expression())
+                .prop_map(|(base, index)| AssignmentTarget::IndexAccess { base: Box::new(base), index: Box::new(index) }),
+        ]
+    }).boxed()
+}
+
+fn simple_statement() -> BoxedStrategy<Statement<'static>> {
+    prop_oneof![
+        (names(3), vec(expression(), 0..3))
+            .prop_map(|(names, values)| Statement::LocalAssignment(LocalAssignment { names, values })),
+        (vec(assignment_target(), 1..4), vec(expression(), 1..4))
+            .prop_map(|(targets, values)| Statement::Assignment(Assignment { targets, values })),
+        (identifier(), vec(expression(), 0..3))
+            .prop_map(|(name, arguments)| Statement::FunctionCall(call(name, arguments))),
+        Just(Statement::Break),
+    ].boxed()
+}
+
+fn function_name() -> BoxedStrategy<(FunctionName<'static>, bool)> {
+    prop_oneof![
+        identifier().prop_map(|name| (FunctionName { segments: vec![name], method: None }, true)),
+        (names(3), option::of(identifier()))
+            .prop_map(|(segments, method)| (FunctionName { segments, method }, false)),
+    ].boxed()
+}
+
+/// Generates return statements with up to two values.
+pub fn return_statement() -> BoxedStrategy<ReturnStatement<'static>> {
+    vec(expression(), 0..3)
+        .prop_map(|values| ReturnStatement { values })
+        .boxed()
+}
+
+/// Makes a chunk of `statements`, ending with `last` if there is one, since
+/// a return can't be anywhere else.
+fn block(mut statements: Vec<Statement<'static>>, last: Option<ReturnStatement<'static>>) -> Chunk<'static> {
+    statements.extend(last.map(Statement::Return));
+    Chunk { statements }
+}
+
+/// Generates statements, including block statements with nested bodies.
+/// Returns only show up at the end of those bodies.
+pub fn statement() -> BoxedStrategy<Statement<'static>> {
+    simple_statement().prop_recursive(3, 24, 3, |inner| {
+        let body = || (vec(inner.clone(), 0..3), option::of(return_statement())).prop_map(|(statements, last)| block(statements, last));
+
+        prop_oneof![
+            (identifier(), expression(), expression(), option::of(expression()), body())
+                .prop_map(|(var, start, end, step, body)| Statement::NumericFor(NumericFor { var, start, end, step, body })),
+            (names(3), vec(expression(), 1..3), body())
+                .prop_map(|(vars, item_source, body)| Statement::GenericFor(GenericFor { vars, item_source, body })),
+            (expression(), body(), vec((expression(), body()), 0..2), option::of(body()))
+                .prop_map(|(condition, body, else_if_branches, else_branch)| Statement::IfStatement(IfStatement {
+                    condition,
+                    body,
+                    else_if_branches,
+                    else_branch,
+                })),
+            (expression(), body())
+                .prop_map(|(condition, body)| Statement::WhileLoop(WhileLoop { condition, body })),
+            (expression(), body())
+                .prop_map(|(condition, body)| Statement::RepeatLoop(RepeatLoop { condition, body })),
+            body().prop_map(Statement::Do),
+            (function_name(), vec(identifier(), 0..3), body())
+                .prop_map(|((name, local), parameters, body)| Statement::FunctionDeclaration(FunctionDeclaration {
+                    name,
+                    body,
+                    parameters,
+                    local,
+                })),
+        ]
+    }).boxed()
+}
+
+/// Generates whole chunks of a few statements.
+pub fn chunk() -> BoxedStrategy<Chunk<'static>> {
+    (vec(statement(), 0..4), option::of(return_statement()))
+        .prop_map(|(statements, last)| block(statements, last))
+        .boxed()
+}
+
+impl Arbitrary for Expression<'static> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        expression()
+    }
+}
+
+impl Arbitrary for Statement<'static> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        statement()
+    }
+}
+
+impl Arbitrary for Chunk<'static> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        chunk()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use emitter::emit_chunk;
+    use parser::parse_from_tokens;
+    use tokenizer::tokenize;
+
+    proptest! {
+        #[test]
+        fn emitted_chunks_parse_back(chunk in any::<Chunk<'static>>()) {
+            let mut source = String::new();
+            emit_chunk(&mut source, &chunk).unwrap();
+
+            let tokens = tokenize(&source);
+            prop_assert!(tokens.is_ok(), "{:?}\nsource was:\n{}", tokens, source);
+            let tokens = tokens.unwrap();
+
+            let parsed = parse_from_tokens(&tokens);
+            prop_assert!(parsed.is_ok(), "{:?}\nsource was:\n{}", parsed, source);
+
+            prop_assert_eq!(parsed.unwrap(), chunk, "source was:\n{}", source);
+        }
+    }
+}
@@ -49,10 +49,7 @@ impl BinaryOpKind {
     }
 
     pub fn is_right_associative(&self) -> bool {
-        match *self {
-            BinaryOpKind::Exponent | BinaryOpKind::Concat => true,
-            _ => false,
-        }
+        matches!(*self, BinaryOpKind::Exponent | BinaryOpKind::Concat)
     }
 }
 
@@ -75,13 +72,40 @@ pub struct BinaryOp<'a> {
 pub struct FunctionCall<'a> {
     #[serde(borrow)]
     pub name_expression: Box<Expression<'a>>,
+
+    /// The name after `:` in a method call like `io:write(x)`, which passes
+    /// `name_expression` as the first argument.
+    #[serde(default)]
+    pub method: Option<Cow<'a, str>>,
+
     pub arguments: Vec<Expression<'a>>,
 }
 
+// var ::= Name | prefixexp `[´ exp `]´ | prefixexp `.´ Name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AssignmentTarget<'a> {
+    #[serde(borrow)]
+    Name(Cow<'a, str>),
+
+    // base `.´ name
+    FieldAccess {
+        #[serde(borrow)]
+        base: Box<Expression<'a>>,
+        name: Cow<'a, str>,
+    },
+
+    // base `[´ index `]´
+    IndexAccess {
+        #[serde(borrow)]
+        base: Box<Expression<'a>>,
+        index: Box<Expression<'a>>,
+    },
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Assignment<'a> {
     #[serde(borrow)]
-    pub names: Vec<Cow<'a, str>>,
+    pub targets: Vec<AssignmentTarget<'a>>,
     pub values: Vec<Expression<'a>>,
 }
 
@@ -133,6 +157,13 @@ pub struct RepeatLoop<'a> {
     pub body: Chunk<'a>,
 }
 
+// retstat ::= return [explist] [';']
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReturnStatement<'a> {
+    #[serde(borrow)]
+    pub values: Vec<Expression<'a>>,
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct FunctionName<'a> {
     pub segments: Vec<Cow<'a, str>>,
@@ -145,7 +176,7 @@ impl<'a> fmt::Display for FunctionName<'a> {
         f.write_str(&s)?;
         if let Some(m) = &self.method {
             f.write_str(":")?;
-            f.write_str(&m)?;
+            f.write_str(m)?;
         }
         Ok(())
     }
@@ -200,6 +231,11 @@ pub enum Expression<'a> {
     Table(TableLiteral<'a>),
     FunctionCall(FunctionCall<'a>),
     Name(Cow<'a, str>),
+
+    /// A field or index of a name, like `t.x` or `t[1]`, either read as a
+    /// value or called as a function.
+    Var(Box<AssignmentTarget<'a>>),
+
     ParenExpression(Box<Expression<'a>>),
     UnaryOp(UnaryOp<'a>),
     BinaryOp(BinaryOp<'a>),
@@ -221,6 +257,126 @@ pub struct TableLiteral<'a> {
     pub items: Vec<(Option<TableKey<'a>>, Expression<'a>)>,
 }
 
+/// The key that an item of a table literal is stored under.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EffectiveKey<'t, 'a: 't> {
+    /// The key was written out.
+    Explicit(&'t TableKey<'a>),
+
+    /// A positional item, stored at this index, starting at 1.
+    Index(usize),
+
+    /// A function call or `...` as the last item, which stores every value
+    /// it produces starting at this index.
+    Expands(usize),
+}
+
+impl<'a> TableLiteral<'a> {
+    /// Pairs each item with the key it's stored under. Only positional items
+    /// advance the index, so `{"a", x = 1, "b"}` stores `"b"` at 2.
+    pub fn entries<'t>(&'t self) -> Vec<(EffectiveKey<'t, 'a>, &'t Expression<'a>)> {
+        let mut index = 0;
+
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(position, (key, value))| {
+                let key = match key {
+                    Some(key) => EffectiveKey::Explicit(key),
+                    None => {
+                        index += 1;
+
+                        let expands = matches!(value, Expression::FunctionCall(_) | Expression::VarArg);
+
+                        if expands && position == self.items.len() - 1 {
+                            EffectiveKey::Expands(index)
+                        } else {
+                            EffectiveKey::Index(index)
+                        }
+                    },
+                };
+
+                (key, value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod table_literal_tests {
+    use super::*;
+
+    use parser::parse_from_tokens;
+    use tokenizer::tokenize;
+
+    fn with_table<F: FnOnce(&TableLiteral)>(source: &str, callback: F) {
+        let tokens = tokenize(source).unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+
+        match &chunk.statements[0] {
+            Statement::LocalAssignment(LocalAssignment { values, .. }) => match &values[0] {
+                Expression::Table(table) => callback(table),
+                other => panic!("expected a table, got {:?}", other),
+            },
+            other => panic!("expected a local assignment, got {:?}", other),
+        }
+    }
+
+    fn describe(key: &EffectiveKey) -> String {
+        match key {
+            EffectiveKey::Explicit(TableKey::Name(name)) => name.to_string(),
+            EffectiveKey::Explicit(TableKey::Expression(_)) => "[...]".to_string(),
+            EffectiveKey::Index(index) => index.to_string(),
+            EffectiveKey::Expands(index) => format!("{}...", index),
+        }
+    }
+
+    #[test]
+    fn interleaved() {
+        with_table("local t = {\"a\", x = 1, \"b\", [3] = 2, \"c\"}", |table| {
+            let keys: Vec<_> = table.entries().iter().map(|(key, _)| describe(key)).collect();
+            assert_eq!(keys, vec!["1", "x", "2", "[...]", "3"]);
+        });
+    }
+
+    #[test]
+    fn nested() {
+        with_table("local t = {x = {\"a\", \"b\"}, {y = 1, \"c\"}}", |table| {
+            let keys: Vec<_> = table.entries()
+                .iter()
+                .map(|(key, value)| match value {
+                    Expression::Table(inner) => {
+                        let inner_keys: Vec<_> = inner.entries().iter().map(|(key, _)| describe(key)).collect();
+                        format!("{} = {{{}}}", describe(key), inner_keys.join(", "))
+                    },
+                    _ => describe(key),
+                })
+                .collect();
+
+            assert_eq!(keys, vec!["x = {1, 2}", "1 = {y, 1}"]);
+        });
+    }
+
+    #[test]
+    fn trailing_call() {
+        with_table("local t = {f(), \"a\", g()}", |table| {
+            let keys: Vec<_> = table.entries().iter().map(|(key, _)| describe(key)).collect();
+            assert_eq!(keys, vec!["1", "2", "3..."]);
+        });
+
+        with_table("local t = {\"a\", (g()), x = f()}", |table| {
+            let keys: Vec<_> = table.entries().iter().map(|(key, _)| describe(key)).collect();
+            assert_eq!(keys, vec!["1", "2", "x"]);
+        });
+
+        let table = TableLiteral {
+            items: vec![(None, Expression::Nil), (None, Expression::VarArg)],
+        };
+        let keys: Vec<_> = table.entries().iter().map(|(key, _)| describe(key)).collect();
+        assert_eq!(keys, vec!["1", "2..."]);
+    }
+}
+
 // stat ::=  ‘;’ |
 //     varlist ‘=’ explist |
 //     functioncall |
@@ -248,6 +404,16 @@ pub enum Statement<'a> {
     WhileLoop(WhileLoop<'a>),
     RepeatLoop(RepeatLoop<'a>),
     FunctionDeclaration(FunctionDeclaration<'a>),
+
+    /// A `do ... end` block.
+    Do(Chunk<'a>),
+
+    /// Only ever the last statement of a chunk, like the grammar's `retstat`.
+    Return(ReturnStatement<'a>),
+
+    /// Allowed anywhere in a block, like from Lua 5.2 on. Whether it's inside
+    /// a loop isn't checked.
+    Break,
 }
 
 // chunk ::= block
@@ -256,4 +422,265 @@ pub enum Statement<'a> {
 pub struct Chunk<'a> {
     #[serde(borrow)]
     pub statements: Vec<Statement<'a>>,
-}
\ No newline at end of file
+}
+
+fn cow_into_owned<'a>(value: Cow<'a, str>) -> Cow<'static, str> {
+    Cow::Owned(value.into_owned())
+}
+
+fn cows_into_owned<'a>(values: Vec<Cow<'a, str>>) -> Vec<Cow<'static, str>> {
+    values.into_iter().map(cow_into_owned).collect()
+}
+
+fn expressions_into_owned<'a>(values: Vec<Expression<'a>>) -> Vec<Expression<'static>> {
+    values.into_iter().map(Expression::into_owned).collect()
+}
+
+impl<'a> UnaryOp<'a> {
+    pub fn into_owned(self) -> UnaryOp<'static> {
+        UnaryOp {
+            operator: self.operator,
+            argument: Box::new(self.argument.into_owned()),
+        }
+    }
+}
+
+impl<'a> BinaryOp<'a> {
+    pub fn into_owned(self) -> BinaryOp<'static> {
+        BinaryOp {
+            operator: self.operator,
+            left: Box::new(self.left.into_owned()),
+            right: Box::new(self.right.into_owned()),
+        }
+    }
+}
+
+impl<'a> FunctionCall<'a> {
+    pub fn into_owned(self) -> FunctionCall<'static> {
+        FunctionCall {
+            name_expression: Box::new(self.name_expression.into_owned()),
+            method: self.method.map(cow_into_owned),
+            arguments: expressions_into_owned(self.arguments),
+        }
+    }
+}
+
+impl<'a> Assignment<'a> {
+    pub fn into_owned(self) -> Assignment<'static> {
+        Assignment {
+            targets: self.targets.into_iter().map(AssignmentTarget::into_owned).collect(),
+            values: expressions_into_owned(self.values),
+        }
+    }
+}
+
+impl<'a> AssignmentTarget<'a> {
+    pub fn into_owned(self) -> AssignmentTarget<'static> {
+        match self {
+            AssignmentTarget::Name(name) => AssignmentTarget::Name(cow_into_owned(name)),
+            AssignmentTarget::FieldAccess { base, name } => AssignmentTarget::FieldAccess {
+                base: Box::new(base.into_owned()),
+                name: cow_into_owned(name),
+            },
+            AssignmentTarget::IndexAccess { base, index } => AssignmentTarget::IndexAccess {
+                base: Box::new(base.into_owned()),
+                index: Box::new(index.into_owned()),
+            },
+        }
+    }
+}
+
+impl<'a> LocalAssignment<'a> {
+    pub fn into_owned(self) -> LocalAssignment<'static> {
+        LocalAssignment {
+            names: cows_into_owned(self.names),
+            values: expressions_into_owned(self.values),
+        }
+    }
+}
+
+impl<'a> NumericFor<'a> {
+    pub fn into_owned(self) -> NumericFor<'static> {
+        NumericFor {
+            var: cow_into_owned(self.var),
+            start: self.start.into_owned(),
+            end: self.end.into_owned(),
+            step: self.step.map(Expression::into_owned),
+            body: self.body.into_owned(),
+        }
+    }
+}
+
+impl<'a> GenericFor<'a> {
+    pub fn into_owned(self) -> GenericFor<'static> {
+        GenericFor {
+            vars: cows_into_owned(self.vars),
+            item_source: expressions_into_owned(self.item_source),
+            body: self.body.into_owned(),
+        }
+    }
+}
+
+impl<'a> IfStatement<'a> {
+    pub fn into_owned(self) -> IfStatement<'static> {
+        IfStatement {
+            condition: self.condition.into_owned(),
+            body: self.body.into_owned(),
+            else_if_branches: self.else_if_branches
+                .into_iter()
+                .map(|(condition, body)| (condition.into_owned(), body.into_owned()))
+                .collect(),
+            else_branch: self.else_branch.map(Chunk::into_owned),
+        }
+    }
+}
+
+impl<'a> WhileLoop<'a> {
+    pub fn into_owned(self) -> WhileLoop<'static> {
+        WhileLoop {
+            condition: self.condition.into_owned(),
+            body: self.body.into_owned(),
+        }
+    }
+}
+
+impl<'a> RepeatLoop<'a> {
+    pub fn into_owned(self) -> RepeatLoop<'static> {
+        RepeatLoop {
+            condition: self.condition.into_owned(),
+            body: self.body.into_owned(),
+        }
+    }
+}
+
+impl<'a> ReturnStatement<'a> {
+    pub fn into_owned(self) -> ReturnStatement<'static> {
+        ReturnStatement {
+            values: expressions_into_owned(self.values),
+        }
+    }
+}
+
+impl<'a> FunctionName<'a> {
+    pub fn into_owned(self) -> FunctionName<'static> {
+        FunctionName {
+            segments: cows_into_owned(self.segments),
+            method: self.method.map(cow_into_owned),
+        }
+    }
+}
+
+impl<'a> FunctionDeclaration<'a> {
+    pub fn into_owned(self) -> FunctionDeclaration<'static> {
+        FunctionDeclaration {
+            name: self.name.into_owned(),
+            body: self.body.into_owned(),
+            parameters: cows_into_owned(self.parameters),
+            local: self.local,
+        }
+    }
+}
+
+impl<'a> Expression<'a> {
+    pub fn into_owned(self) -> Expression<'static> {
+        match self {
+            Expression::Nil => Expression::Nil,
+            Expression::Bool(value) => Expression::Bool(value),
+            Expression::Number(value) => Expression::Number(cow_into_owned(value)),
+            Expression::String(value) => Expression::String(value.into_owned()),
+            Expression::VarArg => Expression::VarArg,
+            Expression::Table(value) => Expression::Table(value.into_owned()),
+            Expression::FunctionCall(value) => Expression::FunctionCall(value.into_owned()),
+            Expression::Name(value) => Expression::Name(cow_into_owned(value)),
+            Expression::Var(value) => Expression::Var(Box::new(value.into_owned())),
+            Expression::ParenExpression(value) => Expression::ParenExpression(Box::new(value.into_owned())),
+            Expression::UnaryOp(value) => Expression::UnaryOp(value.into_owned()),
+            Expression::BinaryOp(value) => Expression::BinaryOp(value.into_owned()),
+        }
+    }
+}
+
+impl<'a> TableKey<'a> {
+    pub fn into_owned(self) -> TableKey<'static> {
+        match self {
+            TableKey::Expression(value) => TableKey::Expression(value.into_owned()),
+            TableKey::Name(value) => TableKey::Name(cow_into_owned(value)),
+        }
+    }
+}
+
+impl<'a> TableLiteral<'a> {
+    pub fn into_owned(self) -> TableLiteral<'static> {
+        TableLiteral {
+            items: self.items
+                .into_iter()
+                .map(|(key, value)| (key.map(TableKey::into_owned), value.into_owned()))
+                .collect(),
+        }
+    }
+}
+
+impl<'a> Statement<'a> {
+    pub fn into_owned(self) -> Statement<'static> {
+        match self {
+            Statement::Assignment(value) => Statement::Assignment(value.into_owned()),
+            Statement::LocalAssignment(value) => Statement::LocalAssignment(value.into_owned()),
+            Statement::FunctionCall(value) => Statement::FunctionCall(value.into_owned()),
+            Statement::NumericFor(value) => Statement::NumericFor(value.into_owned()),
+            Statement::GenericFor(value) => Statement::GenericFor(value.into_owned()),
+            Statement::IfStatement(value) => Statement::IfStatement(value.into_owned()),
+            Statement::WhileLoop(value) => Statement::WhileLoop(value.into_owned()),
+            Statement::RepeatLoop(value) => Statement::RepeatLoop(value.into_owned()),
+            Statement::FunctionDeclaration(value) => Statement::FunctionDeclaration(value.into_owned()),
+            Statement::Do(value) => Statement::Do(value.into_owned()),
+            Statement::Return(value) => Statement::Return(value.into_owned()),
+            Statement::Break => Statement::Break,
+        }
+    }
+}
+
+impl<'a> Chunk<'a> {
+    /// Converts this chunk into one that owns all of its data, detaching it
+    /// from the source it was parsed from.
+    pub fn into_owned(self) -> Chunk<'static> {
+        Chunk {
+            statements: self.statements.into_iter().map(Statement::into_owned).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod into_owned_tests {
+    use super::*;
+
+    use std::thread;
+
+    use dump::dump_chunk;
+    use parser::parse_from_tokens;
+    use tokenizer::{tokenize, Token};
+
+    #[test]
+    fn outlives_source() {
+        let (tokens, chunk, expected): (Vec<Token<'static>>, Chunk<'static>, String) = {
+            let source = String::from("local a, b = 1 + -2, {x = 'y', [3] = (f(a))}\n\
+                function M.m:go(p) while p do local q = p .. \"!\" end end\n\
+                for i = 1, 10 do if i then g() elseif b then else end end\n\
+                for k, v in pairs(a) do repeat h(k) until v end\n\
+                do local c = a end\n\
+                local function l() while l do break end return l, 'x' end\n\
+                return a");
+
+            let tokens = tokenize(&source).unwrap();
+            let chunk = parse_from_tokens(&tokens).unwrap().into_owned();
+            let expected = dump_chunk(&chunk);
+
+            (tokens.into_iter().map(Token::into_owned).collect(), chunk, expected)
+        };
+
+        // The source is gone, and the results can move to another thread.
+        let (tokens, chunk) = thread::spawn(move || (tokens, chunk)).join().unwrap();
+
+        assert_eq!(dump_chunk(&chunk), expected);
+        assert_eq!(parse_from_tokens(&tokens).unwrap(), chunk);
+    }
+}
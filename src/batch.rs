@@ -0,0 +1,210 @@
+//! Tokenizing and parsing many in-memory sources across several threads.
+//!
+//! Results come back in input order, and a panic while parsing one source is
+//! recorded as that source's error instead of taking down the whole batch.
+
+use std::any::Any;
+use std::error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ast::Chunk;
+use parser::parse_from_tokens;
+use tokenizer::{tokenize, TokenizeError};
+
+/// Controls how [parse_many] spreads work across threads.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// How many threads to parse on. Defaults to the available parallelism.
+    pub threads: Option<usize>,
+}
+
+/// The reason a single source in a batch failed to parse.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceError {
+    /// The source couldn't be tokenized.
+    Tokenize(TokenizeError),
+
+    /// The source was tokenized, but couldn't be parsed.
+    Parse(String),
+
+    /// The parser panicked. Holds the panic message, if it was a string.
+    Panic(String),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::Tokenize(err) => write!(f, "could not tokenize source: {}", err),
+            SourceError::Parse(message) => write!(f, "could not parse source: {}", message),
+            SourceError::Panic(message) => write!(f, "the parser panicked: {}", message),
+        }
+    }
+}
+
+impl error::Error for SourceError {}
+
+/// The outcome of parsing one source in a batch.
+#[derive(Debug)]
+pub struct SourceResult<N> {
+    /// The name the source was passed in with.
+    pub name: N,
+
+    pub result: Result<Chunk<'static>, SourceError>,
+
+    /// Time spent tokenizing and parsing this source.
+    pub elapsed: Duration,
+}
+
+/// Tokenizes and parses a single source into an owned AST.
+///
+/// This is what [parse_many] does for each source, minus the panic handling.
+pub fn parse_source(source: &str) -> Result<Chunk<'static>, SourceError> {
+    let tokens = tokenize(source).map_err(SourceError::Tokenize)?;
+    let chunk = parse_from_tokens(&tokens).map_err(SourceError::Parse)?;
+
+    Ok(chunk.into_owned())
+}
+
+/// Parses every source on a pool of threads, returning one result per source
+/// in the order they were given.
+pub fn parse_many<N, I>(sources: I, options: &BatchOptions) -> Vec<SourceResult<N>>
+where
+    N: Send,
+    I: IntoIterator<Item = (N, String)>,
+{
+    let sources: Vec<(N, String)> = sources.into_iter().collect();
+
+    run_batch(sources, thread_count(options), |source| parse_source(&source))
+        .into_iter()
+        .map(|(name, result, elapsed)| SourceResult {
+            name,
+            result: result.unwrap_or_else(|payload| Err(SourceError::Panic(panic_message(payload)))),
+            elapsed,
+        })
+        .collect()
+}
+
+pub(crate) fn thread_count(options: &BatchOptions) -> usize {
+    options.threads
+        .unwrap_or_else(|| thread::available_parallelism().map(|count| count.get()).unwrap_or(1))
+        .max(1)
+}
+
+/// Runs `parse` over every item, with each thread pulling the next unclaimed
+/// item until none are left. A panic while parsing an item is caught and
+/// returned as that item's result.
+pub(crate) fn run_batch<N, T, R, F>(items: Vec<(N, T)>, threads: usize, parse: F) -> Vec<(N, thread::Result<R>, Duration)>
+where
+    N: Send,
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let count = items.len();
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let finished = Mutex::new(Vec::with_capacity(count));
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(count) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+
+                let (index, (name, item)) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                let start = Instant::now();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| parse(item)));
+                let elapsed = start.elapsed();
+
+                finished.lock().unwrap().push((index, name, result, elapsed));
+            });
+        }
+    });
+
+    let mut finished = finished.into_inner().unwrap();
+    finished.sort_by_key(|&(index, ..)| index);
+
+    finished
+        .into_iter()
+        .map(|(_, name, result, elapsed)| (name, result, elapsed))
+        .collect()
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generated_sources() -> Vec<(usize, String)> {
+        (0..48)
+            .map(|index| {
+                let source = match index % 4 {
+                    0 => format!("local a{} = {}\nprint(a{})", index, index, index),
+                    1 => format!("for i = 1, {} do\n\tf(i, {{x = i}})\nend", index),
+                    2 => format!("local function f{}(a, b)\n\tprint(a, b)\nend", index),
+                    _ => format!("local x = \"unclosed {}", index),
+                };
+
+                (index, source)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_sequential_parsing() {
+        let sources = generated_sources();
+        let options = BatchOptions {
+            threads: Some(4),
+        };
+
+        let results = parse_many(sources.clone(), &options);
+        assert_eq!(results.len(), sources.len());
+
+        for (result, (name, source)) in results.iter().zip(&sources) {
+            assert_eq!(result.name, *name);
+            assert_eq!(format!("{:?}", result.result), format!("{:?}", parse_source(source)));
+        }
+
+        assert!(results.iter().any(|result| result.result.is_ok()));
+        assert!(results.iter().any(|result| result.result.is_err()));
+    }
+
+    #[test]
+    fn panics_are_isolated() {
+        let sources = vec![(0, "ok".to_string()), (1, "boom".to_string()), (2, "ok".to_string())];
+
+        let results = run_batch(sources, 2, |source| {
+            if source == "boom" {
+                panic!("boom");
+            }
+
+            source.len()
+        });
+
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|(name, result, _)| (name, result.map_err(panic_message)))
+            .collect();
+
+        assert_eq!(results, vec![
+            (0, Ok(2)),
+            (1, Err("boom".to_string())),
+            (2, Ok(2)),
+        ]);
+    }
+}
@@ -0,0 +1,372 @@
+//! An opt-in cache for parse results, keyed by a hash of the source text and
+//! the options it was tokenized with.
+//!
+//! Invalidation is purely content based: the same source and options always
+//! produce the same AST, so there is no staleness to track. Each entry keeps the source it
+//! was parsed from, so two sources whose hashes collide never share a result.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use ast::Chunk;
+use parser::parse_from_tokens;
+use tokenizer::{tokenize_with_options, TokenizeError, TokenizerOptions};
+
+/// The key that cached chunks are stored under. It's the same for a given
+/// source and options on every platform, so it can be written to disk.
+pub type CacheKey = u64;
+
+/// Storage for cached chunks.
+///
+/// Implement this to keep parse results somewhere other than memory. Keys can
+/// collide, so a backend has to keep the source next to each chunk and only
+/// return the chunk for an exact match.
+pub trait CacheBackend {
+    /// Returns the chunk stored under `key`, if there is one and it was parsed
+    /// from `source`.
+    fn get(&mut self, key: CacheKey, source: &str) -> Option<Arc<Chunk<'static>>>;
+
+    /// Stores a chunk parsed from `source` under `key`, possibly evicting
+    /// other entries.
+    fn insert(&mut self, key: CacheKey, source: &str, chunk: Arc<Chunk<'static>>);
+}
+
+#[derive(Debug)]
+struct MemoryEntry {
+    source: Box<str>,
+    chunk: Arc<Chunk<'static>>,
+    generation: u64,
+}
+
+/// An in-memory backend that holds at most `capacity` chunks, evicting the
+/// least recently used chunk when full.
+#[derive(Debug)]
+pub struct MemoryBackend {
+    capacity: usize,
+    entries: HashMap<CacheKey, MemoryEntry>,
+
+    /// Every entry by the generation it was last used in, oldest first.
+    recency: BTreeMap<u64, CacheKey>,
+    next_generation: u64,
+}
+
+impl MemoryBackend {
+    pub fn new(capacity: usize) -> MemoryBackend {
+        MemoryBackend {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_generation: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn take_generation(&mut self) -> u64 {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        generation
+    }
+}
+
+impl CacheBackend for MemoryBackend {
+    fn get(&mut self, key: CacheKey, source: &str) -> Option<Arc<Chunk<'static>>> {
+        let generation = self.take_generation();
+        let entry = self.entries.get_mut(&key).filter(|entry| &*entry.source == source)?;
+
+        self.recency.remove(&entry.generation);
+        self.recency.insert(generation, key);
+        entry.generation = generation;
+
+        Some(Arc::clone(&entry.chunk))
+    }
+
+    fn insert(&mut self, key: CacheKey, source: &str, chunk: Arc<Chunk<'static>>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        match self.entries.get(&key) {
+            Some(existing) => {
+                self.recency.remove(&existing.generation);
+            },
+            None if self.entries.len() >= self.capacity => {
+                let oldest = self.recency.keys().next().cloned();
+
+                if let Some(key) = oldest.and_then(|generation| self.recency.remove(&generation)) {
+                    self.entries.remove(&key);
+                }
+            },
+            None => {},
+        }
+
+        let generation = self.take_generation();
+        self.recency.insert(generation, key);
+        self.entries.insert(key, MemoryEntry {
+            source: source.into(),
+            chunk,
+            generation,
+        });
+    }
+}
+
+/// The reason a source couldn't be parsed through a [ParseCache].
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheError {
+    Tokenize(TokenizeError),
+    Parse(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Tokenize(err) => write!(f, "could not tokenize source: {}", err),
+            CacheError::Parse(message) => write!(f, "could not parse source: {}", message),
+        }
+    }
+}
+
+impl error::Error for CacheError {}
+
+/// Counters describing how effective a cache has been.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of calls answered from the cache.
+    pub hits: usize,
+
+    /// The number of calls that had to tokenize and parse the source.
+    pub misses: usize,
+}
+
+/// Memoizes parse results by the content of the source.
+///
+/// Failed parses are never cached.
+pub struct ParseCache<B = MemoryBackend> {
+    backend: B,
+    stats: CacheStats,
+}
+
+impl ParseCache<MemoryBackend> {
+    /// Creates a cache that keeps up to `capacity` chunks in memory.
+    pub fn new(capacity: usize) -> ParseCache<MemoryBackend> {
+        ParseCache::with_backend(MemoryBackend::new(capacity))
+    }
+}
+
+impl<B: CacheBackend> ParseCache<B> {
+    pub fn with_backend(backend: B) -> ParseCache<B> {
+        ParseCache {
+            backend,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Parses the given source, reusing a previous result for identical
+    /// source text if the backend still holds one.
+    ///
+    /// Results are only reused for the same `options`, since they decide what
+    /// the source means, like whether `goto` is a keyword.
+    pub fn parse(&mut self, source: &str, options: &TokenizerOptions) -> Result<Arc<Chunk<'static>>, CacheError> {
+        let key = cache_key(source, options);
+
+        if let Some(chunk) = self.backend.get(key, source) {
+            self.stats.hits += 1;
+            return Ok(chunk);
+        }
+
+        self.stats.misses += 1;
+
+        let tokens = tokenize_with_options(source, options).map_err(CacheError::Tokenize)?;
+        let chunk = parse_from_tokens(&tokens).map_err(CacheError::Parse)?;
+        let chunk = Arc::new(chunk.into_owned());
+
+        self.backend.insert(key, source, Arc::clone(&chunk));
+
+        Ok(chunk)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+}
+
+/// Computes the key that the given source and options are cached under, with
+/// 64-bit FNV-1a. Unlike the standard library's hashers, its output never
+/// changes.
+pub fn cache_key(source: &str, options: &TokenizerOptions) -> CacheKey {
+    let mut hasher = KeyHasher(0xcbf2_9ce4_8422_2325);
+    hasher.write(source.as_bytes());
+    options.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// FNV-1a as a [Hasher], so that options can be hashed with their derived
+/// [Hash]. Integers are written little-endian and sizes as 64 bits, so keys
+/// don't depend on the platform.
+struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_isize(&mut self, value: isize) {
+        self.write_u64(value as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use version::LuaVersion;
+
+    #[test]
+    fn hit_avoids_reparse() {
+        let mut cache = ParseCache::new(4);
+        let options = TokenizerOptions::default();
+
+        let first = cache.parse("local x = 5", &options).unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 1 });
+
+        let second = cache.parse("local x = 5", &options).unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn different_source_misses() {
+        let mut cache = ParseCache::new(4);
+        let options = TokenizerOptions::default();
+
+        cache.parse("local x = 5", &options).unwrap();
+        cache.parse("local x = 6", &options).unwrap();
+
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 2 });
+    }
+
+    #[test]
+    fn errors_are_not_cached() {
+        let mut cache = ParseCache::new(4);
+        let options = TokenizerOptions::default();
+
+        assert!(cache.parse("local = 5", &options).is_err());
+        assert!(cache.parse("local = 5", &options).is_err());
+
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 2 });
+        assert!(cache.backend().is_empty());
+    }
+
+    #[test]
+    fn differing_options_miss() {
+        let mut cache = ParseCache::new(4);
+        let options = TokenizerOptions::default();
+        let lua51 = TokenizerOptions {
+            version: Some(LuaVersion::Lua51),
+            ..TokenizerOptions::default()
+        };
+
+        let first = cache.parse("local x = 5", &options).unwrap();
+        let second = cache.parse("local x = 5", &lua51).unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 2 });
+        assert!(!Arc::ptr_eq(&first, &second));
+
+        cache.parse("local x = 5", &lua51).unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+
+        // The options can decide whether a source parses at all.
+        let lua52 = TokenizerOptions {
+            version: Some(LuaVersion::Lua52),
+            ..TokenizerOptions::default()
+        };
+
+        assert!(cache.parse("local goto = 1", &options).is_ok());
+        assert!(cache.parse("local goto = 1", &lua52).is_err());
+    }
+
+    #[test]
+    fn keys_are_stable() {
+        let options = TokenizerOptions::default();
+
+        assert_eq!(cache_key("a", &options), 0xb365_c124_dc61_ebd7);
+        assert_eq!(cache_key("local x = 5", &options), cache_key("local x = 5", &options));
+        assert_ne!(cache_key("local x = 5", &options), cache_key("local x = 6", &options));
+        assert_ne!(cache_key("", &options), cache_key("", &TokenizerOptions {
+            unicode_identifiers: true,
+            ..TokenizerOptions::default()
+        }));
+    }
+
+    #[test]
+    fn colliding_keys_are_not_shared() {
+        let mut backend = MemoryBackend::new(4);
+        let chunk = Arc::new(Chunk {
+            statements: Vec::new(),
+        });
+
+        backend.insert(1, "local a", Arc::clone(&chunk));
+
+        assert!(backend.get(1, "local b").is_none());
+        assert!(Arc::ptr_eq(&backend.get(1, "local a").unwrap(), &chunk));
+
+        // A different source under the same key replaces the entry.
+        backend.insert(1, "local b", Arc::clone(&chunk));
+        assert!(backend.get(1, "local a").is_none());
+        assert_eq!(backend.len(), 1);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ParseCache::new(2);
+        let options = TokenizerOptions::default();
+
+        cache.parse("local a", &options).unwrap();
+        cache.parse("local b", &options).unwrap();
+
+        // Touch 'a' so that 'b' becomes the oldest entry.
+        cache.parse("local a", &options).unwrap();
+        cache.parse("local c", &options).unwrap();
+
+        assert_eq!(cache.backend().len(), 2);
+
+        cache.parse("local a", &options).unwrap();
+        cache.parse("local b", &options).unwrap();
+
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 4 });
+    }
+}
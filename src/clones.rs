@@ -0,0 +1,350 @@
+//! Detection of duplicated code within and across chunks.
+//!
+//! Clones are runs of consecutive statements in a block, which covers single
+//! statements, whole function bodies, and parts of them. Statements are
+//! compared by their tokens, so formatting and comments never matter.
+//! Optionally, identifiers can be compared by the order they first appear in
+//! instead of by name, which finds code that was copied and then had its
+//! variables renamed.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use analysis::{collect, Span, SpannedStatement};
+use tokenizer::{StringLiteral, Token, TokenKind};
+use visit::visit_statement;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloneOptions {
+    /// The smallest number of AST nodes, counting statements and expressions,
+    /// that a piece of code needs to have to be reported as a clone.
+    pub min_nodes: usize,
+
+    /// Whether code that only differs in the names of its identifiers should
+    /// count as a clone, as long as the names are used consistently.
+    pub ignore_names: bool,
+}
+
+impl Default for CloneOptions {
+    fn default() -> CloneOptions {
+        CloneOptions {
+            min_nodes: 10,
+            ignore_names: false,
+        }
+    }
+}
+
+/// One copy of a duplicated piece of code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CloneInstance {
+    /// The index of the file that the copy is in, in the order the files were
+    /// given to [find_clones].
+    pub file: usize,
+
+    pub span: Span,
+
+    /// The number of consecutive statements that make up the copy.
+    pub statements: usize,
+}
+
+/// A set of pieces of code that are all duplicates of each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloneGroup {
+    /// The number of AST nodes in each copy.
+    pub nodes: usize,
+
+    /// Every copy, ordered by file and then by position.
+    pub instances: Vec<CloneInstance>,
+}
+
+/// Finds duplicated code across the given files, each given as its tokens.
+///
+/// Only the longest duplicated runs of statements are reported: if a run is a
+/// clone, the smaller runs inside of every one of its copies aren't reported
+/// separately.
+pub fn find_clones(files: &[&[Token]], options: &CloneOptions) -> Result<Vec<CloneGroup>, String> {
+    let mut collected = Vec::with_capacity(files.len());
+
+    for tokens in files {
+        collected.push(collect(tokens)?.statements);
+    }
+
+    // Every block in every file, as a list of indices into `collected`.
+    let mut blocks: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+
+    for (file, statements) in collected.iter().enumerate() {
+        for (index, statement) in statements.iter().enumerate() {
+            blocks.entry((file, statement.block)).or_default().push(index);
+        }
+    }
+
+    let blocks: Vec<(usize, Vec<&SpannedStatement>)> = blocks
+        .into_iter()
+        .map(|((file, _), indices)| (file, indices.into_iter().map(|index| &collected[file][index]).collect()))
+        .collect();
+
+    let fingerprints: Vec<Vec<String>> = blocks
+        .iter()
+        .map(|(file, statements)| {
+            statements
+                .iter()
+                .map(|statement| fingerprint(&files[*file][statement.tokens.clone()], options.ignore_names))
+                .collect()
+        })
+        .collect();
+
+    let mut occurrences: HashMap<&str, Vec<(usize, usize)>> = HashMap::new();
+
+    for (block, block_fingerprints) in fingerprints.iter().enumerate() {
+        for (position, print) in block_fingerprints.iter().enumerate() {
+            occurrences.entry(print.as_str()).or_default().push((block, position));
+        }
+    }
+
+    // Runs of statements keyed by the fingerprint of the whole run.
+    let mut runs: HashMap<String, (usize, BTreeSet<CloneInstance>)> = HashMap::new();
+
+    for places in occurrences.values() {
+        for (i, &a) in places.iter().enumerate() {
+            for &b in &places[i + 1..] {
+                let length = matching_length(&fingerprints, a, b);
+
+                // Runs that could be extended backwards are found from an
+                // earlier starting statement instead.
+                if length == 0 || extends_backwards(&fingerprints, a, b) {
+                    continue;
+                }
+
+                for &(block, position) in &[a, b] {
+                    let (file, statements) = &blocks[block];
+                    let run = &statements[position..position + length];
+                    let first = run[0];
+                    let last = run[length - 1];
+
+                    let key = fingerprint(&files[*file][first.tokens.start..last.tokens.end], options.ignore_names);
+                    let nodes = run.iter().map(|statement| count_nodes(statement)).sum();
+
+                    let entry = runs.entry(key).or_insert_with(|| (nodes, BTreeSet::new()));
+                    entry.1.insert(CloneInstance {
+                        file: *file,
+                        span: Span {
+                            start: first.span.start,
+                            end: last.span.end,
+                        },
+                        statements: length,
+                    });
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<CloneGroup> = runs
+        .into_iter()
+        .filter(|(_, (nodes, instances))| *nodes >= options.min_nodes && instances.len() > 1)
+        .map(|(_, (nodes, instances))| CloneGroup {
+            nodes,
+            instances: instances.into_iter().collect(),
+        })
+        .collect();
+
+    let contained = |inner: &CloneInstance, outer: &CloneInstance| {
+        inner != outer
+            && inner.file == outer.file
+            && inner.span.start >= outer.span.start
+            && inner.span.end <= outer.span.end
+    };
+
+    let subsumed: Vec<bool> = groups
+        .iter()
+        .map(|group| {
+            groups.iter().any(|other| {
+                group.instances.iter().all(|inner| {
+                    other.instances.iter().any(|outer| contained(inner, outer))
+                })
+            })
+        })
+        .collect();
+
+    let mut index = 0;
+    groups.retain(|_| {
+        index += 1;
+        !subsumed[index - 1]
+    });
+
+    groups.sort_by(|a, b| a.instances.cmp(&b.instances));
+
+    Ok(groups)
+}
+
+/// Returns how many statements match starting from `a` and `b`, stopping
+/// before the runs would overlap.
+fn matching_length(fingerprints: &[Vec<String>], a: (usize, usize), b: (usize, usize)) -> usize {
+    let (a_block, a_position) = a;
+    let (b_block, b_position) = b;
+
+    let mut length = 0;
+
+    loop {
+        let a_next = a_position + length;
+        let b_next = b_position + length;
+
+        if a_block == b_block && a_next >= b_position {
+            break;
+        }
+
+        match (fingerprints[a_block].get(a_next), fingerprints[b_block].get(b_next)) {
+            (Some(a), Some(b)) if a == b => length += 1,
+            _ => break,
+        }
+    }
+
+    length
+}
+
+fn extends_backwards(fingerprints: &[Vec<String>], a: (usize, usize), b: (usize, usize)) -> bool {
+    let (a_block, a_position) = a;
+    let (b_block, b_position) = b;
+
+    a_position > 0
+        && b_position > 0
+        && fingerprints[a_block][a_position - 1] == fingerprints[b_block][b_position - 1]
+}
+
+fn count_nodes(statement: &SpannedStatement) -> usize {
+    let mut nodes = 0;
+    visit_statement(&statement.statement, &mut |_| nodes += 1);
+    nodes
+}
+
+/// Builds a string that's equal for two token sequences exactly when they're
+/// clones of each other.
+fn fingerprint(tokens: &[Token], ignore_names: bool) -> String {
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    let mut result = String::new();
+
+    for token in tokens {
+        match &token.kind {
+            TokenKind::Identifier(name) if ignore_names => {
+                let next = names.len();
+                let index = *names.entry(name).or_insert(next);
+                result.push_str(&format!("${}", index));
+            },
+            // Quote style is formatting, so quoted strings only compare by
+            // their contents.
+            TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content })
+            | TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content }) => {
+                result.push_str(&format!("String({:?})", raw_content));
+            },
+            kind => result.push_str(&format!("{:?}", kind)),
+        }
+
+        result.push('\n');
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokenizer::tokenize;
+
+    fn clones_in(sources: &[&str], options: &CloneOptions) -> Vec<Vec<(usize, String)>> {
+        let tokens: Vec<_> = sources.iter().map(|source| tokenize(source).unwrap()).collect();
+        let files: Vec<&[Token]> = tokens.iter().map(|tokens| tokens.as_slice()).collect();
+
+        find_clones(&files, options)
+            .unwrap()
+            .into_iter()
+            .map(|group| {
+                group.instances
+                    .iter()
+                    .map(|instance| (instance.file, sources[instance.file][instance.span.start..instance.span.end].to_string()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    const PLANTED: &str = "function a(x)
+	local total = x + 1
+	print(total * 2, total)
+	print(\"done\")
+end
+
+function b(y)
+	local total = y + 1
+	-- This copy is formatted differently.
+	print(total*2,
+		total)
+	print('done')
+end
+
+function c(z)
+	local sum = z + 1
+	print(sum * 2, sum)
+	print(\"done\")
+end
+";
+
+    #[test]
+    fn exact_clones_ignore_formatting() {
+        let options = CloneOptions {
+            min_nodes: 5,
+            ignore_names: false,
+        };
+
+        let groups = clones_in(&[PLANTED], &options);
+        assert_eq!(groups.len(), 1);
+
+        let copies: Vec<_> = groups[0].iter().map(|(_, source)| source.as_str()).collect();
+        assert_eq!(copies, vec![
+            "print(total * 2, total)\n\tprint(\"done\")",
+            "print(total*2,\n\t\ttotal)\n\tprint('done')",
+        ]);
+    }
+
+    #[test]
+    fn renamed_clones() {
+        let options = CloneOptions {
+            min_nodes: 5,
+            ignore_names: true,
+        };
+
+        let groups = clones_in(&[PLANTED], &options);
+        assert_eq!(groups.len(), 1);
+
+        let copies: Vec<_> = groups[0].iter().map(|(_, source)| source.lines().next().unwrap()).collect();
+        assert_eq!(copies, vec!["function a(x)", "function b(y)", "function c(z)"]);
+    }
+
+    #[test]
+    fn inconsistent_renaming_is_not_a_clone() {
+        let options = CloneOptions {
+            min_nodes: 1,
+            ignore_names: true,
+        };
+
+        let groups = clones_in(&["local a = 1\nprint(a, a)\nlocal b = 1\nprint(b, c)"], &options);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].iter().map(|(_, source)| source.as_str()).collect::<Vec<_>>(), vec!["local a = 1", "local b = 1"]);
+    }
+
+    #[test]
+    fn clones_across_files() {
+        let first = "local t = {1, 2, 3}\nprint(t, #t)\n";
+        let second = "print(1)\nlocal t = {1, 2, 3}\nprint(t, #t)\n";
+
+        let options = CloneOptions {
+            min_nodes: 11,
+            ignore_names: false,
+        };
+
+        assert_eq!(clones_in(&[first, second], &options).len(), 0);
+
+        let groups = clones_in(&[first, second], &CloneOptions::default());
+        assert_eq!(groups, vec![vec![
+            (0, "local t = {1, 2, 3}\nprint(t, #t)".to_string()),
+            (1, "local t = {1, 2, 3}\nprint(t, #t)".to_string()),
+        ]]);
+    }
+}
@@ -0,0 +1,279 @@
+//! Golden tests over a directory of Lua files, comparing what mab makes of
+//! each file against an expectation checked in next to it.
+//!
+//! Each `name.lua` in the directory is paired with `name.ast.sexp` or
+//! `name.tokens.sexp`, depending on the [CorpusMode], holding the
+//! [compact dump](../dump/index.html) of the result. Files that fail to
+//! tokenize or parse are dumped as `(error "...")`, so expected failures can
+//! be part of a corpus too. Whitespace in expectations doesn't matter, so
+//! they can be reformatted by hand.
+//!
+//! Downstream crates can point [run_corpus] at their own Lua files to catch
+//! changes in how mab parses them:
+//!
+//! ```no_run
+//! use mab::corpus::{run_corpus, CorpusMode};
+//!
+//! run_corpus("tests/lua", CorpusMode::Ast).unwrap().assert_passed();
+//! ```
+//!
+//! Running with the `MAB_BLESS` environment variable set writes the current
+//! results as the new expectations instead of failing.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use dump::{dump_chunk, dump_tokens};
+use parser::parse_from_tokens;
+use tokenizer::tokenize;
+
+/// The environment variable that turns on blessing in [run_corpus].
+pub const BLESS_VAR: &str = "MAB_BLESS";
+
+/// What to compare for each file in a corpus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CorpusMode {
+    /// The AST, from `name.ast.sexp`.
+    Ast,
+
+    /// The token stream, without whitespace or comments, from
+    /// `name.tokens.sexp`.
+    Tokens,
+}
+
+impl CorpusMode {
+    /// The extension of the expectation files for this mode.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CorpusMode::Ast => "ast.sexp",
+            CorpusMode::Tokens => "tokens.sexp",
+        }
+    }
+
+    /// The compact dump of `source` for this mode.
+    pub fn dump(&self, source: &str) -> String {
+        let tokens = match tokenize(source) {
+            Ok(tokens) => tokens,
+            Err(err) => return dump_error(&format!("could not tokenize: {}", err)),
+        };
+
+        match self {
+            CorpusMode::Tokens => dump_tokens(&tokens),
+            CorpusMode::Ast => match parse_from_tokens(&tokens) {
+                Ok(chunk) => dump_chunk(&chunk),
+                Err(message) => dump_error(&message),
+            },
+        }
+    }
+}
+
+fn dump_error(message: &str) -> String {
+    format!("(error {:?})", message)
+}
+
+/// Why a file in a corpus didn't match its expectation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorpusFailure {
+    /// There's no expectation for the file yet.
+    Missing {
+        path: PathBuf,
+        actual: String,
+    },
+
+    /// The expectation doesn't match.
+    Mismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for CorpusFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorpusFailure::Missing { path, actual } => {
+                writeln!(f, "{}: no expectation, set {} to create it. Got:", path.display(), BLESS_VAR)?;
+                write!(f, "{}", actual)
+            },
+            CorpusFailure::Mismatch { path, expected, actual } => {
+                writeln!(f, "{}: doesn't match its expectation (- expected, + actual):", path.display())?;
+                write!(f, "{}", diff_lines(expected, actual))
+            },
+        }
+    }
+}
+
+/// The results of [run_corpus].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorpusReport {
+    /// The Lua files that matched their expectations.
+    pub passed: Vec<PathBuf>,
+
+    /// The Lua files whose expectations were written or rewritten.
+    pub blessed: Vec<PathBuf>,
+
+    pub failures: Vec<CorpusFailure>,
+}
+
+impl CorpusReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Panics with every failure if there were any, for use in tests.
+    pub fn assert_passed(&self) {
+        if !self.is_success() {
+            panic!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} passed, {} blessed, {} failed", self.passed.len(), self.blessed.len(), self.failures.len())?;
+
+        for failure in &self.failures {
+            write!(f, "\n\n{}", failure)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks every `.lua` file directly inside `dir` against its expectation,
+/// blessing them if [BLESS_VAR] is set to anything but an empty string or
+/// `0`.
+///
+/// Only problems reading the directory or its files are errors; mismatches
+/// are reported in the [CorpusReport].
+pub fn run_corpus<P: AsRef<Path>>(dir: P, mode: CorpusMode) -> io::Result<CorpusReport> {
+    let bless = match env::var(BLESS_VAR) {
+        Ok(value) => !value.is_empty() && value != "0",
+        Err(_) => false,
+    };
+
+    run_corpus_with(dir, mode, bless)
+}
+
+/// Like [run_corpus], but decides whether to bless from `bless` instead of
+/// the environment.
+pub fn run_corpus_with<P: AsRef<Path>>(dir: P, mode: CorpusMode, bless: bool) -> io::Result<CorpusReport> {
+    let mut sources = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_file() && path.extension() == Some("lua".as_ref()) {
+            sources.push(path);
+        }
+    }
+
+    sources.sort();
+
+    let mut report = CorpusReport::default();
+
+    for path in sources {
+        let actual = mode.dump(&fs::read_to_string(&path)?);
+        let expectation_path = path.with_extension(mode.extension());
+
+        let expected = match fs::read_to_string(&expectation_path) {
+            Ok(expected) => Some(expected),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        match expected {
+            Some(ref expected) if normalize(expected) == normalize(&actual) => report.passed.push(path),
+            _ if bless => {
+                fs::write(&expectation_path, format!("{}\n", actual))?;
+                report.blessed.push(path);
+            },
+            Some(expected) => report.failures.push(CorpusFailure::Mismatch {
+                path,
+                expected,
+                actual,
+            }),
+            None => report.failures.push(CorpusFailure::Missing {
+                path,
+                actual,
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Collapses runs of whitespace into single spaces and drops whitespace just
+/// inside parentheses, so only the structure of a dump is compared.
+fn normalize(dump: &str) -> String {
+    dump.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("( ", "(")
+        .replace(" )", ")")
+}
+
+/// A line-by-line diff, with unchanged lines prefixed by two spaces.
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let a: Vec<&str> = expected.trim_end().lines().collect();
+    let b: Vec<&str> = actual.trim_end().lines().collect();
+
+    // lengths[i][j] is the length of the longest common subsequence of
+    // a[i..] and b[j..].
+    let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut output = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            output.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            output.push(format!("- {}", a[i]));
+            i += 1;
+        } else {
+            output.push(format!("+ {}", b[j]));
+            j += 1;
+        }
+    }
+
+    output.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_is_ignored() {
+        assert_eq!(normalize("(chunk\n  (call f\tx)\n)\n"), normalize("(chunk (call f x))"));
+        assert_ne!(normalize("(call f x)"), normalize("(call fx)"));
+    }
+
+    #[test]
+    fn diffs() {
+        let diff = diff_lines("(chunk\n  (call f)\n  (call g))", "(chunk\n  (call f)\n  (call h))");
+        assert_eq!(diff.lines().collect::<Vec<_>>(), vec!["  (chunk", "    (call f)", "-   (call g))", "+   (call h))"]);
+    }
+
+    #[test]
+    fn errors_are_dumped() {
+        assert_eq!(CorpusMode::Ast.dump("local = 1"), "(error \"Expected end of file, but found 'local' at line 1, column 1\")");
+        assert!(CorpusMode::Tokens.dump("'open").starts_with("(error \"could not tokenize"));
+    }
+}
@@ -0,0 +1,501 @@
+//! A lossless concrete syntax tree, for tools like formatters that need every
+//! token and every piece of whitespace and comments.
+//!
+//! Tokens already carry the whitespace and comments before them, so the tree
+//! only has to group them: statements and the blocks inside them are nodes,
+//! and everything else is a token leaf. Expressions are kept as runs of
+//! tokens inside their statement for now.
+//!
+//! The parser builds the tree as it goes, in the same pass that checks the
+//! chunk, and the AST is lowered from the finished tree.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::Range;
+
+use ast::*;
+use parser::{parse_chunk, parse_expression_from_tokens, parse_expression_list_from_tokens, parse_statement_at};
+use parser_core::{ParseState, SyntaxSink};
+use tokenizer::{Symbol, Token, TokenKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NodeKind {
+    /// The whole chunk. Its children are statements, followed by the
+    /// end-of-file token if there's whitespace or comments after the last one.
+    Chunk,
+
+    /// The body of a loop, branch or function. Its children are statements.
+    Block,
+
+    Assignment,
+    LocalAssignment,
+    FunctionCall,
+    NumericFor,
+    GenericFor,
+    IfStatement,
+    WhileLoop,
+    RepeatLoop,
+    FunctionDeclaration,
+    Do,
+    Return,
+    Break,
+}
+
+impl NodeKind {
+    pub(crate) fn of_statement(statement: &Statement) -> NodeKind {
+        match statement {
+            Statement::Assignment(_) => NodeKind::Assignment,
+            Statement::LocalAssignment(_) => NodeKind::LocalAssignment,
+            Statement::FunctionCall(_) => NodeKind::FunctionCall,
+            Statement::NumericFor(_) => NodeKind::NumericFor,
+            Statement::GenericFor(_) => NodeKind::GenericFor,
+            Statement::IfStatement(_) => NodeKind::IfStatement,
+            Statement::WhileLoop(_) => NodeKind::WhileLoop,
+            Statement::RepeatLoop(_) => NodeKind::RepeatLoop,
+            Statement::FunctionDeclaration(_) => NodeKind::FunctionDeclaration,
+            Statement::Do(_) => NodeKind::Do,
+            Statement::Return(_) => NodeKind::Return,
+            Statement::Break => NodeKind::Break,
+        }
+    }
+}
+
+/// A child of a [Node].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Node(Node),
+
+    /// The index of a token in [SyntaxTree::tokens].
+    Token(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Element>,
+}
+
+impl Node {
+    /// The indices of every token under this node, in source order.
+    pub fn tokens(&self) -> Vec<usize> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens(&self, tokens: &mut Vec<usize>) {
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.collect_tokens(tokens),
+                Element::Token(index) => tokens.push(*index),
+            }
+        }
+    }
+
+    /// The statement nodes directly under this node.
+    pub fn statements(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Node(node) if node.kind != NodeKind::Block => Some(node),
+            _ => None,
+        })
+    }
+
+    /// The tokens directly under this node that come before each block, and
+    /// then the ones after the last block.
+    fn runs(&self) -> Vec<Range<usize>> {
+        let mut runs = Vec::new();
+        let mut run: Option<Range<usize>> = None;
+
+        for child in &self.children {
+            match child {
+                Element::Token(index) => {
+                    run = Some(match run {
+                        Some(run) => run.start..index + 1,
+                        None => *index..index + 1,
+                    });
+                },
+                Element::Node(_) => runs.push(run.take().unwrap_or(0..0)),
+            }
+        }
+
+        runs.push(run.unwrap_or(0..0));
+        runs
+    }
+
+    /// The blocks directly under this node, like the branches of an `if`.
+    pub fn blocks(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Node(node) if node.kind == NodeKind::Block => Some(node),
+            _ => None,
+        })
+    }
+}
+
+/// A concrete syntax tree over a token stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree<'a> {
+    tokens: &'a [Token<'a>],
+    root: Node,
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Builds the tree for a whole chunk.
+    pub fn parse(tokens: &'a [Token<'a>]) -> Result<SyntaxTree<'a>, String> {
+        let builder = RefCell::new(TreeBuilder::default());
+        let state = ParseState {
+            sink: Some(&builder),
+            ..ParseState::new(tokens)
+        };
+
+        parse_chunk(state)?;
+
+        let builder = builder.into_inner();
+        let mut root = builder.root.expect("The chunk's block was never finished");
+
+        root.kind = NodeKind::Chunk;
+
+        if builder.next < tokens.len() {
+            root.children.push(Element::Token(builder.next));
+        }
+
+        Ok(SyntaxTree {
+            tokens,
+            root,
+        })
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    pub fn tokens(&self) -> &'a [Token<'a>] {
+        self.tokens
+    }
+
+    /// The exact source text under `node`, including the whitespace and
+    /// comments before its first token.
+    pub fn text(&self, node: &Node) -> String {
+        node.tokens()
+            .into_iter()
+            .map(|index| self.tokens[index].to_source())
+            .collect()
+    }
+
+    /// The exact source text the tree was built from.
+    pub fn source(&self) -> String {
+        self.text(&self.root)
+    }
+
+    /// Converts the tree into the abstract syntax tree.
+    ///
+    /// Statements and blocks are taken from the tree. Only the runs of tokens
+    /// between them, like the condition of a loop, are parsed.
+    pub fn to_ast(&self) -> Result<Chunk<'a>, String> {
+        self.lower_block(&self.root)
+    }
+
+    fn lower_block(&self, node: &Node) -> Result<Chunk<'a>, String> {
+        let statements = node.statements()
+            .map(|statement| self.lower_statement(statement))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Chunk {
+            statements,
+        })
+    }
+
+    fn lower_statement(&self, node: &Node) -> Result<Statement<'a>, String> {
+        let malformed = || format!("Malformed {:?} node", node.kind);
+
+        let runs: Vec<&'a [Token<'a>]> = node.runs().into_iter().map(|run| &self.tokens[run]).collect();
+        let blocks: Vec<&Node> = node.blocks().collect();
+        let block = |index: usize| blocks.get(index).ok_or_else(malformed).and_then(|block| self.lower_block(block));
+        let run = |index: usize, skip_start: usize, skip_end: usize| {
+            runs.get(index)
+                .and_then(|run| run.get(skip_start..run.len().checked_sub(skip_end)?))
+                .ok_or_else(malformed)
+        };
+
+        let statement = match node.kind {
+            NodeKind::Assignment | NodeKind::LocalAssignment | NodeKind::FunctionCall | NodeKind::Return | NodeKind::Break => {
+                let tokens = run(0, 0, 0)?;
+
+                return match parse_statement_at(tokens, 0)? {
+                    Some((end, statement)) if end == tokens.len() => Ok(statement),
+                    _ => Err(malformed()),
+                };
+            },
+            NodeKind::Do => Statement::Do(block(0)?),
+            NodeKind::WhileLoop => Statement::WhileLoop(WhileLoop {
+                condition: parse_expression_from_tokens(run(0, 1, 1)?)?,
+                body: block(0)?,
+            }),
+            NodeKind::RepeatLoop => Statement::RepeatLoop(RepeatLoop {
+                condition: parse_expression_from_tokens(run(1, 1, 0)?)?,
+                body: block(0)?,
+            }),
+            NodeKind::IfStatement => {
+                let mut else_if_branches = Vec::new();
+                let mut else_branch = None;
+
+                for index in 1..blocks.len() {
+                    match run(index, 0, 0)?.first().map(|token| &token.kind) {
+                        Some(TokenKind::Symbol(Symbol::ElseIf)) => {
+                            else_if_branches.push((parse_expression_from_tokens(run(index, 1, 1)?)?, block(index)?));
+                        },
+                        Some(TokenKind::Symbol(Symbol::Else)) => else_branch = Some(block(index)?),
+                        _ => return Err(malformed()),
+                    }
+                }
+
+                Statement::IfStatement(IfStatement {
+                    condition: parse_expression_from_tokens(run(0, 1, 1)?)?,
+                    body: block(0)?,
+                    else_if_branches,
+                    else_branch,
+                })
+            },
+            NodeKind::NumericFor => {
+                // for Name = exp, exp [, exp] do
+                let header = run(0, 1, 1)?;
+                let var = header.first().and_then(Token::as_identifier).ok_or_else(malformed)?;
+                let mut values = parse_expression_list_from_tokens(header.get(2..).ok_or_else(malformed)?)?.into_iter();
+
+                Statement::NumericFor(NumericFor {
+                    var: Cow::from(var),
+                    start: values.next().ok_or_else(malformed)?,
+                    end: values.next().ok_or_else(malformed)?,
+                    step: values.next(),
+                    body: block(0)?,
+                })
+            },
+            NodeKind::GenericFor => {
+                // for namelist in explist do
+                let header = run(0, 1, 1)?;
+                let split = header.iter()
+                    .position(|token| token.kind == TokenKind::Symbol(Symbol::In))
+                    .ok_or_else(malformed)?;
+
+                Statement::GenericFor(GenericFor {
+                    vars: names(&header[..split]).ok_or_else(malformed)?,
+                    item_source: parse_expression_list_from_tokens(&header[split + 1..])?,
+                    body: block(0)?,
+                })
+            },
+            NodeKind::FunctionDeclaration => {
+                // [local] function funcname ( namelist )
+                let header = run(0, 0, 1)?;
+                let local = header.first().is_some_and(|token| token.kind == TokenKind::Symbol(Symbol::Local));
+                let header = header.get(if local { 2 } else { 1 }..).ok_or_else(malformed)?;
+                let paren = header.iter()
+                    .position(|token| token.kind == TokenKind::Symbol(Symbol::LeftParen))
+                    .ok_or_else(malformed)?;
+
+                Statement::FunctionDeclaration(FunctionDeclaration {
+                    local,
+                    name: function_name(&header[..paren]).ok_or_else(malformed)?,
+                    parameters: names(&header[paren + 1..]).ok_or_else(malformed)?,
+                    body: block(0)?,
+                })
+            },
+            NodeKind::Chunk | NodeKind::Block => return Err(malformed()),
+        };
+
+        Ok(statement)
+    }
+}
+
+/// Names separated by commas, like the variables of a generic for.
+fn names<'a>(tokens: &'a [Token<'a>]) -> Option<Vec<Cow<'a, str>>> {
+    tokens.iter()
+        .enumerate()
+        .map(|(index, token)| match token.as_identifier() {
+            Some(name) if index % 2 == 0 => Some(Some(Cow::from(name))),
+            None if index % 2 == 1 && token.kind == TokenKind::Symbol(Symbol::Comma) => Some(None),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|names| names.into_iter().flatten().collect())
+}
+
+/// A function name like `a.b:c`.
+fn function_name<'a>(tokens: &'a [Token<'a>]) -> Option<FunctionName<'a>> {
+    let (segments, method) = match tokens.iter().position(|token| token.kind == TokenKind::Symbol(Symbol::Colon)) {
+        Some(colon) if colon + 2 == tokens.len() => (&tokens[..colon], Some(Cow::from(tokens[colon + 1].as_identifier()?))),
+        Some(_) => return None,
+        None => (tokens, None),
+    };
+
+    let segments = segments.iter()
+        .enumerate()
+        .map(|(index, token)| match token.as_identifier() {
+            Some(name) if index % 2 == 0 => Some(Some(Cow::from(name))),
+            None if index % 2 == 1 && token.kind == TokenKind::Symbol(Symbol::Dot) => Some(None),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect();
+
+    Some(FunctionName {
+        segments,
+        method,
+    })
+}
+
+/// Builds the tree from what the parser tells it.
+#[derive(Default)]
+struct TreeBuilder {
+    /// The blocks and entered statements that haven't finished yet.
+    open: Vec<Node>,
+
+    /// The first token that isn't in the tree yet.
+    next: usize,
+
+    root: Option<Node>,
+}
+
+impl TreeBuilder {
+    /// Adds the tokens before `end` that aren't in the tree yet to the
+    /// innermost open node.
+    fn take_tokens(&mut self, end: usize) {
+        if let Some(node) = self.open.last_mut() {
+            node.children.extend((self.next..end).map(Element::Token));
+        }
+
+        self.next = self.next.max(end);
+    }
+
+    fn close(&mut self) {
+        let node = self.open.pop().expect("Nothing to close");
+
+        match self.open.last_mut() {
+            Some(parent) => parent.children.push(Element::Node(node)),
+            None => self.root = Some(node),
+        }
+    }
+}
+
+impl SyntaxSink for TreeBuilder {
+    fn start_block(&mut self, start: usize) {
+        self.take_tokens(start);
+        self.open.push(Node {
+            kind: NodeKind::Block,
+            children: Vec::new(),
+        });
+    }
+
+    fn finish_block(&mut self, end: usize) {
+        self.take_tokens(end);
+        self.close();
+    }
+
+    fn enter_statement(&mut self, kind: NodeKind, start: usize) {
+        self.take_tokens(start);
+        self.open.push(Node {
+            kind,
+            children: Vec::new(),
+        });
+    }
+
+    fn finish_statement(&mut self, kind: NodeKind, start: usize, end: usize) {
+        let entered = self.open.last().is_some_and(|node| node.kind != NodeKind::Block);
+
+        if !entered {
+            self.enter_statement(kind, start);
+        }
+
+        self.take_tokens(end);
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_from_tokens;
+    use tokenizer::tokenize;
+
+    const SOURCE: &str = "-- leading comment
+local a = 1 --[[ trailing ]]
+if a then
+	print(a)
+elseif b then
+else
+	while a do f() end
+end
+
+function t:m(x) repeat local y = x until y end
+-- the end
+";
+
+    fn outline(node: &Node, depth: usize, lines: &mut Vec<String>) {
+        lines.push(format!("{}{:?}", "  ".repeat(depth), node.kind));
+
+        for child in &node.children {
+            if let Element::Node(child) = child {
+                outline(child, depth + 1, lines);
+            }
+        }
+    }
+
+    #[test]
+    fn structure() {
+        let tokens = tokenize(SOURCE).unwrap();
+        let tree = SyntaxTree::parse(&tokens).unwrap();
+
+        let mut lines = Vec::new();
+        outline(tree.root(), 0, &mut lines);
+
+        assert_eq!(lines, vec![
+            "Chunk",
+            "  LocalAssignment",
+            "  IfStatement",
+            "    Block",
+            "      FunctionCall",
+            "    Block",
+            "    Block",
+            "      WhileLoop",
+            "        Block",
+            "          FunctionCall",
+            "  FunctionDeclaration",
+            "    Block",
+            "      RepeatLoop",
+            "        Block",
+            "          LocalAssignment",
+        ]);
+
+        let function = tree.root().statements().nth(2).unwrap();
+        assert_eq!(tree.text(function), "\n\nfunction t:m(x) repeat local y = x until y end");
+    }
+
+    #[test]
+    fn lossless() {
+        let tokens = tokenize(SOURCE).unwrap();
+        let tree = SyntaxTree::parse(&tokens).unwrap();
+
+        assert_eq!(tree.source(), SOURCE);
+        assert_eq!(tree.to_ast().unwrap(), parse_from_tokens(&tokens).unwrap());
+    }
+
+    #[test]
+    fn lowering() {
+        let source = "for i = 1, 10, 2 do t[i] = i end
+for k, v in pairs(t), nil do print(k, v) end
+local function a.b.c(x, y) return x, y end
+do break end";
+
+        let tokens = tokenize(source).unwrap();
+        let tree = SyntaxTree::parse(&tokens).unwrap();
+
+        assert_eq!(tree.to_ast().unwrap(), parse_from_tokens(&tokens).unwrap());
+    }
+
+    #[test]
+    fn invalid_source() {
+        let tokens = tokenize("while a do").unwrap();
+        assert!(SyntaxTree::parse(&tokens).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Token-level comparison of two versions of a source, for telling apart
+//! changes that only reformat code from changes that affect it.
+//!
+//! Whitespace and comments are part of each token's prefix, so they're never
+//! compared. Everything else, including the exact spelling of string
+//! literals, is.
+
+use analysis::Span;
+use tokenizer::{tokenize, Token, TokenKind, TokenizeError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Whether number literals are compared by the value they represent
+    /// instead of how they're written, so that `1.0` and `1.00` are equal.
+    pub numbers_by_value: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EditKind {
+    /// Tokens that are only in the second source.
+    Insert,
+
+    /// Tokens that are only in the first source.
+    Delete,
+
+    /// Tokens in the first source that were replaced by different tokens in
+    /// the second.
+    Replace,
+}
+
+/// A run of tokens that differs between two sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TokenEdit {
+    pub kind: EditKind,
+
+    /// The affected tokens in the first source. For an insertion, this is
+    /// empty and positioned where the new tokens would go.
+    pub a: Span,
+
+    /// The affected tokens in the second source. For a deletion, this is
+    /// empty and positioned where the removed tokens used to be.
+    pub b: Span,
+}
+
+/// Whether two sources only differ in whitespace and comments.
+pub fn tokens_semantic_eq(a: &str, b: &str, options: &DiffOptions) -> Result<bool, TokenizeError> {
+    Ok(token_diff(a, b, options)?.is_empty())
+}
+
+/// Lists the edits that turn the tokens of `a` into the tokens of `b`,
+/// ignoring whitespace and comments.
+pub fn token_diff(a: &str, b: &str, options: &DiffOptions) -> Result<Vec<TokenEdit>, TokenizeError> {
+    let a = tokenize(a)?;
+    let b = tokenize(b)?;
+
+    Ok(diff_tokens(&a, &b, options))
+}
+
+/// Like [token_diff], but for sources that are already tokenized.
+pub fn diff_tokens(a: &[Token], b: &[Token], options: &DiffOptions) -> Vec<TokenEdit> {
+    let a = significant(a);
+    let b = significant(b);
+
+    // lengths[i][j] is the length of the longest common subsequence of a[i..]
+    // and b[j..].
+    let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if same_token(&a[i].kind, &b[j].kind, options) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut changed_a, mut changed_b) = (0, 0);
+
+    loop {
+        let matched = i < a.len() && j < b.len() && same_token(&a[i].kind, &b[j].kind, options);
+
+        if matched || (i == a.len() && j == b.len()) {
+            if i > changed_a || j > changed_b {
+                let kind = match (i > changed_a, j > changed_b) {
+                    (true, true) => EditKind::Replace,
+                    (true, false) => EditKind::Delete,
+                    _ => EditKind::Insert,
+                };
+
+                edits.push(TokenEdit {
+                    kind,
+                    a: span_of(&a, changed_a, i),
+                    b: span_of(&b, changed_b, j),
+                });
+            }
+
+            if !matched {
+                break;
+            }
+
+            i += 1;
+            j += 1;
+            changed_a = i;
+            changed_b = j;
+        } else if j == b.len() || (i < a.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    edits
+}
+
+/// The tokens that take part in the comparison: everything but the end of
+/// the file, which only exists to hold trailing whitespace and comments.
+fn significant<'t, 'a>(tokens: &'t [Token<'a>]) -> Vec<&'t Token<'a>> {
+    tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::EndOfFile)
+        .collect()
+}
+
+/// The source covered by `tokens[start..end]`, or an empty span where that
+/// range would be if it's empty.
+fn span_of(tokens: &[&Token], start: usize, end: usize) -> Span {
+    if start < end {
+        Span {
+            start: tokens[start].start_position.bytes,
+            end: tokens[end - 1].end_position.bytes,
+        }
+    } else {
+        let at = match tokens.get(start) {
+            Some(token) => token.start_position.bytes,
+            None => tokens.last().map(|token| token.end_position.bytes).unwrap_or(0),
+        };
+
+        Span {
+            start: at,
+            end: at,
+        }
+    }
+}
+
+fn same_token(a: &TokenKind, b: &TokenKind, options: &DiffOptions) -> bool {
+    match (a, b) {
+        (TokenKind::NumberLiteral { text: a, .. }, TokenKind::NumberLiteral { text: b, .. }) if options.numbers_by_value => {
+            match (number_value(a), number_value(b)) {
+                (Some(a), Some(b)) => a == b,
+                _ => a == b,
+            }
+        },
+        _ => a == b,
+    }
+}
+
+fn number_value(literal: &str) -> Option<f64> {
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, literal),
+    };
+
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as f64,
+        None => digits.parse::<f64>().ok()?,
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "local a = 1.0\nwhile a do\n\tprint(a, \"hi\")\nend\n";
+
+    #[test]
+    fn reformatting_is_equal() {
+        let reformatted = "-- a comment\nlocal   a=1.0 while a do print( a,\"hi\" ) --[[ inline ]] end";
+        let options = DiffOptions::default();
+
+        assert!(tokens_semantic_eq(ORIGINAL, reformatted, &options).unwrap());
+        assert!(tokens_semantic_eq(ORIGINAL, ORIGINAL, &options).unwrap());
+        assert!(!tokens_semantic_eq(ORIGINAL, "local a = 1.0", &options).unwrap());
+    }
+
+    #[test]
+    fn single_replacement() {
+        let changed = "local a = 1.0\nwhile a do\n\tprint(a, \"bye\")\nend\n";
+        let edits = token_diff(ORIGINAL, changed, &DiffOptions::default()).unwrap();
+
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].kind, EditKind::Replace);
+        assert_eq!(&ORIGINAL[edits[0].a.start..edits[0].a.end], "\"hi\"");
+        assert_eq!(&changed[edits[0].b.start..edits[0].b.end], "\"bye\"");
+    }
+
+    #[test]
+    fn insertions_and_deletions() {
+        let changed = "local a = 1.0\nwhile a do\n\tprint(a, b, \"hi\")\nend\n";
+        let edits = token_diff(ORIGINAL, changed, &DiffOptions::default()).unwrap();
+
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].kind, EditKind::Insert);
+        assert_eq!(edits[0].a.start, edits[0].a.end);
+        assert_eq!(&ORIGINAL[edits[0].a.start..], "\"hi\")\nend\n");
+        assert_eq!(&changed[edits[0].b.start..edits[0].b.end], "b,");
+
+        let edits = token_diff(changed, ORIGINAL, &DiffOptions::default()).unwrap();
+
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].kind, EditKind::Delete);
+        assert_eq!(&changed[edits[0].a.start..edits[0].a.end], "b,");
+
+        let edits = token_diff("", "print(a)", &DiffOptions::default()).unwrap();
+        assert_eq!(edits, vec![TokenEdit {
+            kind: EditKind::Insert,
+            a: Span { start: 0, end: 0 },
+            b: Span { start: 0, end: 8 },
+        }]);
+    }
+
+    #[test]
+    fn numbers() {
+        let by_text = DiffOptions::default();
+        let by_value = DiffOptions {
+            numbers_by_value: true,
+        };
+
+        assert!(!tokens_semantic_eq("local a = 1.0", "local a = 1.00", &by_text).unwrap());
+        assert!(tokens_semantic_eq("local a = 1.0", "local a = 1.00", &by_value).unwrap());
+        assert!(tokens_semantic_eq("local a = 16", "local a = 0x10", &by_value).unwrap());
+        assert!(tokens_semantic_eq("local a = 100", "local a = 1e2", &by_value).unwrap());
+        assert!(!tokens_semantic_eq("local a = 1", "local a = 2", &by_value).unwrap());
+    }
+}
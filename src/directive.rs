@@ -0,0 +1,282 @@
+//! Machine-readable directives in comments, like Luau's `--!strict`,
+//! `--# pragmas`, and tool options like `-- luacheck: ignore 211`.
+//!
+//! Comments aren't part of the AST, so directives are found in the token
+//! stream and attributed to either the whole chunk or a single statement.
+
+use analysis::{collect, Span};
+use tokenizer::{Comment, Token, TokenPrefix, BYTE_ORDER_MARK};
+
+/// Controls which comments are treated as directives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectiveOptions {
+    /// Text that starts a directive. Prefixes made of punctuation, like `!`,
+    /// must come right after the `--`; prefixes that start with a letter or
+    /// digit, like `luacheck:`, can have spaces before them.
+    pub prefixes: Vec<String>,
+}
+
+impl Default for DirectiveOptions {
+    fn default() -> DirectiveOptions {
+        DirectiveOptions {
+            prefixes: vec!["!".to_string(), "#".to_string(), "luacheck:".to_string()],
+        }
+    }
+}
+
+/// A single directive comment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Directive {
+    /// The prefix from [DirectiveOptions::prefixes] that the comment matched.
+    pub prefix: String,
+
+    /// The first word after the prefix, like `strict` or `ignore`.
+    pub name: String,
+
+    /// The rest of the words, split on whitespace and commas.
+    pub arguments: Vec<String>,
+
+    /// The whole comment after the `--`, as written. Tools that don't know a
+    /// directive, or split its arguments differently, can use this instead.
+    pub text: String,
+
+    /// The source of the whole comment, including the `--`.
+    pub span: Span,
+
+    /// The line the comment starts on, starting at 1.
+    pub line: usize,
+}
+
+/// The directives attached to one statement.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatementDirectives {
+    /// The source of the statement the directives belong to.
+    pub span: Span,
+
+    /// The line the statement starts on, starting at 1.
+    pub line: usize,
+
+    pub directives: Vec<Directive>,
+}
+
+/// Every directive in a chunk, grouped by what it applies to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkDirectives {
+    /// Directives before the first statement, which apply to the whole chunk.
+    pub file: Vec<Directive>,
+
+    /// Statements that have directives attached to them, in source order.
+    pub statements: Vec<StatementDirectives>,
+}
+
+/// Finds every directive comment in a chunk.
+///
+/// A directive is attached to a statement when it trails the statement on
+/// the same line, or sits on its own line right before it. One that does
+/// neither, like a directive before the `end` of a block, is attached to the
+/// innermost statement that contains it. Directives before the first
+/// statement, and in a chunk with no statements, apply to the whole chunk.
+pub fn directives(tokens: &[Token], options: &DirectiveOptions) -> Result<ChunkDirectives, String> {
+    let mut statements = collect(tokens)?.statements;
+    statements.sort_by_key(|statement| statement.tokens.start);
+
+    let mut result = ChunkDirectives::default();
+    let mut attached: Vec<Vec<Directive>> = vec![Vec::new(); statements.len()];
+
+    for index in 0..tokens.len() {
+        for directive in prefix_directives(tokens, index, options) {
+            let previous_line = index
+                .checked_sub(1)
+                .map(|previous| tokens[previous].end_position.line);
+
+            let trailing = statements
+                .iter()
+                .position(|statement| statement.tokens.end == index)
+                .filter(|_| previous_line == Some(directive.line));
+
+            let owner = trailing
+                .or_else(|| statements.iter().position(|statement| statement.tokens.start == index))
+                .or_else(|| {
+                    // Statements are sorted by where they start, so the last
+                    // one containing the token is the innermost.
+                    statements
+                        .iter()
+                        .rposition(|statement| statement.tokens.start < index && index < statement.tokens.end)
+                });
+
+            match owner {
+                Some(0) if index == statements[0].tokens.start && trailing.is_none() => {
+                    result.file.push(directive);
+                },
+                Some(owner) => attached[owner].push(directive),
+                None => result.file.push(directive),
+            }
+        }
+    }
+
+    for (statement, directives) in statements.iter().zip(attached) {
+        if !directives.is_empty() {
+            result.statements.push(StatementDirectives {
+                span: statement.span,
+                line: tokens[statement.tokens.start].start_position.line,
+                directives,
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+/// Parses the directives in the comments before the token at `index`.
+fn prefix_directives(tokens: &[Token], index: usize, options: &DirectiveOptions) -> Vec<Directive> {
+    let token = &tokens[index];
+
+    // Prefixes aren't positioned, so walk back from the token to find where
+    // each one starts.
+    let mut end = token.start_position.bytes;
+    let mut line = token.start_position.line;
+    let mut found = Vec::new();
+
+    for item in token.prefix.iter().rev() {
+        let (length, newlines) = match item {
+            TokenPrefix::Whitespace(whitespace) => (whitespace.len(), whitespace.matches('\n').count()),
+            TokenPrefix::Shebang(line) => (line.len(), 0),
+            TokenPrefix::ByteOrderMark => (BYTE_ORDER_MARK.len_utf8(), 0),
+            TokenPrefix::Comment(comment) => {
+                let source = comment.to_source();
+                (source.len(), source.matches('\n').count())
+            },
+        };
+
+        let start = end - length;
+        line -= newlines;
+
+        if let TokenPrefix::Comment(comment) = item {
+            let content = match comment {
+                Comment::SingleLine { content } => content,
+                Comment::MultiLine { content, .. } => content,
+            };
+
+            if let Some(mut directive) = parse_directive(content, options) {
+                directive.span = Span {
+                    start,
+                    end,
+                };
+                directive.line = line;
+                found.push(directive);
+            }
+        }
+
+        end = start;
+    }
+
+    found.reverse();
+    found
+}
+
+/// Parses a directive out of the text of a comment, without its position.
+pub(crate) fn parse_directive(content: &str, options: &DirectiveOptions) -> Option<Directive> {
+    for prefix in &options.prefixes {
+        let spaced = prefix.starts_with(|c: char| c.is_alphanumeric());
+        let candidate = if spaced { content.trim_start() } else { content };
+
+        if !candidate.starts_with(prefix.as_str()) {
+            continue;
+        }
+
+        let mut words = candidate[prefix.len()..]
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .map(str::to_string);
+
+        let name = words.next()?;
+
+        return Some(Directive {
+            prefix: prefix.clone(),
+            name,
+            arguments: words.collect(),
+            text: content.to_string(),
+            span: Span {
+                start: 0,
+                end: 0,
+            },
+            line: 0,
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokenizer::tokenize;
+
+    const SOURCE: &str = "--!strict
+--!foo bar, baz
+-- Ordinary comments like this #1 aren't directives, and neither is --! this.
+local a = 1 -- luacheck: ignore 211
+while a do
+	-- luacheck: globals print
+	print(a)
+	--# unroll
+end
+";
+
+    fn summarize(directives: &[Directive]) -> Vec<(&str, &str, Vec<&str>, &str)> {
+        directives
+            .iter()
+            .map(|directive| (
+                directive.prefix.as_str(),
+                directive.name.as_str(),
+                directive.arguments.iter().map(String::as_str).collect(),
+                &SOURCE[directive.span.start..directive.span.end],
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn file_level() {
+        let tokens = tokenize(SOURCE).unwrap();
+        let found = directives(&tokens, &DirectiveOptions::default()).unwrap();
+
+        assert_eq!(summarize(&found.file), vec![
+            ("!", "strict", vec![], "--!strict"),
+            ("!", "foo", vec!["bar", "baz"], "--!foo bar, baz"),
+        ]);
+        assert_eq!(found.file[1].text, "!foo bar, baz");
+        assert_eq!(found.file[1].line, 2);
+    }
+
+    #[test]
+    fn statement_level() {
+        let tokens = tokenize(SOURCE).unwrap();
+        let found = directives(&tokens, &DirectiveOptions::default()).unwrap();
+
+        let statements: Vec<_> = found.statements
+            .iter()
+            .map(|statement| (statement.line, summarize(&statement.directives)))
+            .collect();
+
+        assert_eq!(statements, vec![
+            (4, vec![("luacheck:", "ignore", vec!["211"], "-- luacheck: ignore 211")]),
+            (5, vec![("#", "unroll", vec![], "--# unroll")]),
+            (7, vec![("luacheck:", "globals", vec!["print"], "-- luacheck: globals print")]),
+        ]);
+    }
+
+    #[test]
+    fn custom_prefixes() {
+        let tokens = tokenize("-- selene: allow(unused)\n-- luacheck: ignore\nlocal a = 1").unwrap();
+        let options = DirectiveOptions {
+            prefixes: vec!["selene:".to_string()],
+        };
+
+        let found = directives(&tokens, &options).unwrap();
+
+        let names: Vec<_> = found.file.iter().map(|directive| directive.name.as_str()).collect();
+        assert_eq!(names, vec!["allow(unused)"]);
+        assert!(found.statements.is_empty());
+    }
+}
@@ -0,0 +1,317 @@
+//! A compact S-expression form of tokens and ASTs, meant for people to read
+//! and diff, like in the expectations of a [corpus](../corpus/index.html).
+//!
+//! Statements go on their own lines and blocks are indented, so a change to
+//! the AST shows up as a change to the lines around it. Expressions always
+//! stay on one line:
+//!
+//! ```text
+//! (chunk
+//!   (local (a) ((+ 1 (call f x))))
+//!   (while a
+//!     (call print a)))
+//! ```
+
+use ast::*;
+use tokenizer::{Token, TokenKind};
+
+/// The compact form of a whole chunk.
+pub fn dump_chunk(chunk: &Chunk) -> String {
+    let mut output = String::new();
+    write_block(&mut output, "chunk", chunk, 0);
+    output
+}
+
+/// The compact form of a single statement, with any blocks inside it
+/// indented on lines of their own.
+pub fn dump_statement(statement: &Statement) -> String {
+    let mut output = String::new();
+    write_statement(&mut output, statement, 0);
+    output
+}
+
+/// The compact form of an expression, on one line.
+pub fn dump_expression(expression: &Expression) -> String {
+    match expression {
+        Expression::Nil => "nil".to_string(),
+        Expression::Bool(value) => value.to_string(),
+        Expression::Number(value) => value.to_string(),
+        Expression::String(literal) => literal.to_source(),
+        Expression::VarArg => "...".to_string(),
+        Expression::Table(table) => {
+            let mut output = "(table".to_string();
+
+            for (key, value) in &table.items {
+                output.push(' ');
+
+                match key {
+                    None => output.push_str(&dump_expression(value)),
+                    Some(TableKey::Name(name)) => output.push_str(&format!("(= {} {})", name, dump_expression(value))),
+                    Some(TableKey::Expression(key)) => output.push_str(&format!("(= [{}] {})", dump_expression(key), dump_expression(value))),
+                }
+            }
+
+            output.push(')');
+            output
+        },
+        Expression::FunctionCall(call) => dump_call(call),
+        Expression::Name(name) => name.to_string(),
+        Expression::Var(target) => dump_target(target),
+        Expression::ParenExpression(inner) => format!("(paren {})", dump_expression(inner)),
+        Expression::UnaryOp(op) => {
+            let operator = match op.operator {
+                UnaryOpKind::Negate => "-",
+                UnaryOpKind::BooleanNot => "not",
+                UnaryOpKind::Length => "#",
+            };
+
+            format!("({} {})", operator, dump_expression(&op.argument))
+        },
+        Expression::BinaryOp(op) => {
+            let operator = match op.operator {
+                BinaryOpKind::Add => "+",
+                BinaryOpKind::Subtract => "-",
+                BinaryOpKind::Multiply => "*",
+                BinaryOpKind::Divide => "/",
+                BinaryOpKind::Exponent => "^",
+                BinaryOpKind::Concat => "..",
+            };
+
+            format!("({} {} {})", operator, dump_expression(&op.left), dump_expression(&op.right))
+        },
+    }
+}
+
+/// The compact form of a token stream, one token per line. Whitespace and
+/// comments are left out.
+pub fn dump_tokens(tokens: &[Token]) -> String {
+    let lines: Vec<String> = tokens
+        .iter()
+        .filter_map(|token| Some(match &token.kind {
+            TokenKind::Symbol(symbol) => format!("(symbol {})", symbol.to_str()),
+            TokenKind::Identifier(name) => format!("(name {})", name),
+            TokenKind::NumberLiteral { text, .. } => format!("(number {})", text),
+            TokenKind::StringLiteral(literal) => format!("(string {})", literal.to_source()),
+            TokenKind::InterpolatedString(part) => format!("(interpolated {})", part.to_source()),
+            TokenKind::Error { text, .. } => format!("(error {})", text),
+            TokenKind::Whitespace(_) | TokenKind::Comment(_) => return None,
+            TokenKind::EndOfFile => "(eof)".to_string(),
+        }))
+        .collect();
+
+    format!("(tokens\n{})", indent_lines(&lines))
+}
+
+fn indent_lines(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("  {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+fn dump_call(call: &FunctionCall) -> String {
+    let mut output = match &call.method {
+        Some(method) => format!("(method {} {}", dump_expression(&call.name_expression), method),
+        None => format!("(call {}", dump_expression(&call.name_expression)),
+    };
+
+    for argument in &call.arguments {
+        output.push(' ');
+        output.push_str(&dump_expression(argument));
+    }
+
+    output.push(')');
+    output
+}
+
+fn dump_target(target: &AssignmentTarget) -> String {
+    match target {
+        AssignmentTarget::Name(name) => name.to_string(),
+        AssignmentTarget::FieldAccess { base, name } => format!("(field {} {})", dump_expression(base), name),
+        AssignmentTarget::IndexAccess { base, index } => format!("(index {} {})", dump_expression(base), dump_expression(index)),
+    }
+}
+
+fn dump_targets(targets: &[AssignmentTarget]) -> String {
+    let items: Vec<String> = targets.iter().map(dump_target).collect();
+    format!("({})", items.join(" "))
+}
+
+fn dump_list(expressions: &[Expression]) -> String {
+    let items: Vec<String> = expressions.iter().map(dump_expression).collect();
+    format!("({})", items.join(" "))
+}
+
+fn dump_names<S: AsRef<str>>(names: &[S]) -> String {
+    let names: Vec<&str> = names.iter().map(AsRef::as_ref).collect();
+    format!("({})", names.join(" "))
+}
+
+fn write_indent(output: &mut String, depth: usize) {
+    for _ in 0..depth {
+        output.push_str("  ");
+    }
+}
+
+/// Writes `(head`, then each statement of `block` on its own line, then the
+/// closing paren.
+fn write_block(output: &mut String, head: &str, block: &Chunk, depth: usize) {
+    output.push('(');
+    output.push_str(head);
+
+    for statement in &block.statements {
+        output.push('\n');
+        write_statement(output, statement, depth + 1);
+    }
+
+    output.push(')');
+}
+
+/// Writes a statement whose blocks follow a header on the same line, like
+/// `(while a` followed by the body.
+fn write_with_blocks(output: &mut String, header: &str, blocks: &[(&str, &Chunk)], trailer: Option<&str>, depth: usize) {
+    output.push_str(header);
+
+    for (head, block) in blocks {
+        output.push('\n');
+        write_indent(output, depth + 1);
+        write_block(output, head, block, depth + 1);
+    }
+
+    if let Some(trailer) = trailer {
+        output.push(' ');
+        output.push_str(trailer);
+    }
+
+    output.push(')');
+}
+
+fn write_statement(output: &mut String, statement: &Statement, depth: usize) {
+    write_indent(output, depth);
+
+    match statement {
+        Statement::Assignment(assignment) => {
+            output.push_str(&format!("(assign {} {})", dump_targets(&assignment.targets), dump_list(&assignment.values)));
+        },
+        Statement::LocalAssignment(assignment) => {
+            output.push_str(&format!("(local {} {})", dump_names(&assignment.names), dump_list(&assignment.values)));
+        },
+        Statement::FunctionCall(call) => output.push_str(&dump_call(call)),
+        Statement::NumericFor(numeric_for) => {
+            let mut header = format!("(for {} {} {}", numeric_for.var, dump_expression(&numeric_for.start), dump_expression(&numeric_for.end));
+
+            if let Some(step) = &numeric_for.step {
+                header.push(' ');
+                header.push_str(&dump_expression(step));
+            }
+
+            write_with_blocks(output, &header, &[("do", &numeric_for.body)], None, depth);
+        },
+        Statement::GenericFor(generic_for) => {
+            let header = format!("(for-in {} {}", dump_names(&generic_for.vars), dump_list(&generic_for.item_source));
+            write_with_blocks(output, &header, &[("do", &generic_for.body)], None, depth);
+        },
+        Statement::IfStatement(if_statement) => {
+            let conditions: Vec<String> = if_statement.else_if_branches
+                .iter()
+                .map(|(condition, _)| format!("elseif {}", dump_expression(condition)))
+                .collect();
+
+            let mut blocks = vec![("then", &if_statement.body)];
+            blocks.extend(conditions.iter().map(String::as_str).zip(if_statement.else_if_branches.iter().map(|(_, body)| body)));
+
+            if let Some(else_branch) = &if_statement.else_branch {
+                blocks.push(("else", else_branch));
+            }
+
+            write_with_blocks(output, &format!("(if {}", dump_expression(&if_statement.condition)), &blocks, None, depth);
+        },
+        Statement::WhileLoop(while_loop) => {
+            let header = format!("(while {}", dump_expression(&while_loop.condition));
+            write_with_blocks(output, &header, &[("do", &while_loop.body)], None, depth);
+        },
+        Statement::RepeatLoop(repeat_loop) => {
+            let trailer = dump_expression(&repeat_loop.condition);
+            write_with_blocks(output, "(repeat", &[("do", &repeat_loop.body)], Some(&trailer), depth);
+        },
+        Statement::FunctionDeclaration(declaration) => {
+            let kind = if declaration.local { "local-function" } else { "function" };
+            let header = format!("({} {} {}", kind, declaration.name, dump_names(&declaration.parameters));
+            write_with_blocks(output, &header, &[("do", &declaration.body)], None, depth);
+        },
+        Statement::Return(return_statement) => {
+            output.push_str("(return");
+
+            for value in &return_statement.values {
+                output.push(' ');
+                output.push_str(&dump_expression(value));
+            }
+
+            output.push(')');
+        },
+        Statement::Do(body) => write_block(output, "do", body, depth),
+        Statement::Break => output.push_str("(break)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_from_tokens;
+    use tokenizer::tokenize;
+
+    fn dump_source(source: &str) -> String {
+        dump_chunk(&parse_from_tokens(&tokenize(source).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn statements_and_blocks() {
+        let source = "local a, b = 1 + 2 * 3, {x = 1, [2] = 'y', f(a)}\n\
+            while a do\n\
+                if not a then print(#b) elseif a then g() else end\n\
+            end\n\
+            repeat f() until (a)\n\
+            function M.go:now(x, y) end";
+
+        assert_eq!(dump_source(source), "\
+(chunk
+  (local (a b) ((+ 1 (* 2 3)) (table (= x 1) (= [2] 'y') (call f a))))
+  (while a
+    (do
+      (if (not a)
+        (then
+          (call print (# b)))
+        (elseif a
+          (call g))
+        (else))))
+  (repeat
+    (do
+      (call f)) (paren a))
+  (function M.go:now (x y)
+    (do)))");
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(dump_source("for i = 1, 10, 2 do end for k, v in pairs(t) do f(k) end"), "\
+(chunk
+  (for i 1 10 2
+    (do))
+  (for-in (k v) ((call pairs t))
+    (do
+      (call f k))))");
+
+        assert_eq!(dump_source(""), "(chunk)");
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = tokenize("local s = \"hi\" -- done\n").unwrap();
+
+        assert_eq!(dump_tokens(&tokens), "\
+(tokens
+  (symbol local)
+  (name s)
+  (symbol =)
+  (string \"hi\")
+  (eof))");
+    }
+}
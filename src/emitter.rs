@@ -1,98 +1,416 @@
 use std::fmt::{self, Write};
 
 use ast::*;
+use tokenizer::StringLiteral;
 
-pub fn emit_chunk<'a>(w: &mut Write, chunk: &Chunk<'a>) -> fmt::Result {
-    for statement in &chunk.statements {
-        emit_statement(w, statement)?;
+pub fn emit_chunk<'a>(w: &mut dyn Write, chunk: &Chunk<'a>) -> fmt::Result {
+    emit_block(w, chunk, 0)
+}
+
+fn emit_block<'a>(w: &mut dyn Write, chunk: &Chunk<'a>, indent: usize) -> fmt::Result {
+    for (index, statement) in chunk.statements.iter().enumerate() {
+        emit_indent(w, indent)?;
+
+        // Otherwise, the statement before would be called with it.
+        if index > 0 && starts_with_paren(statement) {
+            write!(w, ";")?;
+        }
+
+        emit_statement(w, statement, indent)?;
+        writeln!(w)?;
     }
 
     Ok(())
 }
 
-fn emit_statement<'a>(w: &mut Write, statement: &Statement<'a>) -> fmt::Result {
-    match statement {
-        &Statement::Assignment(ref value) => emit_assignment(w, value)?,
-        &Statement::LocalAssignment(ref value) => emit_local_assignment(w, value)?,
-        &Statement::FunctionCall(ref value) => emit_function_call(w, value)?,
-        &Statement::NumericFor(ref value) => emit_numeric_for(w, value)?,
-        &Statement::GenericFor(ref value) => emit_generic_for(w, value)?,
-        &Statement::IfStatement(ref value) => emit_if_statement(w, value)?,
-        &Statement::WhileLoop(ref value) => emit_while_loop(w, value)?,
-        &Statement::RepeatLoop(ref value) => emit_repeat_loop(w, value)?,
-        &Statement::FunctionDeclaration(ref value) => emit_function_declaration(w, value)?,
+/// Tells whether `statement` is emitted starting with `(`, like `(t).x = 1`.
+fn starts_with_paren(statement: &Statement) -> bool {
+    let mut expression = match statement {
+        Statement::Assignment(assignment) => match assignment.targets.first() {
+            Some(AssignmentTarget::FieldAccess { base, .. }) | Some(AssignmentTarget::IndexAccess { base, .. }) => &**base,
+            _ => return false,
+        },
+        Statement::FunctionCall(call) => &*call.name_expression,
+        _ => return false,
+    };
+
+    loop {
+        expression = match expression {
+            Expression::Name(_) => return false,
+            Expression::FunctionCall(call) => &*call.name_expression,
+            Expression::Var(target) => match &**target {
+                AssignmentTarget::Name(_) => return false,
+                AssignmentTarget::FieldAccess { base, .. } | AssignmentTarget::IndexAccess { base, .. } => &**base,
+            },
+            // Anything else is either in parens already or gets wrapped in
+            // them by emit_prefix_expression.
+            _ => return true,
+        };
+    }
+}
+
+fn emit_indent(w: &mut dyn Write, indent: usize) -> fmt::Result {
+    for _ in 0..indent {
+        write!(w, "\t")?;
     }
 
     Ok(())
 }
 
-fn emit_assignment<'a>(w: &mut Write, _assignment: &Assignment<'a>) -> fmt::Result {
-    write!(w, "assignment")?;
+fn emit_statement<'a>(w: &mut dyn Write, statement: &Statement<'a>, indent: usize) -> fmt::Result {
+    match statement {
+        Statement::Assignment(value) => emit_assignment(w, value)?,
+        Statement::LocalAssignment(value) => emit_local_assignment(w, value)?,
+        Statement::FunctionCall(value) => emit_function_call(w, value)?,
+        Statement::NumericFor(value) => emit_numeric_for(w, value, indent)?,
+        Statement::GenericFor(value) => emit_generic_for(w, value, indent)?,
+        Statement::IfStatement(value) => emit_if_statement(w, value, indent)?,
+        Statement::WhileLoop(value) => emit_while_loop(w, value, indent)?,
+        Statement::RepeatLoop(value) => emit_repeat_loop(w, value, indent)?,
+        Statement::FunctionDeclaration(value) => emit_function_declaration(w, value, indent)?,
+        Statement::Do(body) => {
+            write!(w, "do")?;
+            emit_body(w, body, indent, "end")?;
+        },
+        Statement::Return(value) => emit_return(w, value)?,
+        Statement::Break => write!(w, "break")?,
+    }
 
     Ok(())
 }
 
-fn emit_local_assignment<'a>(w: &mut Write, _assignment: &LocalAssignment<'a>) -> fmt::Result {
-    write!(w, "local assignment")?;
+fn emit_names<'a>(w: &mut dyn Write, names: &[::std::borrow::Cow<'a, str>]) -> fmt::Result {
+    write!(w, "{}", names.join(", "))
+}
+
+fn emit_expressions<'a>(w: &mut dyn Write, expressions: &[Expression<'a>]) -> fmt::Result {
+    for (index, expression) in expressions.iter().enumerate() {
+        if index > 0 {
+            write!(w, ", ")?;
+        }
+
+        emit_expression(w, expression)?;
+    }
 
     Ok(())
 }
 
-fn emit_function_call<'a>(w: &mut Write, _function_call: &FunctionCall<'a>) -> fmt::Result {
-    write!(w, "function call")?;
+/// Writes the body of a block statement followed by its closing keyword.
+fn emit_body<'a>(w: &mut dyn Write, body: &Chunk<'a>, indent: usize, closing: &str) -> fmt::Result {
+    writeln!(w)?;
+    emit_block(w, body, indent + 1)?;
+    emit_indent(w, indent)?;
+    write!(w, "{}", closing)
+}
 
-    Ok(())
+fn emit_assignment<'a>(w: &mut dyn Write, assignment: &Assignment<'a>) -> fmt::Result {
+    for (index, target) in assignment.targets.iter().enumerate() {
+        if index > 0 {
+            write!(w, ", ")?;
+        }
+
+        emit_assignment_target(w, target)?;
+    }
+
+    write!(w, " = ")?;
+    emit_expressions(w, &assignment.values)
 }
 
-fn emit_numeric_for<'a>(w: &mut Write, _numeric_for: &NumericFor<'a>) -> fmt::Result {
-    write!(w, "numeric for")?;
+fn emit_assignment_target<'a>(w: &mut dyn Write, target: &AssignmentTarget<'a>) -> fmt::Result {
+    match target {
+        AssignmentTarget::Name(name) => write!(w, "{}", name),
+        AssignmentTarget::FieldAccess { base, name } => {
+            emit_prefix_expression(w, base)?;
+            write!(w, ".{}", name)
+        },
+        AssignmentTarget::IndexAccess { base, index } => {
+            emit_prefix_expression(w, base)?;
 
-    Ok(())
+            let mut index_source = String::new();
+            emit_expression(&mut index_source, index)?;
+
+            // Like with table keys, `[[` would start a long string.
+            if index_source.starts_with('[') {
+                write!(w, "[ {}]", index_source)
+            } else {
+                write!(w, "[{}]", index_source)
+            }
+        },
+    }
+}
+
+// Only names, fields, indexes, calls and parenthesized expressions can have a
+// field, index or call after them, so anything else is wrapped in parens.
+fn emit_prefix_expression<'a>(w: &mut dyn Write, expression: &Expression<'a>) -> fmt::Result {
+    match expression {
+        Expression::Name(_)
+        | Expression::Var(_)
+        | Expression::FunctionCall(_)
+        | Expression::ParenExpression(_) => emit_expression(w, expression),
+        _ => {
+            write!(w, "(")?;
+            emit_expression(w, expression)?;
+            write!(w, ")")
+        },
+    }
 }
 
-fn emit_generic_for<'a>(w: &mut Write, _generic_for: &GenericFor<'a>) -> fmt::Result {
-    write!(w, "generic for")?;
+fn emit_local_assignment<'a>(w: &mut dyn Write, assignment: &LocalAssignment<'a>) -> fmt::Result {
+    write!(w, "local ")?;
+    emit_names(w, &assignment.names)?;
+
+    if !assignment.values.is_empty() {
+        write!(w, " = ")?;
+        emit_expressions(w, &assignment.values)?;
+    }
 
     Ok(())
 }
 
-fn emit_if_statement<'a>(w: &mut Write, _if_statement: &IfStatement<'a>) -> fmt::Result {
-    write!(w, "if statement")?;
+fn emit_return<'a>(w: &mut dyn Write, return_statement: &ReturnStatement<'a>) -> fmt::Result {
+    write!(w, "return")?;
+
+    if !return_statement.values.is_empty() {
+        write!(w, " ")?;
+        emit_expressions(w, &return_statement.values)?;
+    }
 
     Ok(())
 }
 
-fn emit_while_loop<'a>(w: &mut Write, _while_loop: &WhileLoop<'a>) -> fmt::Result {
-    write!(w, "while loop")?;
+fn emit_function_call<'a>(w: &mut dyn Write, function_call: &FunctionCall<'a>) -> fmt::Result {
+    emit_prefix_expression(w, &function_call.name_expression)?;
 
-    Ok(())
+    if let Some(method) = &function_call.method {
+        write!(w, ":{}", method)?;
+    }
+
+    write!(w, "(")?;
+    emit_expressions(w, &function_call.arguments)?;
+    write!(w, ")")
 }
 
-fn emit_repeat_loop<'a>(w: &mut Write, _repeat_loop: &RepeatLoop<'a>) -> fmt::Result {
-    write!(w, "repeat loop")?;
+fn emit_numeric_for<'a>(w: &mut dyn Write, numeric_for: &NumericFor<'a>, indent: usize) -> fmt::Result {
+    write!(w, "for {} = ", numeric_for.var)?;
+    emit_expression(w, &numeric_for.start)?;
+    write!(w, ", ")?;
+    emit_expression(w, &numeric_for.end)?;
 
-    Ok(())
+    if let Some(step) = &numeric_for.step {
+        write!(w, ", ")?;
+        emit_expression(w, step)?;
+    }
+
+    write!(w, " do")?;
+    emit_body(w, &numeric_for.body, indent, "end")
 }
 
-fn emit_function_declaration<'a>(w: &mut Write, _function_declaration: &FunctionDeclaration<'a>) -> fmt::Result {
-    write!(w, "function declaration")?;
+fn emit_generic_for<'a>(w: &mut dyn Write, generic_for: &GenericFor<'a>, indent: usize) -> fmt::Result {
+    write!(w, "for ")?;
+    emit_names(w, &generic_for.vars)?;
+    write!(w, " in ")?;
+    emit_expressions(w, &generic_for.item_source)?;
+    write!(w, " do")?;
+    emit_body(w, &generic_for.body, indent, "end")
+}
 
-    Ok(())
+fn emit_if_statement<'a>(w: &mut dyn Write, if_statement: &IfStatement<'a>, indent: usize) -> fmt::Result {
+    write!(w, "if ")?;
+    emit_expression(w, &if_statement.condition)?;
+    write!(w, " then")?;
+    writeln!(w)?;
+    emit_block(w, &if_statement.body, indent + 1)?;
+
+    for (condition, body) in &if_statement.else_if_branches {
+        emit_indent(w, indent)?;
+        write!(w, "elseif ")?;
+        emit_expression(w, condition)?;
+        write!(w, " then")?;
+        writeln!(w)?;
+        emit_block(w, body, indent + 1)?;
+    }
+
+    if let Some(body) = &if_statement.else_branch {
+        emit_indent(w, indent)?;
+        write!(w, "else")?;
+        writeln!(w)?;
+        emit_block(w, body, indent + 1)?;
+    }
+
+    emit_indent(w, indent)?;
+    write!(w, "end")
+}
+
+fn emit_while_loop<'a>(w: &mut dyn Write, while_loop: &WhileLoop<'a>, indent: usize) -> fmt::Result {
+    write!(w, "while ")?;
+    emit_expression(w, &while_loop.condition)?;
+    write!(w, " do")?;
+    emit_body(w, &while_loop.body, indent, "end")
+}
+
+fn emit_repeat_loop<'a>(w: &mut dyn Write, repeat_loop: &RepeatLoop<'a>, indent: usize) -> fmt::Result {
+    write!(w, "repeat")?;
+    emit_body(w, &repeat_loop.body, indent, "until ")?;
+    emit_expression(w, &repeat_loop.condition)
+}
+
+fn emit_function_declaration<'a>(w: &mut dyn Write, function_declaration: &FunctionDeclaration<'a>, indent: usize) -> fmt::Result {
+    if function_declaration.local {
+        write!(w, "local ")?;
+    }
+
+    write!(w, "function {}(", function_declaration.name)?;
+    emit_names(w, &function_declaration.parameters)?;
+    write!(w, ")")?;
+    emit_body(w, &function_declaration.body, indent, "end")
+}
+
+fn emit_expression<'a>(w: &mut dyn Write, expression: &Expression<'a>) -> fmt::Result {
+    match expression {
+        Expression::Nil => write!(w, "nil"),
+        Expression::Bool(value) => write!(w, "{}", value),
+        Expression::Number(value) => write!(w, "{}", value),
+        Expression::String(value) => emit_string(w, value),
+        Expression::VarArg => write!(w, "..."),
+        Expression::Table(value) => emit_table(w, value),
+        Expression::FunctionCall(value) => emit_function_call(w, value),
+        Expression::Name(value) => write!(w, "{}", value),
+        Expression::Var(target) => emit_assignment_target(w, target),
+        Expression::ParenExpression(inner) => {
+            write!(w, "(")?;
+            emit_expression(w, inner)?;
+            write!(w, ")")
+        },
+        Expression::UnaryOp(op) => {
+            let mut argument = String::new();
+            emit_expression(&mut argument, &op.argument)?;
+
+            match op.operator {
+                UnaryOpKind::Negate => {
+                    // A minus directly before a number or another minus would
+                    // turn into a negative literal or a comment.
+                    if argument.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+                        write!(w, "- ")?;
+                    } else {
+                        write!(w, "-")?;
+                    }
+                },
+                UnaryOpKind::BooleanNot => write!(w, "not ")?,
+                UnaryOpKind::Length => write!(w, "#")?,
+            }
+
+            write!(w, "{}", argument)
+        },
+        Expression::BinaryOp(op) => {
+            emit_expression(w, &op.left)?;
+            write!(w, " {} ", binary_operator(&op.operator))?;
+            emit_expression(w, &op.right)
+        },
+    }
+}
+
+fn binary_operator(operator: &BinaryOpKind) -> &'static str {
+    match *operator {
+        BinaryOpKind::Add => "+",
+        BinaryOpKind::Subtract => "-",
+        BinaryOpKind::Multiply => "*",
+        BinaryOpKind::Divide => "/",
+        BinaryOpKind::Exponent => "^",
+        BinaryOpKind::Concat => "..",
+    }
+}
+
+fn emit_string<'a>(w: &mut dyn Write, literal: &StringLiteral<'a>) -> fmt::Result {
+    match literal {
+        StringLiteral::DoubleQuote { raw_content } => write!(w, "\"{}\"", raw_content),
+        StringLiteral::SingleQuote { raw_content } => write!(w, "'{}'", raw_content),
+        StringLiteral::LongForm { raw_content, depth } => {
+            let equals = "=".repeat(*depth as usize);
+            write!(w, "[{}[{}]{}]", equals, raw_content, equals)
+        },
+    }
+}
+
+fn emit_table<'a>(w: &mut dyn Write, table: &TableLiteral<'a>) -> fmt::Result {
+    write!(w, "{{")?;
+
+    for (index, (key, value)) in table.items.iter().enumerate() {
+        if index > 0 {
+            write!(w, ", ")?;
+        }
+
+        match key {
+            Some(TableKey::Name(name)) => write!(w, "{} = ", name)?,
+            Some(TableKey::Expression(key)) => {
+                let mut key_source = String::new();
+                emit_expression(&mut key_source, key)?;
+
+                // `[[` would start a long string instead of a key.
+                if key_source.starts_with('[') {
+                    write!(w, "[ {}] = ", key_source)?;
+                } else {
+                    write!(w, "[{}] = ", key_source)?;
+                }
+            },
+            None => {},
+        }
+
+        emit_expression(w, value)?;
+    }
+
+    write!(w, "}}")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use parser::parse_from_tokens;
+    use tokenizer::tokenize;
+
+    fn emit(chunk: &Chunk) -> String {
+        let mut output = String::new();
+        emit_chunk(&mut output, chunk).unwrap();
+        output
+    }
+
     #[test]
     fn emit_empty_chunk() {
         let chunk = Chunk {
             statements: Vec::new(),
         };
 
-        let mut output = String::new();
-        emit_chunk(&mut output, &chunk).unwrap();
+        assert_eq!(&emit(&chunk), "");
+    }
+
+    #[test]
+    fn emit_round_trips() {
+        let source = "local a, b = 1, {x = 'y', [2] = [==[z]==], [ [[w]]] = x, - - 3}
+function t.f:m(p, q)
+	for i = 1, #p, - 1 ^ 2 do
+		print(i .. \"\", -(a + b) * 2 ^ -q)
+	end
+end
+if a then
+	repeat
+		local c
+	until not c
+elseif b then
+	while a do
+		f()
+		io.stdout:write(t[1].x())
+		;(t).x = f().y
+		;(\"s\"):upper()
+	end
+else
+	for k, v in pairs(a) do
+	end
+end
+";
 
-        assert_eq!(&output, "");
+        let tokens = tokenize(source).unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+
+        assert_eq!(emit(&chunk), source);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,416 @@
+//! Evaluation of constant expressions without a Lua VM.
+//!
+//! Only expressions made of literals and operators can be evaluated. Anything
+//! that depends on a variable, calls a function, or has behavior that can't be
+//! reproduced exactly, like concatenating NaN, evaluates to `None`.
+
+use std::cmp::Ordering;
+
+use ast::*;
+use tokenizer::StringLiteral;
+use version::LuaVersion;
+
+/// The result of evaluating a constant expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaValue {
+    Nil,
+    Bool(bool),
+
+    /// A number with the integer subtype. Only produced by versions that have
+    /// one.
+    Integer(i64),
+
+    Float(f64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalOptions {
+    /// The version whose arithmetic and number formatting to follow.
+    pub version: LuaVersion,
+
+    /// Whether strings are converted to numbers in arithmetic, like Lua does.
+    /// Off by default, since it's rarely intended in constant expressions.
+    pub coerce_strings: bool,
+}
+
+impl LuaValue {
+    /// Whether the value counts as true in a condition.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, LuaValue::Nil | LuaValue::Bool(false))
+    }
+
+    /// Compares two values with the semantics of Lua's `==` operator. NaN is
+    /// not equal to anything, including itself.
+    pub fn lua_eq(&self, other: &LuaValue) -> bool {
+        match (self, other) {
+            (LuaValue::Integer(_), LuaValue::Float(_))
+            | (LuaValue::Float(_), LuaValue::Integer(_))
+            | (LuaValue::Float(_), LuaValue::Float(_))
+            | (LuaValue::Integer(_), LuaValue::Integer(_)) => self.compare_numbers(other) == Some(Ordering::Equal),
+            _ => self == other,
+        }
+    }
+
+    /// Compares two values with the semantics of Lua's `<` operator. Returns
+    /// `None` when Lua would raise an error instead.
+    pub fn lua_lt(&self, other: &LuaValue) -> Option<bool> {
+        match (self, other) {
+            (LuaValue::String(a), LuaValue::String(b)) => Some(a < b),
+            _ if self.is_number() && other.is_number() => {
+                Some(self.compare_numbers(other) == Some(Ordering::Less))
+            },
+            _ => None,
+        }
+    }
+
+    /// Compares two values with the semantics of Lua's `<=` operator. Returns
+    /// `None` when Lua would raise an error instead.
+    pub fn lua_le(&self, other: &LuaValue) -> Option<bool> {
+        match (self, other) {
+            (LuaValue::String(a), LuaValue::String(b)) => Some(a <= b),
+            _ if self.is_number() && other.is_number() => {
+                let ordering = self.compare_numbers(other);
+                Some(ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal))
+            },
+            _ => None,
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        matches!(self, LuaValue::Integer(_) | LuaValue::Float(_))
+    }
+
+    /// Orders two numbers exactly, even when comparing integers to floats that
+    /// can't represent them. Returns `None` if either is NaN.
+    fn compare_numbers(&self, other: &LuaValue) -> Option<Ordering> {
+        match (self, other) {
+            (LuaValue::Integer(a), LuaValue::Integer(b)) => Some(a.cmp(b)),
+            (LuaValue::Float(a), LuaValue::Float(b)) => a.partial_cmp(b),
+            (LuaValue::Integer(a), LuaValue::Float(b)) => compare_integer_to_float(*a, *b),
+            (LuaValue::Float(a), LuaValue::Integer(b)) => compare_integer_to_float(*b, *a).map(Ordering::reverse),
+            _ => None,
+        }
+    }
+}
+
+/// 2^63, the first float above every `i64`.
+const INTEGER_LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+fn compare_integer_to_float(integer: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        None
+    } else if float >= INTEGER_LIMIT {
+        Some(Ordering::Less)
+    } else if float < -INTEGER_LIMIT {
+        Some(Ordering::Greater)
+    } else if integer < float.ceil() as i64 {
+        // Both bounds are in range here, and an integer is less than a float
+        // exactly when it's less than the float rounded up.
+        Some(Ordering::Less)
+    } else if integer > float.floor() as i64 {
+        Some(Ordering::Greater)
+    } else {
+        Some(Ordering::Equal)
+    }
+}
+
+/// Evaluates a constant expression.
+pub fn const_eval(expression: &Expression, options: &EvalOptions) -> Option<LuaValue> {
+    match expression {
+        Expression::Nil => Some(LuaValue::Nil),
+        Expression::Bool(value) => Some(LuaValue::Bool(*value)),
+        Expression::Number(literal) => parse_number(literal, options.version),
+        Expression::String(literal) => decode_string(literal).map(LuaValue::String),
+        Expression::ParenExpression(inner) => const_eval(inner, options),
+        Expression::UnaryOp(op) => {
+            let argument = const_eval(&op.argument, options)?;
+
+            match op.operator {
+                UnaryOpKind::BooleanNot => Some(LuaValue::Bool(!argument.is_truthy())),
+                UnaryOpKind::Negate => match to_number(argument, options)? {
+                    LuaValue::Integer(value) => Some(LuaValue::Integer(value.wrapping_neg())),
+                    LuaValue::Float(value) => Some(LuaValue::Float(-value)),
+                    _ => None,
+                },
+                UnaryOpKind::Length => match argument {
+                    LuaValue::String(value) => Some(make_integer(value.len() as i64, options.version)),
+                    _ => None,
+                },
+            }
+        },
+        Expression::BinaryOp(op) => {
+            let left = const_eval(&op.left, options)?;
+            let right = const_eval(&op.right, options)?;
+
+            match op.operator {
+                BinaryOpKind::Concat => {
+                    let mut result = to_concat_string(left, options.version)?;
+                    result.push_str(&to_concat_string(right, options.version)?);
+                    Some(LuaValue::String(result))
+                },
+                operator => arithmetic(operator, to_number(left, options)?, to_number(right, options)?),
+            }
+        },
+        Expression::VarArg
+        | Expression::Table(_)
+        | Expression::FunctionCall(_)
+        | Expression::Name(_)
+        | Expression::Var(_) => None,
+    }
+}
+
+fn make_integer(value: i64, version: LuaVersion) -> LuaValue {
+    if version.has_integers() {
+        LuaValue::Integer(value)
+    } else {
+        LuaValue::Float(value as f64)
+    }
+}
+
+/// Applies an arithmetic operator. Integers only come from literals in versions
+/// that have them, so two integers always follow integer semantics.
+fn arithmetic(operator: BinaryOpKind, left: LuaValue, right: LuaValue) -> Option<LuaValue> {
+    if let (LuaValue::Integer(a), LuaValue::Integer(b)) = (&left, &right) {
+        match operator {
+            BinaryOpKind::Add => return Some(LuaValue::Integer(a.wrapping_add(*b))),
+            BinaryOpKind::Subtract => return Some(LuaValue::Integer(a.wrapping_sub(*b))),
+            BinaryOpKind::Multiply => return Some(LuaValue::Integer(a.wrapping_mul(*b))),
+            _ => {},
+        }
+    }
+
+    let a = to_float(&left)?;
+    let b = to_float(&right)?;
+
+    let result = match operator {
+        BinaryOpKind::Add => a + b,
+        BinaryOpKind::Subtract => a - b,
+        BinaryOpKind::Multiply => a * b,
+        BinaryOpKind::Divide => a / b,
+        BinaryOpKind::Exponent => a.powf(b),
+        BinaryOpKind::Concat => return None,
+    };
+
+    Some(LuaValue::Float(result))
+}
+
+fn to_float(value: &LuaValue) -> Option<f64> {
+    match value {
+        LuaValue::Integer(value) => Some(*value as f64),
+        LuaValue::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn to_number(value: LuaValue, options: &EvalOptions) -> Option<LuaValue> {
+    match value {
+        LuaValue::Integer(_) | LuaValue::Float(_) => Some(value),
+        LuaValue::String(ref text) if options.coerce_strings => {
+            let trimmed = text.trim_matches(|c: char| c.is_ascii_whitespace());
+            parse_number(trimmed, options.version)
+        },
+        _ => None,
+    }
+}
+
+fn to_concat_string(value: LuaValue, version: LuaVersion) -> Option<String> {
+    match value {
+        LuaValue::String(value) => Some(value),
+        LuaValue::Integer(value) => Some(value.to_string()),
+        LuaValue::Float(value) => format_float(value, version),
+        LuaValue::Nil | LuaValue::Bool(_) => None,
+    }
+}
+
+/// Parses a number the way the given version of Lua reads literals.
+fn parse_number(literal: &str, version: LuaVersion) -> Option<LuaValue> {
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, literal),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        if version.has_integers() {
+            // Hexadecimal integers wrap around instead of becoming floats.
+            let value = hex.chars().fold(0i64, |total, c| {
+                total.wrapping_mul(16).wrapping_add(c.to_digit(16).unwrap() as i64)
+            });
+
+            LuaValue::Integer(value)
+        } else {
+            LuaValue::Float(hex.chars().fold(0.0, |total, c| total * 16.0 + c.to_digit(16).unwrap() as f64))
+        }
+    } else {
+        if digits.is_empty() || !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            return None;
+        }
+
+        let is_integer = digits.chars().all(|c| c.is_ascii_digit());
+
+        match digits.parse::<i64>() {
+            Ok(value) if is_integer && version.has_integers() => LuaValue::Integer(value),
+            _ => LuaValue::Float(digits.parse::<f64>().ok()?),
+        }
+    };
+
+    if negative {
+        match value {
+            LuaValue::Integer(value) => Some(LuaValue::Integer(value.wrapping_neg())),
+            LuaValue::Float(value) => Some(LuaValue::Float(-value)),
+            _ => None,
+        }
+    } else {
+        Some(value)
+    }
+}
+
+/// Formats a float the way `tostring` does, which is C's `%.14g`. Lua 5.3 adds
+/// `.0` to floats that would otherwise look like integers.
+fn format_float(value: f64, version: LuaVersion) -> Option<String> {
+    // How NaN prints depends on the C library Lua was built against.
+    if value.is_nan() {
+        return None;
+    }
+
+    let mut result = if value.is_infinite() {
+        return Some(if value > 0.0 { "inf" } else { "-inf" }.to_string());
+    } else if value == 0.0 {
+        if value.is_sign_negative() { "-0" } else { "0" }.to_string()
+    } else {
+        let scientific = format!("{:.13e}", value);
+        let exponent_index = scientific.find('e').unwrap();
+        let exponent: i32 = scientific[exponent_index + 1..].parse().unwrap();
+
+        if !(-4..14).contains(&exponent) {
+            let mantissa = trim_fraction(&scientific[..exponent_index]);
+            let sign = if exponent < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+        } else {
+            trim_fraction(&format!("{:.*}", (13 - exponent) as usize, value)).to_string()
+        }
+    };
+
+    if version.has_integers() && !result.contains(['.', 'e']) {
+        result.push_str(".0");
+    }
+
+    Some(result)
+}
+
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+/// Decodes the contents of a string literal. Returns `None` for escapes that
+/// aren't understood or that produce invalid UTF-8.
+fn decode_string(literal: &StringLiteral) -> Option<String> {
+    String::from_utf8(literal.value()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_expression_from_tokens;
+    use tokenizer::tokenize;
+
+    fn eval(source: &str, version: LuaVersion) -> Option<LuaValue> {
+        let options = EvalOptions {
+            version,
+            ..EvalOptions::default()
+        };
+
+        let tokens = tokenize(source).unwrap();
+        let expression = parse_expression_from_tokens(&tokens).unwrap();
+        const_eval(&expression, &options)
+    }
+
+    fn string(value: &str) -> Option<LuaValue> {
+        Some(LuaValue::String(value.to_string()))
+    }
+
+    #[test]
+    fn simple_config_values() {
+        assert_eq!(eval("60 * 60 * 24", LuaVersion::Lua53), Some(LuaValue::Integer(86400)));
+        assert_eq!(eval("60 * 60 * 24", LuaVersion::Lua51), Some(LuaValue::Float(86400.0)));
+        assert_eq!(eval("\"v\" .. \"1.2\"", LuaVersion::Lua51), string("v1.2"));
+        assert_eq!(eval("'a\\tb\\65' .. [[\nc]]", LuaVersion::Lua51), string("a\tbAc"));
+        assert_eq!(eval("not nil", LuaVersion::Lua51), Some(LuaValue::Bool(true)));
+        assert_eq!(eval("#\"four\"", LuaVersion::Lua53), Some(LuaValue::Integer(4)));
+    }
+
+    #[test]
+    fn division_across_versions() {
+        assert_eq!(eval("6 / 2", LuaVersion::Lua53), Some(LuaValue::Float(3.0)));
+        assert_eq!(eval("7 / 2", LuaVersion::Lua51), Some(LuaValue::Float(3.5)));
+
+        assert_eq!(eval("'' .. 6 / 2", LuaVersion::Lua53), string("3.0"));
+        assert_eq!(eval("'' .. 6 / 2", LuaVersion::Lua51), string("3"));
+        assert_eq!(eval("'' .. 6 * 2", LuaVersion::Lua53), string("12"));
+        assert_eq!(eval("'' .. 1 / 3", LuaVersion::Lua51), string("0.33333333333333"));
+        assert_eq!(eval("'' .. 1 / 0", LuaVersion::Lua53), string("inf"));
+        assert_eq!(eval("'' .. 2 ^ 63", LuaVersion::Lua53), string("9.2233720368548e+18"));
+    }
+
+    #[test]
+    fn integer_overflow() {
+        assert_eq!(eval("9223372036854775807 + 1", LuaVersion::Lua53), Some(LuaValue::Integer(i64::MIN)));
+        assert_eq!(eval("9223372036854775807 + 1", LuaVersion::Lua51), Some(LuaValue::Float(9223372036854775808.0)));
+        assert_eq!(eval("9223372036854775808", LuaVersion::Lua53), Some(LuaValue::Float(9223372036854775808.0)));
+        assert_eq!(eval("0xffffffffffffffff", LuaVersion::Lua53), Some(LuaValue::Integer(-1)));
+    }
+
+    #[test]
+    fn nan_comparisons() {
+        let nan = eval("0 / 0", LuaVersion::Lua53).unwrap();
+        let one = LuaValue::Integer(1);
+
+        assert!(!nan.lua_eq(&nan));
+        assert_eq!(nan.lua_lt(&one), Some(false));
+        assert_eq!(one.lua_lt(&nan), Some(false));
+        assert_eq!(nan.lua_le(&nan), Some(false));
+
+        assert_eq!(eval("'' .. 0 / 0", LuaVersion::Lua53), None);
+    }
+
+    #[test]
+    fn mixed_comparisons() {
+        let big = LuaValue::Integer(i64::MAX);
+        let float = LuaValue::Float(9223372036854775807.0);
+
+        // The float rounds up to 2^63, which is larger than any integer.
+        assert_eq!(big.lua_lt(&float), Some(true));
+        assert!(!big.lua_eq(&float));
+        assert!(LuaValue::Integer(3).lua_eq(&LuaValue::Float(3.0)));
+        assert_eq!(LuaValue::Float(2.5).lua_le(&LuaValue::Integer(2)), Some(false));
+        assert_eq!(LuaValue::String("a".to_string()).lua_lt(&LuaValue::String("b".to_string())), Some(true));
+        assert_eq!(LuaValue::String("1".to_string()).lua_lt(&LuaValue::Integer(1)), None);
+    }
+
+    #[test]
+    fn non_constant_expressions() {
+        assert_eq!(eval("x + 1", LuaVersion::Lua53), None);
+        assert_eq!(eval("f()", LuaVersion::Lua53), None);
+        assert_eq!(eval("{}", LuaVersion::Lua53), None);
+        assert_eq!(eval("'a' .. nil", LuaVersion::Lua53), None);
+        assert_eq!(eval("'10' + 1", LuaVersion::Lua53), None);
+
+        let options = EvalOptions {
+            version: LuaVersion::Lua53,
+            coerce_strings: true,
+        };
+
+        let tokens = tokenize("' 10 ' + 1").unwrap();
+        let expression = parse_expression_from_tokens(&tokens).unwrap();
+        assert_eq!(const_eval(&expression, &options), Some(LuaValue::Integer(11)));
+    }
+}
@@ -0,0 +1,482 @@
+//! A callback-based alternative to building an AST, for consumers like
+//! syntax highlighters and indexers that only need to know what's where.
+//!
+//! Events are produced by the parser itself as it goes, in the same pass
+//! that checks the source, so handlers see a statement before anything after
+//! it has been parsed. No statements are kept.
+
+use std::cell::RefCell;
+use std::ops::{ControlFlow, Range};
+
+use analysis::Span;
+use ast::FunctionName;
+use cst::NodeKind;
+use parser::parse_chunk;
+use parser_core::{ParseState, SyntaxSink};
+use tokenizer::{tokenize, StringLiteral, Token, TokenKind};
+
+/// What to do after an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visit {
+    Continue,
+
+    /// Don't produce events for the blocks inside the current statement or
+    /// function. Only meaningful for the events that start one.
+    SkipChildren,
+
+    /// Stop producing events altogether.
+    Abort,
+}
+
+/// Receives events from [parse_events]. Every method does nothing by
+/// default, so handlers only implement the ones they're interested in.
+///
+/// Where a statement ends isn't known until it has been parsed, so its span
+/// comes with the event that ends it.
+#[allow(unused_variables)]
+pub trait ParseEvents {
+    /// A statement of the given kind starts at the byte offset `start`.
+    fn statement_start(&mut self, kind: NodeKind, start: usize) -> Visit {
+        Visit::Continue
+    }
+
+    /// The statement from the latest unmatched `statement_start` ends.
+    /// `span` covers the whole statement, including any blocks inside it.
+    fn statement_end(&mut self, kind: NodeKind, span: Span) {}
+
+    /// A function declaration starts, right after the `statement_start` for
+    /// it. `name` is the full name, like `M.foo` or `Class:method`, and
+    /// `selection` covers just the name.
+    fn enter_function(&mut self, name: &str, selection: Span) -> Visit {
+        Visit::Continue
+    }
+
+    /// The function from the latest unmatched `enter_function` ends. `span`
+    /// covers the whole declaration.
+    fn exit_function(&mut self, span: Span) {}
+
+    /// An identifier that isn't inside a nested block of the current
+    /// statement.
+    fn identifier(&mut self, name: &str, span: Span) -> Visit {
+        Visit::Continue
+    }
+
+    fn string_literal(&mut self, literal: &StringLiteral, span: Span) -> Visit {
+        Visit::Continue
+    }
+
+    fn number_literal(&mut self, literal: &str, span: Span) -> Visit {
+        Visit::Continue
+    }
+}
+
+/// Tokenizes and parses `source`, reporting what's in it to `handler`
+/// instead of building an AST.
+///
+/// Returns [ControlFlow::Break] if the handler aborted. Errors are only found
+/// when parsing reaches them, so a handler may receive events for the
+/// statements before an error.
+pub fn parse_events<H: ParseEvents>(source: &str, handler: &mut H) -> Result<ControlFlow<()>, String> {
+    let tokens = tokenize(source).map_err(|err| format!("could not tokenize source: {}", err))?;
+    parse_events_from_tokens(&tokens, handler)
+}
+
+/// Like [parse_events], but for a source that's already tokenized.
+pub fn parse_events_from_tokens<'a, H: ParseEvents>(tokens: &'a [Token<'a>], handler: &mut H) -> Result<ControlFlow<()>, String> {
+    let sink = RefCell::new(EventSink {
+        tokens,
+        handler,
+        next: 0,
+        open: Vec::new(),
+        muted: 0,
+        aborted: false,
+    });
+
+    let state = ParseState {
+        sink: Some(&sink),
+        ..ParseState::new(tokens)
+    };
+
+    let result = parse_chunk(state);
+
+    // Parsing stops short once the handler aborts, so whatever went wrong
+    // after that doesn't matter.
+    if sink.borrow().aborted {
+        return Ok(ControlFlow::Break(()));
+    }
+
+    result?;
+
+    Ok(ControlFlow::Continue(()))
+}
+
+/// A statement that has been entered and hasn't finished.
+struct OpenStatement {
+    function: bool,
+
+    /// Whether the handler asked to skip the blocks inside it.
+    skip: bool,
+
+    /// Whether it's inside a block that's being skipped, so the handler
+    /// never heard about it.
+    muted: bool,
+}
+
+/// Turns what the parser tells it into calls on a [ParseEvents] handler.
+struct EventSink<'a, 'h, H: 'h> {
+    tokens: &'a [Token<'a>],
+    handler: &'h mut H,
+
+    /// The first token that hasn't been reported or skipped.
+    next: usize,
+
+    open: Vec<OpenStatement>,
+
+    /// How many of the open blocks are being skipped.
+    muted: usize,
+
+    aborted: bool,
+}
+
+impl<'a, 'h, H: ParseEvents> EventSink<'a, 'h, H> {
+    fn quiet(&self) -> bool {
+        self.aborted || self.muted > 0
+    }
+
+    /// Notes what the handler asked for, returning whether it wants to skip
+    /// the current statement's blocks.
+    fn visit(&mut self, visit: Visit) -> bool {
+        match visit {
+            Visit::Continue => false,
+            Visit::SkipChildren => true,
+            Visit::Abort => {
+                self.aborted = true;
+                false
+            },
+        }
+    }
+
+    /// Reports the tokens before `end` that haven't been reported yet.
+    fn report_tokens(&mut self, end: usize) {
+        while self.next < end {
+            let token = &self.tokens[self.next];
+            let span = span_of(self.tokens, self.next..self.next + 1);
+            self.next += 1;
+
+            if self.quiet() {
+                continue;
+            }
+
+            let visit = match &token.kind {
+                TokenKind::Identifier(name) => self.handler.identifier(name, span),
+                TokenKind::StringLiteral(literal) => self.handler.string_literal(literal, span),
+                TokenKind::NumberLiteral { text, .. } => self.handler.number_literal(text, span),
+                _ => Visit::Continue,
+            };
+
+            self.visit(visit);
+        }
+    }
+
+    fn open(&mut self, kind: NodeKind, start: usize, function: Option<(&FunctionName, Range<usize>)>) {
+        self.report_tokens(start);
+
+        let muted = self.quiet();
+        let is_function = function.is_some();
+        let mut skip = false;
+
+        if !muted {
+            let visit = self.handler.statement_start(kind, self.tokens[start].start_position.bytes);
+            skip = self.visit(visit);
+
+            if let Some((name, selection)) = function.filter(|_| !self.aborted) {
+                let visit = self.handler.enter_function(&name.to_string(), span_of(self.tokens, selection));
+                skip |= self.visit(visit);
+            }
+        }
+
+        self.open.push(OpenStatement {
+            function: is_function,
+            skip,
+            muted,
+        });
+    }
+}
+
+impl<'a, 'h, H: ParseEvents> SyntaxSink for EventSink<'a, 'h, H> {
+    fn start_block(&mut self, start: usize) {
+        self.report_tokens(start);
+
+        if self.muted > 0 || self.open.last().is_some_and(|statement| statement.skip) {
+            self.muted += 1;
+        }
+    }
+
+    fn finish_block(&mut self, end: usize) {
+        self.report_tokens(end);
+        self.muted = self.muted.saturating_sub(1);
+    }
+
+    fn enter_statement(&mut self, kind: NodeKind, start: usize) {
+        self.open(kind, start, None);
+    }
+
+    fn enter_function(&mut self, start: usize, name: &FunctionName, name_tokens: Range<usize>) {
+        self.open(NodeKind::FunctionDeclaration, start, Some((name, name_tokens)));
+    }
+
+    fn finish_statement(&mut self, kind: NodeKind, start: usize, end: usize) {
+        // An entered statement has already had its first tokens reported,
+        // while everything before any other statement was reported by the
+        // time it started.
+        let entered = self.next > start;
+
+        if !entered {
+            self.open(kind, start, None);
+        }
+
+        self.report_tokens(end);
+
+        let statement = self.open.pop().expect("Finished a statement that was never started");
+
+        if statement.muted || self.aborted {
+            return;
+        }
+
+        let span = span_of(self.tokens, start..end);
+
+        if statement.function {
+            self.handler.exit_function(span);
+        }
+
+        self.handler.statement_end(kind, span);
+    }
+
+    fn stopped(&self) -> bool {
+        self.aborted
+    }
+}
+
+fn span_of(tokens: &[Token], range: Range<usize>) -> Span {
+    Span {
+        start: tokens[range.start].start_position.bytes,
+        end: tokens[range.end - 1].end_position.bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use analysis::{symbols, SymbolInfo, SymbolKind};
+
+    #[derive(Debug, PartialEq)]
+    struct Outline {
+        name: String,
+        span: Span,
+        selection: Span,
+        children: Vec<Outline>,
+    }
+
+    /// Rebuilds the tree of functions from events alone.
+    #[derive(Default)]
+    struct Outliner {
+        roots: Vec<Outline>,
+        open: Vec<Outline>,
+    }
+
+    impl ParseEvents for Outliner {
+        fn enter_function(&mut self, name: &str, selection: Span) -> Visit {
+            self.open.push(Outline {
+                name: name.to_string(),
+                span: selection,
+                selection,
+                children: Vec::new(),
+            });
+
+            Visit::Continue
+        }
+
+        fn exit_function(&mut self, span: Span) {
+            let mut function = self.open.pop().unwrap();
+            function.span = span;
+
+            match self.open.last_mut() {
+                Some(parent) => parent.children.push(function),
+                None => self.roots.push(function),
+            }
+        }
+    }
+
+    fn outline_of(symbols: &[SymbolInfo]) -> Vec<Outline> {
+        symbols
+            .iter()
+            .filter(|symbol| symbol.kind != SymbolKind::Local)
+            .map(|symbol| Outline {
+                name: symbol.name.clone(),
+                span: symbol.span,
+                selection: symbol.selection_span,
+                children: outline_of(&symbol.children),
+            })
+            .collect()
+    }
+
+    const SOURCE: &str = "local count = 0
+function setup(options)
+	local function helper(x)
+		print(\"helping\", x)
+	end
+
+	while options do
+		function nested() end
+	end
+end
+
+function Class:method(a, b) end
+function M.util.join() end
+";
+
+    #[test]
+    fn outline_matches_symbols() {
+        let mut outliner = Outliner::default();
+        let flow = parse_events(SOURCE, &mut outliner).unwrap();
+
+        assert_eq!(flow, ControlFlow::Continue(()));
+        assert!(outliner.open.is_empty());
+
+        let tokens = tokenize(SOURCE).unwrap();
+        assert_eq!(outliner.roots, outline_of(&symbols(&tokens).unwrap()));
+
+        let names: Vec<_> = outliner.roots.iter().map(|outline| outline.name.as_str()).collect();
+        assert_eq!(names, vec!["setup", "Class:method", "M.util.join"]);
+        assert_eq!(outliner.roots[0].children.len(), 2);
+    }
+
+    /// Records events as text, optionally skipping or stopping at a
+    /// statement.
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        skip: Option<NodeKind>,
+        abort_at: Option<&'static str>,
+    }
+
+    impl ParseEvents for Recorder {
+        fn statement_start(&mut self, kind: NodeKind, _: usize) -> Visit {
+            self.events.push(format!("start {:?}", kind));
+
+            if self.skip == Some(kind) {
+                Visit::SkipChildren
+            } else {
+                Visit::Continue
+            }
+        }
+
+        fn statement_end(&mut self, kind: NodeKind, _: Span) {
+            self.events.push(format!("end {:?}", kind));
+        }
+
+        fn identifier(&mut self, name: &str, _: Span) -> Visit {
+            self.events.push(format!("name {}", name));
+
+            if self.abort_at == Some(name) {
+                Visit::Abort
+            } else {
+                Visit::Continue
+            }
+        }
+
+        fn string_literal(&mut self, literal: &StringLiteral, span: Span) -> Visit {
+            self.events.push(format!("string {} at {}", literal.to_source(), span.start));
+            Visit::Continue
+        }
+
+        fn number_literal(&mut self, literal: &str, _: Span) -> Visit {
+            self.events.push(format!("number {}", literal));
+            Visit::Continue
+        }
+    }
+
+    const LOOP: &str = "while go do f('x') end g(1)";
+
+    #[test]
+    fn events_in_order() {
+        let mut recorder = Recorder::default();
+        assert_eq!(parse_events(LOOP, &mut recorder), Ok(ControlFlow::Continue(())));
+
+        assert_eq!(recorder.events, vec![
+            "start WhileLoop",
+            "name go",
+            "start FunctionCall",
+            "name f",
+            "string 'x' at 14",
+            "end FunctionCall",
+            "end WhileLoop",
+            "start FunctionCall",
+            "name g",
+            "number 1",
+            "end FunctionCall",
+        ]);
+    }
+
+    #[test]
+    fn skipping_and_aborting() {
+        let mut recorder = Recorder {
+            skip: Some(NodeKind::WhileLoop),
+            ..Recorder::default()
+        };
+        assert_eq!(parse_events(LOOP, &mut recorder), Ok(ControlFlow::Continue(())));
+
+        assert_eq!(recorder.events, vec![
+            "start WhileLoop",
+            "name go",
+            "end WhileLoop",
+            "start FunctionCall",
+            "name g",
+            "number 1",
+            "end FunctionCall",
+        ]);
+
+        let mut recorder = Recorder {
+            abort_at: Some("f"),
+            ..Recorder::default()
+        };
+
+        assert_eq!(parse_events(LOOP, &mut recorder), Ok(ControlFlow::Break(())));
+        assert_eq!(recorder.events.last().map(String::as_str), Some("name f"));
+    }
+
+    #[test]
+    fn skipped_blocks_are_still_checked() {
+        let mut recorder = Recorder {
+            skip: Some(NodeKind::FunctionDeclaration),
+            ..Recorder::default()
+        };
+
+        assert_eq!(parse_events("function f() g() end h()", &mut recorder), Ok(ControlFlow::Continue(())));
+        assert_eq!(recorder.events, vec![
+            "start FunctionDeclaration",
+            "name f",
+            "end FunctionDeclaration",
+            "start FunctionCall",
+            "name h",
+            "end FunctionCall",
+        ]);
+
+        let mut recorder = Recorder {
+            skip: Some(NodeKind::FunctionDeclaration),
+            ..Recorder::default()
+        };
+
+        assert!(parse_events("function f() g( end", &mut recorder).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let mut recorder = Recorder::default();
+
+        assert!(parse_events("f() end", &mut recorder).is_err());
+        assert_eq!(recorder.events.first().map(String::as_str), Some("start FunctionCall"));
+        assert!(parse_events("f(\"", &mut Recorder::default()).is_err());
+    }
+}
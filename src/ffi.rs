@@ -0,0 +1,162 @@
+//! `extern "C"` functions for using the parser from other languages.
+//!
+//! The matching C header is `include/mab.h`. To build a shared library, run
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! Ownership rules:
+//!
+//! * [mab_parse] returns a handle that the caller owns and must release with
+//!   [mab_result_free] exactly once.
+//! * Strings returned by the `mab_result_*` accessors are owned by the handle.
+//!   They stay valid until the handle is freed and must not be freed by the
+//!   caller. They're UTF-8 and not NUL-terminated; their length in bytes is
+//!   written to the `length` out parameter.
+//! * No function keeps a reference to the source buffer after it returns.
+//!
+//! No function unwinds into the caller. If the parser panics, [mab_parse]
+//! returns a handle describing the failure; accessors that panic return null
+//! or false.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::str;
+
+use serde_json;
+
+use parser::parse_from_tokens;
+use tokenizer::tokenize;
+
+/// The result of a parse, which C callers only see through a pointer.
+pub struct MabParseResult {
+    ast_json: Option<String>,
+    error: Option<String>,
+}
+
+fn parse_buffer(source: &[u8]) -> MabParseResult {
+    let failure = |message: String| MabParseResult {
+        ast_json: None,
+        error: Some(message),
+    };
+
+    let source = match str::from_utf8(source) {
+        Ok(source) => source,
+        Err(err) => return failure(format!("source is not valid UTF-8: {}", err)),
+    };
+
+    let tokens = match tokenize(source) {
+        Ok(tokens) => tokens,
+        Err(err) => return failure(format!("could not tokenize source: {}", err)),
+    };
+
+    match parse_from_tokens(&tokens) {
+        Ok(chunk) => MabParseResult {
+            ast_json: Some(serde_json::to_string(&chunk).expect("AST could not be serialized")),
+            error: None,
+        },
+        Err(message) => failure(message),
+    }
+}
+
+/// Writes the string's length to `length` and returns a pointer to its bytes,
+/// or returns null and writes 0 if there's no string.
+unsafe fn export_str(value: Option<&str>, length: *mut usize) -> *const u8 {
+    let (pointer, size) = match value {
+        Some(value) => (value.as_ptr(), value.len()),
+        None => (ptr::null(), 0),
+    };
+
+    if !length.is_null() {
+        *length = size;
+    }
+
+    pointer
+}
+
+/// Parses `length` bytes of UTF-8 source starting at `source`.
+///
+/// Never returns null. `source` may only be null if `length` is 0.
+///
+/// # Safety
+///
+/// `source` must point to at least `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mab_parse(source: *const u8, length: usize) -> *mut MabParseResult {
+    let result = panic::catch_unwind(|| {
+        let source = if source.is_null() {
+            &[][..]
+        } else {
+            slice::from_raw_parts(source, length)
+        };
+
+        parse_buffer(source)
+    });
+
+    let result = result.unwrap_or_else(|_| MabParseResult {
+        ast_json: None,
+        error: Some("the parser panicked".to_string()),
+    });
+
+    Box::into_raw(Box::new(result))
+}
+
+/// Returns whether the source parsed successfully. Returns false for a null
+/// handle.
+///
+/// # Safety
+///
+/// `result` must be null or a handle from [mab_parse] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mab_result_success(result: *const MabParseResult) -> bool {
+    if result.is_null() {
+        return false;
+    }
+
+    (*result).error.is_none()
+}
+
+/// Returns the error message if parsing failed, or null if it succeeded.
+///
+/// # Safety
+///
+/// `result` must be null or a handle from [mab_parse] that hasn't been freed.
+/// `length` must be null or point to writable memory for a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn mab_result_error(result: *const MabParseResult, length: *mut usize) -> *const u8 {
+    let result = AssertUnwindSafe(result);
+
+    panic::catch_unwind(|| {
+        let error = result.as_ref().and_then(|result| result.error.as_ref());
+        export_str(error.map(String::as_str), length)
+    }).unwrap_or(ptr::null())
+}
+
+/// Returns the AST as JSON if parsing succeeded, or null if it failed.
+///
+/// The JSON has the same shape as the AST files in `parse_examples/results`.
+///
+/// # Safety
+///
+/// `result` must be null or a handle from [mab_parse] that hasn't been freed.
+/// `length` must be null or point to writable memory for a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn mab_result_ast_json(result: *const MabParseResult, length: *mut usize) -> *const u8 {
+    let result = AssertUnwindSafe(result);
+
+    panic::catch_unwind(|| {
+        let json = result.as_ref().and_then(|result| result.ast_json.as_ref());
+        export_str(json.map(String::as_str), length)
+    }).unwrap_or(ptr::null())
+}
+
+/// Frees a handle returned by [mab_parse]. Does nothing for null.
+///
+/// # Safety
+///
+/// `result` must be null or a handle from [mab_parse] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mab_result_free(result: *mut MabParseResult) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
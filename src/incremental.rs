@@ -0,0 +1,376 @@
+//! Reparsing of a file after a text edit, reusing the parts of the previous
+//! AST that the edit couldn't have affected.
+//!
+//! Reuse happens at the granularity of top-level statements. Statements that
+//! were reused are shared with the previous [ParsedFile] rather than copied,
+//! so `Arc::ptr_eq` tells whether a statement was rebuilt.
+
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use ast::{Chunk, Statement};
+use parser::{expect_end_of_stream, parse_statement_at};
+use tokenizer::{tokenize, Token, TokenKind, TokenizeError};
+
+/// A replacement of the bytes `start..end` of a source with new text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// The byte offset where the replaced range begins, inclusive.
+    pub start: usize,
+
+    /// The byte offset where the replaced range ends, exclusive.
+    pub end: usize,
+
+    /// The text to put in place of the replaced range.
+    pub replacement: String,
+}
+
+/// The reason a file couldn't be parsed or reparsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReparseError {
+    /// The edit's range was out of bounds or split a character.
+    InvalidEdit,
+
+    Tokenize(TokenizeError),
+    Parse(String),
+}
+
+impl fmt::Display for ReparseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReparseError::InvalidEdit => write!(f, "edit range is not valid for the source"),
+            ReparseError::Tokenize(err) => write!(f, "could not tokenize source: {}", err),
+            ReparseError::Parse(message) => write!(f, "could not parse source: {}", message),
+        }
+    }
+}
+
+impl error::Error for ReparseError {}
+
+/// A top-level statement along with the range of source it was parsed from.
+#[derive(Debug, Clone)]
+pub struct ParsedItem {
+    /// The parsed statement, shared between files when it's reused.
+    pub statement: Arc<Statement<'static>>,
+
+    /// The byte offset of the statement's first token, inclusive.
+    pub start: usize,
+
+    /// The byte offset of the end of the statement's last token, exclusive.
+    pub end: usize,
+}
+
+/// A source file together with its parsed top-level statements.
+#[derive(Debug, Clone)]
+pub struct ParsedFile {
+    source: String,
+    items: Vec<ParsedItem>,
+}
+
+impl ParsedFile {
+    /// Parses a file from scratch.
+    pub fn parse(source: String) -> Result<ParsedFile, ReparseError> {
+        let items = {
+            let tokens = tokenize(&source).map_err(ReparseError::Tokenize)?;
+            parse_items(&tokens, 0, |_| None)?.0
+        };
+
+        Ok(ParsedFile {
+            source,
+            items,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn items(&self) -> &[ParsedItem] {
+        &self.items
+    }
+
+    /// Builds a standalone chunk out of this file's statements.
+    pub fn to_chunk(&self) -> Chunk<'static> {
+        Chunk {
+            statements: self.items.iter().map(|item| (*item.statement).clone()).collect(),
+        }
+    }
+}
+
+/// Applies `edit` to the source of `old` and parses the result, reusing every
+/// top-level statement that the edit couldn't have changed.
+///
+/// The statement directly before the edit is always reparsed, since the
+/// parser looks one token past the end of a statement to decide where it ends.
+/// Statements after the edit are reused once reparsing reaches a token that
+/// begins exactly where one of them began before; if that never happens, the
+/// rest of the file is parsed normally, which makes structural edits like
+/// deleting an `end` behave exactly as a full parse would.
+pub fn reparse(old: &ParsedFile, edit: &TextEdit) -> Result<ParsedFile, ReparseError> {
+    if edit.start > edit.end
+        || edit.end > old.source.len()
+        || !old.source.is_char_boundary(edit.start)
+        || !old.source.is_char_boundary(edit.end)
+    {
+        return Err(ReparseError::InvalidEdit);
+    }
+
+    let mut source = String::with_capacity(old.source.len() - (edit.end - edit.start) + edit.replacement.len());
+    source.push_str(&old.source[..edit.start]);
+    source.push_str(&edit.replacement);
+    source.push_str(&old.source[edit.end..]);
+
+    let edited_end = edit.start + edit.replacement.len();
+    let shift = |offset: usize| offset - edit.end + edited_end;
+
+    let kept = old.items
+        .iter()
+        .take_while(|item| item.end < edit.start)
+        .count()
+        .saturating_sub(1);
+
+    let items = {
+        let tokens = tokenize(&source).map_err(ReparseError::Tokenize)?;
+
+        let restart_at = match kept {
+            0 => 0,
+            _ => old.items[kept - 1].end,
+        };
+
+        let first_token = tokens
+            .iter()
+            .position(|token| token.start_position.bytes >= restart_at)
+            .unwrap_or(tokens.len());
+
+        // Maps the start of a token in the new source back to the index of an
+        // old statement after the edit that began at the same text.
+        let resume_at = |offset: usize| {
+            if offset < edited_end {
+                return None;
+            }
+
+            let old_offset = offset - edited_end + edit.end;
+
+            old.items
+                .binary_search_by_key(&old_offset, |item| item.start)
+                .ok()
+        };
+
+        let (reparsed, resumed) = parse_items(&tokens, first_token, resume_at)?;
+
+        let mut items = Vec::with_capacity(old.items.len());
+        items.extend(old.items[..kept].iter().cloned());
+        items.extend(reparsed);
+
+        if let Some(resumed) = resumed {
+            items.extend(old.items[resumed..].iter().map(|item| ParsedItem {
+                statement: Arc::clone(&item.statement),
+                start: shift(item.start),
+                end: shift(item.end),
+            }));
+        }
+
+        items
+    };
+
+    Ok(ParsedFile {
+        source,
+        items,
+    })
+}
+
+/// Parses top-level statements starting at the token at index `position`.
+///
+/// Before each statement, `resume_at` is consulted with the byte offset of the
+/// next token; if it returns an index, parsing stops and that index is handed
+/// back so that the caller can reuse statements from there on.
+fn parse_items<'a, F>(tokens: &'a [Token<'a>], position: usize, resume_at: F) -> Result<(Vec<ParsedItem>, Option<usize>), ReparseError>
+where
+    F: Fn(usize) -> Option<usize>,
+{
+    let mut items = Vec::new();
+    let mut position = position;
+
+    loop {
+        if let Some(token) = tokens.get(position) {
+            if token.kind != TokenKind::EndOfFile {
+                if let Some(index) = resume_at(token.start_position.bytes) {
+                    return Ok((items, Some(index)));
+                }
+            }
+        }
+
+        match parse_statement_at(tokens, position).map_err(ReparseError::Parse)? {
+            Some((next_position, statement)) => {
+                items.push(ParsedItem {
+                    statement: Arc::new(statement.into_owned()),
+                    start: tokens[position].start_position.bytes,
+                    end: tokens[next_position - 1].end_position.bytes,
+                });
+
+                position = next_position;
+            },
+            None => break,
+        }
+    }
+
+    expect_end_of_stream(tokens, position).map_err(|message| ReparseError::Parse(message.into()))?;
+
+    Ok((items, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_from_tokens;
+
+    fn full_parse(source: &str) -> Result<Chunk<'static>, ReparseError> {
+        let tokens = tokenize(source).map_err(ReparseError::Tokenize)?;
+        let chunk = parse_from_tokens(&tokens).map_err(ReparseError::Parse)?;
+
+        Ok(chunk.into_owned())
+    }
+
+    fn edit_at(source: &str, needle: &str, replacement: &str) -> TextEdit {
+        let start = source.find(needle).unwrap();
+
+        TextEdit {
+            start,
+            end: start + needle.len(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    fn check_matches_full_parse(file: &ParsedFile) {
+        assert_eq!(file.to_chunk(), full_parse(file.source()).unwrap());
+
+        for item in file.items() {
+            let tokens = tokenize(&file.source()[item.start..item.end]).unwrap();
+            let chunk = parse_from_tokens(&tokens).unwrap();
+            assert_eq!(chunk.statements, vec![(*item.statement).clone()]);
+        }
+    }
+
+    fn large_source(function_count: usize) -> String {
+        let mut source = String::new();
+
+        for i in 0..function_count {
+            source.push_str(&format!("function f{}(a, b)\n\tlocal x = a + b\n\tprint(x)\n\tprint(\"{}\")\nend\n", i, i));
+        }
+
+        source
+    }
+
+    #[test]
+    fn edit_inside_one_function_only_rebuilds_it() {
+        let old = ParsedFile::parse(large_source(1000)).unwrap();
+        assert_eq!(old.items().len(), 1000);
+
+        let edit = edit_at(old.source(), "print(\"500\")", "print(\"five hundred\", x)");
+        let new = reparse(&old, &edit).unwrap();
+
+        check_matches_full_parse(&new);
+
+        let rebuilt: Vec<usize> = old.items()
+            .iter()
+            .zip(new.items())
+            .enumerate()
+            .filter(|&(_, (a, b))| !Arc::ptr_eq(&a.statement, &b.statement))
+            .map(|(index, _)| index)
+            .collect();
+
+        // The statement before the edit is always reparsed as well.
+        assert_eq!(rebuilt, vec![499, 500]);
+    }
+
+    #[test]
+    fn spans_after_the_edit_are_shifted() {
+        let old = ParsedFile::parse("local a = 1\nlocal b = 2\nlocal c = 3\n".to_string()).unwrap();
+        let new = reparse(&old, &edit_at(old.source(), "1", "100")).unwrap();
+
+        let spans: Vec<_> = new.items().iter().map(|item| (item.start, item.end)).collect();
+        assert_eq!(spans, vec![(0, 13), (14, 25), (26, 37)]);
+        assert!(Arc::ptr_eq(&old.items()[2].statement, &new.items()[2].statement));
+
+        check_matches_full_parse(&new);
+    }
+
+    #[test]
+    fn deleting_an_end_falls_back_to_full_parse() {
+        let source = "function a()\nend\nfunction b()\nprint(1)\nend\nfunction c()\nend\n";
+        let old = ParsedFile::parse(source.to_string()).unwrap();
+
+        let end_of_b = source.find("print(1)\nend").unwrap() + "print(1)\n".len();
+        let edit = TextEdit {
+            start: end_of_b,
+            end: end_of_b + 3,
+            replacement: String::new(),
+        };
+
+        let result = reparse(&old, &edit);
+        let expected = full_parse(&source.replacen("print(1)\nend", "print(1)\n", 1));
+
+        assert!(expected.is_err());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn edit_that_merges_statements() {
+        let old = ParsedFile::parse("local a\nlocal b\nlocal c\n".to_string()).unwrap();
+        let new = reparse(&old, &edit_at(old.source(), "local b", "= 1")).unwrap();
+
+        assert_eq!(new.items().len(), 2);
+        check_matches_full_parse(&new);
+    }
+
+    #[test]
+    fn edit_that_comments_out_the_rest() {
+        let old = ParsedFile::parse("local a = 1\nlocal b = 2\nlocal c = 3\n".to_string()).unwrap();
+
+        let start = old.source().find("local b").unwrap();
+        let edit = TextEdit {
+            start,
+            end: start,
+            replacement: "--[[".to_string(),
+        };
+
+        // A block comment that's never closed is an error, just like in a
+        // full parse.
+        assert!(matches!(reparse(&old, &edit), Err(ReparseError::Tokenize(TokenizeError::UnclosedComment { .. }))));
+        assert!(full_parse(&old.source().replacen("local b", "--[[local b", 1)).is_err());
+
+        let edit = TextEdit {
+            start,
+            end: start,
+            replacement: "--[[ ]]".to_string(),
+        };
+
+        let new = reparse(&old, &edit).unwrap();
+        check_matches_full_parse(&new);
+
+        let edit = TextEdit {
+            start,
+            end: start,
+            replacement: "--".to_string(),
+        };
+
+        let new = reparse(&old, &edit).unwrap();
+        assert_eq!(new.items().len(), 2);
+        check_matches_full_parse(&new);
+    }
+
+    #[test]
+    fn invalid_edits_are_rejected() {
+        let old = ParsedFile::parse("local a = 1".to_string()).unwrap();
+
+        let edit = TextEdit {
+            start: 5,
+            end: 50,
+            replacement: String::new(),
+        };
+
+        assert_eq!(reparse(&old, &edit).unwrap_err(), ReparseError::InvalidEdit);
+    }
+}
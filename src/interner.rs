@@ -0,0 +1,179 @@
+//! Interning of identifiers, so that analyses can compare and hash names as
+//! small integers instead of as strings.
+//!
+//! An [Interner] can be shared between any number of sources, so that the
+//! same name gets the same [SymbolId] in all of them.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use tokenizer::{tokenize_with_options, Token, TokenKind, TokenizeError, TokenizerOptions};
+
+/// An interned string. Only means something to the [Interner] that gave it
+/// out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SymbolId(pub u32);
+
+impl SymbolId {
+    /// The id as an index, for tables with an entry for each interned string.
+    /// Ids are handed out in order, starting from 0.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// The table of interned strings.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    ids: HashMap<Box<str>, SymbolId>,
+    strings: Vec<Box<str>>,
+    string_literals: bool,
+}
+
+impl Interner {
+    /// An interner for identifiers.
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// An interner for identifiers and the values of string literals. A
+    /// literal is interned by what it stands for, so `'a'` and `"\97"` get
+    /// the same id as the name `a`. Literals that aren't valid UTF-8 aren't
+    /// interned.
+    pub fn with_string_literals() -> Interner {
+        Interner {
+            string_literals: true,
+            ..Interner::default()
+        }
+    }
+
+    /// The id of `text`, interning it if it's new.
+    ///
+    /// # Panics
+    /// Panics if more than `u32::MAX` strings have been interned.
+    pub fn intern(&mut self, text: &str) -> SymbolId {
+        if let Some(&id) = self.ids.get(text) {
+            return id;
+        }
+
+        let id = SymbolId(u32::try_from(self.strings.len()).expect("too many interned strings"));
+        self.strings.push(text.into());
+        self.ids.insert(text.into(), id);
+
+        id
+    }
+
+    /// The id of `text`, if it's been interned.
+    pub fn get(&self, text: &str) -> Option<SymbolId> {
+        self.ids.get(text).cloned()
+    }
+
+    /// The string behind `id`, if it came from this interner.
+    pub fn resolve(&self, id: SymbolId) -> Option<&str> {
+        self.strings.get(id.index()).map(|text| &**text)
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    fn intern_token(&mut self, token: &Token) -> Option<SymbolId> {
+        match &token.kind {
+            TokenKind::Identifier(name) => Some(self.intern(name)),
+            TokenKind::StringLiteral(literal) if self.string_literals => {
+                let value = String::from_utf8(literal.value()?).ok()?;
+                Some(self.intern(&value))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// A token, along with the id of its name or string value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InternedToken<'a> {
+    pub token: Token<'a>,
+
+    /// The id of the identifier, or of the string literal's value if the
+    /// interner takes string literals.
+    pub symbol: Option<SymbolId>,
+}
+
+/// Tokenizes a source string like [tokenize][::tokenize], interning
+/// identifiers into `interner` on the way.
+pub fn tokenize_interned<'a>(source: &'a str, interner: &mut Interner) -> Result<Vec<InternedToken<'a>>, TokenizeError> {
+    tokenize_interned_with_options(source, &TokenizerOptions::default(), interner)
+}
+
+/// Tokenizes a source string like [tokenize_interned], with extensions
+/// enabled by `options`.
+pub fn tokenize_interned_with_options<'a>(source: &'a str, options: &TokenizerOptions, interner: &mut Interner) -> Result<Vec<InternedToken<'a>>, TokenizeError> {
+    let tokens = tokenize_with_options(source, options)?;
+
+    Ok(tokens.into_iter()
+        .map(|token| InternedToken {
+            symbol: interner.intern_token(&token),
+            token,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let mut interner = Interner::new();
+
+        let a = interner.intern("self");
+        let b = interner.intern("print");
+
+        assert_eq!(interner.intern("self"), a);
+        assert_ne!(a, b);
+        assert_eq!(a.index(), 0);
+        assert_eq!(b.index(), 1);
+        assert_eq!(interner.get("print"), Some(b));
+        assert_eq!(interner.get("other"), None);
+        assert_eq!(interner.resolve(b), Some("print"));
+        assert_eq!(interner.resolve(SymbolId(2)), None);
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn tokens() {
+        let mut interner = Interner::new();
+        let tokens = tokenize_interned("local x = x + y\nprint(x, 'x')", &mut interner).unwrap();
+
+        let x = interner.get("x").unwrap();
+        let symbols: Vec<_> = tokens.iter().map(|token| token.symbol).collect();
+
+        assert_eq!(symbols.iter().filter(|&&symbol| symbol == Some(x)).count(), 3);
+        assert_eq!(tokens[1].symbol, Some(x));
+        assert_eq!(tokens[0].symbol, None);
+        assert_eq!(tokens[11].symbol, None);
+        assert_eq!(interner.len(), 3);
+
+        // The same interner gives the same ids for another source.
+        let more = tokenize_interned("return y", &mut interner).unwrap();
+        assert_eq!(more[1].symbol, interner.get("y"));
+        assert_eq!(interner.len(), 3);
+    }
+
+    #[test]
+    fn string_literals() {
+        let mut interner = Interner::with_string_literals();
+        let tokens = tokenize_interned("t['a'] = a .. \"\\97\" .. '\\255'", &mut interner).unwrap();
+
+        let a = interner.get("a");
+        assert!(a.is_some());
+        assert_eq!(tokens[2].symbol, a);
+        assert_eq!(tokens[5].symbol, a);
+        assert_eq!(tokens[7].symbol, a);
+        assert_eq!(tokens[9].symbol, None);
+    }
+}
@@ -1,15 +1,54 @@
-#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate serde_derive;
 extern crate serde;
-extern crate regex;
+extern crate unicode_ident;
+
+#[cfg(any(feature = "wasm", feature = "ffi"))]
+extern crate serde_json;
+
+#[cfg(feature = "arbitrary")]
+extern crate proptest;
 
 #[macro_use]
 mod parser_core;
 
+pub mod analysis;
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
 pub mod ast;
+pub mod batch;
+pub mod cache;
+pub mod clones;
+#[cfg(feature = "fs")]
+pub mod corpus;
+pub mod cst;
+pub mod diff;
+pub mod directive;
+pub mod dump;
 pub mod emitter;
+pub mod events;
+pub mod eval;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod incremental;
+pub mod interner;
+pub mod line_index;
+pub mod lint;
+pub mod message;
+pub mod minify;
 pub mod tokenizer;
+pub mod visit;
 pub mod parser;
+#[cfg(feature = "fs")]
+pub mod project;
+pub mod query;
+pub mod reusable;
+pub mod rewrite;
+pub mod scope;
+pub mod session;
+pub mod version;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 pub use tokenizer::*;
 pub use parser::*;
\ No newline at end of file
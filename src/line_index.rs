@@ -0,0 +1,345 @@
+//! Conversions between the byte offsets the tokenizer produces and the line
+//! and column positions editors use.
+//!
+//! Columns come in three units: bytes, chars, and UTF-16 code units, which
+//! is what the Language Server Protocol counts in. They only differ on lines
+//! with non-ASCII text, which is exactly where hand-written conversions tend
+//! to go wrong.
+
+use analysis::Span;
+use tokenizer::{SourcePosition, BYTE_ORDER_MARK};
+
+/// A position as the Language Server Protocol describes it: a line and a
+/// column in UTF-16 code units, both starting at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct LspPosition {
+    pub line: usize,
+    pub character: usize,
+}
+
+/// A range as the Language Server Protocol describes it. The end is
+/// exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+/// The start of every line in a source, for converting between offsets and
+/// positions without rescanning the source each time.
+///
+/// Lines and columns here start at 0. A line's columns stop at the end of
+/// its text, before the `\n` or `\r\n` that ends it; columns past that are
+/// clamped to it. [position_of][LineIndex::position_of] and
+/// [offset_of][LineIndex::offset_of] are the exception, and count like the
+/// tokenizer does instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+
+    /// The byte offset each line starts at.
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> LineIndex<'a> {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(index, _)| index + 1));
+
+        LineIndex {
+            source,
+            line_starts,
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The line that the byte at `offset` is on. Offsets past the end of the
+    /// source are on the last line.
+    pub fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        }
+    }
+
+    /// The text of `line`, without the newline that ends it.
+    pub fn line_text(&self, line: usize) -> Option<&'a str> {
+        let start = *self.line_starts.get(line)?;
+        let end = self.line_starts.get(line + 1).map(|next| next - 1).unwrap_or(self.source.len());
+
+        let text = &self.source[start..end];
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    /// The byte offset of the start of `line`, clamped to the last line.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line.min(self.line_starts.len() - 1)]
+    }
+
+    /// The text of the line `offset` is on, and how many bytes into it
+    /// `offset` is. The column is clamped to the end of the line's text and
+    /// moved back to the start of any character it's in the middle of.
+    fn split(&self, offset: usize) -> (&'a str, usize) {
+        let line = self.line_of(offset);
+        let text = self.line_text(line).unwrap_or("");
+        let mut column = offset.saturating_sub(self.line_starts[line]).min(text.len());
+
+        while !text.is_char_boundary(column) {
+            column -= 1;
+        }
+
+        (text, column)
+    }
+
+    /// How many bytes come before `offset` on its line.
+    pub fn byte_column(&self, offset: usize) -> usize {
+        self.split(offset).1
+    }
+
+    /// How many chars come before `offset` on its line.
+    pub fn char_column(&self, offset: usize) -> usize {
+        let (text, column) = self.split(offset);
+        text[..column].chars().count()
+    }
+
+    /// How many UTF-16 code units come before `offset` on its line.
+    pub fn utf16_column(&self, offset: usize) -> usize {
+        let (text, column) = self.split(offset);
+        text[..column].chars().map(char::len_utf16).sum()
+    }
+
+    /// The byte offset of the char column `column` on `line`.
+    pub fn offset_of_char_column(&self, line: usize, column: usize) -> usize {
+        let text = self.line_text(line).unwrap_or("");
+        let within = text.char_indices().nth(column).map(|(index, _)| index).unwrap_or(text.len());
+
+        self.line_start(line) + within
+    }
+
+    /// The byte offset of the UTF-16 column `column` on `line`. A column in
+    /// the middle of a surrogate pair is moved back to the start of its
+    /// character.
+    pub fn offset_of_utf16_column(&self, line: usize, column: usize) -> usize {
+        let text = self.line_text(line).unwrap_or("");
+        let mut units = 0;
+
+        for (index, c) in text.char_indices() {
+            units += c.len_utf16();
+
+            if units > column {
+                return self.line_start(line) + index;
+            }
+        }
+
+        self.line_start(line) + text.len()
+    }
+
+    pub fn char_to_utf16_column(&self, line: usize, column: usize) -> usize {
+        self.utf16_column(self.offset_of_char_column(line, column))
+    }
+
+    pub fn utf16_to_char_column(&self, line: usize, column: usize) -> usize {
+        self.char_column(self.offset_of_utf16_column(line, column))
+    }
+
+    /// The [SourcePosition] the tokenizer would give for `offset` with its
+    /// default options, with lines and char columns starting at 1, a `\r` counted as a column, and a
+    /// byte order mark at the start of the source not counted. Offsets
+    /// past the end of the source or in the middle of a character are moved
+    /// back to the start of one.
+    pub fn position_of(&self, offset: usize) -> SourcePosition {
+        let mut offset = offset.min(self.source.len());
+
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line = self.line_of(offset);
+        let start = self.tokenizer_line_start(line).unwrap_or(0).min(offset);
+
+        SourcePosition {
+            bytes: offset,
+            line: line + 1,
+            column: self.source[start..offset].chars().count() + 1,
+        }
+    }
+
+    /// The byte offset of a line and column counted like [position_of][LineIndex::position_of]
+    /// does, from 1. The column just past the end of a line is where its
+    /// newline is. Returns `None` for positions that aren't in the source.
+    pub fn offset_of(&self, line: usize, column: usize) -> Option<usize> {
+        let line = line.checked_sub(1)?;
+        let start = self.tokenizer_line_start(line)?;
+        let end = self.line_starts.get(line + 1).map_or(self.source.len(), |next| next - 1);
+        let text = &self.source[start..end];
+
+        text.char_indices()
+            .map(|(index, _)| index)
+            .chain(Some(text.len()))
+            .nth(column.checked_sub(1)?)
+            .map(|index| start + index)
+    }
+
+    /// Where the tokenizer starts counting columns on `line`, which is after
+    /// the byte order mark on the first line.
+    fn tokenizer_line_start(&self, line: usize) -> Option<usize> {
+        if line == 0 && self.source.starts_with(BYTE_ORDER_MARK) {
+            Some(BYTE_ORDER_MARK.len_utf8())
+        } else {
+            self.line_starts.get(line).cloned()
+        }
+    }
+
+    pub fn lsp_position(&self, offset: usize) -> LspPosition {
+        LspPosition {
+            line: self.line_of(offset),
+            character: self.utf16_column(offset),
+        }
+    }
+
+    pub fn offset_of_lsp_position(&self, position: LspPosition) -> usize {
+        self.offset_of_utf16_column(position.line, position.character)
+    }
+
+    pub fn lsp_range(&self, span: Span) -> LspRange {
+        LspRange {
+            start: self.lsp_position(span.start),
+            end: self.lsp_position(span.end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "é" is two bytes and one UTF-16 unit, "😀" is four bytes and a
+    // surrogate pair.
+    const SOURCE: &str = "local a = 1\r\nlocal é = \"😀!\"\n\nprint(é)";
+
+    #[test]
+    fn lines() {
+        let index = LineIndex::new(SOURCE);
+
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line_text(0), Some("local a = 1"));
+        assert_eq!(index.line_text(1), Some("local é = \"😀!\""));
+        assert_eq!(index.line_text(2), Some(""));
+        assert_eq!(index.line_text(4), None);
+
+        assert_eq!(index.line_of(0), 0);
+        assert_eq!(index.line_of(13), 1);
+        assert_eq!(index.line_of(SOURCE.len() + 10), 3);
+    }
+
+    #[test]
+    fn mixed_width_columns() {
+        let index = LineIndex::new(SOURCE);
+        let line = index.line_start(1);
+        let bang = SOURCE.find('!').unwrap();
+
+        assert_eq!(bang - line, 16);
+        assert_eq!(index.byte_column(bang), 16);
+        assert_eq!(index.char_column(bang), 12);
+        assert_eq!(index.utf16_column(bang), 13);
+
+        assert_eq!(index.offset_of_char_column(1, 12), bang);
+        assert_eq!(index.offset_of_utf16_column(1, 13), bang);
+        assert_eq!(index.char_to_utf16_column(1, 12), 13);
+        assert_eq!(index.utf16_to_char_column(1, 13), 12);
+    }
+
+    #[test]
+    fn round_trips() {
+        let index = LineIndex::new(SOURCE);
+
+        for (offset, _) in SOURCE.char_indices() {
+            let line = index.line_of(offset);
+
+            // Newlines aren't part of the line, so they clamp to its end.
+            if index.line_text(line).unwrap().len() < offset - index.line_start(line) {
+                continue;
+            }
+
+            assert_eq!(index.offset_of_char_column(line, index.char_column(offset)), offset);
+            assert_eq!(index.offset_of_utf16_column(line, index.utf16_column(offset)), offset);
+            assert_eq!(index.offset_of_lsp_position(index.lsp_position(offset)), offset);
+        }
+    }
+
+    #[test]
+    fn clamping() {
+        let index = LineIndex::new(SOURCE);
+        let emoji = SOURCE.find('😀').unwrap();
+
+        // Past the end of the line's text, including its `\r\n`.
+        assert_eq!(index.offset_of_char_column(0, 100), 11);
+        assert_eq!(index.char_column(12), 11);
+        assert_eq!(index.offset_of_lsp_position(LspPosition { line: 2, character: 5 }), index.line_start(2));
+
+        // In the middle of the emoji.
+        assert_eq!(index.byte_column(emoji + 2), index.byte_column(emoji));
+        assert_eq!(index.offset_of_utf16_column(1, index.utf16_column(emoji) + 1), emoji);
+
+        // Past the last line.
+        assert_eq!(index.offset_of_char_column(10, 0), index.line_start(3));
+    }
+
+    #[test]
+    fn tokenizer_positions() {
+        use tokenizer::{tokenize, tokenize_recovering};
+
+        let source = "local a = 1\r\nlocal é = \"😀!\" --[[ x\r\ny ]]\n\nprint(é, $)\r\n\r\nreturn";
+        let index = LineIndex::new(source);
+        let (tokens, errors) = tokenize_recovering(source);
+
+        let positions = tokens.iter()
+            .flat_map(|token| vec![token.start_position, token.end_position])
+            .chain(errors.iter().filter_map(|err| err.position()));
+
+        for position in positions {
+            assert_eq!(index.position_of(position.bytes), position);
+            assert_eq!(index.offset_of(position.line, position.column), Some(position.bytes));
+        }
+
+        // The last line has no newline, and its end is still a position.
+        let end = tokenize("a\nbc").unwrap()[1].end_position;
+        assert_eq!(LineIndex::new("a\nbc").position_of(3), SourcePosition { bytes: 3, line: 2, column: 2 });
+        assert_eq!(LineIndex::new("a\nbc").position_of(100), end);
+        assert_eq!(LineIndex::new("a\nbc").offset_of(2, 3), Some(4));
+
+        assert_eq!(index.offset_of(0, 1), None);
+        assert_eq!(index.offset_of(1, 0), None);
+        assert_eq!(index.offset_of(1, 14), None);
+        assert_eq!(index.offset_of(1, 13), Some(12));
+        assert_eq!(index.offset_of(100, 1), None);
+
+        // In the middle of the emoji.
+        let emoji = source.find('😀').unwrap();
+        assert_eq!(index.position_of(emoji + 2), index.position_of(emoji));
+
+        let source = "\u{FEFF}local x\n\u{FEFF}";
+        let index = LineIndex::new(source);
+        let start = tokenize(source).unwrap_err().position().unwrap();
+        assert_eq!(index.position_of(3), SourcePosition { bytes: 3, line: 1, column: 1 });
+        assert_eq!(index.position_of(0), SourcePosition { bytes: 0, line: 1, column: 1 });
+        assert_eq!(index.offset_of(1, 1), Some(3));
+        assert_eq!(index.position_of(start.bytes), start);
+        assert_eq!(index.offset_of(start.line, start.column), Some(start.bytes));
+    }
+
+    #[test]
+    fn spans_to_ranges() {
+        let index = LineIndex::new(SOURCE);
+        let start = SOURCE.find('"').unwrap();
+        let end = SOURCE.rfind('"').unwrap() + 1;
+
+        assert_eq!(index.lsp_range(Span { start, end }), LspRange {
+            start: LspPosition { line: 1, character: 10 },
+            end: LspPosition { line: 1, character: 15 },
+        });
+    }
+}
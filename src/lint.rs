@@ -0,0 +1,907 @@
+//! A framework for checks that run over a parsed chunk and report problems.
+//!
+//! Each check is a [Rule]. Rules are collected in a [Registry] and run by
+//! [lint], which computes scope information once and shares it between every
+//! rule that's enabled in the given [LintConfig].
+//!
+//! A diagnostic can be silenced by a comment on the line before it that names
+//! the rule, like `-- lua-parser: disable-next-line unused-local`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use analysis::collect;
+use ast::*;
+use directive::{parse_directive, DirectiveOptions};
+use scope::{self, BindingKind, ScopeInfo, StatementId};
+use tokenizer::{Comment, SourcePosition, StringLiteral, Token, TokenPrefix, START_POSITION};
+use visit::{for_each_statement, visit_own_expressions};
+
+/// Starts a comment that controls the linter.
+const COMMENT_PREFIX: &str = "lua-parser:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem reported by a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The name of the rule that reported this diagnostic, like `unused-local`.
+    pub rule: String,
+
+    /// The stable code of the rule that reported this diagnostic.
+    pub code: String,
+
+    pub severity: Severity,
+    pub message: String,
+
+    /// Where the problem starts. Problems that belong to the whole chunk span
+    /// all of it.
+    pub start: SourcePosition,
+
+    /// Where the problem ends.
+    pub end: SourcePosition,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {} ({})", self.severity, self.code, self.message, self.rule)
+    }
+}
+
+/// A configuration value for a single rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    Bool(bool),
+    Number(f64),
+    String(String),
+    List(Vec<String>),
+}
+
+/// The configuration for a single rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleConfig {
+    pub enabled: bool,
+
+    /// Overrides the rule's default severity.
+    pub severity: Option<Severity>,
+
+    /// Rule-specific settings, keyed by name.
+    pub values: HashMap<String, ConfigValue>,
+}
+
+impl Default for RuleConfig {
+    fn default() -> RuleConfig {
+        RuleConfig {
+            enabled: true,
+            severity: None,
+            values: HashMap::new(),
+        }
+    }
+}
+
+impl RuleConfig {
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.values.get(key)
+    }
+
+    /// Returns the list stored under `key`, or an empty list if there isn't one.
+    pub fn list(&self, key: &str) -> &[String] {
+        match self.values.get(key) {
+            Some(ConfigValue::List(values)) => values,
+            _ => &[],
+        }
+    }
+}
+
+/// The configuration for a lint run, keyed by rule name.
+///
+/// Rules without an entry run with their defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+    pub rules: HashMap<String, RuleConfig>,
+}
+
+impl LintConfig {
+    pub fn rule(&self, name: &str) -> Option<&RuleConfig> {
+        self.rules.get(name)
+    }
+
+    pub fn rule_mut(&mut self, name: &str) -> &mut RuleConfig {
+        self.rules.entry(name.to_string()).or_default()
+    }
+}
+
+/// Everything a rule gets to look at.
+pub struct LintContext<'c, 'a: 'c> {
+    pub chunk: &'c Chunk<'a>,
+    pub scopes: &'c ScopeInfo,
+    pub config: &'c RuleConfig,
+}
+
+/// Collects the diagnostics reported by a single rule.
+pub struct DiagnosticSink<'s> {
+    rule: String,
+    code: String,
+    severity: Severity,
+
+    /// Where each statement starts and ends, indexed by [StatementId].
+    spans: &'s [(SourcePosition, SourcePosition)],
+    chunk_span: (SourcePosition, SourcePosition),
+    diagnostics: &'s mut Vec<Diagnostic>,
+}
+
+impl<'s> DiagnosticSink<'s> {
+    /// Reports a problem with the whole chunk at the rule's configured
+    /// severity.
+    pub fn report<S: Into<String>>(&mut self, message: S) {
+        let severity = self.severity;
+        self.report_with_severity(severity, None, message);
+    }
+
+    /// Reports a problem with one statement at the rule's configured severity.
+    pub fn report_at<S: Into<String>>(&mut self, statement: StatementId, message: S) {
+        let severity = self.severity;
+        self.report_with_severity(severity, Some(statement), message);
+    }
+
+    /// Reports a problem with one statement, or with the whole chunk if
+    /// `statement` is `None`.
+    pub fn report_with_severity<S: Into<String>>(&mut self, severity: Severity, statement: Option<StatementId>, message: S) {
+        let (start, end) = match statement {
+            Some(statement) => self.spans[statement],
+            None => self.chunk_span,
+        };
+
+        self.diagnostics.push(Diagnostic {
+            rule: self.rule.clone(),
+            code: self.code.clone(),
+            severity,
+            message: message.into(),
+            start,
+            end,
+        });
+    }
+}
+
+/// A single check that can be run over a chunk.
+pub trait Rule {
+    /// The name used to configure the rule, like `unused-local`.
+    fn name(&self) -> &str;
+
+    /// A short, stable code identifying the rule, like `L002`.
+    fn code(&self) -> &str;
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check(&self, context: &LintContext, sink: &mut DiagnosticSink);
+}
+
+/// A set of rules that can be run together.
+#[derive(Default)]
+pub struct Registry {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Registry {
+    /// Creates a registry with no rules in it.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Creates a registry with every rule that ships with this crate.
+    pub fn with_builtin_rules() -> Registry {
+        let mut registry = Registry::new();
+        registry.register(Box::new(UndefinedGlobal));
+        registry.register(Box::new(UnusedLocal));
+        registry.register(Box::new(Shadowing));
+        registry.register(Box::new(DuplicateKey));
+        registry.register(Box::new(BannedApi::default()));
+        registry
+    }
+
+    pub fn register(&mut self, rule: Box<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &dyn Rule> {
+        self.rules.iter().map(|rule| rule.as_ref())
+    }
+}
+
+/// Parses `tokens` and runs every enabled rule in `registry` over them.
+///
+/// Diagnostics are returned grouped by rule, in registration order, followed
+/// by warnings about suppression comments that name rules that don't exist.
+pub fn lint(tokens: &[Token], registry: &Registry, config: &LintConfig) -> Result<Vec<Diagnostic>, String> {
+    let mut statements = collect(tokens)?.statements;
+    statements.sort_by_key(|statement| statement.tokens.start);
+
+    // Sorted by where they start, statements are in the same order that
+    // statement IDs count them in.
+    let spans: Vec<_> = statements
+        .iter()
+        .map(|statement| (tokens[statement.tokens.start].start_position, tokens[statement.tokens.end - 1].end_position))
+        .collect();
+
+    let chunk_span = (START_POSITION, tokens.last().map_or(START_POSITION, |token| token.end_position));
+
+    let chunk = Chunk {
+        statements: statements
+            .into_iter()
+            .filter(|statement| statement.block == 0)
+            .map(|statement| statement.statement)
+            .collect(),
+    };
+
+    let scopes = scope::analyze(&chunk);
+    let default_config = RuleConfig::default();
+    let mut diagnostics = Vec::new();
+
+    for rule in registry.rules() {
+        let rule_config = config.rule(rule.name()).unwrap_or(&default_config);
+
+        if !rule_config.enabled {
+            continue;
+        }
+
+        let context = LintContext {
+            chunk: &chunk,
+            scopes: &scopes,
+            config: rule_config,
+        };
+
+        let mut sink = DiagnosticSink {
+            rule: rule.name().to_string(),
+            code: rule.code().to_string(),
+            severity: rule_config.severity.unwrap_or_else(|| rule.default_severity()),
+            spans: &spans,
+            chunk_span,
+            diagnostics: &mut diagnostics,
+        };
+
+        rule.check(&context, &mut sink);
+    }
+
+    let suppressions = suppressions(tokens);
+
+    diagnostics.retain(|diagnostic| {
+        !suppressions.iter().any(|suppression| {
+            suppression.line == diagnostic.start.line && suppression.rules.contains(&diagnostic.rule)
+        })
+    });
+
+    for suppression in &suppressions {
+        for rule in &suppression.rules {
+            if registry.rules().all(|known| known.name() != rule) {
+                diagnostics.push(Diagnostic {
+                    rule: "unknown-rule".to_string(),
+                    code: "L000".to_string(),
+                    severity: Severity::Warning,
+                    message: format!("unknown rule '{}' in disable-next-line comment", rule),
+                    start: suppression.start,
+                    end: suppression.end,
+                });
+            }
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+/// A `disable-next-line` comment.
+struct Suppression {
+    rules: Vec<String>,
+
+    /// The line that the comment applies to.
+    line: usize,
+
+    start: SourcePosition,
+    end: SourcePosition,
+}
+
+/// Finds every `disable-next-line` comment in the token stream.
+fn suppressions(tokens: &[Token]) -> Vec<Suppression> {
+    let options = DirectiveOptions {
+        prefixes: vec![COMMENT_PREFIX.to_string()],
+    };
+
+    let mut found = Vec::new();
+    let mut position = START_POSITION;
+
+    for token in tokens {
+        // Prefixes aren't positioned, so walk forward from the end of the
+        // previous token to find where each one is.
+        for item in &token.prefix {
+            let end = position.next_position(&item.to_source());
+
+            if let TokenPrefix::Comment(comment) = item {
+                let content = match comment {
+                    Comment::SingleLine { content } => content,
+                    Comment::MultiLine { content, .. } => content,
+                };
+
+                let directive = parse_directive(content, &options)
+                    .filter(|directive| directive.name == "disable-next-line");
+
+                if let Some(directive) = directive {
+                    found.push(Suppression {
+                        rules: directive.arguments,
+                        line: end.line + 1,
+                        start: position,
+                        end,
+                    });
+                }
+            }
+
+            position = end;
+        }
+
+        position = token.end_position;
+    }
+
+    found
+}
+
+/// Names that every Lua environment is assumed to provide.
+pub const STANDARD_GLOBALS: &[&str] = &[
+    "_G", "_VERSION", "_ENV", "arg", "assert", "collectgarbage", "coroutine", "debug", "dofile",
+    "error", "getfenv", "getmetatable", "io", "ipairs", "load", "loadfile", "loadstring", "math",
+    "module", "next", "os", "package", "pairs", "pcall", "print", "rawequal", "rawget", "rawlen",
+    "rawset", "require", "select", "setfenv", "setmetatable", "string", "table", "tonumber",
+    "tostring", "type", "unpack", "utf8", "xpcall",
+];
+
+/// Reports reads of globals that are neither standard nor assigned anywhere in
+/// the chunk.
+///
+/// Extra allowed globals can be listed under the `globals` config value.
+pub struct UndefinedGlobal;
+
+impl Rule for UndefinedGlobal {
+    fn name(&self) -> &str {
+        "undefined-global"
+    }
+
+    fn code(&self) -> &str {
+        "L001"
+    }
+
+    fn check(&self, context: &LintContext, sink: &mut DiagnosticSink) {
+        let allowed = context.config.list("globals");
+        let mut reported: Vec<&str> = Vec::new();
+
+        for reference in &context.scopes.references {
+            if reference.binding.is_some() {
+                continue;
+            }
+
+            let name = reference.name.as_str();
+
+            let defined = STANDARD_GLOBALS.contains(&name)
+                || allowed.iter().any(|allowed| allowed == name)
+                || context.scopes.global_writes.iter().any(|written| written == name);
+
+            if !defined && !reported.contains(&name) {
+                reported.push(name);
+                sink.report_at(reference.statement, format!("undefined global '{}'", name));
+            }
+        }
+    }
+}
+
+/// Reports locals and parameters that are never read.
+///
+/// Names starting with an underscore are exempt, as is the implicit `self`.
+pub struct UnusedLocal;
+
+impl Rule for UnusedLocal {
+    fn name(&self) -> &str {
+        "unused-local"
+    }
+
+    fn code(&self) -> &str {
+        "L002"
+    }
+
+    fn check(&self, context: &LintContext, sink: &mut DiagnosticSink) {
+        for binding in &context.scopes.bindings {
+            if binding.reads > 0 || binding.implicit || binding.name.starts_with('_') {
+                continue;
+            }
+
+            let description = match binding.kind {
+                BindingKind::Local => "local variable",
+                BindingKind::LocalFunction => "local function",
+                BindingKind::Parameter => "parameter",
+                BindingKind::LoopVariable => "loop variable",
+            };
+
+            sink.report_at(binding.statement, format!("unused {} '{}'", description, binding.name));
+        }
+    }
+}
+
+/// Reports bindings that hide another binding with the same name.
+pub struct Shadowing;
+
+impl Rule for Shadowing {
+    fn name(&self) -> &str {
+        "shadowing"
+    }
+
+    fn code(&self) -> &str {
+        "L003"
+    }
+
+    fn check(&self, context: &LintContext, sink: &mut DiagnosticSink) {
+        for binding in &context.scopes.bindings {
+            if binding.shadows.is_some() && !binding.implicit && binding.name != "_" {
+                sink.report_at(binding.statement, format!("'{}' shadows an earlier definition", binding.name));
+            }
+        }
+    }
+}
+
+/// Reports table literals that assign the same key more than once.
+pub struct DuplicateKey;
+
+impl Rule for DuplicateKey {
+    fn name(&self) -> &str {
+        "duplicate-key"
+    }
+
+    fn code(&self) -> &str {
+        "L004"
+    }
+
+    fn check(&self, context: &LintContext, sink: &mut DiagnosticSink) {
+        let mut statement = 0;
+
+        for_each_statement(context.chunk, &mut |current| {
+            visit_own_expressions(current, &mut |expression| {
+                let table = match expression {
+                    Expression::Table(table) => table,
+                    _ => return,
+                };
+
+                let mut seen: Vec<String> = Vec::new();
+
+                for (key, _) in table.entries() {
+                    let key = match key {
+                        EffectiveKey::Explicit(key) => match constant_key(key) {
+                            Some(key) => key,
+                            None => continue,
+                        },
+                        EffectiveKey::Index(index) => format!("[{}]", index),
+                        EffectiveKey::Expands(_) => continue,
+                    };
+
+                    if seen.contains(&key) {
+                        sink.report_at(statement, format!("duplicate key {} in table literal", key));
+                    } else {
+                        seen.push(key);
+                    }
+                }
+            });
+
+            statement += 1;
+        });
+    }
+}
+
+/// A global that shouldn't be used, for [BannedApi].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BannedName {
+    /// The name of the global, like `loadstring`.
+    pub name: String,
+
+    /// What to use instead, which is included in the diagnostic.
+    pub replacement: Option<String>,
+
+    /// Overrides the severity configured for the rule.
+    pub severity: Option<Severity>,
+
+    /// Whether a call with `.` and a method call with `:` match each other,
+    /// so that banning `io.write` also catches `io:write(x)`. Off by default,
+    /// since the two usually aren't the same function.
+    pub either_call: bool,
+}
+
+impl BannedName {
+    pub fn new<S: Into<String>>(name: S) -> BannedName {
+        BannedName {
+            name: name.into(),
+            replacement: None,
+            severity: None,
+            either_call: false,
+        }
+    }
+
+    pub fn with_replacement<S: Into<String>>(mut self, replacement: S) -> BannedName {
+        self.replacement = Some(replacement.into());
+        self
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> BannedName {
+        self.severity = Some(severity);
+        self
+    }
+
+    pub fn with_either_call(mut self) -> BannedName {
+        self.either_call = true;
+        self
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        if self.either_call {
+            self.name.replace(':', ".") == path.replace(':', ".")
+        } else {
+            self.name == path
+        }
+    }
+}
+
+/// Reports every use of a banned or deprecated global, including uses through
+/// a local alias like `local exec = loadstring`. Locals that happen to have the
+/// same name as a banned global are not reported.
+///
+/// Names can be given when creating the rule, and more can be listed under the
+/// `names` config value. Field paths like `os.execute` match calls through that
+/// field, and method paths like `io:write` match method calls. A name only
+/// matches the other form of call if it's created with
+/// [BannedName::with_either_call].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BannedApi {
+    names: Vec<BannedName>,
+}
+
+impl BannedApi {
+    pub fn new(names: Vec<BannedName>) -> BannedApi {
+        BannedApi {
+            names,
+        }
+    }
+}
+
+impl Rule for BannedApi {
+    fn name(&self) -> &str {
+        "banned-api"
+    }
+
+    fn code(&self) -> &str {
+        "L005"
+    }
+
+    fn check(&self, context: &LintContext, sink: &mut DiagnosticSink) {
+        let configured: Vec<BannedName> = context.config
+            .list("names")
+            .iter()
+            .map(BannedName::new)
+            .collect();
+
+        // Every use as the statement it's in, the path as written, and the
+        // path with any alias replaced by the global it stands for.
+        let mut uses: Vec<(StatementId, String, String)> = Vec::new();
+
+        for reference in &context.scopes.references {
+            if let Some(global) = global_name(context.scopes, reference) {
+                uses.push((reference.statement, reference.name.clone(), global.to_string()));
+            }
+        }
+
+        for call in &context.scopes.calls {
+            let reference = &context.scopes.references[call.reference];
+
+            if let Some(global) = global_name(context.scopes, reference) {
+                uses.push((
+                    reference.statement,
+                    format!("{}{}", reference.name, call.path),
+                    format!("{}{}", global, call.path),
+                ));
+            }
+        }
+
+        uses.sort_by_key(|&(statement, _, _)| statement);
+
+        for (statement, written, global) in uses {
+            let banned = self.names
+                .iter()
+                .chain(&configured)
+                .find(|banned| banned.matches(&global));
+
+            let banned = match banned {
+                Some(banned) => banned,
+                None => continue,
+            };
+
+            let mut message = format!("'{}' should not be used", banned.name);
+
+            if global != written {
+                message.push_str(&format!(" (used through '{}')", written));
+            }
+
+            if let Some(replacement) = &banned.replacement {
+                message.push_str(&format!("; use '{}' instead", replacement));
+            }
+
+            match banned.severity {
+                Some(severity) => sink.report_with_severity(severity, Some(statement), message),
+                None => sink.report_at(statement, message),
+            }
+        }
+    }
+}
+
+/// The global that a reference reads, either directly or through an alias.
+fn global_name<'s>(scopes: &'s ScopeInfo, reference: &'s scope::Reference) -> Option<&'s str> {
+    match reference.binding {
+        Some(binding) => scopes.bindings[binding].alias_of.as_deref(),
+        None => Some(&reference.name),
+    }
+}
+
+/// Describes a table key if it's a constant that can be compared statically.
+fn constant_key(key: &TableKey) -> Option<String> {
+    match key {
+        TableKey::Name(name) => Some(format!("'{}'", name)),
+        TableKey::Expression(Expression::String(literal)) => {
+            let content = match literal {
+                StringLiteral::DoubleQuote { raw_content }
+                | StringLiteral::SingleQuote { raw_content } => {
+                    // Escapes would need decoding to compare reliably.
+                    if raw_content.contains('\\') {
+                        return None;
+                    }
+
+                    raw_content
+                },
+                StringLiteral::LongForm { raw_content, .. } => raw_content,
+            };
+
+            Some(format!("'{}'", content))
+        },
+        TableKey::Expression(Expression::Number(number)) => Some(format!("[{}]", number)),
+        TableKey::Expression(Expression::Bool(value)) => Some(format!("[{}]", value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokenizer::tokenize;
+
+    fn lint_source(source: &str, config: &LintConfig) -> Vec<String> {
+        let tokens = tokenize(source).unwrap();
+
+        lint(&tokens, &Registry::with_builtin_rules(), config)
+            .unwrap()
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn undefined_global() {
+        let diagnostics = lint_source("print(foo, foo)\nfunction bar() end\nprint(bar)", &LintConfig::default());
+
+        assert_eq!(diagnostics, vec![
+            "warning[L001]: undefined global 'foo' (undefined-global)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn undefined_global_config() {
+        let mut config = LintConfig::default();
+        config.rule_mut("undefined-global").values.insert(
+            "globals".to_string(),
+            ConfigValue::List(vec!["foo".to_string()]),
+        );
+
+        assert!(lint_source("print(foo)", &config).is_empty());
+    }
+
+    #[test]
+    fn unused_local() {
+        let diagnostics = lint_source("local a, _b = 1, 2\nlocal function f(x) end\nf()", &LintConfig::default());
+
+        assert_eq!(diagnostics, vec![
+            "warning[L002]: unused local variable 'a' (unused-local)".to_string(),
+            "warning[L002]: unused parameter 'x' (unused-local)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn shadowing() {
+        let diagnostics = lint_source("local a = 1\nfunction f(a) print(a) end\nprint(a)", &LintConfig::default());
+
+        assert_eq!(diagnostics, vec![
+            "warning[L003]: 'a' shadows an earlier definition (shadowing)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn duplicate_key() {
+        let diagnostics = lint_source("print({ a = 1, [\"a\"] = 2, [1] = 3, [1] = 4, b = { c = 1, c = 2 } }, { [2] = 1, 'x', 'y' })", &LintConfig::default());
+
+        assert_eq!(diagnostics, vec![
+            "warning[L004]: duplicate key 'a' in table literal (duplicate-key)".to_string(),
+            "warning[L004]: duplicate key [1] in table literal (duplicate-key)".to_string(),
+            "warning[L004]: duplicate key 'c' in table literal (duplicate-key)".to_string(),
+            "warning[L004]: duplicate key [2] in table literal (duplicate-key)".to_string(),
+        ]);
+    }
+
+    fn lint_banned(source: &str, names: Vec<BannedName>) -> Vec<String> {
+        let tokens = tokenize(source).unwrap();
+
+        let mut registry = Registry::new();
+        registry.register(Box::new(BannedApi::new(names)));
+
+        lint(&tokens, &registry, &LintConfig::default())
+            .unwrap()
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn banned_api_calls_and_aliases() {
+        let names = vec![
+            BannedName::new("loadstring").with_replacement("load"),
+            BannedName::new("unpack").with_severity(Severity::Error),
+        ];
+
+        let diagnostics = lint_banned("loadstring(code)\nlocal exec = loadstring\nlocal run = exec\nrun(code)\nprint(unpack)", names);
+
+        assert_eq!(diagnostics, vec![
+            "warning[L005]: 'loadstring' should not be used; use 'load' instead (banned-api)".to_string(),
+            "warning[L005]: 'loadstring' should not be used; use 'load' instead (banned-api)".to_string(),
+            "warning[L005]: 'loadstring' should not be used (used through 'exec'); use 'load' instead (banned-api)".to_string(),
+            "warning[L005]: 'loadstring' should not be used (used through 'run'); use 'load' instead (banned-api)".to_string(),
+            "error[L005]: 'unpack' should not be used (banned-api)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn banned_api_shadowed_by_local() {
+        let names = vec![BannedName::new("loadstring")];
+
+        assert!(lint_banned("local loadstring = 1\nprint(loadstring)\nfunction f(unpack) loadstring(unpack) end", names).is_empty());
+    }
+
+    #[test]
+    fn banned_api_field_paths() {
+        let names = vec![
+            BannedName::new("os.execute").with_replacement("run"),
+            BannedName::new("io.stdout.flush"),
+        ];
+
+        let diagnostics = lint_banned("os.execute(a)\nlocal x = os.execute(b)\nio.stdout.flush()\nos.exit()\nlocal sys = os\nsys.execute(c)", names);
+
+        assert_eq!(diagnostics, vec![
+            "warning[L005]: 'os.execute' should not be used; use 'run' instead (banned-api)".to_string(),
+            "warning[L005]: 'os.execute' should not be used; use 'run' instead (banned-api)".to_string(),
+            "warning[L005]: 'io.stdout.flush' should not be used (banned-api)".to_string(),
+            "warning[L005]: 'os.execute' should not be used (used through 'sys.execute'); use 'run' instead (banned-api)".to_string(),
+        ]);
+
+        assert!(lint_banned("local os = {}\nos.execute(a)", vec![BannedName::new("os.execute")]).is_empty());
+    }
+
+    #[test]
+    fn banned_api_method_calls() {
+        let names = vec![BannedName::new("io:write"), BannedName::new("file.close")];
+
+        let diagnostics = lint_banned("io:write(a)\nio.write(b)\nfile:close()\nfile.close(f)\nio:read()", names);
+
+        assert_eq!(diagnostics, vec![
+            "warning[L005]: 'io:write' should not be used (banned-api)".to_string(),
+            "warning[L005]: 'file.close' should not be used (banned-api)".to_string(),
+        ]);
+
+        // Either form of call can be matched on purpose.
+        let names = vec![BannedName::new("io:write").with_either_call()];
+
+        assert_eq!(lint_banned("io:write(a)\nio.write(b)\nio:read()", names), vec![
+            "warning[L005]: 'io:write' should not be used (banned-api)".to_string(),
+            "warning[L005]: 'io:write' should not be used (banned-api)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn banned_api_from_config() {
+        let mut config = LintConfig::default();
+        config.rule_mut("banned-api").values.insert(
+            "names".to_string(),
+            ConfigValue::List(vec!["setfenv".to_string()]),
+        );
+
+        assert_eq!(lint_source("setfenv(1, {})", &config), vec![
+            "warning[L005]: 'setfenv' should not be used (banned-api)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn disabled_rules_and_severity_overrides() {
+        let mut config = LintConfig::default();
+        config.rule_mut("unused-local").enabled = false;
+        config.rule_mut("undefined-global").severity = Some(Severity::Error);
+
+        let diagnostics = lint_source("local a = foo", &config);
+
+        assert_eq!(diagnostics, vec![
+            "error[L001]: undefined global 'foo' (undefined-global)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn diagnostic_spans() {
+        let tokens = tokenize("local a = 1\nif a then\n\tlocal b = { x = 1, x = 2 }\nend\nprint(c)").unwrap();
+        let diagnostics = lint(&tokens, &Registry::with_builtin_rules(), &LintConfig::default()).unwrap();
+
+        let spans: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code.as_str(), diagnostic.start.line, diagnostic.start.column, diagnostic.end.column))
+            .collect();
+
+        assert_eq!(spans, vec![
+            ("L001", 5, 1, 9),
+            ("L002", 3, 2, 28),
+            ("L004", 3, 2, 28),
+        ]);
+    }
+
+    #[test]
+    fn suppressed_rule() {
+        let source = "-- lua-parser: disable-next-line unused-local\nlocal a = 1\nlocal b = 1 --[[ lua-parser: disable-next-line undefined-global, shadowing ]]\nlocal b = foo";
+
+        assert_eq!(lint_source(source, &LintConfig::default()), vec![
+            "warning[L002]: unused local variable 'b' (unused-local)".to_string(),
+            "warning[L002]: unused local variable 'b' (unused-local)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn suppression_only_covers_the_next_line_and_named_rules() {
+        let source = "-- lua-parser: disable-next-line shadowing\nlocal a = 1\n\n-- lua-parser: disable-next-line unused-local\n\nlocal b = 2";
+
+        assert_eq!(lint_source(source, &LintConfig::default()), vec![
+            "warning[L002]: unused local variable 'a' (unused-local)".to_string(),
+            "warning[L002]: unused local variable 'b' (unused-local)".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn suppression_with_unknown_rule() {
+        let tokens = tokenize("local _a = 1\n  -- lua-parser: disable-next-line unused-locals\nlocal b = 2").unwrap();
+        let diagnostics = lint(&tokens, &Registry::with_builtin_rules(), &LintConfig::default()).unwrap();
+
+        let messages: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+
+        assert_eq!(messages, vec![
+            "warning[L002]: unused local variable 'b' (unused-local)".to_string(),
+            "warning[L000]: unknown rule 'unused-locals' in disable-next-line comment (unknown-rule)".to_string(),
+        ]);
+
+        let unknown = &diagnostics[1];
+        assert_eq!((unknown.start.line, unknown.start.column), (2, 3));
+        assert_eq!((unknown.end.line, unknown.end.column), (2, 49));
+    }
+}
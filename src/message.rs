@@ -0,0 +1,249 @@
+//! Parse errors as structured data, so they can be shown in languages other
+//! than English.
+//!
+//! Every error the parser reports is a [ParseMessage]: a stable
+//! [MessageCode] plus the arguments needed to describe it. Text is only
+//! produced when a message is rendered through a [MessageCatalog].
+//! [EnglishCatalog] is the one used by the entrypoints that return errors as
+//! strings.
+
+use std::error;
+use std::fmt;
+
+use tokenizer::SourcePosition;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum MessageCode {
+    /// The parser stopped without saying why.
+    Unknown,
+
+    /// A token showed up where it can't be, like a stray `end`.
+    UnexpectedToken,
+
+    /// Something other than an expression was found where only an expression
+    /// can go.
+    ExpectedExpression,
+
+    /// Blocks or expressions are nested too deeply to parse without
+    /// overflowing the stack.
+    NestedTooDeeply,
+
+    /// The AST would take up more than the memory budget.
+    OverMemoryBudget,
+
+    /// A repeating construct matched without consuming any tokens. This is a
+    /// bug in the parser rather than in the source.
+    NoProgress,
+
+    /// An expression stands alone where a statement should be, like `x + 1`
+    /// on a line by itself.
+    UnexpectedExpression,
+
+    /// A `return` is followed by more of its block, like `return 1 x = 2`.
+    /// It has to be the last statement.
+    StatementAfterReturn,
+
+    /// A function call is on the left of an `=`, like `f() = 1`. Only names,
+    /// fields and indexes can be assigned to.
+    AssignmentToCall,
+}
+
+impl MessageCode {
+    pub const ALL: [MessageCode; 9] = [
+        MessageCode::Unknown,
+        MessageCode::UnexpectedToken,
+        MessageCode::ExpectedExpression,
+        MessageCode::NestedTooDeeply,
+        MessageCode::OverMemoryBudget,
+        MessageCode::NoProgress,
+        MessageCode::UnexpectedExpression,
+        MessageCode::StatementAfterReturn,
+        MessageCode::AssignmentToCall,
+    ];
+
+    /// A short code that never changes between releases, like `P001`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MessageCode::Unknown => "P000",
+            MessageCode::UnexpectedToken => "P001",
+            MessageCode::ExpectedExpression => "P002",
+            MessageCode::NestedTooDeeply => "P003",
+            MessageCode::OverMemoryBudget => "P004",
+            MessageCode::NoProgress => "P005",
+            MessageCode::UnexpectedExpression => "P006",
+            MessageCode::StatementAfterReturn => "P007",
+            MessageCode::AssignmentToCall => "P008",
+        }
+    }
+}
+
+impl fmt::Display for MessageCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// A parse error, without any text.
+///
+/// Which arguments are set depends on the code; the rest are left empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseMessage {
+    pub code: MessageCode,
+
+    /// Descriptions of what could have been there instead, like
+    /// `end of file`.
+    pub expected: Vec<String>,
+
+    /// A description of the token that was found instead, like `'end'` or
+    /// `identifier 'foo'`.
+    pub found: Option<String>,
+
+    /// The name of the construct being parsed, like `expression`.
+    pub construct: Option<String>,
+
+    /// The limit that was hit, for [MessageCode::NestedTooDeeply] and
+    /// [MessageCode::OverMemoryBudget]. Zero for other codes.
+    pub limit: usize,
+
+    /// Where the problem is, when it's tied to a token.
+    pub position: Option<SourcePosition>,
+
+    /// Where the problem ends, when it covers more than one token, like the
+    /// whole expression for [MessageCode::UnexpectedExpression].
+    pub end: Option<SourcePosition>,
+}
+
+impl ParseMessage {
+    pub fn new(code: MessageCode) -> ParseMessage {
+        ParseMessage {
+            code,
+            expected: Vec::new(),
+            found: None,
+            construct: None,
+            limit: 0,
+            position: None,
+            end: None,
+        }
+    }
+
+    /// Turns the message into text using `catalog`.
+    pub fn render(&self, catalog: &dyn MessageCatalog) -> String {
+        catalog.render(self)
+    }
+}
+
+/// Renders in English.
+impl fmt::Display for ParseMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(&EnglishCatalog))
+    }
+}
+
+impl error::Error for ParseMessage {}
+
+impl From<ParseMessage> for String {
+    fn from(message: ParseMessage) -> String {
+        message.to_string()
+    }
+}
+
+impl From<Box<ParseMessage>> for String {
+    fn from(message: Box<ParseMessage>) -> String {
+        message.to_string()
+    }
+}
+
+/// Turns [ParseMessage]s into text for people to read.
+pub trait MessageCatalog {
+    fn render(&self, message: &ParseMessage) -> String;
+}
+
+/// The catalog used when errors are returned as strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EnglishCatalog;
+
+impl MessageCatalog for EnglishCatalog {
+    fn render(&self, message: &ParseMessage) -> String {
+        let found = message.found.as_deref().unwrap_or("nothing");
+
+        let mut text = match message.code {
+            MessageCode::Unknown => "No error reported".to_string(),
+            MessageCode::UnexpectedToken => format!("Expected {}, but found {}", message.expected.join(" or "), found),
+            MessageCode::ExpectedExpression => format!("Expected an expression, but found {}", found),
+            MessageCode::NestedTooDeeply => format!("Code is nested more than {} levels deep", message.limit),
+            MessageCode::OverMemoryBudget => format!("The AST needs more than the memory budget of {} bytes", message.limit),
+            MessageCode::NoProgress => {
+                let construct = message.construct.as_deref().unwrap_or("An item");
+                format!("{} matched without consuming any tokens", construct)
+            },
+            MessageCode::UnexpectedExpression => "unexpected expression: only function calls and assignments can stand alone as statements".to_string(),
+            MessageCode::StatementAfterReturn => format!("Expected the end of the block after 'return', but found {}", found),
+            MessageCode::AssignmentToCall => "Cannot assign to the result of a function call".to_string(),
+        };
+
+        if let Some(position) = message.position {
+            text.push_str(&format!(" at line {}, column {}", position.line, position.column));
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_structured;
+    use tokenizer::tokenize;
+
+    /// A catalog that only uses the structured arguments, the way a
+    /// translation would.
+    struct ShoutingCatalog;
+
+    impl MessageCatalog for ShoutingCatalog {
+        fn render(&self, message: &ParseMessage) -> String {
+            format!(
+                "{}! WANTED {} GOT {}",
+                message.code,
+                message.expected.join("|").to_uppercase(),
+                message.found.as_deref().unwrap_or("-").to_uppercase(),
+            )
+        }
+    }
+
+    #[test]
+    fn unexpected_token() {
+        let tokens = tokenize("local a = 1 end").unwrap();
+        let message = parse_structured(&tokens).unwrap_err();
+
+        assert_eq!(message.code, MessageCode::UnexpectedToken);
+        assert_eq!(message.expected, vec!["end of file"]);
+        assert_eq!(message.found.as_deref(), Some("'end'"));
+        assert_eq!(message.position.map(|position| position.line), Some(1));
+
+        assert_eq!(message.render(&EnglishCatalog), "Expected end of file, but found 'end' at line 1, column 13");
+        assert_eq!(message.to_string(), message.render(&EnglishCatalog));
+        assert_eq!(message.render(&ShoutingCatalog), "P001! WANTED END OF FILE GOT 'END'");
+
+        let boxed: Box<dyn error::Error> = message;
+        assert_eq!(boxed.to_string(), "Expected end of file, but found 'end' at line 1, column 13");
+    }
+
+    #[test]
+    fn limits() {
+        let mut message = ParseMessage::new(MessageCode::NestedTooDeeply);
+        message.limit = 200;
+
+        assert_eq!(message.render(&EnglishCatalog), "Code is nested more than 200 levels deep");
+        assert_eq!(message.render(&ShoutingCatalog), "P003! WANTED  GOT -");
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let mut codes: Vec<_> = MessageCode::ALL.iter().map(MessageCode::code).collect();
+        codes.sort();
+        codes.dedup();
+
+        assert_eq!(codes.len(), MessageCode::ALL.len());
+    }
+}
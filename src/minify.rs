@@ -0,0 +1,382 @@
+//! Transforms that make a chunk smaller without changing what it does.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use ast::*;
+use scope::analyze;
+
+/// Controls which names [shorten_locals] leaves alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShortenOptions {
+    /// Locals with these names keep them. `self` is always kept.
+    pub keep: Vec<String>,
+}
+
+/// Renames local variables, local functions, loop variables and parameters to
+/// the shortest names that don't change what any name refers to.
+///
+/// Globals, table keys, method names and the contents of strings are never
+/// touched. Each binding gets the first name, in the order `a` through `z`,
+/// then `a1` through `z1`, and so on, that isn't a global, a kept name, or the
+/// name of another binding that's visible where it's declared. Bindings in
+/// sibling scopes can end up with the same name.
+pub fn shorten_locals<'a>(chunk: &mut Chunk<'a>, options: &ShortenOptions) {
+    let info = analyze(chunk);
+
+    let mut reserved: HashSet<String> = info.global_reads
+        .into_iter()
+        .chain(info.global_writes)
+        .chain(options.keep.iter().cloned())
+        .collect();
+    reserved.insert("self".to_string());
+
+    let mut renamer = Renamer {
+        options,
+        reserved,
+        scopes: vec![Vec::new()],
+    };
+
+    renamer.statements(chunk);
+}
+
+/// The `index`th short name.
+fn short_name(index: usize) -> String {
+    let letter = (b'a' + (index % 26) as u8) as char;
+
+    match index / 26 {
+        0 => letter.to_string(),
+        suffix => format!("{}{}", letter, suffix),
+    }
+}
+
+/// Walks a chunk in the same order as [analyze], renaming declarations and
+/// every name that resolves to them.
+struct Renamer<'o> {
+    options: &'o ShortenOptions,
+    reserved: HashSet<String>,
+
+    /// The bindings visible in each open scope, as (original name, new name),
+    /// in declaration order.
+    scopes: Vec<Vec<(String, String)>>,
+}
+
+impl<'o> Renamer<'o> {
+    fn declare<'a>(&mut self, name: &mut Cow<'a, str>) {
+        let new_name = if name == "self" || self.options.keep.iter().any(|kept| kept == name) {
+            name.to_string()
+        } else {
+            let visible: HashSet<&str> = self.scopes
+                .iter()
+                .flatten()
+                .map(|(_, new_name)| new_name.as_str())
+                .collect();
+
+            (0..)
+                .map(short_name)
+                .find(|candidate| !self.reserved.contains(candidate) && !visible.contains(candidate.as_str()))
+                .expect("Ran out of short names")
+        };
+
+        let original = name.to_string();
+        *name = Cow::Owned(new_name.clone());
+
+        self.scopes
+            .last_mut()
+            .expect("Declared a name with no open scope")
+            .push((original, new_name));
+    }
+
+    /// Renames a read of or assignment to `name`, if it refers to a binding.
+    fn refer<'a>(&self, name: &mut Cow<'a, str>) {
+        let found = self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(original, _)| original == name);
+
+        if let Some((_, new_name)) = found {
+            *name = Cow::Owned(new_name.clone());
+        }
+    }
+
+    fn assignment_target<'a>(&mut self, target: &mut AssignmentTarget<'a>) {
+        match target {
+            AssignmentTarget::Name(name) => self.refer(name),
+            AssignmentTarget::FieldAccess { base, .. } => self.expression(base),
+            AssignmentTarget::IndexAccess { base, index } => {
+                self.expression(base);
+                self.expression(index);
+            },
+        }
+    }
+
+    fn block<'a>(&mut self, chunk: &mut Chunk<'a>) {
+        self.scopes.push(Vec::new());
+        self.statements(chunk);
+        self.scopes.pop();
+    }
+
+    fn statements<'a>(&mut self, chunk: &mut Chunk<'a>) {
+        for statement in &mut chunk.statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement<'a>(&mut self, statement: &mut Statement<'a>) {
+        match statement {
+            Statement::Assignment(assignment) => {
+                self.expressions(&mut assignment.values);
+
+                for target in &mut assignment.targets {
+                    self.assignment_target(target);
+                }
+            },
+            Statement::LocalAssignment(assignment) => {
+                self.expressions(&mut assignment.values);
+
+                for name in &mut assignment.names {
+                    self.declare(name);
+                }
+            },
+            Statement::FunctionCall(call) => self.function_call(call),
+            Statement::NumericFor(numeric_for) => {
+                self.expression(&mut numeric_for.start);
+                self.expression(&mut numeric_for.end);
+
+                if let Some(step) = &mut numeric_for.step {
+                    self.expression(step);
+                }
+
+                self.scopes.push(Vec::new());
+                self.declare(&mut numeric_for.var);
+                self.statements(&mut numeric_for.body);
+                self.scopes.pop();
+            },
+            Statement::GenericFor(generic_for) => {
+                self.expressions(&mut generic_for.item_source);
+
+                self.scopes.push(Vec::new());
+
+                for var in &mut generic_for.vars {
+                    self.declare(var);
+                }
+
+                self.statements(&mut generic_for.body);
+                self.scopes.pop();
+            },
+            Statement::IfStatement(if_statement) => {
+                self.expression(&mut if_statement.condition);
+                self.block(&mut if_statement.body);
+
+                for (condition, body) in &mut if_statement.else_if_branches {
+                    self.expression(condition);
+                    self.block(body);
+                }
+
+                if let Some(body) = &mut if_statement.else_branch {
+                    self.block(body);
+                }
+            },
+            Statement::WhileLoop(while_loop) => {
+                self.expression(&mut while_loop.condition);
+                self.block(&mut while_loop.body);
+            },
+            Statement::RepeatLoop(repeat_loop) => {
+                self.scopes.push(Vec::new());
+                self.statements(&mut repeat_loop.body);
+                self.expression(&mut repeat_loop.condition);
+                self.scopes.pop();
+            },
+            Statement::FunctionDeclaration(declaration) => self.function_declaration(declaration),
+            Statement::Do(body) => self.block(body),
+            Statement::Return(return_statement) => self.expressions(&mut return_statement.values),
+            Statement::Break => {},
+        }
+    }
+
+    fn function_declaration<'a>(&mut self, declaration: &mut FunctionDeclaration<'a>) {
+        let name = &mut declaration.name;
+
+        if let Some(first) = name.segments.first_mut() {
+            if declaration.local {
+                self.declare(first);
+            } else {
+                self.refer(first);
+            }
+        }
+
+        // The implicit `self` of methods never needs to be renamed, since
+        // `self` is always kept and reserved.
+        self.scopes.push(Vec::new());
+
+        for parameter in &mut declaration.parameters {
+            self.declare(parameter);
+        }
+
+        self.statements(&mut declaration.body);
+        self.scopes.pop();
+    }
+
+    fn function_call<'a>(&mut self, call: &mut FunctionCall<'a>) {
+        self.expression(&mut call.name_expression);
+        self.expressions(&mut call.arguments);
+    }
+
+    fn expressions<'a>(&mut self, expressions: &mut [Expression<'a>]) {
+        for expression in expressions {
+            self.expression(expression);
+        }
+    }
+
+    fn expression<'a>(&mut self, expression: &mut Expression<'a>) {
+        match expression {
+            Expression::Nil
+            | Expression::Bool(_)
+            | Expression::Number(_)
+            | Expression::String(_)
+            | Expression::VarArg => {},
+            Expression::Table(table) => {
+                for (key, value) in &mut table.items {
+                    if let Some(TableKey::Expression(key)) = key {
+                        self.expression(key);
+                    }
+
+                    self.expression(value);
+                }
+            },
+            Expression::FunctionCall(call) => self.function_call(call),
+            Expression::Name(name) => self.refer(name),
+            Expression::Var(target) => self.assignment_target(target),
+            Expression::ParenExpression(inner) => self.expression(inner),
+            Expression::UnaryOp(op) => self.expression(&mut op.argument),
+            Expression::BinaryOp(op) => {
+                self.expression(&mut op.left);
+                self.expression(&mut op.right);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use emitter::emit_chunk;
+    use parser::parse_from_tokens;
+    use scope::ScopeInfo;
+    use tokenizer::tokenize;
+
+    const PROGRAM: &str = "local verbose_output = true
+local function format_message(message, severity)
+	local formatted = \"log: \" .. message
+	if severity then
+		local severity_label = \"[\" .. severity .. \"] \"
+		print(severity_label .. formatted)
+	end
+	print(formatted)
+end
+function logger.write(message, destination)
+	for index, line in ipairs(split(message)) do
+		local message = format_message(line, index)
+		destination(message, {verbose_output = verbose_output})
+	end
+	local counter = 0
+	while counter do
+		local counter = counter + 1
+		print(counter)
+	end
+end
+function logger:flush(buffer)
+	flush_buffer(self, buffer)
+	repeat
+		local remaining_items = #buffer
+	until remaining_items
+end
+";
+
+    fn emit(chunk: &Chunk) -> String {
+        let mut output = String::new();
+        emit_chunk(&mut output, chunk).unwrap();
+        output
+    }
+
+    fn shorten(source: &str, options: &ShortenOptions) -> String {
+        let tokens = tokenize(source).unwrap();
+        let mut chunk = parse_from_tokens(&tokens).unwrap();
+        shorten_locals(&mut chunk, options);
+        emit(&chunk)
+    }
+
+    /// Strips names out of the scope information, leaving which reference
+    /// resolves to which binding.
+    fn structure(info: &ScopeInfo) -> impl PartialEq + ::std::fmt::Debug {
+        let bindings: Vec<_> = info.bindings
+            .iter()
+            .map(|binding| (binding.kind, binding.scope, binding.implicit, binding.reads, binding.writes))
+            .collect();
+        let references: Vec<_> = info.references.iter().map(|reference| reference.binding).collect();
+
+        (info.scopes.clone(), bindings, references, info.global_reads.clone(), info.global_writes.clone())
+    }
+
+    #[test]
+    fn scope_structure_is_preserved() {
+        let tokens = tokenize(PROGRAM).unwrap();
+        let before = parse_from_tokens(&tokens).unwrap();
+
+        let shortened = shorten(PROGRAM, &ShortenOptions::default());
+        let tokens = tokenize(&shortened).unwrap();
+        let after = parse_from_tokens(&tokens).unwrap();
+
+        assert_eq!(structure(&analyze(&after)), structure(&analyze(&before)));
+        assert!(shortened.len() < PROGRAM.len() * 3 / 4, "only shrank to {} bytes:\n{}", shortened.len(), shortened);
+    }
+
+    #[test]
+    fn names_are_reused_safely() {
+        let source = "local first = 1
+if first then
+	local second = first
+	print(second)
+end
+while first do
+	local third = first
+	local first = third
+	print(first, a, \"first\", {first = first})
+end
+";
+
+        assert_eq!(shorten(source, &ShortenOptions::default()), "local b = 1
+if b then
+	local c = b
+	print(c)
+end
+while b do
+	local c = b
+	local d = c
+	print(d, a, \"first\", {first = d})
+end
+");
+    }
+
+    #[test]
+    fn kept_names() {
+        let options = ShortenOptions {
+            keep: vec!["exported".to_string()],
+        };
+
+        assert_eq!(
+            shorten("local exported, hidden = 1, 2
+function t:m(p)
+	print(self, p, hidden)
+end
+", &options),
+            "local exported, a = 1, 2
+function t:m(b)
+	print(self, b, a)
+end
+",
+        );
+    }
+}
@@ -1,30 +1,374 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::mem;
+use std::ops::ControlFlow;
+use std::time::Instant;
 
-use tokenizer::{Token, TokenKind, Symbol, StringLiteral};
+use message::{MessageCode, ParseMessage};
+use tokenizer::{tokenize_from_into, SourcePosition, Token, TokenKind, TokenizerOptions, Symbol, StringLiteral, START_POSITION};
 use ast::*;
+use cst::NodeKind;
 use parser_core::*;
 
+pub use parser_core::ParseMetrics;
+
 pub fn parse_from_tokens<'a>(tokens: &'a [Token<'a>]) -> Result<Chunk<'a>, String> {
-    let state = ParseState::new(tokens);
+    parse_structured(tokens).map_err(String::from)
+}
+
+/// Parses like [parse_from_tokens], but returns errors as a [ParseMessage]
+/// that can be rendered in any language.
+pub fn parse_structured<'a>(tokens: &'a [Token<'a>]) -> Result<Chunk<'a>, Box<ParseMessage>> {
+    parse_chunk(ParseState::new(tokens))
+}
+
+/// Parses like [parse_from_tokens], and also reports counters describing how
+/// much work the parser did.
+///
+/// Collecting metrics reads the system clock, which isn't available on
+/// `wasm32-unknown-unknown`.
+pub fn parse_with_metrics<'a>(tokens: &'a [Token<'a>]) -> (Result<Chunk<'a>, String>, ParseMetrics) {
+    let start = Instant::now();
+    let (result, metrics) = parse_counting(tokens);
+    let elapsed = start.elapsed();
+
+    (result, ParseMetrics {
+        elapsed,
+        ..metrics
+    })
+}
+
+/// Why a parse with a memory budget failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetedParseError {
+    /// The tokens aren't valid.
+    Parse(String),
+
+    /// The AST would have taken up more than `budget` bytes. Parsing stopped
+    /// once `used` bytes had been counted.
+    OverBudget {
+        budget: usize,
+        used: usize,
+    },
+}
+
+/// Parses like [parse_with_metrics], but gives up as soon as the AST would
+/// take up more than `budget` bytes, as counted by
+/// [ast_bytes](struct.ParseMetrics.html#structfield.ast_bytes).
+pub fn parse_with_memory_budget<'a>(tokens: &'a [Token<'a>], budget: usize) -> (Result<Chunk<'a>, BudgetedParseError>, ParseMetrics) {
+    let start = Instant::now();
+    let metrics = RefCell::new(ParseMetrics::default());
+    let memory_budget = MemoryBudget::new(budget);
+
+    let state = ParseState {
+        memory_budget: Some(&memory_budget),
+        ..ParseState::new(tokens).with_metrics(&metrics)
+    };
 
+    let result = parse_chunk(state).map_err(|message| match message.code {
+        MessageCode::OverMemoryBudget => BudgetedParseError::OverBudget {
+            budget,
+            used: memory_budget.used(),
+        },
+        _ => BudgetedParseError::Parse(String::from(message)),
+    });
+
+    (result, ParseMetrics {
+        elapsed: start.elapsed(),
+        ..metrics.into_inner()
+    })
+}
+
+/// Whether a token stream is a whole chunk, as an interactive prompt would
+/// need to know before running it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Completeness {
+    /// The tokens parse as a chunk.
+    Complete,
+
+    /// The tokens don't parse, but could once more input is added, like
+    /// `while x do` or `print(`.
+    Incomplete,
+
+    /// The tokens don't parse, and no amount of additional input would fix
+    /// that.
+    Invalid(String),
+}
+
+/// Decides whether `tokens` is a complete chunk.
+///
+/// A parse that failed is incomplete if the parser ran out of tokens while
+/// trying to match something.
+pub fn completeness<'a>(tokens: &'a [Token<'a>]) -> Completeness {
+    let (result, metrics) = parse_counting(tokens);
+
+    let end = tokens.iter()
+        .position(|token| token.kind == TokenKind::EndOfFile)
+        .unwrap_or(tokens.len());
+
+    match result {
+        Ok(_) => Completeness::Complete,
+        Err(_) if metrics.furthest_token >= end => Completeness::Incomplete,
+        Err(message) => Completeness::Invalid(message),
+    }
+}
+
+fn parse_counting<'a>(tokens: &'a [Token<'a>]) -> (Result<Chunk<'a>, String>, ParseMetrics) {
+    let metrics = RefCell::new(ParseMetrics::default());
+    let result = parse_chunk(ParseState::new(tokens).with_metrics(&metrics));
+
+    (result.map_err(String::from), metrics.into_inner())
+}
+
+pub(crate) fn parse_chunk<'a>(state: ParseState<'a, '_>) -> Result<Chunk<'a>, Box<ParseMessage>> {
     let (state, chunk) = match ParseChunk.parse(state) {
         Ok(result) => result,
-        Err(ParseAbort::NoMatch) => return Err("No error reported".to_string()),
+        Err(ParseAbort::NoMatch) => return Err(Box::new(ParseMessage::new(MessageCode::Unknown))),
         Err(ParseAbort::Error(message)) => return Err(message),
     };
 
-    match state.peek() {
-        Some(Token { kind: TokenKind::EndOfFile, .. }) => {},
-        Some(token) => return Err(format!("A token was left at the end of the stream: {:?}", token)),
-        None => {},
-    }
+    expect_end_of_stream(state.tokens, state.position)?;
 
     Ok(chunk)
 }
 
+/// How far [parse_until] got through a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumePoint {
+    /// The index of the first token that hasn't been parsed.
+    pub token: usize,
+
+    /// The end of the last statement that was parsed, or the start of the
+    /// source if none were.
+    pub position: SourcePosition,
+
+    /// Whether every statement in the chunk has been parsed.
+    pub finished: bool,
+}
+
+impl ResumePoint {
+    /// The start of a chunk.
+    pub fn start() -> ResumePoint {
+        ResumePoint {
+            token: 0,
+            position: SourcePosition {
+                bytes: 0,
+                line: 1,
+                column: 1,
+            },
+            finished: false,
+        }
+    }
+}
+
+/// Parses the top-level statements of a chunk one at a time, handing each to
+/// `callback` until it returns [ControlFlow::Break].
+///
+/// Statements after the one that stopped parsing aren't looked at, so errors
+/// in them aren't reported. Returns the statements that were parsed, including
+/// the one that stopped parsing, and a point that [parse_until_from] can pick
+/// up from.
+pub fn parse_until<'a, F>(tokens: &'a [Token<'a>], callback: F) -> Result<(Vec<Statement<'a>>, ResumePoint), String>
+where
+    F: FnMut(&Statement<'a>) -> ControlFlow<()>,
+{
+    parse_until_from(tokens, ResumePoint::start(), callback)
+}
+
+/// Continues a [parse_until] from where it stopped.
+pub fn parse_until_from<'a, F>(tokens: &'a [Token<'a>], from: ResumePoint, mut callback: F) -> Result<(Vec<Statement<'a>>, ResumePoint), String>
+where
+    F: FnMut(&Statement<'a>) -> ControlFlow<()>,
+{
+    let mut statements = Vec::new();
+    let mut resume = from;
+
+    while !resume.finished {
+        let (next, statement) = match parse_statement_at(tokens, resume.token)? {
+            Some(parsed) => parsed,
+            None => {
+                expect_end_of_stream(tokens, resume.token)?;
+                resume.finished = true;
+                break;
+            },
+        };
+
+        resume.token = next;
+        resume.position = tokens[next - 1].end_position;
+
+        let flow = callback(&statement);
+        statements.push(statement);
+
+        if flow.is_break() {
+            break;
+        }
+    }
+
+    Ok((statements, resume))
+}
+
+/// Parses the longest run of complete top-level statements at the start of
+/// `source`, for sources that have something other than Lua after them.
+///
+/// Returns the chunk and the byte offset where it ends, which is the length
+/// of `source` when all of it is Lua. Parsing only ever stops between
+/// top-level statements. It's still an error if the first statement can't be
+/// parsed, or if nothing can be.
+pub fn parse_prefix(source: &str) -> Result<(Chunk<'static>, usize), String> {
+    // Whatever follows the chunk might not even tokenize, so only the tokens
+    // before the first error are parsed.
+    let mut tokens = Vec::new();
+    let tokenized = tokenize_from_into(source, START_POSITION, &TokenizerOptions::default(), &mut tokens);
+
+    let mut statements = Vec::new();
+    let mut position = 0;
+
+    loop {
+        match parse_statement_at(&tokens, position) {
+            Ok(Some((next, statement))) => {
+                statements.push(statement.into_owned());
+                position = next;
+            },
+            Ok(None) => break,
+            Err(_) if !statements.is_empty() => break,
+            Err(message) => return Err(message),
+        }
+    }
+
+    let finished = tokenized.is_ok() && expect_end_of_stream(&tokens, position).is_ok();
+
+    let end = if finished {
+        source.len()
+    } else if let Some(last) = position.checked_sub(1) {
+        tokens[last].end_position.bytes
+    } else {
+        // Not a single statement could be parsed, so report why.
+        return match tokenized {
+            Err(err) => Err(format!("could not tokenize source: {}", err)),
+            Ok(()) => Err(parse_from_tokens(&tokens).err().unwrap_or_default()),
+        };
+    };
+
+    Ok((Chunk {
+        statements,
+    }, end))
+}
+
+/// Parses a single statement starting at the token at index `position`.
+///
+/// On success, returns the index of the first token after the statement.
+/// Returns `Ok(None)` if no statement starts at `position`.
+pub(crate) fn parse_statement_at<'a>(tokens: &'a [Token<'a>], position: usize) -> Result<Option<(usize, Statement<'a>)>, String> {
+    let state = ParseState {
+        position,
+        ..ParseState::new(tokens)
+    };
+
+    match ParseStatement.parse(state) {
+        Ok((state, statement)) => Ok(Some((state.position, statement))),
+        Err(ParseAbort::NoMatch) => Ok(None),
+        Err(ParseAbort::Error(message)) => Err(message.to_string()),
+    }
+}
+
+/// Parses a stream of tokens that should contain exactly one expression.
+pub(crate) fn parse_expression_from_tokens<'a>(tokens: &'a [Token<'a>]) -> Result<Expression<'a>, String> {
+    let state = ParseState::new(tokens);
+
+    let (state, expression) = match ParseExpression.parse(state) {
+        Ok(result) => result,
+        Err(ParseAbort::NoMatch) => {
+            let mut message = ParseMessage::new(MessageCode::ExpectedExpression);
+            message.construct = Some("expression".to_string());
+
+            if let Some(token) = tokens.first().filter(|token| token.kind != TokenKind::EndOfFile) {
+                message.found = Some(token.to_string());
+                message.position = Some(token.start_position);
+            }
+
+            return Err(message.into());
+        },
+        Err(ParseAbort::Error(message)) => return Err(message.to_string()),
+    };
+
+    expect_end_of_stream(tokens, state.position)?;
+
+    Ok(expression)
+}
+
+/// Parses a stream of tokens that should contain exactly one
+/// comma-separated list of expressions, like the values of an assignment.
+pub(crate) fn parse_expression_list_from_tokens<'a>(tokens: &'a [Token<'a>]) -> Result<Vec<Expression<'a>>, String> {
+    let state = ParseState::new(tokens);
+
+    let (state, expressions) = match DelimitedOneOrMore(ParseExpression, ParseSymbol(Symbol::Comma)).parse(state) {
+        Ok(result) => result,
+        Err(ParseAbort::NoMatch) => return Err(parse_expression_from_tokens(tokens).err().unwrap_or_default()),
+        Err(ParseAbort::Error(message)) => return Err(message.to_string()),
+    };
+
+    expect_end_of_stream(tokens, state.position)?;
+
+    Ok(expressions)
+}
+
+/// Checks that the token at `position` is the end of the stream, as it should
+/// be once every statement has been parsed.
+pub(crate) fn expect_end_of_stream(tokens: &[Token], position: usize) -> Result<(), Box<ParseMessage>> {
+    if let Some(message) = unexpected_expression(tokens, position) {
+        return Err(Box::new(message));
+    }
+
+    match tokens.get(position) {
+        Some(Token { kind: TokenKind::EndOfFile, .. }) => Ok(()),
+        Some(token) => {
+            let mut message = ParseMessage::new(MessageCode::UnexpectedToken);
+            message.expected = vec!["end of file".to_string()];
+            message.found = Some(token.to_string());
+            message.position = Some(token.start_position);
+            Err(Box::new(message))
+        },
+        None => Ok(()),
+    }
+}
+
+/// Reports an expression at `position` that can't be a statement, like
+/// `x + 1` or `(f())`.
+///
+/// Expressions followed by something that could continue them into a call or
+/// an assignment, like `(` or `=`, aren't reported here, since the problem
+/// is somewhere in what follows.
+fn unexpected_expression(tokens: &[Token], position: usize) -> Option<ParseMessage> {
+    let state = ParseState {
+        position,
+        ..ParseState::new(tokens)
+    };
+
+    let (after, _) = ParseExpression.parse(state).ok()?;
+
+    let continues = matches!(
+        tokens.get(after.position).map(|token| &token.kind),
+        Some(TokenKind::Symbol(Symbol::Equal | Symbol::Comma | Symbol::LeftParen | Symbol::LeftBracket
+            | Symbol::LeftBrace | Symbol::Dot | Symbol::Colon))
+            | Some(TokenKind::StringLiteral(_))
+    );
+
+    if continues {
+        return None;
+    }
+
+    let mut message = ParseMessage::new(MessageCode::UnexpectedExpression);
+    message.construct = Some("expression".to_string());
+    message.found = Some(tokens[position].to_string());
+    message.position = Some(tokens[position].start_position);
+    message.end = Some(tokens[after.position - 1].end_position);
+
+    Some(message)
+}
+
 struct ParseToken<'a>(pub TokenKind<'a>);
 
-define_parser!(ParseToken<'state>, &'state Token<'state>, |this: &ParseToken<'state>, state: ParseState<'state>| {
+define_parser!(ParseToken<'state>, &'state Token<'state>, |this: &ParseToken<'state>, state: ParseState<'state, 'h>| {
     match state.peek() {
         Some(token) => {
             if token.kind == this.0 {
@@ -38,23 +382,29 @@ define_parser!(ParseToken<'state>, &'state Token<'state>, |this: &ParseToken<'st
 });
 
 struct ParseNumber;
-define_parser!(ParseNumber, Cow<'state, str>, |_, state: ParseState<'state>| {
-    match state.peek() {
-        Some(&Token { kind: TokenKind::NumberLiteral(ref value), .. }) => Ok((state.advance(1), Cow::from(value.as_ref()))),
-        _ => Err(ParseAbort::NoMatch),
+define_parser!(ParseNumber, Cow<'state, str>, |_, state: ParseState<'state, 'h>| {
+    match state.peek().and_then(Token::as_number) {
+        Some(value) => {
+            state.allocate(value.len())?;
+            Ok((state.advance(1), Cow::from(value)))
+        },
+        None => Err(ParseAbort::NoMatch),
     }
 });
 
 struct ParseIdentifier;
-define_parser!(ParseIdentifier, Cow<'state, str>, |_, state: ParseState<'state>| {
-    match state.peek() {
-        Some(&Token { kind: TokenKind::Identifier(ref name), .. }) => Ok((state.advance(1), Cow::from(name.as_ref()))),
-        _ => Err(ParseAbort::NoMatch),
+define_parser!(ParseIdentifier, Cow<'state, str>, |_, state: ParseState<'state, 'h>| {
+    match state.peek().and_then(Token::as_identifier) {
+        Some(name) => {
+            state.allocate(name.len())?;
+            Ok((state.advance(1), Cow::from(name)))
+        },
+        None => Err(ParseAbort::NoMatch),
     }
 });
 
 struct ParseSymbol(pub Symbol);
-define_parser!(ParseSymbol, Symbol, |this: &ParseSymbol, state: ParseState<'state>| {
+define_parser!(ParseSymbol, Symbol, |this: &ParseSymbol, state: ParseState<'state, 'h>| {
     let (state, token) = ParseToken(TokenKind::Symbol(this.0)).parse(state)?;
     let symbol = match token.kind {
         TokenKind::Symbol(symbol) => symbol,
@@ -67,10 +417,28 @@ define_parser!(ParseSymbol, Symbol, |this: &ParseSymbol, state: ParseState<'stat
 
 // chunk ::= {stat [`;´]} [laststat [`;´]]
 struct ParseChunk;
-define_parser!(ParseChunk, Chunk<'state>, |_, state| {
-    let (state, statements) = ZeroOrMore(ParseStatement).parse(state)?;
+define_parser!(ParseChunk, Chunk<'state>, |_, state: ParseState<'state, 'h>| {
+    state.notify(|sink| sink.start_block(state.position));
+
+    // A sink builds its own tree, so the statements aren't kept.
+    let (state, statements) = match state.sink {
+        Some(_) => {
+            let (state, _) = ZeroOrMore(Discard(ParseStatement)).parse(state.enter()?)?;
+            (state, Vec::new())
+        },
+        None => ZeroOrMore(ParseStatement).parse(state.enter()?)?,
+    };
+
+    if let Some(message) = unexpected_expression(state.tokens, state.position) {
+        return Err(ParseAbort::Error(Box::new(message)));
+    }
 
-    Ok((state, Chunk {
+    state.notify(|sink| sink.finish_block(state.position));
+
+    state.record(|metrics| metrics.statements_built += statements.len());
+    state.allocate(size_of_all(&statements))?;
+
+    Ok((state.exit(), Chunk {
         statements,
     }))
 });
@@ -85,23 +453,52 @@ define_parser!(ParseChunk, Chunk<'state>, |_, state| {
 //     for namelist in explist do chunk end |
 //     function funcname funcbody |
 //     local function Name funcbody |
-//     local namelist [`=´ explist]
-struct ParseStatement;
-define_parser!(ParseStatement, Statement<'state>, |_, state| {
+//     local namelist [`=´ explist] |
+//     break
+// laststat ::= return [explist]
+struct ParseAnyStatement;
+define_parser!(ParseAnyStatement, Statement<'state>, |_, state| {
     parse_first_of!(state, {
         ParseLocalAssignment => Statement::LocalAssignment,
-        ParseFunctionCall => Statement::FunctionCall,
+        ParseAssignmentOrCall => |statement| statement,
         ParseNumericFor => Statement::NumericFor,
         ParseGenericFor => Statement::GenericFor,
         ParseIfStatement => Statement::IfStatement,
         ParseWhileLoop => Statement::WhileLoop,
         ParseRepeatLoop => Statement::RepeatLoop,
         ParseFunctionDeclaration => Statement::FunctionDeclaration,
+        ParseDoBlock => Statement::Do,
+        ParseReturnStatement => Statement::Return,
+        // Hack: parse_first_of! cannot handle unit values
+        ParseBreak => |_| Statement::Break,
     })
 });
 
+struct ParseStatement;
+define_parser!(ParseStatement, Statement<'state>, |_, state: ParseState<'state, 'h>| {
+    if state.stopped() {
+        return Err(ParseAbort::NoMatch);
+    }
+
+    let (next, statement) = ParseAnyStatement.parse(state)?;
+    next.notify(|sink| sink.finish_statement(NodeKind::of_statement(&statement), state.position, next.position));
+
+    // stat [`;´], where the `;` is left out of the statement's own span.
+    let (next, _) = Optional(ParseSymbol(Symbol::Semicolon)).parse(next)?;
+
+    Ok((next, statement))
+});
+
+// Lua 5.1 only allows `break` as the last statement of a block, but later
+// versions allow it anywhere, so it's parsed as an ordinary statement.
+struct ParseBreak;
+define_parser!(ParseBreak, (), |_, state| {
+    let (state, _) = ParseSymbol(Symbol::Break).parse(state)?;
+    Ok((state, ()))
+});
+
 struct ParseUnaryOp;
-define_parser!(ParseUnaryOp, UnaryOpKind, |_, state: ParseState<'state>| {
+define_parser!(ParseUnaryOp, UnaryOpKind, |_, state: ParseState<'state, 'h>| {
     if let Some(&Token { kind: TokenKind::Symbol(symbol), .. }) = state.peek() {
         let kind = match symbol {
             Symbol::Minus => UnaryOpKind::Negate,
@@ -117,7 +514,7 @@ define_parser!(ParseUnaryOp, UnaryOpKind, |_, state: ParseState<'state>| {
 });
 
 struct ParseBinaryOp;
-define_parser!(ParseBinaryOp, BinaryOpKind, |_, state: ParseState<'state>| {
+define_parser!(ParseBinaryOp, BinaryOpKind, |_, state: ParseState<'state, 'h>| {
     if let Some(&Token { kind: TokenKind::Symbol(symbol), .. }) = state.peek() {
         let kind = match symbol {
             Symbol::Plus => BinaryOpKind::Add,
@@ -136,16 +533,12 @@ define_parser!(ParseBinaryOp, BinaryOpKind, |_, state: ParseState<'state>| {
 });
 
 struct ParseExpressionAtPrecedence(u8);
-define_parser!(ParseExpressionAtPrecedence, Expression<'state>, |this: &ParseExpressionAtPrecedence, state| {
+define_parser!(ParseExpressionAtPrecedence, Expression<'state>, |this: &ParseExpressionAtPrecedence, state: ParseState<'state, 'h>| {
     let min_precedence = this.0;
-    let (mut state, mut atom_lhs) = ParseExpressionAtom.parse(state)?;
-
-    loop {
-        let (next_state, operator) = match ParseBinaryOp.parse(state) {
-            Ok(v) => v,
-            Err(_) => break,
-        };
+    let (mut state, mut atom_lhs) = ParseExpressionAtom.parse(state.enter()?)?;
+    state.record(|metrics| metrics.expressions_built += 1);
 
+    while let Ok((next_state, operator)) = ParseBinaryOp.parse(state) {
         if operator.precedence() < min_precedence {
             break;
         }
@@ -158,6 +551,8 @@ define_parser!(ParseExpressionAtPrecedence, Expression<'state>, |this: &ParseExp
 
         let (next_state, atom_rhs) = ParseExpressionAtPrecedence(next_min_precedence).parse(next_state)?;
         state = next_state;
+        state.record(|metrics| metrics.expressions_built += 1);
+        state.allocate(2 * mem::size_of::<Expression>())?;
 
         atom_lhs = Expression::BinaryOp(BinaryOp {
             operator,
@@ -166,7 +561,7 @@ define_parser!(ParseExpressionAtPrecedence, Expression<'state>, |this: &ParseExp
         });
     }
 
-    Ok((state, atom_lhs))
+    Ok((state.exit(), atom_lhs))
 });
 
 struct ParseExpression;
@@ -176,21 +571,18 @@ define_parser!(ParseExpression, Expression<'state>, |_, state| {
 
 struct ParseExpressionAtom;
 define_parser!(ParseExpressionAtom, Expression<'state>, |_, state| {
-    if let Ok((next_state, expression)) = ParseUnaryExpression.parse(state) {
-        Ok((next_state, expression))
-    } else if let Ok((next_state, expression)) = ParseParenExpression.parse(state) {
-        Ok((next_state, expression))
-    } else if let Ok((next_state, expression)) = ParseValue.parse(state) {
-        Ok((next_state, expression))
-    } else {
-        Err(ParseAbort::NoMatch)
-    }
+    parse_first_of!(state, {
+        ParseUnaryExpression => |expression| expression,
+        ParsePrefixExpression => |expression| expression,
+        ParseValue => |expression| expression,
+    })
 });
 
 struct ParseUnaryExpression;
 define_parser!(ParseUnaryExpression, Expression<'state>, |_, state| {
     let (state, operator) = ParseUnaryOp.parse(state)?;
     let (state, argument) = ParseExpressionAtPrecedence(operator.precedence()).parse(state)?;
+    state.allocate(mem::size_of::<Expression>())?;
 
     Ok((state, Expression::UnaryOp(UnaryOp {
         operator,
@@ -203,6 +595,7 @@ define_parser!(ParseParenExpression, Expression<'state>, |_, state| {
     let (state, _) = ParseSymbol(Symbol::LeftParen).parse(state)?;
     let (state, expression) = ParseExpression.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::RightParen).parse(state)?;
+    state.allocate(mem::size_of::<Expression>())?;
 
     Ok((state, Expression::ParenExpression(Box::new(expression))))
 });
@@ -211,8 +604,6 @@ struct ParseValue;
 define_parser!(ParseValue, Expression<'state>, |_, state| {
     parse_first_of!(state, {
         ParseNumber => Expression::Number,
-        ParseFunctionCall => Expression::FunctionCall,
-        ParseIdentifier => Expression::Name,
         ParseTableLiteral => Expression::Table,
         ParseBoolean => Expression::Bool,
         // Hack: parse_first_of! cannot handle unit values
@@ -234,13 +625,157 @@ define_parser!(ParseNil, (), |_, state| {
 });
 
 struct ParseString;
-define_parser!(ParseString, StringLiteral<'state>, |_, state: ParseState<'state>| {
+define_parser!(ParseString, StringLiteral<'state>, |_, state: ParseState<'state, 'h>| {
     match state.peek() {
-        Some(&Token { kind: TokenKind::StringLiteral(ref value), .. }) => Ok((state.advance(1), value.clone())),
+        Some(&Token { kind: TokenKind::StringLiteral(ref value), .. }) => {
+            state.allocate(string_length(value))?;
+            Ok((state.advance(1), value.clone()))
+        },
         _ => Err(ParseAbort::NoMatch),
     }
 });
 
+// stat ::= varlist `=´ explist | functioncall
+// varlist ::= var {`,´ var}
+//
+// Both kinds of statement start with a prefix expression, so it's parsed once
+// and what follows it decides which one this is.
+struct ParseAssignmentOrCall;
+define_parser!(ParseAssignmentOrCall, Statement<'state>, |_, state: ParseState<'state, 'h>| {
+    let (mut next, first) = ParsePrefixExpression.parse(state)?;
+
+    if let Expression::FunctionCall(call) = first {
+        if !continues_varlist(next) {
+            return Ok((next, Statement::FunctionCall(call)));
+        }
+
+        return Err(assignment_to_call(state, next));
+    }
+
+    let mut targets = vec![assignment_target(first)?];
+
+    while let Ok((after_comma, _)) = ParseSymbol(Symbol::Comma).parse(next) {
+        let (after_target, target) = ParseAssignmentTarget.parse(after_comma)?;
+        targets.push(target);
+        next = after_target;
+    }
+
+    let (state, _) = ParseSymbol(Symbol::Equal).parse(next)?;
+    let (state, expressions) = DelimitedOneOrMore(ParseExpression, ParseSymbol(Symbol::Comma)).parse(state)?;
+
+    state.allocate(size_of_all(&targets) + size_of_all(&expressions))?;
+
+    Ok((state, Statement::Assignment(Assignment {
+        targets,
+        values: expressions,
+    })))
+});
+
+// prefixexp ::= var | functioncall | `(´ exp `)´
+// var ::= Name | prefixexp `[´ exp `]´ | prefixexp `.´ Name
+// functioncall ::= prefixexp args | prefixexp `:´ Name args
+// args ::= `(´ [explist] `)´
+//
+// A plain name stays an `Expression::Name`, and anything with a field or
+// index after it becomes an `Expression::Var`.
+struct ParsePrefixExpression;
+define_parser!(ParsePrefixExpression, Expression<'state>, |_, state: ParseState<'state, 'h>| {
+    let (mut state, mut expression) = match ParseIdentifier.parse(state) {
+        Ok((state, name)) => (state, Expression::Name(name)),
+        Err(ParseAbort::NoMatch) => ParseParenExpression.parse(state)?,
+        Err(error) => return Err(error),
+    };
+
+    loop {
+        if let Ok((next, _)) = ParseSymbol(Symbol::Dot).parse(state) {
+            let (next, name) = ParseIdentifier.parse(next)?;
+            state = next;
+            state.allocate(mem::size_of::<AssignmentTarget>() + mem::size_of::<Expression>())?;
+            expression = Expression::Var(Box::new(AssignmentTarget::FieldAccess { base: Box::new(expression), name }));
+        } else if let Ok((next, _)) = ParseSymbol(Symbol::LeftBracket).parse(state) {
+            let (next, index) = ParseExpression.parse(next)?;
+            let (next, _) = ParseSymbol(Symbol::RightBracket).parse(next)?;
+            state = next;
+            state.allocate(mem::size_of::<AssignmentTarget>() + 2 * mem::size_of::<Expression>())?;
+            expression = Expression::Var(Box::new(AssignmentTarget::IndexAccess { base: Box::new(expression), index: Box::new(index) }));
+        } else if let Ok((next, _)) = ParseSymbol(Symbol::Colon).parse(state) {
+            let (next, method) = ParseIdentifier.parse(next)?;
+            let (next, arguments) = ParseArguments.parse(next)?;
+            state = next;
+            expression = Expression::FunctionCall(FunctionCall {
+                name_expression: Box::new(expression),
+                method: Some(method),
+                arguments,
+            });
+        } else {
+            let (next, arguments) = match ParseArguments.parse(state) {
+                Ok(result) => result,
+                Err(ParseAbort::NoMatch) => break,
+                Err(error) => return Err(error),
+            };
+
+            state = next;
+            expression = Expression::FunctionCall(FunctionCall {
+                name_expression: Box::new(expression),
+                method: None,
+                arguments,
+            });
+        }
+    }
+
+    Ok((state, expression))
+});
+
+struct ParseArguments;
+define_parser!(ParseArguments, Vec<Expression<'state>>, |_, state| {
+    let (state, _) = ParseSymbol(Symbol::LeftParen).parse(state)?;
+    let (state, arguments) = DelimitedZeroOrMore(ParseExpression, ParseSymbol(Symbol::Comma), false).parse(state)?;
+    let (state, _) = ParseSymbol(Symbol::RightParen).parse(state)?;
+    state.allocate(mem::size_of::<Expression>() + size_of_all(&arguments))?;
+
+    Ok((state, arguments))
+});
+
+struct ParseAssignmentTarget;
+define_parser!(ParseAssignmentTarget, AssignmentTarget<'state>, |_, state: ParseState<'state, 'h>| {
+    let (next, expression) = ParsePrefixExpression.parse(state)?;
+
+    if let Expression::FunctionCall(_) = expression {
+        if continues_varlist(next) {
+            return Err(assignment_to_call(state, next));
+        }
+    }
+
+    Ok((next, assignment_target(expression)?))
+});
+
+// A target has to be a name or end in a field or index.
+fn assignment_target(expression: Expression) -> Result<AssignmentTarget, ParseAbort> {
+    match expression {
+        Expression::Name(name) => Ok(AssignmentTarget::Name(name)),
+        Expression::Var(target) => Ok(*target),
+        _ => Err(ParseAbort::NoMatch),
+    }
+}
+
+// A call followed by `=` or `,` can't be a call statement, so it's reported
+// as an attempt to assign to it.
+fn continues_varlist(state: ParseState) -> bool {
+    matches!(
+        state.peek().map(|token| &token.kind),
+        Some(TokenKind::Symbol(Symbol::Equal | Symbol::Comma))
+    )
+}
+
+fn assignment_to_call(start: ParseState, end: ParseState) -> ParseAbort {
+    let mut message = ParseMessage::new(MessageCode::AssignmentToCall);
+    message.construct = Some("assignment".to_string());
+    message.position = start.peek().map(|token| token.start_position);
+    message.end = Some(end.tokens[end.position - 1].end_position);
+
+    ParseAbort::Error(Box::new(message))
+}
+
 // local namelist [`=´ explist]
 struct ParseLocalAssignment;
 define_parser!(ParseLocalAssignment, LocalAssignment<'state>, |_, state| {
@@ -253,50 +788,55 @@ define_parser!(ParseLocalAssignment, LocalAssignment<'state>, |_, state| {
         Err(_) => (state, Vec::new()),
     };
 
+    state.allocate(size_of_all(&names) + size_of_all(&expressions))?;
+
     Ok((state, LocalAssignment {
-        names: names,
+        names,
         values: expressions,
     }))
 });
 
-// functioncall ::= prefixexp args | prefixexp `:´ Name args
-// right now:
-// functioncall ::= Name `(` explist `)`
-struct ParseFunctionCall;
-define_parser!(ParseFunctionCall, FunctionCall<'state>, |_, state| {
-    let (state, name) = ParseIdentifier.parse(state)?;
-    let (state, _) = ParseSymbol(Symbol::LeftParen).parse(state)?;
-    let (state, expressions) = DelimitedZeroOrMore(ParseExpression, ParseSymbol(Symbol::Comma), false).parse(state)?;
-    let (state, _) = ParseSymbol(Symbol::RightParen).parse(state)?;
-
-    Ok((state, FunctionCall {
-        name_expression: Box::new(Expression::Name(name)),
-        arguments: expressions,
-    }))
-});
-
 struct ParseNumericFor;
-define_parser!(ParseNumericFor, NumericFor<'state>, |_, state| {
+define_parser!(ParseNumericFor, NumericFor<'state>, |_, state: ParseState<'state, 'h>| {
+    let statement_start = state.position;
     let (state, _) = ParseSymbol(Symbol::For).parse(state)?;
     let (state, var) = ParseIdentifier.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::Equal).parse(state)?;
-    let (state, start) = ParseExpression.parse(state)?;
-    let (state, _) = ParseSymbol(Symbol::Comma).parse(state)?;
-    let (state, end) = ParseExpression.parse(state)?;
+
+    // Past the `=`, this can only be a numeric for, so anything missing is a
+    // mistake in this loop rather than a sign to try something else.
+    let (state, start) = match ParseExpression.parse(state) {
+        Err(ParseAbort::NoMatch) => return Err(expected_expression(state, "for loop")),
+        result => result?,
+    };
+
+    let (state, _) = match ParseSymbol(Symbol::Comma).parse(state) {
+        Err(ParseAbort::NoMatch) => return Err(unexpected_token(state, &["','"], "for loop")),
+        result => result?,
+    };
+
+    let (state, end) = match ParseExpression.parse(state) {
+        Err(ParseAbort::NoMatch) => return Err(expected_expression(state, "for loop")),
+        result => result?,
+    };
 
     let (state, step) = match state.peek() {
         Some(&Token { kind: TokenKind::Symbol(Symbol::Comma), .. }) => {
-            let (new_state, parsed_step) = ParseExpression.parse(state.advance(1))?;
+            let (new_state, parsed_step) = match ParseExpression.parse(state.advance(1)) {
+                Err(ParseAbort::NoMatch) => return Err(expected_expression(state.advance(1), "for loop")),
+                result => result?,
+            };
 
             (new_state, Some(parsed_step))
         },
         Some(&Token { kind: TokenKind::Symbol(Symbol::Do), .. }) => {
             (state, None)
         },
-        _ => return Err(ParseAbort::NoMatch),
+        _ => return Err(unexpected_token(state, &["','", "'do'"], "for loop")),
     };
 
     let (state, _) = ParseSymbol(Symbol::Do).parse(state)?;
+    state.notify(|sink| sink.enter_statement(NodeKind::NumericFor, statement_start));
     let (state, body) = ParseChunk.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::End).parse(state)?;
 
@@ -310,14 +850,26 @@ define_parser!(ParseNumericFor, NumericFor<'state>, |_, state| {
 });
 
 struct ParseGenericFor;
-define_parser!(ParseGenericFor, GenericFor<'state>, |_, state| {
+define_parser!(ParseGenericFor, GenericFor<'state>, |_, state: ParseState<'state, 'h>| {
+    let statement_start = state.position;
     let (state, _) = ParseSymbol(Symbol::For).parse(state)?;
     let (state, vars) = DelimitedOneOrMore(ParseIdentifier, ParseSymbol(Symbol::Comma)).parse(state)?;
-    let (state, _) = ParseSymbol(Symbol::In).parse(state)?;
+
+    // A numeric for is tried first and reports its own mistakes once it sees
+    // `=`, so only a list of names can get here with one. Anything in place of
+    // `in` is a mistake in this loop.
+    let (state, _) = match ParseSymbol(Symbol::In).parse(state) {
+        Ok(result) => result,
+        Err(ParseAbort::NoMatch) if vars.len() == 1 => return Err(unexpected_token(state, &["'='", "'in'"], "for loop")),
+        Err(ParseAbort::NoMatch) => return Err(unexpected_token(state, &["'in'"], "for loop")),
+        Err(err) => return Err(err),
+    };
     let (state, item_source) = DelimitedOneOrMore(ParseExpression, ParseSymbol(Symbol::Comma)).parse(state)?;
     let (state, _) = ParseSymbol(Symbol::Do).parse(state)?;
+    state.notify(|sink| sink.enter_statement(NodeKind::GenericFor, statement_start));
     let (state, body) = ParseChunk.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::End).parse(state)?;
+    state.allocate(size_of_all(&vars) + size_of_all(&item_source))?;
 
     Ok((state, GenericFor {
         vars,
@@ -327,20 +879,17 @@ define_parser!(ParseGenericFor, GenericFor<'state>, |_, state| {
 });
 
 struct ParseIfStatement;
-define_parser!(ParseIfStatement, IfStatement<'state>, |_, state| {
+define_parser!(ParseIfStatement, IfStatement<'state>, |_, state: ParseState<'state, 'h>| {
+    let statement_start = state.position;
     let (state, _) = ParseSymbol(Symbol::If).parse(state)?;
     let (state, condition) = ParseExpression.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::Then).parse(state)?;
+    state.notify(|sink| sink.enter_statement(NodeKind::IfStatement, statement_start));
     let (state, body) = ParseChunk.parse(state)?;
 
     let mut state = state;
     let mut else_if_branches = Vec::new();
-    loop {
-        let (next_state, _) = match ParseSymbol(Symbol::ElseIf).parse(state) {
-            Ok(v) => v,
-            Err(_) => break,
-        };
-
+    while let Ok((next_state, _)) = ParseSymbol(Symbol::ElseIf).parse(state) {
         let (next_state, condition) = ParseExpression.parse(next_state)?;
         let (next_state, _) = ParseSymbol(Symbol::Then).parse(next_state)?;
         let (next_state, body) = ParseChunk.parse(next_state)?;
@@ -359,6 +908,7 @@ define_parser!(ParseIfStatement, IfStatement<'state>, |_, state| {
     };
 
     let (state, _) = ParseSymbol(Symbol::End).parse(state)?;
+    state.allocate(size_of_all(&else_if_branches))?;
 
     Ok((state, IfStatement {
         condition,
@@ -368,11 +918,31 @@ define_parser!(ParseIfStatement, IfStatement<'state>, |_, state| {
     }))
 });
 
+// do chunk end
+//
+// Loops consume their own `do`, so one at the start of a statement is always
+// a block. That makes a missing `end` an error rather than a failed match.
+struct ParseDoBlock;
+define_parser!(ParseDoBlock, Chunk<'state>, |_, state: ParseState<'state, 'h>| {
+    let statement_start = state.position;
+    let (state, _) = ParseSymbol(Symbol::Do).parse(state)?;
+    state.notify(|sink| sink.enter_statement(NodeKind::Do, statement_start));
+    let (state, body) = ParseChunk.parse(state)?;
+
+    match ParseSymbol(Symbol::End).parse(state) {
+        Ok((state, _)) => Ok((state, body)),
+        Err(ParseAbort::NoMatch) => Err(unexpected_token(state, &["'end'"], "do block")),
+        Err(err) => Err(err),
+    }
+});
+
 struct ParseWhileLoop;
-define_parser!(ParseWhileLoop, WhileLoop<'state>, |_, state| {
+define_parser!(ParseWhileLoop, WhileLoop<'state>, |_, state: ParseState<'state, 'h>| {
+    let statement_start = state.position;
     let (state, _) = ParseSymbol(Symbol::While).parse(state)?;
     let (state, condition) = ParseExpression.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::Do).parse(state)?;
+    state.notify(|sink| sink.enter_statement(NodeKind::WhileLoop, statement_start));
     let (state, body) = ParseChunk.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::End).parse(state)?;
 
@@ -383,8 +953,10 @@ define_parser!(ParseWhileLoop, WhileLoop<'state>, |_, state| {
 });
 
 struct ParseRepeatLoop;
-define_parser!(ParseRepeatLoop, RepeatLoop<'state>, |_, state| {
+define_parser!(ParseRepeatLoop, RepeatLoop<'state>, |_, state: ParseState<'state, 'h>| {
+    let statement_start = state.position;
     let (state, _) = ParseSymbol(Symbol::Repeat).parse(state)?;
+    state.notify(|sink| sink.enter_statement(NodeKind::RepeatLoop, statement_start));
     let (state, body) = ParseChunk.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::Until).parse(state)?;
     let (state, condition) = ParseExpression.parse(state)?;
@@ -395,6 +967,43 @@ define_parser!(ParseRepeatLoop, RepeatLoop<'state>, |_, state| {
     }))
 });
 
+// laststat ::= return [explist] [`;´]
+//
+// Nothing else can follow in the same block, which is checked here so that
+// the statement can be parsed on its own, like for [parse_until].
+struct ParseReturnStatement;
+define_parser!(ParseReturnStatement, ReturnStatement<'state>, |_, state| {
+    let (state, _) = ParseSymbol(Symbol::Return).parse(state)?;
+    let (state, values) = DelimitedZeroOrMore(ParseExpression, ParseSymbol(Symbol::Comma), true).parse(state)?;
+
+    // A trailing comma is allowed above only so that the mistake can be
+    // reported at whatever came after it, instead of at the `return`.
+    if state.tokens[state.position - 1].kind == TokenKind::Symbol(Symbol::Comma) {
+        return Err(expected_expression(state, "return statement"));
+    }
+
+    let (state, _) = Optional(ParseSymbol(Symbol::Semicolon)).parse(state)?;
+
+    match state.peek() {
+        None | Some(&Token { kind: TokenKind::EndOfFile, .. }) => {},
+        Some(&Token { kind: TokenKind::Symbol(Symbol::End | Symbol::Else | Symbol::ElseIf | Symbol::Until), .. }) => {},
+        Some(token) => {
+            let mut message = ParseMessage::new(MessageCode::StatementAfterReturn);
+            message.construct = Some("return statement".to_string());
+            message.found = Some(token.to_string());
+            message.position = Some(token.start_position);
+
+            return Err(ParseAbort::Error(Box::new(message)));
+        },
+    }
+
+    state.allocate(size_of_all(&values))?;
+
+    Ok((state, ReturnStatement {
+        values,
+    }))
+});
+
 struct ParseFunctionName;
 define_parser!(ParseFunctionName, FunctionName<'state>, |_, state| {
     let (state, segments) = DelimitedOneOrMore(ParseIdentifier, ParseSymbol(Symbol::Dot)).parse(state)?;
@@ -408,21 +1017,27 @@ define_parser!(ParseFunctionName, FunctionName<'state>, |_, state| {
         Err(e) => return Err(e),
     };
 
+    state.allocate(size_of_all(&segments))?;
+
     Ok((state, FunctionName {segments, method}))
 });
 
 struct ParseFunctionDeclaration;
-define_parser!(ParseFunctionDeclaration, FunctionDeclaration<'state>, |_, state| {
+define_parser!(ParseFunctionDeclaration, FunctionDeclaration<'state>, |_, state: ParseState<'state, 'h>| {
+    let statement_start = state.position;
     let (state, local) = Optional(ParseSymbol(Symbol::Local)).parse(state)
         .map(|(state, value)| (state, value.is_some()))?;
 
-    let (state, _) = ParseSymbol(Symbol::Function).parse(state)?;
-    let (state, name) = ParseFunctionName.parse(state)?;
+    let (name_start, _) = ParseSymbol(Symbol::Function).parse(state)?;
+    let (state, name) = ParseFunctionName.parse(name_start)?;
+    let name_tokens = name_start.position..state.position;
     let (state, _) = ParseSymbol(Symbol::LeftParen).parse(state)?;
     let (state, parameters) = DelimitedZeroOrMore(ParseIdentifier, ParseSymbol(Symbol::Comma), false).parse(state)?;
     let (state, _) = ParseSymbol(Symbol::RightParen).parse(state)?;
+    state.notify(|sink| sink.enter_function(statement_start, &name, name_tokens));
     let (state, body) = ParseChunk.parse(state)?;
     let (state, _) = ParseSymbol(Symbol::End).parse(state)?;
+    state.allocate(size_of_all(&parameters))?;
 
     Ok((state, FunctionDeclaration {
         local,
@@ -436,7 +1051,7 @@ struct ParseTableKey;
 define_parser!(ParseTableKey, TableKey<'state>, |_, state| {
     // First, try parsing an identifier (Lua allows bare literals as table keys)
     let (state, key) = match ParseIdentifier.parse(state) {
-        Ok((state, identifier)) => (state, TableKey::Name(identifier.into())),
+        Ok((state, identifier)) => (state, TableKey::Name(identifier)),
         Err(ParseAbort::NoMatch) => {
             let (state, _) = ParseSymbol(Symbol::LeftBracket).parse(state)?;
             let (state, key) = ParseExpression.parse(state)?;
@@ -452,12 +1067,15 @@ define_parser!(ParseTableKey, TableKey<'state>, |_, state| {
 
 struct ParseTableValue;
 define_parser!(ParseTableValue, (Option<TableKey<'state>>, Expression<'state>), |_, state| {
-    let (state, key) = Optional(ParseTableKey).parse(state)?;
+    // A bare name is only a key if '=' follows it; otherwise, like in `{x}`,
+    // it's the start of a positional value.
+    let keyed = ParseTableKey.parse(state)
+        .and_then(|(state, key)| Ok((ParseSymbol(Symbol::Equal).parse(state)?.0, key)));
 
-    // We only check for '=' if there was a key
-    let state = match key {
-        Some(_) => ParseSymbol(Symbol::Equal).parse(state)?.0,
-        None => state
+    let (state, key) = match keyed {
+        Ok((state, key)) => (state, Some(key)),
+        Err(ParseAbort::NoMatch) => (state, None),
+        Err(err) => return Err(err),
     };
 
     let (state, value) = ParseExpression.parse(state)?;
@@ -469,7 +1087,512 @@ define_parser!(ParseTableLiteral, TableLiteral<'state>, |_, state| {
     let (state, _) = ParseSymbol(Symbol::LeftBrace).parse(state)?;
     let (state, items) = DelimitedZeroOrMore(ParseTableValue, Or(&[ ParseSymbol(Symbol::Comma), ParseSymbol(Symbol::Semicolon) ]), true).parse(state)?;
     let (state, _) = ParseSymbol(Symbol::RightBrace).parse(state)?;
+    state.allocate(size_of_all(&items))?;
+
     Ok((state, TableLiteral {
         items
     }))
-});
\ No newline at end of file
+});
+
+/// An [MessageCode::UnexpectedToken] error for the token at `state`, or for
+/// the end of the file just after the last token.
+fn unexpected_token(state: ParseState, expected: &[&str], construct: &str) -> ParseAbort {
+    let mut message = ParseMessage::new(MessageCode::UnexpectedToken);
+    message.expected = expected.iter().map(|item| item.to_string()).collect();
+    message.construct = Some(construct.to_string());
+    found_at(&mut message, state);
+
+    ParseAbort::Error(Box::new(message))
+}
+
+/// An [MessageCode::ExpectedExpression] error for the token at `state`, in a
+/// `construct` that needs an expression there, like the limit of a for loop.
+fn expected_expression(state: ParseState, construct: &str) -> ParseAbort {
+    let mut message = ParseMessage::new(MessageCode::ExpectedExpression);
+    message.construct = Some(construct.to_string());
+    found_at(&mut message, state);
+
+    ParseAbort::Error(Box::new(message))
+}
+
+/// Points `message` at the token where `state` is, or at the end of the last
+/// token if the stream ended there.
+fn found_at(message: &mut ParseMessage, state: ParseState) {
+    match state.peek().filter(|token| token.kind != TokenKind::EndOfFile) {
+        Some(token) => {
+            message.found = Some(token.to_string());
+            message.position = Some(token.start_position);
+        },
+        None => {
+            message.found = Some(TokenKind::EndOfFile.to_string());
+            message.position = state.tokens[..state.position].last().map(|token| token.end_position);
+        },
+    }
+}
+
+/// The bytes taken up by the elements of a `Vec`, not counting spare capacity.
+fn size_of_all<T>(items: &[T]) -> usize {
+    mem::size_of_val(items)
+}
+
+fn string_length(literal: &StringLiteral) -> usize {
+    match literal {
+        StringLiteral::DoubleQuote { raw_content }
+        | StringLiteral::SingleQuote { raw_content }
+        | StringLiteral::LongForm { raw_content, .. } => raw_content.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokenizer::tokenize;
+
+    #[test]
+    fn metrics() {
+        let tokens = tokenize("local a = 1 + -b\nwhile a do\n\tf((a))\nend").unwrap();
+        let (result, metrics) = parse_with_metrics(&tokens);
+
+        assert_eq!(result, parse_from_tokens(&tokens));
+        assert_eq!(metrics.statements_built, 3);
+        assert_eq!(metrics.expressions_built, 7);
+        assert_eq!(metrics.max_depth, 4);
+        assert!(metrics.tokens_consumed >= tokens.len() - 1);
+        assert!(metrics.backtracks > 0);
+        assert_eq!(metrics.furthest_token, tokens.len());
+
+        // Each parse counts into its own metrics, whatever else ran before.
+        parse_from_tokens(&tokens).unwrap();
+        let (_, again) = parse_with_metrics(&tokens);
+
+        assert_eq!(again, ParseMetrics {
+            elapsed: again.elapsed,
+            ..metrics
+        });
+    }
+
+    #[test]
+    fn nested_parses_count_separately() {
+        let outer_tokens = tokenize("local a = 1 local b = 2").unwrap();
+        let inner_tokens = tokenize("while a do f(a) end").unwrap();
+
+        let outer = RefCell::new(ParseMetrics::default());
+        let inner = RefCell::new(ParseMetrics::default());
+
+        // Start one parse, run another to completion, then finish the first.
+        let state = ParseState::new(&outer_tokens).with_metrics(&outer);
+        let (state, _) = ParseStatement.parse(state).unwrap();
+        parse_chunk(ParseState::new(&inner_tokens).with_metrics(&inner)).unwrap();
+        ParseStatement.parse(state).unwrap();
+
+        let (_, alone) = parse_with_metrics(&inner_tokens);
+        assert_eq!(inner.into_inner(), ParseMetrics {
+            elapsed: Default::default(),
+            ..alone
+        });
+
+        assert_eq!(outer.into_inner().furthest_token, outer_tokens.len());
+    }
+
+    #[test]
+    fn completeness_of_prefixes() {
+        let check = |source: &str| completeness(&tokenize(source).unwrap());
+
+        assert_eq!(check(""), Completeness::Complete);
+        assert_eq!(check("local a = 1"), Completeness::Complete);
+        assert_eq!(check("while a do\n\tf(a)\nend"), Completeness::Complete);
+
+        assert_eq!(check("local a ="), Completeness::Incomplete);
+        assert_eq!(check("while a do\n\tf(a)\n"), Completeness::Incomplete);
+        assert_eq!(check("print(1, "), Completeness::Incomplete);
+        assert_eq!(check("for i = 1"), Completeness::Incomplete);
+
+        assert!(matches!(check("local = 1"), Completeness::Invalid(_)));
+        assert!(matches!(check("print)"), Completeness::Invalid(_)));
+        assert!(matches!(check("local a = 1 )"), Completeness::Invalid(_)));
+    }
+
+    #[test]
+    fn ast_bytes() {
+        let tokens = tokenize("local a, b = 1, \"hi\"").unwrap();
+        let (_, metrics) = parse_with_metrics(&tokens);
+
+        let expected = mem::size_of::<Statement>()
+            + 2 * mem::size_of::<Cow<str>>() + "ab".len()
+            + 2 * mem::size_of::<Expression>() + "1hi".len();
+
+        assert_eq!(metrics.ast_bytes, expected);
+
+        let (result, _) = parse_with_memory_budget(&tokens, expected);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn memory_budget() {
+        let source = "local value = f(1, {x = 2}) .. \"some text\"\n".repeat(2000);
+        let tokens = tokenize(&source).unwrap();
+
+        let (result, metrics) = parse_with_memory_budget(&tokens, 4096);
+
+        match result {
+            Err(BudgetedParseError::OverBudget { budget, used }) => {
+                assert_eq!(budget, 4096);
+                assert!(used > 4096);
+            },
+            other => panic!("expected to go over budget, got {:?}", other),
+        }
+
+        assert!(metrics.tokens_consumed < tokens.len() / 10, "parsing didn't stop early");
+    }
+
+    #[test]
+    fn memory_budget_without_metrics() {
+        let source = "local value = f(1, {x = 2}) .. \"some text\"\n".repeat(2000);
+        let tokens = tokenize(&source).unwrap();
+        let budget = MemoryBudget::new(4096);
+
+        let state = ParseState {
+            memory_budget: Some(&budget),
+            ..ParseState::new(&tokens)
+        };
+
+        let message = parse_chunk(state).unwrap_err();
+        assert_eq!(message.code, MessageCode::OverMemoryBudget);
+        assert_eq!(message.limit, 4096);
+
+        // The budget stops counting once it's over, but metrics don't, so
+        // they only agree if parsing stopped at the first overrun.
+        let (_, metrics) = parse_with_memory_budget(&tokens, 4096);
+        assert_eq!(budget.used(), metrics.ast_bytes);
+    }
+
+    #[test]
+    fn parse_until_first_function() {
+        let mut source = String::from("local helpers = {}\nlocal count = 0\n");
+        source.push_str("function first(a)\n\tprint(a)\nend\n");
+
+        for index in 0..500 {
+            source.push_str(&format!("local value{} = first({})\n", index, index));
+        }
+
+        // Never reached, so never reported.
+        source.push_str("local = broken\n");
+
+        let tokens = tokenize(&source).unwrap();
+
+        let mut seen = 0;
+        let (statements, resume) = parse_until(&tokens, |statement| {
+            seen += 1;
+
+            match statement {
+                Statement::FunctionDeclaration(_) => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        }).unwrap();
+
+        assert_eq!(seen, 3);
+        assert_eq!(statements.len(), 3);
+        assert!(!resume.finished);
+        assert_eq!(&source[..resume.position.bytes], "local helpers = {}\nlocal count = 0\nfunction first(a)\n\tprint(a)\nend");
+        assert_eq!(tokens[resume.token].kind, TokenKind::Symbol(Symbol::Local));
+
+        assert!(parse_until_from(&tokens, resume, |_| ControlFlow::Continue(())).is_err());
+    }
+
+    #[test]
+    fn parse_until_resumes_to_the_end() {
+        let source = "local a = 1\nprint(a)\nlocal b = a\n";
+        let tokens = tokenize(source).unwrap();
+
+        let (mut statements, resume) = parse_until(&tokens, |_| ControlFlow::Break(())).unwrap();
+        assert_eq!(statements.len(), 1);
+
+        let (rest, resume) = parse_until_from(&tokens, resume, |_| ControlFlow::Continue(())).unwrap();
+        assert!(resume.finished);
+        statements.extend(rest);
+
+        assert_eq!(Chunk { statements }, parse_from_tokens(&tokens).unwrap());
+
+        let (empty, again) = parse_until_from(&tokens, resume, |_| ControlFlow::Continue(())).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(again, resume);
+    }
+
+    #[test]
+    fn parse_prefix_with_payload() {
+        let lua = "local a = 1\nwhile a do\n\tprint(a)\nend\n";
+        let source = format!("{}@@@DATA\x00\x01 local b", lua);
+
+        let (chunk, end) = parse_prefix(&source).unwrap();
+        assert_eq!(end, lua.trim_end().len());
+        assert_eq!(chunk, parse_from_tokens(&tokenize(lua).unwrap()).unwrap().into_owned());
+
+        // Payload that tokenizes but isn't a statement stops parsing too.
+        let (chunk, end) = parse_prefix("print(1) DATA 1 2").unwrap();
+        assert_eq!(chunk.statements.len(), 1);
+        assert_eq!(end, 8);
+    }
+
+    #[test]
+    fn parse_prefix_of_pure_lua() {
+        for source in &["local a = 1\nprint(a) -- done\n", "print(a)", ""] {
+            let (chunk, end) = parse_prefix(source).unwrap();
+            assert_eq!(end, source.len());
+            assert_eq!(chunk, parse_from_tokens(&tokenize(source).unwrap()).unwrap().into_owned());
+        }
+    }
+
+    #[test]
+    fn parse_prefix_never_stops_mid_statement() {
+        // The loop is cut off by the payload, so only the statement before it
+        // counts.
+        let (chunk, end) = parse_prefix("f()\nwhile a do\n\tf()\n@@@").unwrap();
+        assert_eq!(chunk.statements.len(), 1);
+        assert_eq!(end, 3);
+
+        assert!(parse_prefix("while a do @@@").is_err());
+        assert!(parse_prefix("end @@@").is_err());
+        assert!(parse_prefix("@@@").is_err());
+    }
+
+    #[test]
+    fn expressions_as_statements() {
+        let check = |source: &str| {
+            let tokens = tokenize(source).unwrap();
+            parse_structured(&tokens).map(|_| ()).map_err(|message| (message.code, message.position.map(|position| position.bytes), message.end.map(|position| position.bytes)))
+        };
+
+        // The span covers the whole expression.
+        assert_eq!(check("x + 1"), Err((MessageCode::UnexpectedExpression, Some(0), Some(5))));
+        assert_eq!(check("local a = 1
+x
+"), Err((MessageCode::UnexpectedExpression, Some(12), Some(13))));
+        assert_eq!(check("while a do
+	f()
+	a .. b
+end"), Err((MessageCode::UnexpectedExpression, Some(17), Some(23))));
+        assert_eq!(check("t.x"), Err((MessageCode::UnexpectedExpression, Some(0), Some(3))));
+
+        // A call in parentheses isn't a call statement anymore, it's an
+        // expression.
+        assert_eq!(check("(f())"), Err((MessageCode::UnexpectedExpression, Some(0), Some(5))));
+        assert_eq!(check("f()"), Ok(()));
+
+        // Possibly the start of an assignment or a call, so not an
+        // expression statement.
+        assert_eq!(check("x = 1"), Ok(()));
+        assert_eq!(check("x =").unwrap_err().0, MessageCode::UnexpectedToken);
+        assert_eq!(check("print(").unwrap_err().0, MessageCode::UnexpectedToken);
+
+        let message = parse_from_tokens(&tokenize("x + 1").unwrap()).unwrap_err();
+        assert!(message.starts_with("unexpected expression: only function calls and assignments can stand alone as statements at line 1"));
+    }
+
+    #[test]
+    fn return_statements() {
+        use dump::dump_chunk;
+
+        let check = |source: &str| parse_structured(&tokenize(source).unwrap()).map(|chunk| dump_chunk(&chunk));
+
+        assert_eq!(check("return").unwrap(), "(chunk\n  (return))");
+        assert_eq!(check("return 1").unwrap(), "(chunk\n  (return 1))");
+        assert_eq!(check("return 1, 2;").unwrap(), "(chunk\n  (return 1 2))");
+        assert_eq!(check("while a do f() return end").unwrap(), "(chunk\n  (while a\n    (do\n      (call f)\n      (return))))");
+        assert!(check("if a then return a elseif b then return else return end").is_ok());
+        assert!(check("repeat return until a").is_ok());
+
+        // Nothing can follow a return in its block, not even another return.
+        for &(source, position) in &[("return 1\nprint(1)", 9), ("return return", 7), ("return; ;", 8), ("while a do return 1 f() end", 20)] {
+            let message = check(source).unwrap_err();
+            assert_eq!(message.code, MessageCode::StatementAfterReturn, "{}", source);
+            assert_eq!(message.position.map(|position| position.bytes), Some(position), "{}", source);
+        }
+
+        let message = parse_from_tokens(&tokenize("function f()\n\treturn x\n\tx = 1\nend").unwrap()).unwrap_err();
+        assert_eq!(message, "Expected the end of the block after 'return', but found identifier 'x' at line 3, column 2");
+
+        // A comma has to be followed by another value.
+        let message = parse_from_tokens(&tokenize("return 1,").unwrap()).unwrap_err();
+        assert_eq!(message, "Expected an expression, but found end of file at line 1, column 10");
+
+        let message = parse_from_tokens(&tokenize("if a then\n\treturn 1, 2,\nend").unwrap()).unwrap_err();
+        assert_eq!(message, "Expected an expression, but found 'end' at line 3, column 1");
+
+        // A return ends its block, but the block still has to be closed.
+        assert_eq!(check("return end").unwrap_err().code, MessageCode::UnexpectedToken);
+
+        let tokens = tokenize("f() return f").unwrap();
+        let (statements, resume) = parse_until(&tokens, |_| ControlFlow::Continue(())).unwrap();
+        assert!(resume.finished);
+        assert!(matches!(statements[..], [Statement::FunctionCall(_), Statement::Return(_)]));
+    }
+
+    #[test]
+    fn break_statements() {
+        use dump::dump_chunk;
+
+        let check = |source: &str| parse_structured(&tokenize(source).unwrap()).map(|chunk| dump_chunk(&chunk));
+
+        assert_eq!(check("while true do break end").unwrap(), "(chunk\n  (while true\n    (do\n      (break))))");
+        assert_eq!(check("repeat break until a").unwrap(), "(chunk\n  (repeat\n    (do\n      (break)) a))");
+        assert_eq!(check("for i = 1, 2 do break end").unwrap(), "(chunk\n  (for i 1 2\n    (do\n      (break))))");
+
+        // Unlike a return, a break can be followed by more statements, which
+        // Lua 5.2 and later allow even though they can never run.
+        assert_eq!(check("while a do break f() end").unwrap(), "(chunk\n  (while a\n    (do\n      (break)\n      (call f))))");
+        assert_eq!(check("while a do break break end").unwrap(), "(chunk\n  (while a\n    (do\n      (break)\n      (break))))");
+        assert!(check("while a do break return end").is_ok());
+        assert_eq!(check("while a do return break end").unwrap_err().code, MessageCode::StatementAfterReturn);
+
+        // Whether there's a loop to break out of is left to later passes.
+        assert_eq!(check("break").unwrap(), "(chunk\n  (break))");
+    }
+
+    #[test]
+    fn do_blocks() {
+        use dump::dump_chunk;
+
+        let check = |source: &str| parse_structured(&tokenize(source).unwrap()).map(|chunk| dump_chunk(&chunk));
+
+        assert_eq!(check("do end").unwrap(), "(chunk\n  (do))");
+        assert_eq!(check("do do end end").unwrap(), "(chunk\n  (do\n    (do)))");
+        assert_eq!(check("do f() return end").unwrap(), "(chunk\n  (do\n    (call f)\n    (return)))");
+
+        // The `do` of a loop isn't the start of a block statement.
+        assert_eq!(check("while a do do end end").unwrap(), "(chunk\n  (while a\n    (do\n      (do))))");
+        assert_eq!(check("for i = 1, 2 do end").unwrap(), "(chunk\n  (for i 1 2\n    (do)))");
+
+        // A missing `end` is reported where it should have been.
+        let message = check("do\n\tf()").unwrap_err();
+        assert_eq!(message.code, MessageCode::UnexpectedToken);
+        assert_eq!(message.to_string(), "Expected 'end', but found end of file at line 2, column 5");
+
+        let message = check("do f() until a").unwrap_err();
+        assert_eq!(message.to_string(), "Expected 'end', but found 'until' at line 1, column 8");
+
+        // A prompt can still wait for the rest of the block.
+        assert_eq!(completeness(&tokenize("do f()").unwrap()), Completeness::Incomplete);
+        assert!(matches!(completeness(&tokenize("do f() until a").unwrap()), Completeness::Invalid(_)));
+    }
+
+    #[test]
+    fn generic_for_loops() {
+        use dump::dump_statement;
+
+        let check = |source: &str| parse_structured(&tokenize(source).unwrap()).map(|chunk| dump_statement(&chunk.statements[0]));
+
+        assert_eq!(check("for k in t do end").unwrap(), "(for-in (k) (t)\n  (do))");
+        assert_eq!(check("for k, v in pairs(t) do f(k, v) end").unwrap(), "(for-in (k v) ((call pairs t))\n  (do\n    (call f k v)))");
+        assert_eq!(check("for k, v, extra in next, t, nil do end").unwrap(), "(for-in (k v extra) (next t nil)\n  (do))");
+        assert_eq!(check("for i = 1, 2 do end").unwrap(), "(for i 1 2\n  (do))");
+
+        // A missing or misspelled `in` is reported where it should have been.
+        let message = |source: &str| check(source).unwrap_err().to_string();
+        assert_eq!(message("for k, v inn pairs(t) do end"), "Expected 'in', but found identifier 'inn' at line 1, column 10");
+        assert_eq!(message("for k, v pairs(t) do end"), "Expected 'in', but found identifier 'pairs' at line 1, column 10");
+        assert_eq!(message("for k do end"), "Expected '=' or 'in', but found 'do' at line 1, column 7");
+        assert_eq!(message("for k, v"), "Expected 'in', but found end of file at line 1, column 9");
+
+        // After an `=`, mistakes are reported as ones in a numeric for.
+        assert_eq!(message("for k = 1 do end"), "Expected ',', but found 'do' at line 1, column 11");
+        assert_eq!(message("for k = do end"), "Expected an expression, but found 'do' at line 1, column 9");
+        assert_eq!(message("for i = 1, do end"), "Expected an expression, but found 'do' at line 1, column 12");
+        assert_eq!(message("for i = 1, 2, do end"), "Expected an expression, but found 'do' at line 1, column 15");
+        assert_eq!(message("for i = 1, 2\nprint(i) end"), "Expected ',' or 'do', but found identifier 'print' at line 2, column 1");
+        assert_eq!(message("for i = 1"), "Expected ',', but found end of file at line 1, column 10");
+        assert_eq!(message("for a, b = 1, 2 do end"), "Expected 'in', but found '=' at line 1, column 10");
+        assert_eq!(completeness(&tokenize("for k, v").unwrap()), Completeness::Incomplete);
+    }
+
+    #[test]
+    fn assignments() {
+        use dump::dump_chunk;
+
+        let check = |source: &str| parse_structured(&tokenize(source).unwrap()).map(|chunk| dump_chunk(&chunk));
+
+        assert_eq!(check("x = 1").unwrap(), "(chunk\n  (assign (x) (1)))");
+        assert_eq!(check("x, y = f(), 2").unwrap(), "(chunk\n  (assign (x y) ((call f) 2)))");
+        assert_eq!(check("a, b = b, a").unwrap(), "(chunk\n  (assign (a b) (b a)))");
+
+        // Both start with a name, but a call is still a call.
+        assert_eq!(check("f(x)").unwrap(), "(chunk\n  (call f x))");
+        assert_eq!(check("f(x) x = f(x)").unwrap(), "(chunk\n  (call f x)\n  (assign (x) ((call f x))))");
+
+        // Unlike a local, an assignment needs something to assign.
+        assert!(check("x").is_err());
+        assert!(check("x =").is_err());
+        assert!(check("x, = 1").is_err());
+    }
+
+    #[test]
+    fn assignment_targets() {
+        use dump::dump_chunk;
+
+        let check = |source: &str| parse_structured(&tokenize(source).unwrap()).map(|chunk| dump_chunk(&chunk));
+
+        assert_eq!(check("t.x = 1").unwrap(), "(chunk\n  (assign ((field t x)) (1)))");
+        assert_eq!(check("t[k] = v").unwrap(), "(chunk\n  (assign ((index t k)) (v)))");
+        assert_eq!(check("a.b[c].d = e").unwrap(), "(chunk\n  (assign ((field (index (field a b) c) d)) (e)))");
+        assert_eq!(check("t[i + 1] = t").unwrap(), "(chunk\n  (assign ((index t (+ i 1))) (t)))");
+        assert_eq!(check("x, t.y, t[\"z\"] = 1, 2, 3").unwrap(), "(chunk\n  (assign (x (field t y) (index t \"z\")) (1 2 3)))");
+
+        // Targets can be built on any prefix expression, not just names.
+        assert_eq!(check("f().x = 1").unwrap(), "(chunk\n  (assign ((field (call f) x)) (1)))");
+        assert_eq!(check("(t).x = 1").unwrap(), "(chunk\n  (assign ((field (paren t) x)) (1)))");
+        assert_eq!(check("t:get(k)[1], x = 1, 2").unwrap(), "(chunk\n  (assign ((index (method t get k) 1) x) (1 2)))");
+        assert_eq!(check("f()(x).y = 1").unwrap(), "(chunk\n  (assign ((field (call (call f) x) y)) (1)))");
+
+        // Without a `;`, a paren on the next line calls whatever came before.
+        assert_eq!(check("f()\n(t).x = 1").unwrap(), "(chunk\n  (assign ((field (call (call f) t) x)) (1)))");
+        assert_eq!(check("f();\n(t).x = 1").unwrap(), "(chunk\n  (call f)\n  (assign ((field (paren t) x)) (1)))");
+
+        // Assigning to a call is an error, wherever it is in the list.
+        for &(source, start, end) in &[("f() = 1", 0, 3), ("x, t.f(a, b) = 1, 2", 3, 12), ("f(), x = 1", 0, 3), ("t:m(x) = 1", 0, 6), ("(t).f() = 1", 0, 7)] {
+            let message = check(source).unwrap_err();
+            assert_eq!(message.code, MessageCode::AssignmentToCall, "{}", source);
+            assert_eq!(message.position.map(|position| position.bytes), Some(start), "{}", source);
+            assert_eq!(message.end.map(|position| position.bytes), Some(end), "{}", source);
+        }
+
+        let message = parse_from_tokens(&tokenize("x = 1\nf() = 2").unwrap()).unwrap_err();
+        assert_eq!(message, "Cannot assign to the result of a function call at line 2, column 1");
+
+        // A call that isn't assigned to is still a call.
+        assert_eq!(check("f(a) t.x = 1").unwrap(), "(chunk\n  (call f a)\n  (assign ((field t x)) (1)))");
+        assert!(check("f() == 1").is_err());
+        assert!(check("(t) = 1").is_err());
+    }
+
+    #[test]
+    fn call_targets() {
+        use dump::dump_chunk;
+
+        let check = |source: &str| parse_structured(&tokenize(source).unwrap()).map(|chunk| dump_chunk(&chunk));
+
+        assert_eq!(check("os.execute(x)").unwrap(), "(chunk\n  (call (field os execute) x))");
+        assert_eq!(check("t[1].f()").unwrap(), "(chunk\n  (call (field (index t 1) f)))");
+        assert_eq!(check("io:write(1, 2)").unwrap(), "(chunk\n  (method io write 1 2))");
+        assert_eq!(check("f()(x)").unwrap(), "(chunk\n  (call (call f) x))");
+        assert_eq!(check("(a .. b):upper()").unwrap(), "(chunk\n  (method (paren (.. a b)) upper))");
+        assert_eq!(check("x = io.stdout:write(a.b())").unwrap(), "(chunk\n  (assign (x) ((method (field io stdout) write (call (field a b))))))");
+
+        // Fields and indexes can be read as well as called.
+        assert_eq!(check("print(t.x, t[1])").unwrap(), "(chunk\n  (call print (field t x) (index t 1)))");
+        assert_eq!(check("local x = t.a.b").unwrap(), "(chunk\n  (local (x) ((field (field t a) b))))");
+        assert!(check("t:m").is_err());
+        assert!(check("t:(x)").is_err());
+    }
+
+    #[test]
+    fn negative_numbers_are_unary_minus() {
+        use dump::dump_expression;
+
+        let check = |source: &str| dump_expression(&parse_expression_from_tokens(&tokenize(source).unwrap()).unwrap());
+
+        assert_eq!(check("x-1"), "(- x 1)");
+        assert_eq!(check("x - -1"), "(- x (- 1))");
+        assert_eq!(check("-0x10"), "(- 0x10)");
+        assert_eq!(check("-2^2"), "(- (^ 2 2))");
+    }
+}
@@ -1,3 +1,10 @@
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+use std::time::Duration;
+
+use ast::FunctionName;
+use cst::NodeKind;
+use message::{MessageCode, ParseMessage};
 use tokenizer::Token;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -7,45 +14,249 @@ pub enum ParseAbort {
     NoMatch,
 
     /// Indicates that the parser was unable to match the input and hit the
-    /// error described by the returned string.
+    /// error described by the returned message.
     #[allow(dead_code)]
-    Error(String)
+    Error(Box<ParseMessage>)
+}
+
+/// Counters describing the work done during one call to `parse_with_metrics`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseMetrics {
+    /// Tokens stepped over, counting a token again each time it's re-read
+    /// after backtracking.
+    pub tokens_consumed: usize,
+
+    /// Statements built, including ones later thrown away by backtracking.
+    pub statements_built: usize,
+
+    /// Expression nodes built, including ones later thrown away by
+    /// backtracking.
+    pub expressions_built: usize,
+
+    /// Alternatives that were tried and didn't match.
+    pub backtracks: usize,
+
+    /// The deepest nesting of blocks and expressions reached.
+    pub max_depth: usize,
+
+    /// The index of the furthest token the parser looked at.
+    pub furthest_token: usize,
+
+    /// Approximately how many bytes the AST takes up: the size of every node
+    /// stored in a `Vec` or `Box`, plus the length of every name, number and
+    /// string. Nodes thrown away by backtracking are counted too.
+    pub ast_bytes: usize,
+
+    /// Time spent parsing.
+    pub elapsed: Duration,
+}
+
+/// A limit on how many bytes the AST may take up, counted the same way as
+/// [ParseMetrics::ast_bytes].
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    used: Cell<usize>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            used: Cell::new(0),
+        }
+    }
+
+    /// The bytes counted so far. Once this goes over the limit, nothing more
+    /// is counted.
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+}
+
+/// Follows the statements and blocks of a chunk as they're parsed, to build
+/// something other than the AST in the same pass. Positions are indices into
+/// the token stream.
+///
+/// Nothing is taken back when the parser backtracks. That's only safe because
+/// statements are told apart before their first block: once a block has been
+/// parsed, the statement around it either matches or the whole parse fails.
+pub trait SyntaxSink {
+    /// A block starts at `start`, either the whole chunk or the body of the
+    /// innermost statement that was entered.
+    fn start_block(&mut self, start: usize);
+
+    /// The innermost block ends just before `end`.
+    fn finish_block(&mut self, end: usize);
+
+    /// A statement of `kind` that starts at `start` is about to parse its
+    /// first block. Statements without blocks aren't entered.
+    fn enter_statement(&mut self, kind: NodeKind, start: usize);
+
+    /// Like `enter_statement` for a function declaration, whose `name` is
+    /// made up of the tokens in `name_tokens`.
+    #[allow(unused_variables)]
+    fn enter_function(&mut self, start: usize, name: &FunctionName, name_tokens: Range<usize>) {
+        self.enter_statement(NodeKind::FunctionDeclaration, start);
+    }
+
+    /// A statement of `kind` covering `start..end` was parsed. This is the
+    /// first the sink hears of statements without blocks.
+    fn finish_statement(&mut self, kind: NodeKind, start: usize, end: usize);
+
+    /// Whether the sink has heard enough. Once it has, no more statements
+    /// are parsed, so the parse fails wherever it had got to.
+    fn stopped(&self) -> bool {
+        false
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct ParseState<'a> {
+/// How deeply blocks and expressions can nest before parsing fails instead of
+/// overflowing the stack. This is the same limit the reference implementation
+/// puts on nested C calls.
+pub const MAX_DEPTH: usize = 200;
+
+#[derive(Clone, Copy)]
+pub struct ParseState<'a, 'h> {
     pub tokens: &'a [Token<'a>],
     pub position: usize,
+    pub depth: usize,
+
+    /// Where to count the work done, if anywhere. Off by default so the plain
+    /// entrypoints only pay for checking this.
+    pub metrics: Option<&'h RefCell<ParseMetrics>>,
+
+    /// How many bytes the AST may take up before parsing fails. This is
+    /// checked whether or not metrics are being collected.
+    pub memory_budget: Option<&'h MemoryBudget>,
+
+    /// What to tell about the structure of the chunk, if anything. When this
+    /// is set, blocks don't keep their statements.
+    pub sink: Option<&'h RefCell<dyn SyntaxSink + 'h>>,
 }
 
-impl<'a> ParseState<'a> {
-    pub fn new(tokens: &'a [Token]) -> ParseState<'a> {
+impl<'a, 'h> ParseState<'a, 'h> {
+    pub fn new(tokens: &'a [Token]) -> ParseState<'a, 'h> {
         ParseState {
             tokens,
             position: 0,
+            depth: 0,
+            metrics: None,
+            memory_budget: None,
+            sink: None,
+        }
+    }
+
+    pub fn with_metrics(&self, metrics: &'h RefCell<ParseMetrics>) -> ParseState<'a, 'h> {
+        ParseState {
+            metrics: Some(metrics),
+            ..*self
+        }
+    }
+
+    /// Updates the metrics, if this state is collecting them.
+    pub fn record<F: FnOnce(&mut ParseMetrics)>(&self, update: F) {
+        if let Some(metrics) = self.metrics {
+            update(&mut metrics.borrow_mut());
+        }
+    }
+
+    /// Tells the sink about the structure being parsed, if there is one.
+    pub fn notify<F: FnOnce(&mut dyn SyntaxSink)>(&self, event: F) {
+        if let Some(sink) = self.sink {
+            event(&mut *sink.borrow_mut());
+        }
+    }
+
+    /// Whether the sink, if there is one, wants parsing to stop.
+    pub fn stopped(&self) -> bool {
+        self.sink.is_some_and(|sink| sink.borrow().stopped())
+    }
+
+    /// Counts `bytes` toward the size of the AST, failing if that goes over the
+    /// memory budget.
+    pub fn allocate(&self, bytes: usize) -> Result<(), ParseAbort> {
+        self.record(|metrics| metrics.ast_bytes += bytes);
+
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+
+        let used = budget.used.get();
+
+        if used <= budget.limit {
+            budget.used.set(used + bytes);
         }
+
+        if budget.used.get() > budget.limit {
+            let mut message = ParseMessage::new(MessageCode::OverMemoryBudget);
+            message.limit = budget.limit;
+            return Err(ParseAbort::Error(Box::new(message)));
+        }
+
+        Ok(())
     }
 
     pub fn peek(&self) -> Option<&'a Token<'a>> {
+        let position = self.position;
+        self.record(|metrics| metrics.furthest_token = metrics.furthest_token.max(position));
+
         self.tokens.get(self.position)
     }
 
-    pub fn advance(&self, amount: usize) -> ParseState<'a> {
+    pub fn advance(&self, amount: usize) -> ParseState<'a, 'h> {
+        self.record(|metrics| metrics.tokens_consumed += amount);
+
         ParseState {
-            tokens: self.tokens,
             position: self.position + amount,
+            ..*self
+        }
+    }
+
+    /// Goes one level deeper into the tree. Every successful `enter` must be
+    /// paired with an `exit` on the state the nested parser returns.
+    pub fn enter(&self) -> Result<ParseState<'a, 'h>, ParseAbort> {
+        if self.depth >= MAX_DEPTH {
+            let mut message = ParseMessage::new(MessageCode::NestedTooDeeply);
+            message.limit = MAX_DEPTH;
+            return Err(ParseAbort::Error(Box::new(message)));
+        }
+
+        let depth = self.depth + 1;
+        self.record(|metrics| metrics.max_depth = metrics.max_depth.max(depth));
+
+        Ok(ParseState {
+            depth,
+            ..*self
+        })
+    }
+
+    pub fn exit(&self) -> ParseState<'a, 'h> {
+        ParseState {
+            depth: self.depth - 1,
+            ..*self
         }
     }
 }
 
+/// The error for a repeating parser whose item matched without consuming any
+/// tokens, which would otherwise loop forever.
+fn no_progress<'a, P: Parser<'a>>(parser: &P) -> ParseAbort {
+    let mut message = ParseMessage::new(MessageCode::NoProgress);
+    message.construct = Some(parser.item_name());
+    ParseAbort::Error(Box::new(message))
+}
+
 pub trait Parser<'a> {
     type Item: 'a;
 
+    #[allow(dead_code)]
     fn item_name(&self) -> String {
         "UNNAMED_ITEM".to_string()
     }
 
-    fn parse(&self, state: ParseState<'a>) -> Result<(ParseState<'a>, Self::Item), ParseAbort>;
+    fn parse<'h>(&self, state: ParseState<'a, 'h>) -> Result<(ParseState<'a, 'h>, Self::Item), ParseAbort>;
 }
 
 #[macro_export]
@@ -55,7 +266,7 @@ macro_rules! parse_first_of {
             $(
                 match $parser.parse($state) {
                     Ok((state, value)) => return Ok((state, $constructor(value))),
-                    Err(ParseAbort::NoMatch) => {},
+                    Err(ParseAbort::NoMatch) => $state.record(|metrics| metrics.backtracks += 1),
                     Err(ParseAbort::Error(message)) => return Err(ParseAbort::Error(message)),
                 }
             )*
@@ -71,7 +282,7 @@ macro_rules! define_parser {
         impl<'state> Parser<'state> for $name {
             type Item = $result_type;
 
-            fn parse(&self, state: ParseState<'state>) -> Result<(ParseState<'state>, Self::Item), ParseAbort> {
+            fn parse<'h>(&self, state: ParseState<'state, 'h>) -> Result<(ParseState<'state, 'h>, Self::Item), ParseAbort> {
                 $body(self, state)
             }
         }
@@ -87,12 +298,13 @@ impl<'a, ItemParser: Parser<'a>> Parser<'a> for ZeroOrMore<ItemParser> {
         format!("zero or more {}", self.0.item_name())
     }
 
-    fn parse(&self, state: ParseState<'a>) -> Result<(ParseState<'a>, Self::Item), ParseAbort> {
+    fn parse<'h>(&self, state: ParseState<'a, 'h>) -> Result<(ParseState<'a, 'h>, Self::Item), ParseAbort> {
         let mut values = Vec::new();
         let mut state = state;
 
         loop {
             match self.0.parse(state) {
+                Ok((next_state, _)) if next_state.position == state.position => return Err(no_progress(self)),
                 Ok((next_state, value)) => {
                     values.push(value);
                     state = next_state;
@@ -115,22 +327,29 @@ impl<'a, ItemParser: Parser<'a>, DelimiterParser: Parser<'a>> Parser<'a> for Del
         format!("one or more {} separated by {}", self.0.item_name(), self.1.item_name())
     }
 
-    fn parse(&self, state: ParseState<'a>) -> Result<(ParseState<'a>, Self::Item), ParseAbort> {
+    fn parse<'h>(&self, state: ParseState<'a, 'h>) -> Result<(ParseState<'a, 'h>, Self::Item), ParseAbort> {
         let mut values = Vec::new();
 
         let (mut state, value) = self.0.parse(state)?;
         values.push(value);
 
         loop {
+            let start = state.position;
+
             match self.1.parse(state) {
                 Ok((next_state, _)) => {
                     state = next_state;
                 },
-                Err(_) => break,
+                Err(ParseAbort::NoMatch) => break,
+                Err(ParseAbort::Error(message)) => return Err(ParseAbort::Error(message)),
             }
 
             let (next_state, value) = self.0.parse(state)?;
 
+            if next_state.position == start {
+                return Err(no_progress(self));
+            }
+
             state = next_state;
             values.push(value);
         }
@@ -148,7 +367,7 @@ impl<'a, ItemParser: Parser<'a>, DelimiterParser: Parser<'a>> Parser<'a> for Del
         format!("zero or more {} separated by {}", self.0.item_name(), self.1.item_name())
     }
 
-    fn parse(&self, state: ParseState<'a>) -> Result<(ParseState<'a>, Self::Item), ParseAbort> {
+    fn parse<'h>(&self, state: ParseState<'a, 'h>) -> Result<(ParseState<'a, 'h>, Self::Item), ParseAbort> {
         let mut values = Vec::new();
 
         let mut state = match self.0.parse(state) {
@@ -156,15 +375,19 @@ impl<'a, ItemParser: Parser<'a>, DelimiterParser: Parser<'a>> Parser<'a> for Del
                 values.push(value);
                 next_state
             },
-            Err(_) => return Ok((state, Vec::new())),
+            Err(ParseAbort::NoMatch) => return Ok((state, Vec::new())),
+            Err(ParseAbort::Error(message)) => return Err(ParseAbort::Error(message)),
         };
 
         loop {
+            let start = state.position;
+
             match self.1.parse(state) {
                 Ok((next_state, _)) => {
                     state = next_state;
                 },
-                Err(_) => break,
+                Err(ParseAbort::NoMatch) => break,
+                Err(ParseAbort::Error(message)) => return Err(ParseAbort::Error(message)),
             }
 
             let (next_state, value) = match self.0.parse(state) {
@@ -181,6 +404,10 @@ impl<'a, ItemParser: Parser<'a>, DelimiterParser: Parser<'a>> Parser<'a> for Del
                 Err(ParseAbort::Error(message)) => return Err(ParseAbort::Error(message))
             };
 
+            if next_state.position == start {
+                return Err(no_progress(self));
+            }
+
             state = next_state;
             values.push(value);
         }
@@ -189,6 +416,22 @@ impl<'a, ItemParser: Parser<'a>, DelimiterParser: Parser<'a>> Parser<'a> for Del
     }
 }
 
+/// Matches like the inner parser, but drops what it built straight away.
+pub struct Discard<InnerParser>(pub InnerParser);
+
+impl<'a, ItemParser: Parser<'a>> Parser<'a> for Discard<ItemParser> {
+    type Item = ();
+
+    fn item_name(&self) -> String {
+        self.0.item_name()
+    }
+
+    fn parse<'h>(&self, state: ParseState<'a, 'h>) -> Result<(ParseState<'a, 'h>, Self::Item), ParseAbort> {
+        let (state, _) = self.0.parse(state)?;
+        Ok((state, ()))
+    }
+}
+
 pub struct Optional<InnerParser>(pub InnerParser);
 
 impl<'a, ItemParser: Parser<'a>> Parser<'a> for Optional<ItemParser> {
@@ -198,7 +441,7 @@ impl<'a, ItemParser: Parser<'a>> Parser<'a> for Optional<ItemParser> {
         format!("optional {}", self.0.item_name())
     }
 
-    fn parse(&self, state: ParseState<'a>) -> Result<(ParseState<'a>, Self::Item), ParseAbort> {
+    fn parse<'h>(&self, state: ParseState<'a, 'h>) -> Result<(ParseState<'a, 'h>, Self::Item), ParseAbort> {
         match self.0.parse(state) {
             Ok((new_state, matched_value)) => Ok((new_state, Some(matched_value))),
             Err(ParseAbort::NoMatch) => Ok((state, None)),
@@ -212,15 +455,44 @@ pub struct Or<'a, InnerParser: 'a>(pub &'a [InnerParser]);
 impl<'a, InnerParser: Parser<'a>> Parser<'a> for Or<'a, InnerParser> {
     type Item = InnerParser::Item;
 
-    fn parse(&self, state: ParseState<'a>) -> Result<(ParseState<'a>, Self::Item), ParseAbort> {
+    fn parse<'h>(&self, state: ParseState<'a, 'h>) -> Result<(ParseState<'a, 'h>, Self::Item), ParseAbort> {
         for parser in self.0 {
             match parser.parse(state) {
                 Ok((new_state, matched_value)) => return Ok((new_state, matched_value)),
-                Err(ParseAbort::NoMatch) => (),
+                Err(ParseAbort::NoMatch) => state.record(|metrics| metrics.backtracks += 1),
                 Err(ParseAbort::Error(message)) => return Err(ParseAbort::Error(message)),
             }
         }
 
         Err(ParseAbort::NoMatch)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ParseNothing;
+    define_parser!(ParseNothing, (), |_, state| Ok((state, ())));
+
+    #[test]
+    fn zero_progress_is_an_error() {
+        let state = ParseState::new(&[]);
+
+        assert!(matches!(ZeroOrMore(ParseNothing).parse(state), Err(ParseAbort::Error(_))));
+        assert!(matches!(DelimitedOneOrMore(ParseNothing, ParseNothing).parse(state), Err(ParseAbort::Error(_))));
+        assert!(matches!(DelimitedZeroOrMore(ParseNothing, ParseNothing, true).parse(state), Err(ParseAbort::Error(_))));
+    }
+
+    #[test]
+    fn depth_limit() {
+        let mut state = ParseState::new(&[]);
+
+        for _ in 0..MAX_DEPTH {
+            state = state.enter().unwrap();
+        }
+
+        assert!(state.enter().is_err());
+        assert_eq!(state.exit().depth, MAX_DEPTH - 1);
+    }
+}
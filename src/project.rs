@@ -0,0 +1,249 @@
+//! Parsing of many files at once, like every Lua file in a project.
+//!
+//! A single broken or unreadable file never aborts the batch, not even one
+//! that makes the parser panic; its failure is recorded next to the
+//! successful results instead.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ast::Chunk;
+use batch::{panic_message, run_batch, thread_count, BatchOptions};
+use parser::parse_from_tokens;
+use tokenizer::{tokenize_bytes, TokenizeError};
+
+/// Controls which files are picked up by [parse_project] and how they're parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectOptions {
+    /// File extensions, without the leading dot, that are considered Lua
+    /// source files when walking a directory.
+    pub extensions: Vec<String>,
+
+    /// Whether files should be parsed on multiple threads.
+    pub parallel: bool,
+}
+
+impl Default for ProjectOptions {
+    fn default() -> ProjectOptions {
+        ProjectOptions {
+            extensions: vec!["lua".to_string()],
+            parallel: false,
+        }
+    }
+}
+
+/// The reason a single file in a project failed to parse.
+#[derive(Debug)]
+pub enum FileError {
+    /// The file or directory couldn't be read.
+    Io(io::Error),
+
+    /// The file was read, but couldn't be tokenized.
+    Tokenize(TokenizeError),
+
+    /// The file was tokenized, but couldn't be parsed.
+    Parse(String),
+
+    /// The parser panicked. Holds the panic message, if it was a string.
+    Panic(String),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Io(err) => write!(f, "could not read file: {}", err),
+            FileError::Tokenize(err) => write!(f, "could not tokenize file: {}", err),
+            FileError::Parse(message) => write!(f, "could not parse file: {}", message),
+            FileError::Panic(message) => write!(f, "the parser panicked: {}", message),
+        }
+    }
+}
+
+impl error::Error for FileError {}
+
+pub type FileResult = Result<Chunk<'static>, FileError>;
+
+/// The results of parsing a set of files, keyed by path.
+///
+/// Iteration is always in path order, regardless of the order files were
+/// discovered or parsed in.
+#[derive(Debug, Default)]
+pub struct ProjectParse {
+    files: BTreeMap<PathBuf, FileResult>,
+    unreadable: Vec<(PathBuf, io::Error)>,
+}
+
+impl ProjectParse {
+    /// Returns the result for the given path, if it was part of the project.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&FileResult> {
+        self.files.get(path.as_ref())
+    }
+
+    /// Iterates over every file in the project in path order.
+    pub fn iter<'a>(&'a self) -> btree_map::Iter<'a, PathBuf, FileResult> {
+        self.files.iter()
+    }
+
+    /// Iterates over only the files that failed, in path order.
+    pub fn errors(&self) -> impl Iterator<Item = (&PathBuf, &FileError)> {
+        self.files.iter().filter_map(|(path, result)| match result {
+            Ok(_) => None,
+            Err(err) => Some((path, err)),
+        })
+    }
+
+    /// Returns every directory that couldn't be read while walking the
+    /// project, and every entry of a directory that couldn't be read, in the
+    /// order they were found. An entry's error comes with the path of its
+    /// directory, since the entry's own path isn't known, so a directory can
+    /// be listed more than once.
+    pub fn unreadable(&self) -> &[(PathBuf, io::Error)] {
+        &self.unreadable
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a ProjectParse {
+    type Item = (&'a PathBuf, &'a FileResult);
+    type IntoIter = btree_map::Iter<'a, PathBuf, FileResult>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter()
+    }
+}
+
+/// Reads, tokenizes, and parses a single file into an owned AST.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> FileResult {
+    let contents = fs::read(path).map_err(FileError::Io)?;
+    let tokens = tokenize_bytes(&contents).map_err(FileError::Tokenize)?;
+    let chunk = parse_from_tokens(&tokens).map_err(FileError::Parse)?;
+
+    Ok(chunk.into_owned())
+}
+
+/// Parses every file in the given list of paths.
+///
+/// Paths are used as given; no extension filtering is applied.
+pub fn parse_files<I>(paths: I, options: &ProjectOptions) -> ProjectParse
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+
+    let threads = if options.parallel {
+        thread_count(&BatchOptions::default())
+    } else {
+        1
+    };
+
+    ProjectParse {
+        files: parse_paths(paths, threads, |path| parse_file(path)).into_iter().collect(),
+        unreadable: Vec::new(),
+    }
+}
+
+/// Walks the directory at `root` recursively and parses every file whose
+/// extension is listed in `options`.
+///
+/// # Errors
+/// Returns an error only if `root` itself can't be read. Failures to read
+/// nested directories are recorded in [ProjectParse::unreadable].
+pub fn parse_project<P: AsRef<Path>>(root: P, options: &ProjectOptions) -> io::Result<ProjectParse> {
+    let mut paths = Vec::new();
+    let mut unreadable = Vec::new();
+
+    let entries = fs::read_dir(root.as_ref())?;
+    collect_paths(root.as_ref(), entries, options, &mut paths, &mut unreadable);
+
+    let mut project = parse_files(paths, options);
+    project.unreadable = unreadable;
+
+    Ok(project)
+}
+
+/// Symlinks to directories aren't followed, so a link that points back up the
+/// tree can't make the walk loop forever.
+fn collect_paths(directory: &Path, entries: fs::ReadDir, options: &ProjectOptions, paths: &mut Vec<PathBuf>, unreadable: &mut Vec<(PathBuf, io::Error)>) {
+    for entry in entries {
+        let (path, file_type) = match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))) {
+            Ok(result) => result,
+            Err(err) => {
+                unreadable.push((directory.to_path_buf(), err));
+                continue;
+            },
+        };
+
+        if file_type.is_dir() {
+            match fs::read_dir(&path) {
+                Ok(entries) => collect_paths(&path, entries, options, paths, unreadable),
+                Err(err) => unreadable.push((path, err)),
+            }
+        } else if has_lua_extension(&path, options) {
+            paths.push(path);
+        }
+    }
+}
+
+fn has_lua_extension(path: &Path, options: &ProjectOptions) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => options.extensions.iter().any(|wanted| wanted == extension),
+        None => false,
+    }
+}
+
+/// Runs `parse` on every file with the same thread pool as
+/// [parse_many][::batch::parse_many], turning a panic into that file's error.
+fn parse_paths(paths: Vec<PathBuf>, threads: usize, parse: fn(&Path) -> FileResult) -> Vec<(PathBuf, FileResult)> {
+    let items = paths.into_iter().map(|path| (path.clone(), path)).collect();
+
+    run_batch(items, threads, |path: PathBuf| parse(&path))
+        .into_iter()
+        .map(|(path, result, _)| (path, result.unwrap_or_else(|payload| Err(FileError::Panic(panic_message(payload))))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_or_panic(path: &Path) -> FileResult {
+        if path == Path::new("boom.lua") {
+            panic!("boom");
+        }
+
+        Ok(Chunk {
+            statements: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn panics_are_isolated() {
+        let paths = vec![PathBuf::from("a.lua"), PathBuf::from("boom.lua"), PathBuf::from("b.lua")];
+
+        for threads in [1, 4] {
+            let results: BTreeMap<_, _> = parse_paths(paths.clone(), threads, parse_or_panic).into_iter().collect();
+
+            assert_eq!(results.len(), 3);
+            assert!(results[Path::new("a.lua")].is_ok());
+            assert!(results[Path::new("b.lua")].is_ok());
+
+            match &results[Path::new("boom.lua")] {
+                Err(FileError::Panic(message)) => assert_eq!(message, "boom"),
+                other => panic!("Expected a panic error, got {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Structural search over an AST using patterns written as Lua expressions.
+//!
+//! A pattern is an expression that can contain placeholders:
+//!
+//! * `$name` matches any expression and binds it to `name`. If the same name
+//!   appears more than once, every occurrence must match an equivalent
+//!   expression.
+//! * `$_` matches any expression without binding it.
+//! * `$...`, as the last argument of a call, matches any remaining arguments.
+//!
+//! Matching ignores parentheses and the way literals are written, so
+//! `print((1))` matches `print($x)` with `x` bound to `1`, and `f(1.0)` matches
+//! `f(1)`.
+
+use std::collections::HashMap;
+
+use ast::*;
+use parser::parse_expression_from_tokens;
+use tokenizer::{tokenize, StringLiteral};
+use visit::{for_each_node, Node};
+
+const PLACEHOLDER_PREFIX: &str = "__query_";
+const ANY_ARGUMENTS: &str = "__query_any_arguments";
+
+/// A parsed pattern that can be searched for in a chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    expression: Expression<'static>,
+}
+
+/// A node that matched a pattern, along with the expressions bound to each of
+/// the pattern's named placeholders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'c, 'a: 'c> {
+    /// The matched node. Function calls that are used as statements are
+    /// reported as the statement itself.
+    pub node: Node<'c, 'a>,
+
+    pub bindings: HashMap<String, &'c Expression<'a>>,
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Pattern, String> {
+        let source = substitute_placeholders(source);
+        let tokens = tokenize(&source).map_err(|err| format!("Could not tokenize pattern: {}", err))?;
+        let expression = parse_expression_from_tokens(&tokens)?;
+
+        Ok(Pattern {
+            expression: expression.into_owned(),
+        })
+    }
+
+    /// Finds every node in the chunk that matches this pattern, in source
+    /// order. Matches can be nested inside of each other.
+    pub fn find<'c, 'a: 'c>(&self, chunk: &'c Chunk<'a>) -> Vec<Match<'c, 'a>> {
+        let mut matches = Vec::new();
+
+        for_each_node(chunk, &mut |node| {
+            let mut bindings = HashMap::new();
+
+            let matched = match (node, &self.expression) {
+                (Node::Expression(expression), pattern) => {
+                    match_expression(pattern, expression, &mut Some(&mut bindings))
+                },
+                (Node::Statement(Statement::FunctionCall(call)), Expression::FunctionCall(pattern)) => {
+                    match_call(pattern, call, &mut Some(&mut bindings))
+                },
+                (Node::Statement(_), _) => false,
+            };
+
+            if matched {
+                matches.push(Match {
+                    node,
+                    bindings,
+                });
+            }
+        });
+
+        matches
+    }
+}
+
+/// Parses `pattern` and finds every node in the chunk that matches it.
+pub fn find<'c, 'a: 'c>(chunk: &'c Chunk<'a>, pattern: &str) -> Result<Vec<Match<'c, 'a>>, String> {
+    Ok(Pattern::parse(pattern)?.find(chunk))
+}
+
+/// Replaces placeholders with identifiers so that the pattern can be parsed as
+/// an ordinary Lua expression.
+fn substitute_placeholders(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        if rest.starts_with("...") {
+            result.push_str(ANY_ARGUMENTS);
+            rest = &rest[3..];
+        } else {
+            result.push_str(PLACEHOLDER_PREFIX);
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+type Bindings<'b, 'c, 'a> = Option<&'b mut HashMap<String, &'c Expression<'a>>>;
+
+fn strip_parens<'c, 'a>(expression: &'c Expression<'a>) -> &'c Expression<'a> {
+    match expression {
+        Expression::ParenExpression(inner) => strip_parens(inner),
+        _ => expression,
+    }
+}
+
+fn placeholder_name<'p>(expression: &'p Expression) -> Option<&'p str> {
+    match expression {
+        Expression::Name(name) if name != ANY_ARGUMENTS && name.starts_with(PLACEHOLDER_PREFIX) => {
+            Some(&name[PLACEHOLDER_PREFIX.len()..])
+        },
+        _ => None,
+    }
+}
+
+/// Checks whether `target` matches `pattern`. Placeholders in `pattern` are
+/// only recognized when `bindings` is given; otherwise this compares two
+/// expressions for equivalence.
+fn match_expression<'c, 'a>(pattern: &Expression, target: &'c Expression<'a>, bindings: &mut Bindings<'_, 'c, 'a>) -> bool {
+    let pattern = strip_parens(pattern);
+    let target = strip_parens(target);
+
+    if let Some(bindings) = bindings {
+        if let Some(name) = placeholder_name(pattern) {
+            if name == "_" {
+                return true;
+            }
+
+            if let Some(bound) = bindings.get(name) {
+                return match_expression(bound, target, &mut None);
+            }
+
+            bindings.insert(name.to_string(), target);
+            return true;
+        }
+    }
+
+    match (pattern, target) {
+        (Expression::Nil, Expression::Nil) => true,
+        (Expression::VarArg, Expression::VarArg) => true,
+        (Expression::Bool(a), Expression::Bool(b)) => a == b,
+        (Expression::Number(a), Expression::Number(b)) => {
+            match (a.parse::<f64>(), b.parse::<f64>()) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => a == b,
+            }
+        },
+        (Expression::String(a), Expression::String(b)) => strings_match(a, b),
+        (Expression::Name(a), Expression::Name(b)) => a == b,
+        (Expression::Table(a), Expression::Table(b)) => {
+            a.items.len() == b.items.len()
+                && a.items.iter().zip(&b.items).all(|((a_key, a_value), (b_key, b_value))| {
+                    let keys_match = match (a_key, b_key) {
+                        (None, None) => true,
+                        (Some(TableKey::Name(a)), Some(TableKey::Name(b))) => a == b,
+                        (Some(TableKey::Expression(a)), Some(TableKey::Expression(b))) => {
+                            match_expression(a, b, bindings)
+                        },
+                        _ => false,
+                    };
+
+                    keys_match && match_expression(a_value, b_value, bindings)
+                })
+        },
+        (Expression::FunctionCall(a), Expression::FunctionCall(b)) => match_call(a, b, bindings),
+        (Expression::UnaryOp(a), Expression::UnaryOp(b)) => {
+            a.operator == b.operator && match_expression(&a.argument, &b.argument, bindings)
+        },
+        (Expression::BinaryOp(a), Expression::BinaryOp(b)) => {
+            a.operator == b.operator
+                && match_expression(&a.left, &b.left, bindings)
+                && match_expression(&a.right, &b.right, bindings)
+        },
+        _ => false,
+    }
+}
+
+fn match_call<'c, 'a>(pattern: &FunctionCall, target: &'c FunctionCall<'a>, bindings: &mut Bindings<'_, 'c, 'a>) -> bool {
+    if !match_expression(&pattern.name_expression, &target.name_expression, bindings) {
+        return false;
+    }
+
+    let mut arguments = &pattern.arguments[..];
+
+    let any_rest = bindings.is_some() && match arguments.last() {
+        Some(Expression::Name(name)) => name == ANY_ARGUMENTS,
+        _ => false,
+    };
+
+    if any_rest {
+        arguments = &arguments[..arguments.len() - 1];
+
+        if target.arguments.len() < arguments.len() {
+            return false;
+        }
+    } else if target.arguments.len() != arguments.len() {
+        return false;
+    }
+
+    arguments
+        .iter()
+        .zip(&target.arguments)
+        .all(|(pattern, target)| match_expression(pattern, target, bindings))
+}
+
+/// Compares string literals by their contents, ignoring which kind of quotes
+/// were used where the contents are written the same way.
+fn strings_match(a: &StringLiteral, b: &StringLiteral) -> bool {
+    match (a, b) {
+        (StringLiteral::LongForm { raw_content: a, .. }, StringLiteral::LongForm { raw_content: b, .. }) => a == b,
+        (StringLiteral::LongForm { .. }, _) | (_, StringLiteral::LongForm { .. }) => false,
+        (StringLiteral::DoubleQuote { raw_content: a }, StringLiteral::DoubleQuote { raw_content: b })
+        | (StringLiteral::DoubleQuote { raw_content: a }, StringLiteral::SingleQuote { raw_content: b })
+        | (StringLiteral::SingleQuote { raw_content: a }, StringLiteral::DoubleQuote { raw_content: b })
+        | (StringLiteral::SingleQuote { raw_content: a }, StringLiteral::SingleQuote { raw_content: b }) => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_from_tokens;
+
+    fn bound_names(found: &Match) -> Vec<(String, String)> {
+        let mut names: Vec<_> = found.bindings
+            .iter()
+            .map(|(name, expression)| {
+                let value = match expression {
+                    Expression::Name(value) | Expression::Number(value) => value.to_string(),
+                    other => format!("{:?}", other),
+                };
+
+                (name.clone(), value)
+            })
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn calls_with_bindings() {
+        let tokens = tokenize("connect(a, b)\nlocal x = connect(c, d, e)\nconnect(f)").unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+
+        let matches = find(&chunk, "connect($x, $_)").unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(matches!(matches[0].node, Node::Statement(Statement::FunctionCall(_))));
+        assert_eq!(bound_names(&matches[0]), vec![("x".to_string(), "a".to_string())]);
+
+        let matches = find(&chunk, "connect($first, $...)").unwrap();
+        let firsts: Vec<_> = matches.iter().map(|found| bound_names(found)[0].1.clone()).collect();
+        assert_eq!(firsts, vec!["a", "c", "f"]);
+    }
+
+    #[test]
+    fn nested_matches() {
+        let tokens = tokenize("local x = f(f(f(1)))").unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+
+        let matches = find(&chunk, "f($x)").unwrap();
+        assert_eq!(matches.len(), 3);
+
+        assert_eq!(bound_names(&matches[2]), vec![("x".to_string(), "1".to_string())]);
+    }
+
+    #[test]
+    fn repeated_placeholder_needs_equal_subtrees() {
+        let tokens = tokenize("local a = x + x\nlocal b = x + y\nlocal c = (g(1)) + g(1.0)\nlocal d = g(1) + g(2)").unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+
+        let matches = find(&chunk, "$v + $v").unwrap();
+        assert_eq!(matches.len(), 2);
+
+        let matches = find(&chunk, "$v + $w").unwrap();
+        assert_eq!(matches.len(), 4);
+    }
+
+    #[test]
+    fn non_literal_format_strings() {
+        let tokens = tokenize("format(\"%d\", 1)\nformat(fmt, 2)\nformat('%s', x)").unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+
+        let calls = find(&chunk, "format($format, $...)").unwrap();
+        let non_literal: Vec<_> = calls
+            .iter()
+            .filter(|found| !matches!(found.bindings["format"], Expression::String(_)))
+            .collect();
+
+        assert_eq!(non_literal.len(), 1);
+        assert_eq!(bound_names(non_literal[0])[0].1, "fmt");
+
+        assert_eq!(find(&chunk, "format('%d', $...)").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let tokens = tokenize("f()").unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+
+        assert!(find(&chunk, "f(").is_err());
+        assert!(find(&chunk, "local x = 1").is_err());
+    }
+}
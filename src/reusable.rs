@@ -0,0 +1,195 @@
+//! A parser that keeps its buffers between calls, for parsing many small
+//! sources one after another.
+
+use std::error;
+use std::fmt;
+
+use ast::{Chunk, Expression};
+use parser::{parse_expression_from_tokens, parse_from_tokens};
+use tokenizer::{detect_bytecode, tokenize_from_into, Token, TokenizeError, TokenizerOptions, START_POSITION};
+
+/// Settings for a [Parser].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParserOptions {
+    pub tokenizer: TokenizerOptions,
+}
+
+/// Why a [Parser] couldn't parse a source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParserError {
+    Tokenize(TokenizeError),
+    Parse(String),
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParserError::Tokenize(err) => write!(f, "could not tokenize source: {}", err),
+            ParserError::Parse(message) => write!(f, "could not parse source: {}", message),
+        }
+    }
+}
+
+impl error::Error for ParserError {}
+
+/// Tokenizes and parses sources while reusing the allocation of its token
+/// buffer from one call to the next.
+///
+/// A `Parser` holds no references between calls, so one can be kept per
+/// thread and reused indefinitely.
+#[derive(Debug, Default)]
+pub struct Parser {
+    options: ParserOptions,
+
+    /// Always empty between calls; only its capacity is kept.
+    tokens: Vec<Token<'static>>,
+}
+
+impl Parser {
+    pub fn new(options: ParserOptions) -> Parser {
+        Parser {
+            options,
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Parses `source` and hands the AST to `callback`, which can borrow from
+    /// it without anything being copied.
+    pub fn parse_with<R, F>(&mut self, source: &str, callback: F) -> Result<R, ParserError>
+    where
+        F: FnOnce(&Chunk) -> R,
+    {
+        self.parse_chunk(source, |chunk| callback(&chunk))
+    }
+
+    /// Parses `source` into an AST that owns its data.
+    pub fn parse(&mut self, source: &str) -> Result<Chunk<'static>, ParserError> {
+        self.parse_chunk(source, |chunk| chunk.into_owned())
+    }
+
+    /// Parses `source` as a single expression and hands it to `callback`.
+    pub fn parse_expression_with<R, F>(&mut self, source: &str, callback: F) -> Result<R, ParserError>
+    where
+        F: FnOnce(&Expression) -> R,
+    {
+        self.parse_single_expression(source, |expression| callback(&expression))
+    }
+
+    /// Parses `source` as a single expression that owns its data.
+    pub fn parse_expression(&mut self, source: &str) -> Result<Expression<'static>, ParserError> {
+        self.parse_single_expression(source, |expression| expression.into_owned())
+    }
+
+    /// Like [parse_with][Parser::parse_with], but hands over the chunk
+    /// itself, so that it can be made owned without a copy.
+    fn parse_chunk<R, F>(&mut self, source: &str, callback: F) -> Result<R, ParserError>
+    where
+        F: FnOnce(Chunk) -> R,
+    {
+        self.with_tokens(source, |tokens| {
+            parse_from_tokens(tokens)
+                .map(callback)
+                .map_err(ParserError::Parse)
+        })
+    }
+
+    fn parse_single_expression<R, F>(&mut self, source: &str, callback: F) -> Result<R, ParserError>
+    where
+        F: FnOnce(Expression) -> R,
+    {
+        self.with_tokens(source, |tokens| {
+            parse_expression_from_tokens(tokens)
+                .map(callback)
+                .map_err(ParserError::Parse)
+        })
+    }
+
+    /// Tokenizes `source` into the reused buffer and runs `callback` on the
+    /// tokens.
+    fn with_tokens<'s, R, F>(&mut self, source: &'s str, callback: F) -> Result<R, ParserError>
+    where
+        F: FnOnce(&[Token<'s>]) -> Result<R, ParserError>,
+    {
+        if let Some(format) = detect_bytecode(source.as_bytes()) {
+            return Err(ParserError::Tokenize(TokenizeError::PrecompiledBytecode { format }));
+        }
+
+        let mut tokens = relabel(&mut self.tokens);
+
+        let result = match tokenize_from_into(source, START_POSITION, &self.options.tokenizer, &mut tokens) {
+            Ok(()) => callback(&tokens),
+            Err(err) => Err(ParserError::Tokenize(err)),
+        };
+
+        tokens.clear();
+        self.tokens = relabel(&mut tokens);
+
+        result
+    }
+}
+
+/// Moves the allocation of an empty token buffer into a buffer for tokens of
+/// a different lifetime.
+///
+/// This relies on the standard library collecting a `vec::IntoIter` into a
+/// `Vec` of a type with the same size and alignment in place, reusing the
+/// allocation. It does, but doesn't promise to, so if that ever changes this
+/// only costs an allocation per call; `tests/reusable_parser.rs` checks that
+/// it doesn't.
+fn relabel<'a, 'b>(tokens: &mut Vec<Token<'a>>) -> Vec<Token<'b>> {
+    tokens.clear();
+
+    ::std::mem::take(tokens)
+        .into_iter()
+        .map(|_| unreachable!("The token buffer was cleared"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_from_tokens;
+    use tokenizer::tokenize;
+
+    #[test]
+    fn matches_one_shot_parsing() {
+        let mut parser = Parser::new(ParserOptions::default());
+
+        for source in &["local a = 1", "print(\"hi\")\n", "while x do\n\tf(x)\nend -- done"] {
+            let tokens = tokenize(source).unwrap();
+            assert_eq!(parser.parse(source).unwrap(), parse_from_tokens(&tokens).unwrap());
+        }
+
+        assert!(matches!(parser.parse("local = 1"), Err(ParserError::Parse(_))));
+        assert!(matches!(parser.parse("local a = \"oops"), Err(ParserError::Tokenize(_))));
+        assert_eq!(parser.parse("local b = 2").unwrap(), parse_from_tokens(&tokenize("local b = 2").unwrap()).unwrap());
+    }
+
+    #[test]
+    fn expressions() {
+        let mut parser = Parser::new(ParserOptions::default());
+
+        assert_eq!(parser.parse_expression("1 + 2").unwrap(), parse_expression_from_tokens(&tokenize("1 + 2").unwrap()).unwrap());
+        assert!(parser.parse_expression("local a").is_err());
+
+        let count = parser.parse_expression_with("{1, 2, 3}", |expression| match expression {
+            Expression::Table(table) => table.items.len(),
+            _ => 0,
+        });
+        assert_eq!(count, Ok(3));
+    }
+
+    #[test]
+    fn buffer_is_reused() {
+        let mut parser = Parser::new(ParserOptions::default());
+        parser.parse("local a, b, c = 1, 2, 3").unwrap();
+
+        let capacity = parser.tokens.capacity();
+        assert!(capacity >= 11);
+        assert!(parser.tokens.is_empty());
+
+        parser.parse("print(a)").unwrap();
+        assert_eq!(parser.tokens.capacity(), capacity);
+    }
+}
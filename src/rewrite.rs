@@ -0,0 +1,346 @@
+//! Small source rewrites made directly on the token stream, for when a
+//! change doesn't need an AST, like renaming an identifier everywhere or
+//! changing a string constant.
+//!
+//! Tokens carry the whitespace and comments before them, so rendering the
+//! edited stream keeps everything that wasn't edited exactly as it was.
+
+use std::error;
+use std::fmt;
+use std::mem;
+
+use tokenizer::{tokenize, Comment, Token, TokenKind, TokenPrefix, TokenizeError, START_POSITION};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    /// There's no token at the given index.
+    OutOfBounds {
+        index: usize,
+    },
+
+    /// The text given for an edit couldn't be tokenized.
+    Tokenize(TokenizeError),
+
+    /// The text given to [TokenStreamEditor::replace] wasn't exactly one
+    /// token.
+    NotOneToken,
+
+    /// The token at `index` would run together with the one before it, and
+    /// adding whitespace between them couldn't keep them apart.
+    BoundaryChanged {
+        index: usize,
+    },
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::OutOfBounds { index } => write!(f, "there is no token at index {}", index),
+            EditError::Tokenize(err) => write!(f, "could not tokenize replacement: {}", err),
+            EditError::NotOneToken => write!(f, "a replacement must be exactly one token"),
+            EditError::BoundaryChanged { index } => write!(f, "the token at index {} would merge with the one before it", index),
+        }
+    }
+}
+
+impl error::Error for EditError {}
+
+/// Tokenizes text to insert, splitting off the whitespace and comments at the
+/// end of it.
+fn tokenize_insertion<'a>(source: &'a str) -> Result<(Vec<Token<'a>>, Vec<TokenPrefix<'a>>), EditError> {
+    let mut tokens = tokenize(source).map_err(EditError::Tokenize)?;
+
+    let trailing = match tokens.last() {
+        Some(Token { kind: TokenKind::EndOfFile, .. }) => tokens.pop().unwrap().prefix,
+        _ => Vec::new(),
+    };
+
+    Ok((tokens, trailing))
+}
+
+fn end_of_file<'a>(prefix: Vec<TokenPrefix<'a>>) -> Token<'a> {
+    Token {
+        prefix,
+        kind: TokenKind::EndOfFile,
+        start_position: START_POSITION,
+        end_position: START_POSITION,
+    }
+}
+
+fn prepend<'a>(prefix: &mut Vec<TokenPrefix<'a>>, items: Vec<TokenPrefix<'a>>) {
+    let existing = mem::replace(prefix, items);
+    prefix.extend(existing);
+}
+
+/// Edits a token stream in place, keeping the positions of every token up to
+/// date after each edit.
+///
+/// Whenever an edit would make two tokens run together, like inserting `a`
+/// right after `b`, a space is added between them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenStreamEditor<'a> {
+    tokens: Vec<Token<'a>>,
+}
+
+impl<'a> TokenStreamEditor<'a> {
+    pub fn new(tokens: Vec<Token<'a>>) -> TokenStreamEditor<'a> {
+        TokenStreamEditor {
+            tokens,
+        }
+    }
+
+    pub fn tokens(&self) -> &[Token<'a>] {
+        &self.tokens
+    }
+
+    pub fn into_tokens(self) -> Vec<Token<'a>> {
+        self.tokens
+    }
+
+    /// Replaces the token at `index` with the single token in `text`,
+    /// keeping the whitespace and comments before it.
+    pub fn replace(&mut self, index: usize, text: &'a str) -> Result<(), EditError> {
+        self.check_index(index)?;
+
+        let kind = match &tokenize(text).map_err(EditError::Tokenize)?[..] {
+            [token] if token.prefix.is_empty() && token.kind != TokenKind::EndOfFile => token.kind.clone(),
+            _ => return Err(EditError::NotOneToken),
+        };
+
+        self.tokens[index].kind = kind;
+        self.settle(index, index + 1)
+    }
+
+    /// Inserts the tokens in `source`, along with any whitespace and
+    /// comments in it, right before the token at `index`, after the
+    /// whitespace and comments that already come before it. An `index` equal
+    /// to the number of tokens inserts at the end.
+    ///
+    /// `source` can also be just whitespace and comments.
+    pub fn insert_before(&mut self, index: usize, source: &'a str) -> Result<(), EditError> {
+        if index > self.tokens.len() {
+            return Err(EditError::OutOfBounds { index });
+        }
+
+        let (mut inserted, trailing) = tokenize_insertion(source)?;
+        let count = inserted.len();
+
+        match self.tokens.get_mut(index) {
+            Some(next) => {
+                let existing = mem::replace(&mut next.prefix, trailing);
+
+                // The whitespace and comments already before the token stay
+                // in front of everything that's inserted.
+                match inserted.first_mut() {
+                    Some(first) => prepend(&mut first.prefix, existing),
+                    None => prepend(&mut next.prefix, existing),
+                }
+            },
+            None if !trailing.is_empty() => inserted.push(end_of_file(trailing)),
+            None => {},
+        }
+
+        self.tokens.splice(index..index, inserted);
+        self.settle(index, index + count + 1)
+    }
+
+    /// Inserts the tokens in `source`, along with any whitespace and
+    /// comments in it, right after the token at `index`, before the
+    /// whitespace and comments that come after it.
+    pub fn insert_after(&mut self, index: usize, source: &'a str) -> Result<(), EditError> {
+        self.check_index(index)?;
+
+        let (mut inserted, trailing) = tokenize_insertion(source)?;
+        let count = inserted.len();
+
+        match self.tokens.get_mut(index + 1) {
+            Some(next) => prepend(&mut next.prefix, trailing),
+            None if !trailing.is_empty() => inserted.push(end_of_file(trailing)),
+            None => {},
+        }
+
+        self.tokens.splice(index + 1..index + 1, inserted);
+        self.settle(index + 1, index + count + 2)
+    }
+
+    /// Removes the token at `index` along with the whitespace and comments
+    /// before it.
+    pub fn delete(&mut self, index: usize) -> Result<(), EditError> {
+        self.check_index(index)?;
+
+        self.tokens.remove(index);
+        self.settle(index, index + 1)
+    }
+
+    /// The edited source.
+    pub fn render(&self) -> String {
+        self.tokens.iter().map(Token::to_source).collect()
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), EditError> {
+        if index < self.tokens.len() {
+            Ok(())
+        } else {
+            Err(EditError::OutOfBounds { index })
+        }
+    }
+
+    /// Keeps the tokens from `start` up to, but not including, `end` apart
+    /// from the tokens before them, then recomputes the positions of every
+    /// token from `start` on.
+    fn settle(&mut self, start: usize, end: usize) -> Result<(), EditError> {
+        for index in start..end.min(self.tokens.len()) {
+            self.separate(index)?;
+        }
+
+        let mut position = match start.checked_sub(1) {
+            Some(previous) => self.tokens[previous].end_position,
+            None => START_POSITION,
+        };
+
+        for token in &mut self.tokens[start..] {
+            for item in &token.prefix {
+                position = position.next_position(&item.to_source());
+            }
+
+            token.start_position = position;
+            position = position.next_position(&token.kind.to_source());
+            token.end_position = position;
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure the token at `index` still tokenizes as itself after the
+    /// token before it, adding whitespace if it doesn't.
+    fn separate(&mut self, index: usize) -> Result<(), EditError> {
+        if self.stays_separate(index) {
+            return Ok(());
+        }
+
+        let token = &mut self.tokens[index];
+
+        match token.prefix.last() {
+            None => token.prefix.push(TokenPrefix::Whitespace(" ".into())),
+
+            // A line comment runs until the end of the line, so it needs one.
+            Some(TokenPrefix::Comment(Comment::SingleLine { .. })) | Some(TokenPrefix::Shebang(_)) => token.prefix.push(TokenPrefix::Whitespace("\n".into())),
+
+            Some(_) => {},
+        }
+
+        if self.stays_separate(index) {
+            Ok(())
+        } else {
+            Err(EditError::BoundaryChanged { index })
+        }
+    }
+
+    fn stays_separate(&self, index: usize) -> bool {
+        let token = &self.tokens[index];
+
+        let (mut source, expected) = match index.checked_sub(1) {
+            Some(previous) => (self.tokens[previous].kind.to_source(), 2),
+            None => (String::new(), 1),
+        };
+
+        source.push_str(&token.to_source());
+
+        match tokenize(&source) {
+            Ok(retokenized) => {
+                retokenized.len() == expected
+                    && (expected == 1 || retokenized[0].kind == self.tokens[index - 1].kind)
+                    && retokenized[expected - 1].kind == token.kind
+                    && retokenized[expected - 1].prefix == token.prefix
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokenizer::Symbol;
+
+    fn kinds(source: &str) -> Vec<TokenKind<'static>> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind.into_owned())
+            .collect()
+    }
+
+    fn index_of(editor: &TokenStreamEditor, kind: &TokenKind) -> usize {
+        editor.tokens().iter().position(|token| &token.kind == kind).unwrap()
+    }
+
+    const SOURCE: &str = "local a = 1\nprint(a, 'old') -- keep me\nf(a)\n";
+
+    #[test]
+    fn multiple_edits() {
+        let tokens = tokenize(SOURCE).unwrap();
+        let mut editor = TokenStreamEditor::new(tokens);
+
+        // Rename `a` everywhere.
+        for index in 0..editor.tokens().len() {
+            if editor.tokens()[index].kind == TokenKind::Identifier("a".into()) {
+                editor.replace(index, "count").unwrap();
+            }
+        }
+
+        let old = index_of(&editor, &kinds("'old'")[0]);
+        editor.replace(old, "\"new\"").unwrap();
+
+        // Add a comment and an argument, and drop the last call's argument.
+        let print = index_of(&editor, &TokenKind::Identifier("print".into()));
+        editor.insert_before(print, "-- says hello\n").unwrap();
+        editor.insert_after(print + 3, " 2,").unwrap();
+
+        let last_count = editor.tokens().iter().rposition(|token| token.kind == TokenKind::Identifier("count".into())).unwrap();
+        editor.delete(last_count).unwrap();
+
+        let rendered = editor.render();
+        assert_eq!(rendered, "local count = 1\n-- says hello\nprint(count, 2, \"new\") -- keep me\nf()\n");
+        assert_eq!(kinds(&rendered), kinds("local count = 1 print(count, 2, \"new\") f()\n"));
+
+        // Positions were kept up to date along the way.
+        assert_eq!(tokenize(&rendered).unwrap(), editor.into_tokens());
+    }
+
+    #[test]
+    fn boundaries_get_whitespace() {
+        let mut editor = TokenStreamEditor::new(tokenize("f(b)").unwrap());
+
+        editor.insert_after(2, "a").unwrap();
+        assert_eq!(editor.render(), "f(b a)");
+
+        // `-` and `-` would become a comment.
+        let mut editor = TokenStreamEditor::new(tokenize("local x = -y").unwrap());
+        editor.replace(4, "-").unwrap();
+        assert_eq!(editor.render(), "local x = - -");
+
+        // A trailing line comment needs a newline after it.
+        let mut editor = TokenStreamEditor::new(tokenize("f()").unwrap());
+        editor.insert_before(1, "-- open").unwrap();
+        assert_eq!(editor.render(), "f-- open\n()");
+        assert_eq!(kinds(&editor.render()), kinds("f()"));
+
+        assert_eq!(editor.tokens()[1].kind, TokenKind::Symbol(Symbol::LeftParen));
+        assert_eq!(editor.tokens()[1].start_position.line, 2);
+    }
+
+    #[test]
+    fn errors() {
+        let mut editor = TokenStreamEditor::new(tokenize("f(a)").unwrap());
+
+        assert_eq!(editor.replace(1, "a b"), Err(EditError::NotOneToken));
+        assert_eq!(editor.replace(1, ""), Err(EditError::NotOneToken));
+        assert!(matches!(editor.replace(1, "'unclosed"), Err(EditError::Tokenize(_))));
+        assert_eq!(editor.delete(4), Err(EditError::OutOfBounds { index: 4 }));
+        assert_eq!(editor.insert_before(5, "x"), Err(EditError::OutOfBounds { index: 5 }));
+
+        assert_eq!(editor.render(), "f(a)");
+    }
+}
@@ -0,0 +1,570 @@
+//! Resolution of names to the local variables they refer to.
+//!
+//! Every name that's declared in a chunk gets a [Binding], and every read of a
+//! name is either attributed to a binding or recorded as a global access.
+
+use ast::*;
+
+/// An index into [ScopeInfo::scopes].
+pub type ScopeId = usize;
+
+/// An index into [ScopeInfo::bindings].
+pub type BindingId = usize;
+
+/// Identifies a statement at any depth of a chunk, by counting every statement
+/// in the order [for_each_statement](../visit/fn.for_each_statement.html)
+/// visits them, starting at 0.
+pub type StatementId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    /// A variable declared with `local`.
+    Local,
+
+    /// A function declared with `local function`.
+    LocalFunction,
+
+    /// A parameter of a function, including the implicit `self` of methods.
+    Parameter,
+
+    /// A variable introduced by a numeric or generic `for` loop.
+    LoopVariable,
+}
+
+/// A single declared name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub name: String,
+    pub kind: BindingKind,
+
+    /// The scope that the binding was declared in.
+    pub scope: ScopeId,
+
+    /// The innermost statement that declares the binding, like the function
+    /// declaration for a parameter.
+    pub statement: StatementId,
+
+    /// Whether the binding was introduced implicitly, like `self` in methods.
+    pub implicit: bool,
+
+    /// The number of times the binding is read.
+    pub reads: usize,
+
+    /// The number of times the binding is assigned to after its declaration.
+    pub writes: usize,
+
+    /// The binding with the same name that this binding hides, if any.
+    pub shadows: Option<BindingId>,
+
+    /// The global that this binding is another name for, if it was declared
+    /// with just a name as its value, like `local exec = loadstring`. Aliases
+    /// of aliases resolve to the original global.
+    pub alias_of: Option<String>,
+}
+
+/// A single read of a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub name: String,
+
+    /// The binding the name refers to, or `None` for a global.
+    pub binding: Option<BindingId>,
+
+    /// The innermost statement that the read is in.
+    pub statement: StatementId,
+}
+
+/// A call of a function reached through fields or a method of a name, like
+/// `io.stdout:write(x)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallReference {
+    /// The read of the name the path starts with, as an index into
+    /// [ScopeInfo::references].
+    pub reference: usize,
+
+    /// The fields and method after the name, each with the `.` or `:` before
+    /// it, like `.stdout:write`.
+    pub path: String,
+}
+
+/// A block of code that bindings can be declared in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    pub parent: Option<ScopeId>,
+
+    /// Whether this scope is the body of a function.
+    pub is_function: bool,
+
+    /// The bindings declared directly in this scope, in declaration order.
+    pub bindings: Vec<BindingId>,
+}
+
+/// The result of resolving every name in a chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeInfo {
+    /// All scopes in the chunk. The first is always the chunk itself.
+    pub scopes: Vec<Scope>,
+
+    /// All bindings in the chunk, in declaration order.
+    pub bindings: Vec<Binding>,
+
+    /// Every read of a name, in source order.
+    pub references: Vec<Reference>,
+
+    /// Every call through a path of fields or a method, in source order. Calls
+    /// of a plain name, and paths with an index in them, aren't included.
+    pub calls: Vec<CallReference>,
+
+    /// Names that were read without a binding in scope, in source order.
+    pub global_reads: Vec<String>,
+
+    /// Names that were assigned to without a binding in scope, in source order.
+    pub global_writes: Vec<String>,
+}
+
+impl ScopeInfo {
+    /// Returns every binding with the given name.
+    pub fn bindings_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = (BindingId, &'a Binding)> + 'a {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter(move |&(_, binding)| binding.name == name)
+    }
+}
+
+/// Resolves every name in the given chunk.
+pub fn analyze(chunk: &Chunk) -> ScopeInfo {
+    let mut analyzer = Analyzer {
+        info: ScopeInfo {
+            scopes: vec![Scope {
+                parent: None,
+                is_function: true,
+                bindings: Vec::new(),
+            }],
+            bindings: Vec::new(),
+            references: Vec::new(),
+            calls: Vec::new(),
+            global_reads: Vec::new(),
+            global_writes: Vec::new(),
+        },
+        current: 0,
+        statement: 0,
+        statement_count: 0,
+    };
+
+    analyzer.statements(chunk);
+
+    analyzer.info
+}
+
+struct Analyzer {
+    info: ScopeInfo,
+    current: ScopeId,
+
+    /// The innermost statement being analyzed.
+    statement: StatementId,
+    statement_count: usize,
+}
+
+impl Analyzer {
+    fn push_scope(&mut self, is_function: bool) {
+        self.info.scopes.push(Scope {
+            parent: Some(self.current),
+            is_function,
+            bindings: Vec::new(),
+        });
+
+        self.current = self.info.scopes.len() - 1;
+    }
+
+    fn pop_scope(&mut self) {
+        self.current = self.info.scopes[self.current].parent
+            .expect("Popped the root scope");
+    }
+
+    fn resolve(&self, name: &str) -> Option<BindingId> {
+        let mut scope = Some(self.current);
+
+        while let Some(id) = scope {
+            let found = self.info.scopes[id].bindings
+                .iter()
+                .rev()
+                .find(|&&binding| self.info.bindings[binding].name == name);
+
+            if let Some(&binding) = found {
+                return Some(binding);
+            }
+
+            scope = self.info.scopes[id].parent;
+        }
+
+        None
+    }
+
+    fn declare(&mut self, name: &str, kind: BindingKind, implicit: bool) {
+        self.declare_alias(name, kind, implicit, None);
+    }
+
+    fn declare_alias(&mut self, name: &str, kind: BindingKind, implicit: bool, alias_of: Option<String>) {
+        let shadows = self.resolve(name);
+
+        self.info.bindings.push(Binding {
+            name: name.to_string(),
+            kind,
+            scope: self.current,
+            statement: self.statement,
+            implicit,
+            reads: 0,
+            writes: 0,
+            shadows,
+            alias_of,
+        });
+
+        let id = self.info.bindings.len() - 1;
+        self.info.scopes[self.current].bindings.push(id);
+    }
+
+    fn read(&mut self, name: &str) {
+        let binding = self.resolve(name);
+
+        match binding {
+            Some(binding) => self.info.bindings[binding].reads += 1,
+            None => self.info.global_reads.push(name.to_string()),
+        }
+
+        self.info.references.push(Reference {
+            name: name.to_string(),
+            binding,
+            statement: self.statement,
+        });
+    }
+
+    /// Finds the global that `value` is another name for, if any.
+    fn alias_of(&self, value: Option<&Expression>) -> Option<String> {
+        match value {
+            Some(Expression::Name(name)) => match self.resolve(name) {
+                Some(binding) => self.info.bindings[binding].alias_of.clone(),
+                None => Some(name.to_string()),
+            },
+            Some(Expression::ParenExpression(inner)) => self.alias_of(Some(inner)),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, name: &str) {
+        match self.resolve(name) {
+            Some(binding) => self.info.bindings[binding].writes += 1,
+            None => self.info.global_writes.push(name.to_string()),
+        }
+    }
+
+    /// Only a plain name is written to. For a field or index, like `t.x`, the
+    /// table is read instead.
+    fn assignment_target(&mut self, target: &AssignmentTarget) {
+        match target {
+            AssignmentTarget::Name(name) => self.write(name),
+            _ => self.target_base(target),
+        }
+    }
+
+    fn target_base(&mut self, target: &AssignmentTarget) {
+        match target {
+            AssignmentTarget::Name(name) => self.read(name),
+            AssignmentTarget::FieldAccess { base, .. } => self.expression(base),
+            AssignmentTarget::IndexAccess { base, index } => {
+                self.expression(base);
+                self.expression(index);
+            },
+        }
+    }
+
+    fn block(&mut self, chunk: &Chunk) {
+        self.push_scope(false);
+        self.statements(chunk);
+        self.pop_scope();
+    }
+
+    fn statements(&mut self, chunk: &Chunk) {
+        for statement in &chunk.statements {
+            let outer = self.statement;
+            self.statement = self.statement_count;
+            self.statement_count += 1;
+
+            self.statement(statement);
+            self.statement = outer;
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assignment(assignment) => {
+                self.expressions(&assignment.values);
+
+                for target in &assignment.targets {
+                    self.assignment_target(target);
+                }
+            },
+            Statement::LocalAssignment(assignment) => {
+                self.expressions(&assignment.values);
+
+                let aliases: Vec<_> = (0..assignment.names.len())
+                    .map(|index| self.alias_of(assignment.values.get(index)))
+                    .collect();
+
+                for (name, alias_of) in assignment.names.iter().zip(aliases) {
+                    self.declare_alias(name, BindingKind::Local, false, alias_of);
+                }
+            },
+            Statement::FunctionCall(call) => self.function_call(call),
+            Statement::NumericFor(numeric_for) => {
+                self.expression(&numeric_for.start);
+                self.expression(&numeric_for.end);
+
+                if let Some(step) = &numeric_for.step {
+                    self.expression(step);
+                }
+
+                self.push_scope(false);
+                self.declare(&numeric_for.var, BindingKind::LoopVariable, false);
+                self.statements(&numeric_for.body);
+                self.pop_scope();
+            },
+            Statement::GenericFor(generic_for) => {
+                self.expressions(&generic_for.item_source);
+
+                self.push_scope(false);
+
+                for var in &generic_for.vars {
+                    self.declare(var, BindingKind::LoopVariable, false);
+                }
+
+                self.statements(&generic_for.body);
+                self.pop_scope();
+            },
+            Statement::IfStatement(if_statement) => {
+                self.expression(&if_statement.condition);
+                self.block(&if_statement.body);
+
+                for (condition, body) in &if_statement.else_if_branches {
+                    self.expression(condition);
+                    self.block(body);
+                }
+
+                if let Some(body) = &if_statement.else_branch {
+                    self.block(body);
+                }
+            },
+            Statement::WhileLoop(while_loop) => {
+                self.expression(&while_loop.condition);
+                self.block(&while_loop.body);
+            },
+            Statement::RepeatLoop(repeat_loop) => {
+                // The condition of a repeat loop can see locals from its body.
+                self.push_scope(false);
+                self.statements(&repeat_loop.body);
+                self.expression(&repeat_loop.condition);
+                self.pop_scope();
+            },
+            Statement::FunctionDeclaration(declaration) => self.function_declaration(declaration),
+            Statement::Do(body) => self.block(body),
+            Statement::Return(return_statement) => self.expressions(&return_statement.values),
+            Statement::Break => {},
+        }
+    }
+
+    fn function_declaration(&mut self, declaration: &FunctionDeclaration) {
+        let name = &declaration.name;
+
+        if declaration.local {
+            // `local function f` is in scope inside its own body.
+            if let Some(first) = name.segments.first() {
+                self.declare(first, BindingKind::LocalFunction, false);
+            }
+        } else if name.segments.len() == 1 && name.method.is_none() {
+            self.write(&name.segments[0]);
+        } else if let Some(first) = name.segments.first() {
+            self.read(first);
+        }
+
+        self.push_scope(true);
+
+        if name.method.is_some() {
+            self.declare("self", BindingKind::Parameter, true);
+        }
+
+        for parameter in &declaration.parameters {
+            self.declare(parameter, BindingKind::Parameter, false);
+        }
+
+        self.statements(&declaration.body);
+        self.pop_scope();
+    }
+
+    fn function_call(&mut self, call: &FunctionCall) {
+        // The name a path starts with is always the first thing it reads.
+        let reference = self.info.references.len();
+        self.expression(&call.name_expression);
+
+        if let Some(path) = call_path(call) {
+            self.info.calls.push(CallReference {
+                reference,
+                path,
+            });
+        }
+
+        self.expressions(&call.arguments);
+    }
+
+    fn expressions(&mut self, expressions: &[Expression]) {
+        for expression in expressions {
+            self.expression(expression);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Nil
+            | Expression::Bool(_)
+            | Expression::Number(_)
+            | Expression::String(_)
+            | Expression::VarArg => {},
+            Expression::Table(table) => {
+                for (key, value) in &table.items {
+                    if let Some(TableKey::Expression(key)) = key {
+                        self.expression(key);
+                    }
+
+                    self.expression(value);
+                }
+            },
+            Expression::FunctionCall(call) => self.function_call(call),
+            Expression::Name(name) => self.read(name),
+            Expression::Var(target) => self.target_base(target),
+            Expression::ParenExpression(inner) => self.expression(inner),
+            Expression::UnaryOp(op) => self.expression(&op.argument),
+            Expression::BinaryOp(op) => {
+                self.expression(&op.left);
+                self.expression(&op.right);
+            },
+        }
+    }
+}
+
+/// The fields and method that a call goes through after the name it starts
+/// with, if the call has any and none of them are indexes.
+fn call_path(call: &FunctionCall) -> Option<String> {
+    fn fields(expression: &Expression, path: &mut String) -> Option<()> {
+        match expression {
+            Expression::Name(_) => Some(()),
+            Expression::Var(target) => match &**target {
+                AssignmentTarget::FieldAccess { base, name } => {
+                    fields(base, path)?;
+                    path.push('.');
+                    path.push_str(name);
+                    Some(())
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    let mut path = String::new();
+    fields(&call.name_expression, &mut path)?;
+
+    if let Some(method) = &call.method {
+        path.push(':');
+        path.push_str(method);
+    }
+
+    if path.is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parser::parse_from_tokens;
+    use tokenizer::tokenize;
+
+    fn analyze_source(source: &str) -> ScopeInfo {
+        let tokens = tokenize(source).unwrap();
+        let chunk = parse_from_tokens(&tokens).unwrap();
+        analyze(&chunk)
+    }
+
+    #[test]
+    fn reads_resolve_to_locals() {
+        let info = analyze_source("local x = 1\nprint(x, y)");
+
+        assert_eq!(info.bindings.len(), 1);
+        assert_eq!(info.bindings[0].reads, 1);
+        assert_eq!(info.global_reads, vec!["print".to_string(), "y".to_string()]);
+    }
+
+    #[test]
+    fn calls_through_paths_keep_their_reference() {
+        let info = analyze_source("local io = io\nprint(x)\nio.stdout:write(os.time())\nt[1].f()");
+
+        let calls: Vec<_> = info.calls
+            .iter()
+            .map(|call| {
+                let reference = &info.references[call.reference];
+                (reference.name.as_str(), reference.binding, call.path.as_str())
+            })
+            .collect();
+
+        assert_eq!(calls, vec![
+            ("io", Some(0), ".stdout:write"),
+            ("os", None, ".time"),
+        ]);
+    }
+
+    #[test]
+    fn local_value_is_evaluated_before_declaration() {
+        let info = analyze_source("local x = 1\nlocal x = x");
+
+        assert_eq!(info.bindings[0].reads, 1);
+        assert_eq!(info.bindings[1].reads, 0);
+        assert_eq!(info.bindings[1].shadows, Some(0));
+    }
+
+    #[test]
+    fn loop_and_function_scopes() {
+        let info = analyze_source("for i = 1, 10 do print(i) end\nlocal function f(a) f(a) end\nprint(i)");
+
+        let kinds: Vec<_> = info.bindings.iter().map(|binding| (binding.name.as_str(), binding.kind, binding.reads)).collect();
+        assert_eq!(kinds, vec![
+            ("i", BindingKind::LoopVariable, 1),
+            ("f", BindingKind::LocalFunction, 1),
+            ("a", BindingKind::Parameter, 1),
+        ]);
+
+        assert_eq!(info.global_reads, vec!["print".to_string(), "print".to_string(), "i".to_string()]);
+    }
+
+    #[test]
+    fn methods_get_implicit_self() {
+        let info = analyze_source("function a.b:c() end");
+
+        assert_eq!(info.bindings.len(), 1);
+        assert_eq!(info.bindings[0].name, "self");
+        assert!(info.bindings[0].implicit);
+        assert_eq!(info.global_reads, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn aliases_and_references() {
+        let info = analyze_source("local a = print\nlocal b, c = (a), 1\nlocal print = 2\nlocal d = print\nb(c)");
+
+        let aliases: Vec<_> = info.bindings.iter().map(|binding| binding.alias_of.as_deref()).collect();
+        assert_eq!(aliases, vec![Some("print"), Some("print"), None, None, None]);
+
+        let references: Vec<_> = info.references.iter().map(|reference| (reference.name.as_str(), reference.binding)).collect();
+        assert_eq!(references, vec![("print", None), ("a", Some(0)), ("print", Some(3)), ("b", Some(1)), ("c", Some(2))]);
+    }
+}
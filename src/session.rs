@@ -0,0 +1,255 @@
+//! Parsing source that arrives in pieces, like input to a REPL or text read
+//! from a socket.
+//!
+//! Tokens that end before the last newline can't be changed by anything
+//! appended later, so each push only re-tokenizes the final, unsettled line
+//! (plus any string or comment still open from before it).
+
+use ast::Chunk;
+use parser::{completeness, parse_from_tokens, Completeness};
+use tokenizer::{tokenize_from, SourcePosition, Token, TokenKind, TokenizeError, TokenizerOptions};
+
+/// Accumulates source fragments and parses them once they form a chunk.
+#[derive(Debug)]
+pub struct ParseSession {
+    source: String,
+
+    /// Tokens that later input can't change.
+    settled: Vec<Token<'static>>,
+
+    /// Where the source after the settled tokens starts.
+    settled_end: SourcePosition,
+
+    /// The tokens after `settled_end`, or the reason they couldn't be
+    /// tokenized yet.
+    tail: Result<Vec<Token<'static>>, TokenizeError>,
+}
+
+impl Default for ParseSession {
+    fn default() -> ParseSession {
+        ParseSession::new()
+    }
+}
+
+impl ParseSession {
+    pub fn new() -> ParseSession {
+        ParseSession {
+            source: String::new(),
+            settled: Vec::new(),
+            settled_end: SourcePosition {
+                bytes: 0,
+                line: 1,
+                column: 1,
+            },
+            tail: Ok(Vec::new()),
+        }
+    }
+
+    /// The source pushed so far.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Appends a fragment of source. Fragments can split tokens anywhere.
+    pub fn push(&mut self, fragment: &str) {
+        self.source.push_str(fragment);
+
+        let tail = match tokenize_from(&self.source[self.settled_end.bytes..], self.settled_end, &TokenizerOptions::default()) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                self.tail = Err(err);
+                return;
+            },
+        };
+
+        let last_newline = match self.source.rfind('\n') {
+            Some(index) => index,
+            None => {
+                self.tail = Ok(tail.into_iter().map(Token::into_owned).collect());
+                return;
+            },
+        };
+
+        let mut unsettled = Vec::new();
+
+        for token in tail {
+            let settles = unsettled.is_empty()
+                && token.kind != TokenKind::EndOfFile
+                && token.end_position.bytes <= last_newline;
+
+            if settles {
+                self.settled_end = token.end_position;
+                self.settled.push(token.into_owned());
+            } else {
+                unsettled.push(token.into_owned());
+            }
+        }
+
+        self.tail = Ok(unsettled);
+    }
+
+    /// Checks whether the source pushed so far is a complete chunk, needs
+    /// more input, or is already invalid.
+    pub fn status(&self) -> Completeness {
+        let tail = match &self.tail {
+            Ok(tail) => tail,
+            Err(err) => return self.tokenize_status(err),
+        };
+
+        let tokens: Vec<Token> = self.settled.iter().chain(tail).cloned().collect();
+        completeness(&tokens)
+    }
+
+    /// Parses everything that was pushed.
+    pub fn finish(self) -> Result<Chunk<'static>, String> {
+        let tail = self.tail.map_err(|err| format!("could not tokenize source: {}", err))?;
+
+        let mut tokens = self.settled;
+        tokens.extend(tail);
+
+        let chunk = parse_from_tokens(&tokens)?;
+        Ok(chunk.into_owned())
+    }
+
+    fn tokenize_status(&self, err: &TokenizeError) -> Completeness {
+        match *err {
+            TokenizeError::UnclosedString { position } => {
+                // Quoted strings can't contain a raw newline, so one that has
+                // already hit a newline can't be closed by more input. One
+                // escaped with a backslash is fine.
+                let rest = &self.source[position.bytes..];
+                let quoted = rest.starts_with('"') || rest.starts_with('\'');
+
+                if quoted && has_raw_newline(&rest[1..]) {
+                    Completeness::Invalid(format!("could not tokenize source: {}", err))
+                } else {
+                    Completeness::Incomplete
+                }
+            },
+            TokenizeError::UnclosedComment { .. } => Completeness::Incomplete,
+            _ => Completeness::Invalid(format!("could not tokenize source: {}", err)),
+        }
+    }
+}
+
+/// Whether the contents of a quoted string have a newline that isn't escaped
+/// by a backslash.
+fn has_raw_newline(contents: &str) -> bool {
+    let mut backslashes = 0;
+
+    for character in contents.chars() {
+        match character {
+            '\\' => backslashes += 1,
+            '\n' if backslashes % 2 == 0 => return true,
+            // The `\r` of a `\r\n` doesn't end the run of backslashes.
+            '\r' => {},
+            _ => backslashes = 0,
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokenizer::tokenize;
+
+    const PROGRAM: &str = "local greeting = \"hello there\"\n\
+        -- say it a few times\n\
+        for i = 1, 3 do\n\
+        \tprint(greeting, [[long\nstring]], i)\n\
+        end\n\
+        local function shout(message)\n\
+        \tprint(message .. '!')\n\
+        end\n\
+        local joined = \"first\\\nsecond\"\n";
+
+    fn one_shot() -> Chunk<'static> {
+        parse_from_tokens(&tokenize(PROGRAM).unwrap()).unwrap().into_owned()
+    }
+
+    fn push_split(splits: &[usize]) -> ParseSession {
+        let mut session = ParseSession::new();
+        let mut start = 0;
+
+        for &end in splits.iter().chain(Some(&PROGRAM.len())) {
+            session.push(&PROGRAM[start..end]);
+            start = end;
+        }
+
+        session
+    }
+
+    #[test]
+    fn awkward_splits() {
+        let expected = one_shot();
+
+        // Mid-identifier, mid-string, mid-comment, mid-long-string, between
+        // the two characters of `..` and just after an escaped newline.
+        let interesting = [
+            9,
+            22,
+            38,
+            PROGRAM.find("[[").unwrap() + 4,
+            PROGRAM.find("..").unwrap() + 1,
+            PROGRAM.find("first").unwrap() + 7,
+        ];
+        assert_eq!(push_split(&interesting).finish().unwrap(), expected);
+
+        // The string continues past the escaped newline, so it can still be
+        // closed.
+        let mut session = ParseSession::new();
+        session.push(&PROGRAM[..interesting[5]]);
+        assert_eq!(session.status(), Completeness::Incomplete);
+
+        for size in 1..8 {
+            let splits: Vec<usize> = (1..PROGRAM.len()).filter(|index| index % size == 0).collect();
+            assert_eq!(push_split(&splits).finish().unwrap(), expected, "pushing {} bytes at a time", size);
+        }
+    }
+
+    #[test]
+    fn tokens_match_one_shot() {
+        let session = push_split(&(1..PROGRAM.len()).collect::<Vec<_>>());
+
+        let mut tokens = session.settled.clone();
+        tokens.extend(session.tail.unwrap());
+
+        assert_eq!(tokens, tokenize(PROGRAM).unwrap());
+    }
+
+    #[test]
+    fn status() {
+        let mut session = ParseSession::new();
+        assert_eq!(session.status(), Completeness::Complete);
+
+        session.push("while x do\n\tprint(\"a");
+        assert_eq!(session.status(), Completeness::Incomplete);
+
+        session.push("b\")\n");
+        assert_eq!(session.status(), Completeness::Incomplete);
+
+        session.push("end");
+        assert_eq!(session.status(), Completeness::Complete);
+
+        session.push(" print)");
+        assert!(matches!(session.status(), Completeness::Invalid(_)));
+
+        let mut session = ParseSession::new();
+        session.push("local x = \"oops\nlocal y = 1");
+        assert!(matches!(session.status(), Completeness::Invalid(_)));
+
+        let mut session = ParseSession::new();
+        session.push("x = \"a\\\n");
+        assert_eq!(session.status(), Completeness::Incomplete);
+
+        session.push("b\"\n");
+        assert_eq!(session.status(), Completeness::Complete);
+
+        let mut session = ParseSession::new();
+        session.push("x = \"a\\\\\n");
+        assert!(matches!(session.status(), Completeness::Invalid(_)));
+    }
+}
@@ -3,9 +3,15 @@
 //! to construct an AST.
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::iter::FusedIterator;
+use std::mem;
+use std::str;
 
-use regex::{self, Regex};
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+use version::LuaVersion;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Symbol {
@@ -19,13 +25,27 @@ pub enum Symbol {
     Minus,
     Star,
     Slash,
+    DoubleSlash,
     Caret,
+    Percent,
     Hash,
     Dot,
     TwoDots,
     Equal,
+    DoubleEqual,
+    NotEqual,
+    LessThan,
+    LessEqual,
+    GreaterThan,
+    GreaterEqual,
+    Ampersand,
+    Pipe,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
     Comma,
     Colon,
+    DoubleColon,
     Semicolon,
     Ellipse,
     And,
@@ -43,6 +63,9 @@ pub enum Symbol {
     Else,
     ElseIf,
     End,
+    Return,
+    Break,
+    Goto,
     True,
     False,
     Nil,
@@ -62,13 +85,27 @@ impl Symbol {
             Symbol::Minus => "-",
             Symbol::Star => "*",
             Symbol::Slash => "/",
+            Symbol::DoubleSlash => "//",
             Symbol::Caret => "^",
+            Symbol::Percent => "%",
             Symbol::Hash => "#",
             Symbol::Dot => ".",
             Symbol::TwoDots => "..",
             Symbol::Equal => "=",
+            Symbol::DoubleEqual => "==",
+            Symbol::NotEqual => "~=",
+            Symbol::LessThan => "<",
+            Symbol::LessEqual => "<=",
+            Symbol::GreaterThan => ">",
+            Symbol::GreaterEqual => ">=",
+            Symbol::Ampersand => "&",
+            Symbol::Pipe => "|",
+            Symbol::Tilde => "~",
+            Symbol::ShiftLeft => "<<",
+            Symbol::ShiftRight => ">>",
             Symbol::Comma => ",",
             Symbol::Colon => ":",
+            Symbol::DoubleColon => "::",
             Symbol::Semicolon => ";",
             Symbol::Ellipse => "...",
             Symbol::And => "and",
@@ -87,11 +124,47 @@ impl Symbol {
             Symbol::Else => "else",
             Symbol::ElseIf => "elseif",
             Symbol::End => "end",
+            Symbol::Return => "return",
+            Symbol::Break => "break",
+            Symbol::Goto => "goto",
             Symbol::True => "true",
             Symbol::False => "false",
             Symbol::Nil => "nil",
         }
     }
+
+    /// The keyword spelled `name`, if there is one, including `true`,
+    /// `false` and `nil`. `goto` is included too, though it's only a keyword
+    /// since Lua 5.2.
+    pub fn from_keyword(name: &str) -> Option<Symbol> {
+        let symbol = match name {
+            "and" => Symbol::And,
+            "break" => Symbol::Break,
+            "do" => Symbol::Do,
+            "else" => Symbol::Else,
+            "elseif" => Symbol::ElseIf,
+            "end" => Symbol::End,
+            "false" => Symbol::False,
+            "for" => Symbol::For,
+            "function" => Symbol::Function,
+            "goto" => Symbol::Goto,
+            "if" => Symbol::If,
+            "in" => Symbol::In,
+            "local" => Symbol::Local,
+            "nil" => Symbol::Nil,
+            "not" => Symbol::Not,
+            "or" => Symbol::Or,
+            "repeat" => Symbol::Repeat,
+            "return" => Symbol::Return,
+            "then" => Symbol::Then,
+            "true" => Symbol::True,
+            "until" => Symbol::Until,
+            "while" => Symbol::While,
+            _ => return None,
+        };
+
+        Some(symbol)
+    }
 }
 
 /// Represents a position in the source text.
@@ -110,18 +183,43 @@ pub struct SourcePosition {
 impl SourcePosition {
     /// Calculate the source position after stepping over the given string.
     pub fn next_position(&self, consumed: &str) -> SourcePosition {
-        let lines_consumed = consumed.matches("\n").count();
+        self.next_position_with_tab_width(consumed, 1)
+    }
+
+    /// Like [next_position][SourcePosition::next_position], but with tab
+    /// stops every `tab_width` columns. A tab moves to the next tab stop
+    /// instead of counting as one column.
+    pub fn next_position_with_tab_width(&self, consumed: &str, tab_width: usize) -> SourcePosition {
+        self.next_position_in(consumed, tab_width, ColumnUnit::Chars)
+    }
 
-        let column = if lines_consumed > 0 {
-            // If there was a newline we're on a totally different column
-            if let Some(range) = PATTERN_CHARS_AFTER_NEWLINE.find(consumed) {
-                range.end() - range.start()
+    /// Like [next_position][SourcePosition::next_position], but counting
+    /// columns the way the tokenizer does with `options`: with its
+    /// [tab_width][TokenizerOptions::tab_width] and in its
+    /// [column_unit][TokenizerOptions::column_unit].
+    pub fn next_position_with_options(&self, consumed: &str, options: &TokenizerOptions) -> SourcePosition {
+        self.next_position_in(consumed, options.tab_width, options.column_unit)
+    }
+
+    fn next_position_in(&self, consumed: &str, tab_width: usize, unit: ColumnUnit) -> SourcePosition {
+        let lines_consumed = consumed.bytes().filter(|&byte| byte == b'\n').count();
+
+        let advance_column = |column: usize, text: &str| text.chars().fold(column, |column, c| {
+            if c == '\t' && tab_width > 1 {
+                (column.saturating_sub(1) / tab_width + 1).saturating_mul(tab_width).saturating_add(1)
             } else {
-                0
+                column.saturating_add(unit.width(c))
             }
-        } else {
+        });
+
+        let column = match consumed.rfind('\n') {
+            // If there was a newline we're on a totally different column,
+            // counting from the start of the new line. A `\r` before the
+            // `\n` is part of the line break.
+            Some(index) => advance_column(1, &consumed[index + 1..]),
+
             // Otherwise we can just increment the current column by the length of the eaten chars
-            self.column + consumed.len()
+            None => advance_column(self.column, consumed),
         };
 
         SourcePosition {
@@ -147,6 +245,374 @@ pub enum StringLiteral<'a> {
     },
 }
 
+impl<'a> StringLiteral<'a> {
+    /// The literal as it was written, including its delimiters.
+    pub fn to_source(&self) -> String {
+        match self {
+            StringLiteral::DoubleQuote { raw_content } => format!("\"{}\"", raw_content),
+            StringLiteral::SingleQuote { raw_content } => format!("'{}'", raw_content),
+            StringLiteral::LongForm { raw_content, depth } => {
+                let equals = "=".repeat(*depth as usize);
+                format!("[{}[{}]{}]", equals, raw_content, equals)
+            },
+        }
+    }
+
+    pub fn into_owned(self) -> StringLiteral<'static> {
+        match self {
+            StringLiteral::DoubleQuote { raw_content } => StringLiteral::DoubleQuote {
+                raw_content: Cow::Owned(raw_content.into_owned()),
+            },
+            StringLiteral::SingleQuote { raw_content } => StringLiteral::SingleQuote {
+                raw_content: Cow::Owned(raw_content.into_owned()),
+            },
+            StringLiteral::LongForm { raw_content, depth } => StringLiteral::LongForm {
+                raw_content: Cow::Owned(raw_content.into_owned()),
+                depth,
+            },
+        }
+    }
+}
+
+/// How [StringLiteral::from_bytes] should write a string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StringStyle {
+    DoubleQuote,
+    SingleQuote,
+
+    /// A long bracket string like `[==[text]==]`, with the fewest `=` that
+    /// keep the contents from closing it early.
+    LongForm,
+}
+
+impl StringLiteral<'static> {
+    /// Writes arbitrary bytes as a literal that decodes back to exactly those
+    /// bytes, both with [StringLiteral::value] and in every version of Lua.
+    ///
+    /// Quoted strings escape backslashes, their own quote character, control
+    /// characters, and bytes that aren't valid UTF-8. Other text is kept as
+    /// is. Long strings can't contain escapes, and Lua rewrites carriage
+    /// returns inside them, so values with invalid UTF-8 or a `\r` are
+    /// written with double quotes instead.
+    pub fn from_bytes(value: &[u8], style: StringStyle) -> StringLiteral<'static> {
+        match style {
+            StringStyle::DoubleQuote => StringLiteral::DoubleQuote {
+                raw_content: Cow::Owned(escape_quoted(value, '"')),
+            },
+            StringStyle::SingleQuote => StringLiteral::SingleQuote {
+                raw_content: Cow::Owned(escape_quoted(value, '\'')),
+            },
+            StringStyle::LongForm => match str::from_utf8(value) {
+                Ok(text) if !text.contains('\r') => {
+                    let mut depth = 0;
+
+                    while !fits_long_form(text, depth) {
+                        depth += 1;
+                    }
+
+                    // A newline right after the opening bracket is skipped,
+                    // so a value starting with one needs another in front.
+                    let raw_content = if text.starts_with('\n') {
+                        format!("\n{}", text)
+                    } else {
+                        text.to_string()
+                    };
+
+                    StringLiteral::LongForm {
+                        raw_content: Cow::Owned(raw_content),
+                        depth,
+                    }
+                },
+                _ => StringLiteral::from_bytes(value, StringStyle::DoubleQuote),
+            },
+        }
+    }
+}
+
+impl<'a> StringLiteral<'a> {
+    /// The bytes the literal stands for, with escapes decoded. Returns `None`
+    /// for escapes that aren't understood.
+    pub fn value(&self) -> Option<Vec<u8>> {
+        let raw = match self {
+            StringLiteral::LongForm { raw_content, .. } => {
+                // A newline directly after the opening bracket isn't part of
+                // the string.
+                let content = raw_content.strip_prefix("\r\n")
+                    .or_else(|| raw_content.strip_prefix('\n'))
+                    .unwrap_or(raw_content);
+
+                return Some(content.as_bytes().to_vec());
+            },
+            StringLiteral::DoubleQuote { raw_content } | StringLiteral::SingleQuote { raw_content } => raw_content,
+        };
+
+        let mut bytes = Vec::with_capacity(raw.len());
+        let mut chars = raw.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                continue;
+            }
+
+            let escaped = match chars.next()? {
+                'n' => b'\n',
+                't' => b'\t',
+                'r' => b'\r',
+                'a' => 0x07,
+                'b' => 0x08,
+                'f' => 0x0c,
+                'v' => 0x0b,
+                '\\' => b'\\',
+                '"' => b'"',
+                '\'' => b'\'',
+                // Lua reads `\r\n` and `\n\r` as a single newline.
+                newline @ '\n' | newline @ '\r' => {
+                    if chars.peek().is_some_and(|&next| (next == '\n' || next == '\r') && next != newline) {
+                        chars.next();
+                    }
+
+                    b'\n'
+                },
+                digit if digit.is_ascii_digit() => {
+                    let mut value = digit.to_digit(10).unwrap();
+
+                    for _ in 0..2 {
+                        match chars.peek().and_then(|c| c.to_digit(10)) {
+                            Some(next) => {
+                                value = value * 10 + next;
+                                chars.next();
+                            },
+                            None => break,
+                        }
+                    }
+
+                    if value > 255 {
+                        return None;
+                    }
+
+                    value as u8
+                },
+                'z' => {
+                    while chars.peek().is_some_and(|&next| next.is_ascii() && is_lua_whitespace(next as u8)) {
+                        chars.next();
+                    }
+
+                    continue;
+                },
+                'u' if chars.peek() == Some(&'{') => {
+                    let mut escape = String::from("\\u");
+
+                    for c in chars.by_ref() {
+                        escape.push(c);
+
+                        if c == '}' {
+                            break;
+                        }
+                    }
+
+                    let (code, _) = unicode_escape(&escape).ok()?;
+                    bytes.extend_from_slice(&lua_utf8(code));
+                    continue;
+                },
+                _ => return None,
+            };
+
+            bytes.push(escaped);
+        }
+
+        Some(bytes)
+    }
+}
+
+/// Decodes a `\u{XXX}` escape, like `\u{1F600}`, to the character it stands
+/// for. Returns `None` if the escape is malformed, or if it's one Lua accepts
+/// that isn't a Unicode character, like a surrogate or anything above
+/// `\u{10FFFF}`.
+pub fn decode_unicode_escape(escape: &str) -> Option<char> {
+    if !escape.starts_with("\\u{") {
+        return None;
+    }
+
+    match unicode_escape(escape) {
+        Ok((code, length)) if length == escape.len() => char::from_u32(code),
+        _ => None,
+    }
+}
+
+/// Reads the `\u{XXX}` escape at the start of `escape`, giving its code point
+/// and length. If it's malformed, gives the length of what was read instead.
+fn unicode_escape(escape: &str) -> Result<(u32, usize), usize> {
+    let bytes = escape.as_bytes();
+    let digits = count_bytes(&bytes[3..], |byte| byte.is_ascii_hexdigit());
+    let closed = bytes.get(3 + digits) == Some(&b'}');
+    let length = 3 + digits + closed as usize;
+
+    // Lua allows code points up to 2^31, past the end of Unicode.
+    let code = escape[3..3 + digits].chars()
+        .try_fold(0u32, |code, digit| code.checked_mul(16)?.checked_add(digit.to_digit(16)?))
+        .filter(|&code| code <= 0x7FFF_FFFF);
+
+    match code {
+        Some(code) if digits > 0 && closed => Ok((code, length)),
+        _ => Err(length),
+    }
+}
+
+/// Encodes a code point the way Lua does, which extends UTF-8 up to
+/// 0x7FFFFFFF with sequences of up to six bytes.
+fn lua_utf8(code: u32) -> Vec<u8> {
+    if code < 0x80 {
+        return vec![code as u8];
+    }
+
+    let mut code = code;
+    let mut encoded = Vec::new();
+
+    // The most the first byte can hold, which shrinks with every
+    // continuation byte.
+    let mut first_byte_max = 0x3f;
+
+    loop {
+        encoded.push(0x80 | (code & 0x3f) as u8);
+        code >>= 6;
+        first_byte_max >>= 1;
+
+        if code <= first_byte_max {
+            break;
+        }
+    }
+
+    encoded.push(((!first_byte_max << 1) | code) as u8);
+    encoded.reverse();
+    encoded
+}
+
+/// Writes arbitrary bytes as Lua source for a string literal. See
+/// [StringLiteral::from_bytes] for how each style is written.
+pub fn escape_for_lua(value: &[u8], style: StringStyle) -> String {
+    StringLiteral::from_bytes(value, style).to_source()
+}
+
+/// Whether `text` can be the contents of a long string of the given depth.
+fn fits_long_form(text: &str, depth: u32) -> bool {
+    let equals = "=".repeat(depth as usize);
+
+    // The contents end at the first closing bracket of the right depth, which
+    // has to be the one after the text. Lua 5.1 also rejects `[[` inside a
+    // long string with no `=`.
+    let closed = format!("{}]{}]", text, equals);
+    let ends_in_place = closed.find(&format!("]{}]", equals)) == Some(text.len());
+
+    ends_in_place && !(depth == 0 && text.contains("[["))
+}
+
+/// Writes `value` as the contents of a string quoted with `quote`.
+fn escape_quoted(value: &[u8], quote: char) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\t' => escaped.push_str("\\t"),
+                '\r' => escaped.push_str("\\r"),
+                _ if c == quote => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                },
+                // Always using three digits keeps a digit after the escape
+                // from being read as part of it.
+                _ if c.is_ascii_control() => escaped.push_str(&format!("\\{:03}", c as u32)),
+                _ => escaped.push(c),
+            }
+        }
+
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\{:03}", byte));
+        }
+    }
+
+    escaped
+}
+
+/// A piece of a Luau interpolated string like `` `hello {name}!` ``. The
+/// expressions between the pieces are tokenized like any others.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum InterpolatedStringPart<'a> {
+    /// A whole string with no expressions in it, like `` `hello` ``.
+    Simple {
+        raw_content: Cow<'a, str>,
+    },
+
+    /// The text before the first expression, like `` `hello { ``.
+    Begin {
+        raw_content: Cow<'a, str>,
+    },
+
+    /// The text between two expressions, like `}, {`.
+    Middle {
+        raw_content: Cow<'a, str>,
+    },
+
+    /// The text after the last expression, like `` }!` ``.
+    End {
+        raw_content: Cow<'a, str>,
+    },
+}
+
+impl<'a> InterpolatedStringPart<'a> {
+    /// The part as it was written, including its backticks and braces.
+    pub fn to_source(&self) -> String {
+        match self {
+            InterpolatedStringPart::Simple { raw_content } => format!("`{}`", raw_content),
+            InterpolatedStringPart::Begin { raw_content } => format!("`{}{{", raw_content),
+            InterpolatedStringPart::Middle { raw_content } => format!("}}{}{{", raw_content),
+            InterpolatedStringPart::End { raw_content } => format!("}}{}`", raw_content),
+        }
+    }
+
+    pub fn into_owned(self) -> InterpolatedStringPart<'static> {
+        match self {
+            InterpolatedStringPart::Simple { raw_content } => InterpolatedStringPart::Simple {
+                raw_content: Cow::Owned(raw_content.into_owned()),
+            },
+            InterpolatedStringPart::Begin { raw_content } => InterpolatedStringPart::Begin {
+                raw_content: Cow::Owned(raw_content.into_owned()),
+            },
+            InterpolatedStringPart::Middle { raw_content } => InterpolatedStringPart::Middle {
+                raw_content: Cow::Owned(raw_content.into_owned()),
+            },
+            InterpolatedStringPart::End { raw_content } => InterpolatedStringPart::End {
+                raw_content: Cow::Owned(raw_content.into_owned()),
+            },
+        }
+    }
+}
+
+/// Whether a number literal is an integer or a float, which Lua 5.3 and later
+/// keep apart as subtypes of numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NumberKind {
+    Integer,
+    Float,
+
+    /// A LuaJIT signed 64-bit integer, like `12LL`. Only produced with
+    /// [TokenizerOptions::luajit_number_suffixes].
+    Int64,
+
+    /// A LuaJIT unsigned 64-bit integer, like `0xffffULL`. Only produced with
+    /// [TokenizerOptions::luajit_number_suffixes].
+    UInt64,
+
+    /// A LuaJIT imaginary number, like `3i`. Only produced with
+    /// [TokenizerOptions::luajit_number_suffixes].
+    Imaginary,
+}
+
 /// Represents a token kind.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TokenKind<'a> {
@@ -157,14 +623,156 @@ pub enum TokenKind<'a> {
     #[serde(borrow)]
     Identifier(Cow<'a, str>),
 
-    /// A number literal as it appeared in the source.
-    NumberLiteral(Cow<'a, str>),
+    /// A number literal.
+    NumberLiteral {
+        /// The number as it appeared in the source.
+        text: Cow<'a, str>,
+
+        kind: NumberKind,
+    },
 
     StringLiteral(StringLiteral<'a>),
 
+    /// A piece of a Luau interpolated string, only produced with
+    /// [TokenizerOptions::interpolated_strings].
+    InterpolatedString(InterpolatedStringPart<'a>),
+
+    /// Whitespace, only produced with [TokenizerOptions::emit_trivia].
+    Whitespace(Cow<'a, str>),
+
+    /// A comment, only produced with [TokenizerOptions::emit_trivia].
+    Comment(Comment<'a>),
+
+    /// Source that couldn't be tokenized and was skipped over, only produced
+    /// when [TokenizerOptions::on_error] isn't [OnError::Stop].
+    Error {
+        /// The text that was skipped.
+        text: Cow<'a, str>,
+
+        /// Why it couldn't be tokenized.
+        error: TokenizeError,
+    },
+
     EndOfFile,
 }
 
+impl<'a> TokenKind<'a> {
+    /// Whether the token is a keyword like `local` or `and`, as opposed to an
+    /// operator.
+    pub fn is_keyword(&self) -> bool {
+        match self {
+            TokenKind::Symbol(symbol) => symbol.to_str().starts_with(|c: char| c.is_ascii_alphabetic()),
+            _ => false,
+        }
+    }
+
+    /// Whether the token is the given keyword or operator.
+    pub fn is_symbol(&self, symbol: Symbol) -> bool {
+        *self == TokenKind::Symbol(symbol)
+    }
+
+    pub fn is_identifier(&self) -> bool {
+        self.as_identifier().is_some()
+    }
+
+    /// The name, if the token is an identifier.
+    pub fn as_identifier(&self) -> Option<&str> {
+        match self {
+            TokenKind::Identifier(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// The number as it was written, if the token is a number literal.
+    pub fn as_number(&self) -> Option<&str> {
+        match self {
+            TokenKind::NumberLiteral { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Whether the token only exists to hold the whitespace and comments at
+    /// the end of the source.
+    pub fn is_trivia_only(&self) -> bool {
+        *self == TokenKind::EndOfFile
+    }
+
+    /// Whether the token is whitespace or a comment, as produced with
+    /// [TokenizerOptions::emit_trivia].
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenKind::Whitespace(_) | TokenKind::Comment(_))
+    }
+
+    /// What kind of token this is, for error messages. Keywords and operators
+    /// are quoted, like `'end'`.
+    pub fn description(&self) -> Cow<'static, str> {
+        match self {
+            TokenKind::Symbol(symbol) => Cow::Owned(format!("'{}'", symbol.to_str())),
+            TokenKind::Identifier(_) => Cow::Borrowed("identifier"),
+            TokenKind::NumberLiteral { .. } => Cow::Borrowed("number literal"),
+            TokenKind::StringLiteral(_) => Cow::Borrowed("string literal"),
+            TokenKind::InterpolatedString(_) => Cow::Borrowed("interpolated string"),
+            TokenKind::Whitespace(_) => Cow::Borrowed("whitespace"),
+            TokenKind::Comment(_) => Cow::Borrowed("comment"),
+            TokenKind::Error { .. } => Cow::Borrowed("invalid text"),
+            TokenKind::EndOfFile => Cow::Borrowed("end of file"),
+        }
+    }
+
+    /// The token as it was written.
+    pub fn to_source(&self) -> String {
+        match self {
+            TokenKind::Symbol(symbol) => symbol.to_str().to_string(),
+            TokenKind::Identifier(name) => name.to_string(),
+            TokenKind::NumberLiteral { text, .. } => text.to_string(),
+            TokenKind::StringLiteral(literal) => literal.to_source(),
+            TokenKind::InterpolatedString(part) => part.to_source(),
+            TokenKind::Whitespace(whitespace) => whitespace.to_string(),
+            TokenKind::Comment(comment) => comment.to_source(),
+            TokenKind::Error { text, .. } => text.to_string(),
+            TokenKind::EndOfFile => String::new(),
+        }
+    }
+
+    pub fn into_owned(self) -> TokenKind<'static> {
+        match self {
+            TokenKind::Symbol(symbol) => TokenKind::Symbol(symbol),
+            TokenKind::Identifier(name) => TokenKind::Identifier(Cow::Owned(name.into_owned())),
+            TokenKind::NumberLiteral { text, kind } => TokenKind::NumberLiteral {
+                text: Cow::Owned(text.into_owned()),
+                kind,
+            },
+            TokenKind::StringLiteral(literal) => TokenKind::StringLiteral(literal.into_owned()),
+            TokenKind::InterpolatedString(part) => TokenKind::InterpolatedString(part.into_owned()),
+            TokenKind::Whitespace(whitespace) => TokenKind::Whitespace(Cow::Owned(whitespace.into_owned())),
+            TokenKind::Comment(comment) => TokenKind::Comment(comment.into_owned()),
+            TokenKind::Error { text, error } => TokenKind::Error {
+                text: Cow::Owned(text.into_owned()),
+                error,
+            },
+            TokenKind::EndOfFile => TokenKind::EndOfFile,
+        }
+    }
+}
+
+/// Describes the token for people reading a diagnostic, like `'end'` or
+/// `identifier 'foo'`.
+impl<'a> fmt::Display for TokenKind<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Symbol(symbol) => write!(f, "'{}'", symbol.to_str()),
+            TokenKind::Identifier(name) => write!(f, "identifier '{}'", name),
+            TokenKind::NumberLiteral { text, .. } => write!(f, "number '{}'", text),
+            TokenKind::StringLiteral(literal) => write!(f, "string {}", literal.to_source()),
+            TokenKind::InterpolatedString(part) => write!(f, "interpolated string {}", part.to_source()),
+            TokenKind::Whitespace(_) => write!(f, "whitespace"),
+            TokenKind::Comment(_) => write!(f, "comment"),
+            TokenKind::Error { text, .. } => write!(f, "invalid text '{}'", text),
+            TokenKind::EndOfFile => write!(f, "end of file"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Comment<'a> {
     SingleLine {
@@ -176,6 +784,31 @@ pub enum Comment<'a> {
     },
 }
 
+impl<'a> Comment<'a> {
+    /// The comment as it was written, including the leading `--`.
+    pub fn to_source(&self) -> String {
+        match self {
+            Comment::SingleLine { content } => format!("--{}", content),
+            Comment::MultiLine { content, depth } => {
+                let equals = "=".repeat(*depth as usize);
+                format!("--[{}[{}]{}]", equals, content, equals)
+            },
+        }
+    }
+
+    pub fn into_owned(self) -> Comment<'static> {
+        match self {
+            Comment::SingleLine { content } => Comment::SingleLine {
+                content: Cow::Owned(content.into_owned()),
+            },
+            Comment::MultiLine { content, depth } => Comment::MultiLine {
+                content: Cow::Owned(content.into_owned()),
+                depth,
+            },
+        }
+    }
+}
+
 /// An item that appears before tokens, like comments and whitespace.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TokenPrefix<'a> {
@@ -183,6 +816,35 @@ pub enum TokenPrefix<'a> {
     Whitespace(Cow<'a, str>),
 
     Comment(Comment<'a>),
+
+    /// A first line starting with `#!`, like `#!/usr/bin/env lua`, which Lua
+    /// skips. Doesn't include the newline that ends it.
+    Shebang(Cow<'a, str>),
+
+    /// A UTF-8 byte order mark at the very start of the source, which Lua
+    /// skips. It doesn't take up a column.
+    ByteOrderMark,
+}
+
+impl<'a> TokenPrefix<'a> {
+    /// The whitespace or comment as it was written.
+    pub fn to_source(&self) -> String {
+        match self {
+            TokenPrefix::Whitespace(whitespace) => whitespace.to_string(),
+            TokenPrefix::Comment(comment) => comment.to_source(),
+            TokenPrefix::Shebang(line) => line.to_string(),
+            TokenPrefix::ByteOrderMark => BYTE_ORDER_MARK.to_string(),
+        }
+    }
+
+    pub fn into_owned(self) -> TokenPrefix<'static> {
+        match self {
+            TokenPrefix::Whitespace(whitespace) => TokenPrefix::Whitespace(Cow::Owned(whitespace.into_owned())),
+            TokenPrefix::Comment(comment) => TokenPrefix::Comment(comment.into_owned()),
+            TokenPrefix::Shebang(line) => TokenPrefix::Shebang(Cow::Owned(line.into_owned())),
+            TokenPrefix::ByteOrderMark => TokenPrefix::ByteOrderMark,
+        }
+    }
 }
 
 /// A token in the source.
@@ -202,14 +864,83 @@ pub struct Token<'a> {
     pub end_position: SourcePosition,
 }
 
+impl<'a> Token<'a> {
+    /// See [TokenKind::is_keyword].
+    pub fn is_keyword(&self) -> bool {
+        self.kind.is_keyword()
+    }
+
+    /// See [TokenKind::is_symbol].
+    pub fn is_symbol(&self, symbol: Symbol) -> bool {
+        self.kind.is_symbol(symbol)
+    }
+
+    pub fn is_identifier(&self) -> bool {
+        self.kind.is_identifier()
+    }
+
+    /// See [TokenKind::as_identifier].
+    pub fn as_identifier(&self) -> Option<&str> {
+        self.kind.as_identifier()
+    }
+
+    /// See [TokenKind::as_number].
+    pub fn as_number(&self) -> Option<&str> {
+        self.kind.as_number()
+    }
+
+    /// See [TokenKind::is_trivia_only].
+    pub fn is_trivia_only(&self) -> bool {
+        self.kind.is_trivia_only()
+    }
+
+    /// See [TokenKind::is_trivia].
+    pub fn is_trivia(&self) -> bool {
+        self.kind.is_trivia()
+    }
+
+    /// See [TokenKind::description].
+    pub fn description(&self) -> Cow<'static, str> {
+        self.kind.description()
+    }
+
+    /// The token as it was written, including the whitespace and comments
+    /// before it.
+    pub fn to_source(&self) -> String {
+        let mut source: String = self.prefix.iter().map(TokenPrefix::to_source).collect();
+        source.push_str(&self.kind.to_source());
+        source
+    }
+
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            kind: self.kind.into_owned(),
+            prefix: self.prefix.into_iter().map(TokenPrefix::into_owned).collect(),
+            start_position: self.start_position,
+            end_position: self.end_position,
+        }
+    }
+}
+
+/// Shows only the token's kind, leaving out its prefix and position.
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
 /// An error with information about why tokenization failed.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TokenizeError {
     /// The tokenizer encountered an unknown sequence in the source that it
     /// could not parse.
     UnknownSequence {
         /// The location in the source where the unknown sequence began.
         position: SourcePosition,
+
+        /// The start of the unknown sequence, up to the next whitespace and
+        /// cut short if it's long.
+        sequence: String,
     },
 
     /// A string was begun that never finished.
@@ -217,197 +948,767 @@ pub enum TokenizeError {
         position: SourcePosition,
     },
 
+    /// A quoted string has an escape that no version of Lua accepts, like
+    /// the decimal escape `\300` or the empty `\u{}`.
+    InvalidEscape {
+        /// The location of the backslash that starts the escape.
+        position: SourcePosition,
+
+        /// The escape as it's written.
+        sequence: String,
+    },
+
     /// An unclosed multi-line comment
     UnclosedComment {
         position: SourcePosition,
     },
-}
 
-lazy_static! {
-    static ref SYMBOLS: Vec<Symbol> = vec![
-        Symbol::LeftBrace, Symbol::RightBrace,
-        Symbol::LeftBracket, Symbol::RightBracket,
-        Symbol::LeftParen, Symbol::RightParen,
-
-        Symbol::Plus, Symbol::Minus, Symbol::Star, Symbol::Slash, Symbol::Caret, Symbol::TwoDots, Symbol::Dot,
-        Symbol::And, Symbol::Or,
-        Symbol::Hash,
-        Symbol::Equal,
-        Symbol::Comma, Symbol::Colon, Symbol::Semicolon,
-        Symbol::Ellipse,
-
-        Symbol::Local, Symbol::Function,
-        Symbol::If, Symbol::While, Symbol::Repeat, Symbol::Until, Symbol::For,
-        Symbol::Then, Symbol::Do, Symbol::Else, Symbol::ElseIf, Symbol::End,
-        Symbol::In,
-        Symbol::True, Symbol::False, Symbol::Nil,
-        Symbol::Not,
-    ];
+    /// An identifier contained a non-ASCII character, and
+    /// [TokenizerOptions::unicode_identifiers] wasn't enabled.
+    NonAsciiIdentifier {
+        /// The location of the non-ASCII character.
+        position: SourcePosition,
+    },
 
-    static ref STR_TO_SYMBOL: HashMap<&'static str, Symbol> = {
-        let mut map = HashMap::new();
+    /// The input starts with the header of precompiled bytecode.
+    PrecompiledBytecode {
+        format: BytecodeFormat,
+    },
 
-        for &operator in SYMBOLS.iter() {
-            map.insert(operator.to_str(), operator);
-        }
+    /// The input given to [tokenize_bytes] wasn't valid UTF-8.
+    InvalidUtf8 {
+        /// The location of the first byte that isn't part of a valid character.
+        position: SourcePosition,
+    },
 
-        map
-    };
+    /// Something that starts like a number isn't one, like `0x` with no
+    /// digits, `1e` with no exponent, `3a`, or `1.5.2`.
+    MalformedNumber {
+        /// The location where the number began.
+        position: SourcePosition,
 
-    static ref PATTERN_SYMBOL: Regex = {
-        let source = SYMBOLS
-            .iter()
-            .map(|v| regex::escape(v.to_str()))
-            .collect::<Vec<_>>()
-            .join("|");
+        /// Everything Lua would read as part of the number.
+        text: String,
+    },
 
-        Regex::new(&format!("^(?:{})", source)).unwrap()
-    };
+    /// A long string or comment with no `=` has a `[[` inside it, which
+    /// Lua 5.1 rejects. Only reported when [TokenizerOptions::version] is
+    /// 5.1; later versions read the `[[` as part of the contents.
+    NestedLongBracket {
+        /// The location of the inner `[[`.
+        position: SourcePosition,
+    },
 
-    static ref PATTERN_IDENTIFIER: Regex = Regex::new(r"^[_a-zA-Z][_a-zA-Z0-9]*").unwrap();
-    static ref PATTERN_NUMBER_LITERAL: Regex = Regex::new(r"^((-?0x[A-Fa-f\d]+)|(-?((\d*\.\d+)|(\d+))([eE]-?\d+)?))").unwrap();
-    static ref PATTERN_WHITESPACE: Regex = Regex::new(r"^\s+").unwrap();
-    static ref PATTERN_SINGLE_LINE_COMMENT: Regex = Regex::new(r"^--(.*)").unwrap();
-    static ref PATTERN_MULTI_LINE_STRING_START: Regex = Regex::new(r"^\[(=*)\[").unwrap();
-    static ref PATTERN_MULTI_LINE_COMMENT_START: Regex = Regex::new(r"^--\[(=*)\[").unwrap();
+    /// The source is longer, or has more tokens, than
+    /// [TokenizerOptions] allow.
+    LimitExceeded {
+        limit: TokenizerLimit,
 
-    static ref PATTERN_CHARS_AFTER_NEWLINE: Regex = Regex::new(r"\n[^\n]+$").unwrap();
+        /// Where the tokenizer stopped: the start of the source for
+        /// [MaxSourceLen][TokenizerLimit::MaxSourceLen], and the start of the
+        /// first token past the limit for
+        /// [MaxTokens][TokenizerLimit::MaxTokens].
+        position: SourcePosition,
+    },
 }
 
-#[derive(Debug)]
-struct AdvanceResult<'a> {
-    rest: &'a str,
-    contents: &'a str,
-    new_position: SourcePosition,
+/// A limit on how much the tokenizer will take on, with the value it was set
+/// to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenizerLimit {
+    /// See [TokenizerOptions::max_source_len].
+    MaxSourceLen(usize),
+
+    /// See [TokenizerOptions::max_tokens].
+    MaxTokens(usize),
 }
 
-enum AdvanceError {
-    NoMatch,
-    Error(TokenizeError),
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenizeError::UnknownSequence { position, sequence } => {
+                write!(f, "unknown sequence starting at line {}, column {}: '{}'", position.line, position.column, sequence)
+            },
+            TokenizeError::UnclosedString { position } => {
+                write!(f, "unclosed string starting at line {}, column {}", position.line, position.column)
+            },
+            TokenizeError::InvalidEscape { position, sequence } => {
+                write!(f, "invalid escape sequence '{}' at line {}, column {}", sequence, position.line, position.column)
+            },
+            TokenizeError::UnclosedComment { position } => {
+                write!(f, "unclosed comment starting at line {}, column {}", position.line, position.column)
+            },
+            TokenizeError::NonAsciiIdentifier { position } => {
+                write!(f, "non-ASCII character in identifier at line {}, column {}", position.line, position.column)
+            },
+            TokenizeError::PrecompiledBytecode { format } => {
+                write!(f, "input appears to be precompiled {} bytecode, not source", format)
+            },
+            TokenizeError::InvalidUtf8 { position } => {
+                write!(f, "invalid UTF-8 at line {}, column {}", position.line, position.column)
+            },
+            TokenizeError::MalformedNumber { position, text } => {
+                write!(f, "malformed number '{}' at line {}, column {}", text, position.line, position.column)
+            },
+            TokenizeError::NestedLongBracket { position } => {
+                write!(f, "nesting of [[...]] at line {}, column {}", position.line, position.column)
+            },
+            TokenizeError::LimitExceeded { limit: TokenizerLimit::MaxSourceLen(max), .. } => {
+                write!(f, "source is longer than the limit of {} bytes", max)
+            },
+            TokenizeError::LimitExceeded { limit: TokenizerLimit::MaxTokens(max), position } => {
+                write!(f, "more than the limit of {} tokens, at line {}, column {}", max, position.line, position.column)
+            },
+        }
+    }
 }
 
-/// Use a pattern to step forward in the source.
-fn advance<'a>(source: &'a str, position: &SourcePosition, pattern: &Regex) -> Result<AdvanceResult<'a>, AdvanceError> {
-    if let Some(range) = pattern.find(source) {
-        let contents = &source[range.start()..range.end()];
-        let rest = &source[range.end()..];
-        let new_position = position.next_position(contents);
+impl error::Error for TokenizeError {}
 
-        Ok(AdvanceResult {
-            rest,
-            contents,
-            new_position,
-        })
-    } else {
-        Err(AdvanceError::NoMatch)
+impl TokenizeError {
+    /// Where in the source the error is, if it's about one place in it.
+    pub fn position(&self) -> Option<SourcePosition> {
+        match *self {
+            TokenizeError::UnknownSequence { position, .. }
+            | TokenizeError::UnclosedString { position }
+            | TokenizeError::InvalidEscape { position, .. }
+            | TokenizeError::UnclosedComment { position }
+            | TokenizeError::NonAsciiIdentifier { position }
+            | TokenizeError::InvalidUtf8 { position }
+            | TokenizeError::MalformedNumber { position, .. }
+            | TokenizeError::NestedLongBracket { position }
+            | TokenizeError::LimitExceeded { position, .. } => Some(position),
+            TokenizeError::PrecompiledBytecode { .. } => None,
+        }
     }
 }
 
-/// Tries to matches the given pattern against the string slice.
-/// If it does, the 'tokenizer' fn is invokved to turn the result into a token.
-fn advance_token<'a, F>(source: &'a str, position: &SourcePosition, pattern: &Regex, tokenizer: F) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError>
-where
-    F: Fn(&'a str) -> TokenKind<'a>,
-{
-    let result = advance(source, position, pattern)?;
-    let token_kind = tokenizer(result.contents);
-    Ok((result, token_kind))
+/// A kind of precompiled bytecode, recognized by its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BytecodeFormat {
+    /// Output of `luac`, with the major and minor version from its header if
+    /// the header was long enough to include it.
+    Lua {
+        version: Option<(u8, u8)>,
+    },
+
+    /// LuaJIT bytecode, with the version of the bytecode format.
+    LuaJit {
+        version: Option<u8>,
+    },
+
+    /// Luau bytecode, with the version of the bytecode format.
+    Luau {
+        version: u8,
+    },
 }
 
-// Similar to try! or the ? operator, but handles cases differently.
-macro_rules! try_advance {
-    ( $rule: expr ) => (
-        match $rule {
-            Ok(token_kind) => return Ok(token_kind),
-            Err(AdvanceError::Error(err)) => return Err(AdvanceError::Error(err)),
-            Err(_) => {},
+impl fmt::Display for BytecodeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BytecodeFormat::Lua { version: Some((major, minor)) } => write!(f, "Lua {}.{}", major, minor),
+            BytecodeFormat::Lua { version: None } => write!(f, "Lua"),
+            BytecodeFormat::LuaJit { version: Some(version) } => write!(f, "LuaJIT (version {})", version),
+            BytecodeFormat::LuaJit { version: None } => write!(f, "LuaJIT"),
+            BytecodeFormat::Luau { version } => write!(f, "Luau (version {})", version),
         }
-    )
+    }
 }
 
-fn parse_identifier<'a>(current: &'a str, current_position: &SourcePosition) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
-    advance_token(current, &current_position, &PATTERN_IDENTIFIER, |s| {
-        if let Some(&symbol) = STR_TO_SYMBOL.get(s) {
-            TokenKind::Symbol(symbol)
-        } else {
-            TokenKind::Identifier(s.into())
+/// Recognizes the header of precompiled bytecode at the start of `source`.
+pub fn detect_bytecode(source: &[u8]) -> Option<BytecodeFormat> {
+    if source.starts_with(b"\x1bLua") {
+        // The version byte holds the major version in its high nibble.
+        let version = source.get(4).map(|&byte| (byte >> 4, byte & 0xf));
+        return Some(BytecodeFormat::Lua { version });
+    }
+
+    if source.starts_with(b"\x1bLJ") {
+        return Some(BytecodeFormat::LuaJit {
+            version: source.get(3).cloned(),
+        });
+    }
+
+    // Luau bytecode starts with its version instead of a signature. No source
+    // can start with a control character other than whitespace, so the known
+    // versions are safe to claim.
+    match source.first() {
+        Some(&version @ 3..=6) => Some(BytecodeFormat::Luau { version }),
+        _ => None,
+    }
+}
+
+/// Controls which extensions to standard Lua the tokenizer accepts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TokenizerOptions {
+    /// Accept identifiers made of any Unicode XID_Start and XID_Continue
+    /// characters instead of only ASCII letters, digits and underscores.
+    pub unicode_identifiers: bool,
+
+    /// Put tab stops every this many columns when computing the columns of
+    /// positions, like an editor would. Both 0, the default, and 1 count a
+    /// tab as a single column.
+    pub tab_width: usize,
+
+    /// What columns of positions count. The default counts characters, and
+    /// [Utf16][ColumnUnit::Utf16] gives the columns the Language Server
+    /// Protocol uses. Tab stops are kept in any unit.
+    pub column_unit: ColumnUnit,
+
+    /// Only accept the keywords, operators, numbers, long brackets and string
+    /// escapes of this version of Lua. With `None`, the default, everything
+    /// any version accepts is allowed, and `goto` is left as a name for the
+    /// parser to recognize.
+    pub version: Option<LuaVersion>,
+
+    /// Accept Luau's interpolated strings, like `` `hello {name}!` ``, as
+    /// [InterpolatedString][TokenKind::InterpolatedString] tokens.
+    pub interpolated_strings: bool,
+
+    /// Accept LuaJIT's number suffixes: `LL` and `ULL` after an integer and
+    /// `i` after any number, in any case. The suffix is kept in the
+    /// [NumberLiteral][TokenKind::NumberLiteral]'s text, and decides its
+    /// [NumberKind].
+    pub luajit_number_suffixes: bool,
+
+    /// Return whitespace and comments as [Whitespace][TokenKind::Whitespace]
+    /// and [Comment][TokenKind::Comment] tokens of their own instead of in
+    /// the prefix of the token after them. Only a byte order mark and a
+    /// shebang are still kept in a prefix, of the first token.
+    ///
+    /// The parser doesn't accept these tokens; [significant_tokens] turns
+    /// them back into prefixes.
+    pub emit_trivia: bool,
+
+    /// Give up with a [LimitExceeded][TokenizeError::LimitExceeded] error
+    /// on sources longer than this many bytes, before tokenizing any of
+    /// them. `None`, the default, allows any length.
+    pub max_source_len: Option<usize>,
+
+    /// Give up with a [LimitExceeded][TokenizeError::LimitExceeded] error
+    /// instead of returning more than this many tokens, not counting an
+    /// [EndOfFile][TokenKind::EndOfFile] token. `None`, the default, allows
+    /// any number.
+    pub max_tokens: Option<usize>,
+
+    /// What to do with source that can't be tokenized, like a stray `$` or
+    /// an unclosed string. Errors about the source as a whole, like
+    /// [LimitExceeded][TokenizeError::LimitExceeded], always stop the
+    /// tokenizer.
+    pub on_error: OnError,
+}
+
+/// What the columns of positions count. See [TokenizerOptions::column_unit].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    /// Bytes of UTF-8.
+    Bytes,
+
+    /// Characters, so that `é` and `🙂` are one column each.
+    #[default]
+    Chars,
+
+    /// UTF-16 code units, so that `🙂` is two columns.
+    Utf16,
+}
+
+impl ColumnUnit {
+    /// How many columns `c` takes up.
+    fn width(self, c: char) -> usize {
+        match self {
+            ColumnUnit::Bytes => c.len_utf8(),
+            ColumnUnit::Chars => 1,
+            ColumnUnit::Utf16 => c.len_utf16(),
         }
-    })
+    }
+}
+
+/// What the tokenizer does when it can't tokenize part of the source. See
+/// [TokenizerOptions::on_error].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OnError {
+    /// Return the error and stop.
+    #[default]
+    Stop,
+
+    /// Turn the character where the error starts into an
+    /// [Error][TokenKind::Error] token and carry on after it.
+    SkipChar,
+
+    /// Turn everything from where the error starts up to the next whitespace
+    /// into an [Error][TokenKind::Error] token and carry on after it.
+    SkipToWhitespace,
+}
+
+impl TokenizerOptions {
+    /// Whether something added to Lua in version `since` is accepted.
+    fn accepts(&self, since: LuaVersion) -> bool {
+        self.version.is_none_or(|version| version >= since)
+    }
+}
+
+#[derive(Debug)]
+struct AdvanceResult<'a> {
+    rest: &'a str,
+    contents: &'a str,
+    new_position: SourcePosition,
 }
 
-fn parse_number_literal<'a>(current: &'a str, current_position: &SourcePosition) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
-    advance_token(current, &current_position, &PATTERN_NUMBER_LITERAL, |s| TokenKind::NumberLiteral(s.into()))
+enum AdvanceError {
+    NoMatch,
+    Error(TokenizeError),
+}
+
+/// Steps forward over the first `length` bytes of the source.
+fn advance<'a>(source: &'a str, position: &SourcePosition, length: usize, options: &TokenizerOptions) -> AdvanceResult<'a> {
+    let (contents, rest) = source.split_at(length);
+
+    AdvanceResult {
+        rest,
+        contents,
+        new_position: position.next_position_with_options(contents, options),
+    }
 }
 
-fn parse_symbol<'a>(current: &'a str, current_position: &SourcePosition) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
-    advance_token(current, &current_position, &PATTERN_SYMBOL, |s| TokenKind::Symbol(*STR_TO_SYMBOL.get(s).unwrap()))
+/// The number of bytes at the start of `source` that match `predicate`.
+fn count_bytes<F>(source: &[u8], predicate: F) -> usize
+where
+    F: Fn(u8) -> bool,
+{
+    source.iter().take_while(|&&byte| predicate(byte)).count()
 }
 
-fn parse_string_literal<'a>(current: &'a str, current_position: &SourcePosition) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
-    let quote_character = if current.starts_with("\"") {
-        '"'
-    } else if current.starts_with("'") {
-        '\''
+fn parse_identifier<'a>(current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
+    let length = if options.unicode_identifiers {
+        if current.starts_with(|c: char| c == '_' || is_xid_start(c)) {
+            current.find(|c: char| !is_xid_continue(c)).unwrap_or(current.len())
+        } else {
+            0
+        }
+    } else if current.starts_with(|c: char| c == '_' || c.is_ascii_alphabetic()) {
+        count_bytes(current.as_bytes(), |byte| byte == b'_' || byte.is_ascii_alphanumeric())
     } else {
+        0
+    };
+
+    if length == 0 {
         return Err(AdvanceError::NoMatch);
+    }
+
+    let result = advance(current, current_position, length, options);
+
+    // Without a version, `goto` is left as a name for the parser.
+    let goto = options.version.is_some_and(|version| version >= LuaVersion::Lua52);
+
+    let kind = match Symbol::from_keyword(result.contents) {
+        Some(Symbol::Goto) if !goto => TokenKind::Identifier(result.contents.into()),
+        Some(symbol) => TokenKind::Symbol(symbol),
+        None => TokenKind::Identifier(result.contents.into()),
     };
 
-    let mut literal_end = None;
-    let mut last_was_escape = false;
+    Ok((result, kind))
+}
 
-    for (index, character) in current.char_indices().skip(1) {
-        if character == '\\' {
-            last_was_escape = !last_was_escape;
-        } else if character == quote_character {
-            if last_was_escape {
-                last_was_escape = false;
-            } else {
-                literal_end = Some(index);
-                break;
+/// The length of the hex or decimal number at the start of `source`, if
+/// there is one. A `..` after the digits is concatenation, not a fraction.
+fn number_length(source: &str) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let digits_at = |index: usize| count_bytes(bytes.get(index..).unwrap_or(&[]), |byte| byte.is_ascii_digit());
+    let hex_digits_at = |index: usize| count_bytes(bytes.get(index..).unwrap_or(&[]), |byte| byte.is_ascii_hexdigit());
+
+    // An exponent without digits isn't part of the number.
+    let exponent = |index: usize, markers: &[u8]| -> usize {
+        if !bytes.get(index).is_some_and(|byte| markers.contains(byte)) {
+            return index;
+        }
+
+        let mut digits_start = index + 1;
+
+        if let Some(b'-') | Some(b'+') = bytes.get(digits_start) {
+            digits_start += 1;
+        }
+
+        match digits_at(digits_start) {
+            0 => index,
+            count => digits_start + count,
+        }
+    };
+
+    if bytes.starts_with(b"0x") || bytes.starts_with(b"0X") {
+        let whole = hex_digits_at(2);
+
+        let end = if whole > 0 {
+            match (bytes.get(2 + whole), bytes.get(3 + whole)) {
+                (Some(b'.'), Some(b'.')) => 2 + whole,
+                (Some(b'.'), _) => 3 + whole + hex_digits_at(3 + whole),
+                _ => 2 + whole,
             }
-        } else if character == '\r' || character == '\n' {
-            return Err(AdvanceError::Error(TokenizeError::UnclosedString {
-                position: *current_position,
-            }));
+        } else if bytes.get(2) == Some(&b'.') && hex_digits_at(3) > 0 {
+            3 + hex_digits_at(3)
         } else {
-            last_was_escape = false;
+            0
+        };
+
+        if end > 0 {
+            return Some(exponent(end, b"pP"));
         }
     }
 
-    let literal_end = match literal_end {
-        Some(v) => v,
-        None => {
-            return Err(AdvanceError::Error(TokenizeError::UnclosedString {
-                position: *current_position,
-            }));
-        },
+    let whole = digits_at(0);
+
+    let end = match (bytes.get(whole), digits_at(whole + 1)) {
+        (Some(b'.'), _) if bytes.get(whole + 1) == Some(&b'.') && whole > 0 => whole,
+        (Some(b'.'), fraction) if whole + fraction > 0 => whole + 1 + fraction,
+        _ if whole > 0 => whole,
+        _ => return None,
     };
 
-    let literal = match quote_character {
-        '"' => StringLiteral::DoubleQuote {
-            raw_content: Cow::from(&current[1..literal_end])
-        },
-        '\'' => StringLiteral::SingleQuote {
-            raw_content: Cow::from(&current[1..literal_end])
-        },
-        _ => unreachable!(),
+    Some(exponent(end, b"eE"))
+}
+
+/// The length of what Lua reads as a number before checking it: digits,
+/// dots and exponents, and then a letter if one touches the end of them.
+/// Unlike Lua, this stops before a `..`, so that `1..2` is a concatenation.
+fn numeral_length(source: &str) -> usize {
+    let bytes = source.as_bytes();
+    let hex = bytes.starts_with(b"0x") || bytes.starts_with(b"0X");
+    let markers: &[u8] = if hex { b"pP" } else { b"eE" };
+    let mut index = if hex { 2 } else { 0 };
+
+    while let Some(byte) = bytes.get(index) {
+        if markers.contains(byte) {
+            index += 1;
+
+            if let Some(b'-') | Some(b'+') = bytes.get(index) {
+                index += 1;
+            }
+        } else if *byte == b'.' && bytes.get(index + 1) == Some(&b'.') {
+            break;
+        } else if byte.is_ascii_hexdigit() || *byte == b'.' {
+            index += 1;
+        } else {
+            break;
+        }
+    }
+
+    if bytes.get(index).is_some_and(|byte| byte.is_ascii_alphabetic() || *byte == b'_') {
+        index += 1;
+    }
+
+    index
+}
+
+/// Whether a valid number literal has neither a fraction nor an exponent.
+fn is_integer_numeral(text: &str) -> bool {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        !text.contains(['.', 'p', 'P'])
+    } else {
+        !text.contains(['.', 'e', 'E'])
+    }
+}
+
+/// Whether a valid number literal is an integer or a float. Like in Lua, a
+/// fraction or an exponent makes a float, and so does a decimal integer too
+/// big for 64 bits. Hex integers that are too big wrap around instead.
+fn number_kind(text: &str) -> NumberKind {
+    let hex = text.starts_with("0x") || text.starts_with("0X");
+
+    if is_integer_numeral(text) && (hex || text.parse::<i64>().is_ok()) {
+        NumberKind::Integer
+    } else {
+        NumberKind::Float
+    }
+}
+
+/// The length and kind of a LuaJIT suffix at the start of `rest`, which
+/// follows a number. Like in LuaJIT, `LL` and `ULL` only go after integers,
+/// and each letter can be in either case.
+fn luajit_suffix(rest: &str, integer: bool) -> Option<(usize, NumberKind)> {
+    let lower: Vec<u8> = rest.bytes().take(3).map(|byte| byte.to_ascii_lowercase()).collect();
+
+    if lower.starts_with(b"i") {
+        Some((1, NumberKind::Imaginary))
+    } else if integer && lower.starts_with(b"ull") {
+        Some((3, NumberKind::UInt64))
+    } else if integer && lower.starts_with(b"ll") {
+        Some((2, NumberKind::Int64))
+    } else {
+        None
+    }
+}
+
+fn parse_number_literal<'a>(current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
+    let mut length = number_length(current).ok_or(AdvanceError::NoMatch)?;
+    let mut numeral = numeral_length(current);
+
+    let suffix = if options.luajit_number_suffixes {
+        luajit_suffix(&current[length..], is_integer_numeral(&current[..length]))
+    } else {
+        None
+    };
+
+    // Anything touching the suffix makes the number malformed, like `1LLx`.
+    if let Some((suffix_length, _)) = suffix {
+        length += suffix_length;
+
+        let touching = current[length..].bytes().next()
+            .is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.');
+        numeral = if touching { length + 1 } else { length };
+    }
+
+    // Like Lua, a number is read as far as it could go before it's checked,
+    // so `1e`, `0x` and `1.5.2` are errors rather than a number followed by
+    // something else.
+    if numeral != length {
+        return Err(AdvanceError::Error(TokenizeError::MalformedNumber {
+            position: *current_position,
+            text: current[..numeral].to_string(),
+        }));
+    }
+
+    // Hex numbers with a fraction or an exponent came with Lua 5.2.
+    let hex = current.starts_with("0x") || current.starts_with("0X");
+
+    if hex && !options.accepts(LuaVersion::Lua52) && !is_integer_numeral(&current[..length]) {
+        return Err(AdvanceError::Error(TokenizeError::MalformedNumber {
+            position: *current_position,
+            text: current[..numeral].to_string(),
+        }));
+    }
+
+    let result = advance(current, current_position, length, options);
+
+    let kind = TokenKind::NumberLiteral {
+        text: result.contents.into(),
+        kind: suffix.map_or_else(|| number_kind(result.contents), |(_, kind)| kind),
+    };
+    Ok((result, kind))
+}
+
+fn parse_symbol<'a>(current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
+    let bytes = current.as_bytes();
+
+    // Labels came with Lua 5.2, and integer division and bitwise operators
+    // with 5.3. Before then, `//`, `<<`, `>>` and `::` are read as two
+    // separate operators.
+    let labels = options.accepts(LuaVersion::Lua52);
+    let bitwise = options.accepts(LuaVersion::Lua53);
+
+    // Operators that start with another operator are checked first.
+    let (symbol, length) = match (bytes.first(), bytes.get(1)) {
+        (Some(b'.'), Some(b'.')) if bytes.get(2) == Some(&b'.') => (Symbol::Ellipse, 3),
+        (Some(b'.'), Some(b'.')) => (Symbol::TwoDots, 2),
+        (Some(b'/'), Some(b'/')) if bitwise => (Symbol::DoubleSlash, 2),
+        (Some(b'<'), Some(b'<')) if bitwise => (Symbol::ShiftLeft, 2),
+        (Some(b'<'), Some(b'=')) => (Symbol::LessEqual, 2),
+        (Some(b'>'), Some(b'>')) if bitwise => (Symbol::ShiftRight, 2),
+        (Some(b'>'), Some(b'=')) => (Symbol::GreaterEqual, 2),
+        (Some(b'='), Some(b'=')) => (Symbol::DoubleEqual, 2),
+        (Some(b'~'), Some(b'=')) => (Symbol::NotEqual, 2),
+        (Some(b':'), Some(b':')) if labels => (Symbol::DoubleColon, 2),
+
+        (Some(b'{'), _) => (Symbol::LeftBrace, 1),
+        (Some(b'}'), _) => (Symbol::RightBrace, 1),
+        (Some(b'['), _) => (Symbol::LeftBracket, 1),
+        (Some(b']'), _) => (Symbol::RightBracket, 1),
+        (Some(b'('), _) => (Symbol::LeftParen, 1),
+        (Some(b')'), _) => (Symbol::RightParen, 1),
+        (Some(b'+'), _) => (Symbol::Plus, 1),
+        (Some(b'-'), _) => (Symbol::Minus, 1),
+        (Some(b'*'), _) => (Symbol::Star, 1),
+        (Some(b'/'), _) => (Symbol::Slash, 1),
+        (Some(b'^'), _) => (Symbol::Caret, 1),
+        (Some(b'%'), _) => (Symbol::Percent, 1),
+        (Some(b'.'), _) => (Symbol::Dot, 1),
+        (Some(b'#'), _) => (Symbol::Hash, 1),
+        (Some(b'<'), _) => (Symbol::LessThan, 1),
+        (Some(b'>'), _) => (Symbol::GreaterThan, 1),
+        (Some(b'&'), _) if bitwise => (Symbol::Ampersand, 1),
+        (Some(b'|'), _) if bitwise => (Symbol::Pipe, 1),
+        (Some(b'~'), _) if bitwise => (Symbol::Tilde, 1),
+        (Some(b'='), _) => (Symbol::Equal, 1),
+        (Some(b','), _) => (Symbol::Comma, 1),
+        (Some(b':'), _) => (Symbol::Colon, 1),
+        (Some(b';'), _) => (Symbol::Semicolon, 1),
+        _ => return Err(AdvanceError::NoMatch),
+    };
+
+    Ok((advance(current, current_position, length, options), TokenKind::Symbol(symbol)))
+}
+
+/// The length of the escape starting with the backslash at `index`, as far as
+/// where the string continues. Escapes can carry a string onto later lines:
+/// an escaped newline, including `\r\n` and `\n\r`, continues it on the
+/// next line, and `\z` skips all the whitespace after it.
+fn escape_length(bytes: &[u8], index: usize) -> usize {
+    match (bytes.get(index + 1), bytes.get(index + 2)) {
+        (Some(b'\r'), Some(b'\n')) | (Some(b'\n'), Some(b'\r')) => 3,
+        (Some(b'z'), _) => 2 + count_bytes(&bytes[index + 2..], is_lua_whitespace),
+        _ => 2,
+    }
+}
+
+/// Whether Lua counts the byte as whitespace, which unlike Rust includes the
+/// vertical tab.
+fn is_lua_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c)
+}
+
+/// The index of the quote that closes the quoted string at the start of
+/// `current`, if it's closed before a line ends outside of an escape.
+fn closing_quote(current: &str) -> Option<usize> {
+    let bytes = current.as_bytes();
+    let quote = *bytes.first()?;
+    let mut index = 1;
+
+    while let Some(&byte) = bytes.get(index) {
+        match byte {
+            b'\\' => index += escape_length(bytes, index),
+            b'\r' | b'\n' => return None,
+            _ if byte == quote => return Some(index),
+            _ => index += 1,
+        }
+    }
+
+    None
+}
+
+/// Finds the first escape in the content of a quoted string that `version`
+/// doesn't accept, giving where it starts and how long it is.
+///
+/// Without a version, every escape that some version accepts is allowed.
+/// Decimal escapes above 255 are an error in every version, and so are
+/// malformed `\u{XXX}` escapes in versions that have them. From 5.2 on,
+/// unknown escapes like `\q` are errors and `\x` needs two hex digits.
+fn invalid_escape(content: &str, version: Option<LuaVersion>) -> Option<(usize, usize)> {
+    let bytes = content.as_bytes();
+    let at_least = |minimum: LuaVersion| version.is_some_and(|version| version >= minimum);
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] != b'\\' {
+            index += 1;
+            continue;
+        }
+
+        match bytes.get(index + 1) {
+            Some(b'u') if content[index + 1..].starts_with("u{") && (version.is_none() || at_least(LuaVersion::Lua53)) => {
+                match unicode_escape(&content[index..]) {
+                    // 5.3 stops at the end of Unicode, and 5.4 goes to 2^31.
+                    Ok((code, length)) if code > 0x10_FFFF && version == Some(LuaVersion::Lua53) => {
+                        return Some((index, length));
+                    },
+                    Ok((_, length)) => index += length,
+                    Err(length) => return Some((index, length)),
+                }
+            },
+            Some(b'x') if at_least(LuaVersion::Lua52) => {
+                let digits = count_bytes(&bytes[index + 2..], |byte| byte.is_ascii_hexdigit()).min(2);
+
+                if digits < 2 {
+                    return Some((index, 2 + digits));
+                }
+
+                index += 4;
+            },
+            Some(byte) if byte.is_ascii_digit() => {
+                let digits = count_bytes(&bytes[index + 1..], |byte| byte.is_ascii_digit()).min(3);
+
+                if content[index + 1..index + 1 + digits].parse::<u32>().unwrap() > 255 {
+                    return Some((index, 1 + digits));
+                }
+
+                index += 1 + digits;
+            },
+            Some(b'a' | b'b' | b'f' | b'n' | b'r' | b't' | b'v' | b'\\' | b'"' | b'\'' | b'\n' | b'\r' | b'z') => index += 2,
+            Some(_) if at_least(LuaVersion::Lua52) => {
+                let length = content[index + 1..].chars().next().map_or(0, char::len_utf8);
+                return Some((index, 1 + length));
+            },
+            // 5.1 keeps the character after an unknown escape as it is.
+            _ => index += 1 + content[index + 1..].chars().next().map_or(0, char::len_utf8),
+        }
+    }
+
+    None
+}
+
+fn parse_string_literal<'a>(current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
+    if !current.starts_with(['"', '\'']) {
+        return Err(AdvanceError::NoMatch);
+    }
+
+    let literal_end = closing_quote(current).ok_or(AdvanceError::Error(TokenizeError::UnclosedString {
+        position: *current_position,
+    }))?;
+
+    let raw_content = &current[1..literal_end];
+
+    if let Some((start, length)) = invalid_escape(raw_content, options.version) {
+        return Err(AdvanceError::Error(TokenizeError::InvalidEscape {
+            position: current_position.next_position_with_options(&current[..1 + start], options),
+            sequence: raw_content[start..start + length].to_string(),
+        }));
+    }
+
+    let literal = if current.starts_with('"') {
+        StringLiteral::DoubleQuote {
+            raw_content: Cow::from(raw_content),
+        }
+    } else {
+        StringLiteral::SingleQuote {
+            raw_content: Cow::from(raw_content),
+        }
     };
 
     let advance_result = AdvanceResult {
         rest: &current[literal_end + 1..],
         contents: "",
-        new_position: current_position.next_position(&current[..literal_end + 1]),
+        new_position: current_position.next_position_with_options(&current[..literal_end + 1], options),
     };
 
     Ok((advance_result, TokenKind::StringLiteral(literal)))
 }
 
-fn parse_multi_line_string_literal<'a>(current: &'a str, current_position: &SourcePosition) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
-    match parse_multi_line_thing(&PATTERN_MULTI_LINE_STRING_START, current, &current_position) {
+/// Scans the part of an interpolated string at the start of `current`, which
+/// is either the opening backtick or the `}` that closes an expression.
+fn parse_interpolated_string<'a>(current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, InterpolatedStringPart<'a>), AdvanceError> {
+    let bytes = current.as_bytes();
+    let mut index = 1;
+
+    let closer = loop {
+        match bytes.get(index) {
+            Some(b'\\') => index += escape_length(bytes, index),
+            Some(&closer @ b'`') | Some(&closer @ b'{') => break closer,
+            Some(b'\r') | Some(b'\n') | None => {
+                return Err(AdvanceError::Error(TokenizeError::UnclosedString {
+                    position: *current_position,
+                }));
+            },
+            Some(_) => index += 1,
+        }
+    };
+
+    let raw_content = Cow::from(&current[1..index]);
+
+    let part = match (bytes[0], closer) {
+        (b'`', b'`') => InterpolatedStringPart::Simple { raw_content },
+        (b'`', _) => InterpolatedStringPart::Begin { raw_content },
+        (_, b'{') => InterpolatedStringPart::Middle { raw_content },
+        _ => InterpolatedStringPart::End { raw_content },
+    };
+
+    Ok((advance(current, current_position, index + 1, options), part))
+}
+
+fn parse_multi_line_string_literal<'a>(current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
+    match parse_multi_line_thing("[", current, current_position, options) {
         Err(MultiLineError::NoMatch) =>
             Err(AdvanceError::NoMatch),
         Err(MultiLineError::Unclosed(position)) =>
             Err(AdvanceError::Error(TokenizeError::UnclosedString {position})),
+        Err(MultiLineError::Nested(position)) =>
+            Err(AdvanceError::Error(TokenizeError::NestedLongBracket {position})),
         Ok((advance_result, raw_content, depth)) =>
             Ok((advance_result, TokenKind::StringLiteral(
                 StringLiteral::LongForm { raw_content, depth },
@@ -418,78 +1719,101 @@ fn parse_multi_line_string_literal<'a>(current: &'a str, current_position: &Sour
 enum MultiLineError {
     NoMatch,
     Unclosed(SourcePosition),
+    Nested(SourcePosition),
 }
 
-fn parse_multi_line_thing<'a>(re: &Regex, current: &'a str, current_position: &SourcePosition) -> Result<(AdvanceResult<'a>, Cow<'a, str>, u32), MultiLineError> {
-    let captures = re.captures(current).ok_or(MultiLineError::NoMatch)?;
-    let start_match = captures.get(0).unwrap();
+/// Parses something like `[==[ ... ]==]`, preceded by `opener` instead of
+/// the first `[`.
+fn parse_multi_line_thing<'a>(opener: &str, current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, Cow<'a, str>, u32), MultiLineError> {
+    if !current.starts_with(opener) {
+        return Err(MultiLineError::NoMatch);
+    }
+
+    let equals = count_bytes(&current.as_bytes()[opener.len()..], |byte| byte == b'=');
+    let start_length = opener.len() + equals + 1;
 
-    let rest = &current[start_match.end()..];
-    let depth = captures.get(1).unwrap().as_str().len() as u32;
+    if current.as_bytes().get(start_length - 1) != Some(&b'[') {
+        return Err(MultiLineError::NoMatch);
+    }
 
-    let reg_str = format!(r"]={{{}}}]", depth); // Expands to something like ]={5}]
-    let end_reg = Regex::new(reg_str.as_str()).unwrap();
+    let rest = &current[start_length..];
+    let closer = format!("]{}]", "=".repeat(equals));
+    let end = rest.find(&closer);
 
-    let end_captures = end_reg.captures(rest).ok_or(MultiLineError::Unclosed(*current_position))?;
+    // Lua 5.1 gives up on a `[[` inside `[[ ... ]]`, even before finding out
+    // whether the brackets are closed.
+    if equals == 0 && !options.accepts(LuaVersion::Lua52) {
+        if let Some(nested) = rest[..end.unwrap_or(rest.len())].find("[[") {
+            let position = current_position.next_position_with_options(&current[..start_length + nested], options);
+            return Err(MultiLineError::Nested(position));
+        }
+    }
 
-    let end_match = end_captures.get(0).unwrap();
+    let end = end.ok_or(MultiLineError::Unclosed(*current_position))?;
 
-    let content = Cow::from(&rest[..end_match.start()]);
+    let content = Cow::from(&rest[..end]);
 
     let advance_result = AdvanceResult {
-        rest: &rest[end_match.end()..],
+        rest: &rest[end + closer.len()..],
         contents: "",
-        new_position: current_position.next_position(&current[..start_match.end() + end_match.end()]),
+        new_position: current_position.next_position_with_options(&current[..start_length + end + closer.len()], options),
     };
 
-    Ok((advance_result, content, depth))
+    Ok((advance_result, content, equals as u32))
 }
 
-/// Attempts to advance one token into the stream.
-fn tokenize_step<'a>(current: &'a str, current_position: &SourcePosition) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
-    try_advance!(parse_identifier(current, current_position));
-    try_advance!(parse_number_literal(current, current_position));
-    try_advance!(parse_multi_line_string_literal(current, current_position));
-    try_advance!(parse_symbol(current, current_position));
-    try_advance!(parse_string_literal(current, current_position));
-
-    Err(AdvanceError::NoMatch)
+/// Attempts to advance one token into the stream, picking what to scan from
+/// the first character.
+fn tokenize_step<'a>(current: &'a str, current_position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, TokenKind<'a>), AdvanceError> {
+    let bytes = current.as_bytes();
+
+    match bytes.first() {
+        Some(b'_') | Some(b'a'..=b'z') | Some(b'A'..=b'Z') => parse_identifier(current, current_position, options),
+        Some(b'0'..=b'9') => parse_number_literal(current, current_position, options),
+        Some(b'.') if bytes.get(1).is_some_and(u8::is_ascii_digit) => parse_number_literal(current, current_position, options),
+        Some(b'[') => match parse_multi_line_string_literal(current, current_position, options) {
+            Err(AdvanceError::NoMatch) => parse_symbol(current, current_position, options),
+            result => result,
+        },
+        Some(b'"') | Some(b'\'') => parse_string_literal(current, current_position, options),
+        Some(byte) if !byte.is_ascii() && options.unicode_identifiers => parse_identifier(current, current_position, options),
+        Some(_) => parse_symbol(current, current_position, options),
+        None => Err(AdvanceError::NoMatch),
+    }
 }
 
-fn parse_whitespace<'a>(current: &'a str, position: &SourcePosition) -> Result<AdvanceResult<'a>, AdvanceError> {
-    advance(current, position, &PATTERN_WHITESPACE)
+fn parse_whitespace<'a>(current: &'a str, position: &SourcePosition, options: &TokenizerOptions) -> Result<AdvanceResult<'a>, AdvanceError> {
+    match current.find(|c: char| !c.is_whitespace()).unwrap_or(current.len()) {
+        0 => Err(AdvanceError::NoMatch),
+        length => Ok(advance(current, position, length, options)),
+    }
 }
 
-fn parse_multi_line_comment<'a>(current: &'a str, position: &SourcePosition) -> Result<(AdvanceResult<'a>, Comment<'a>), AdvanceError> {
-    match parse_multi_line_thing(&PATTERN_MULTI_LINE_COMMENT_START, current, &position) {
+fn parse_multi_line_comment<'a>(current: &'a str, position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, Comment<'a>), AdvanceError> {
+    match parse_multi_line_thing("--[", current, position, options) {
         Ok((a, content, depth)) =>
             Ok((a, Comment::MultiLine{content, depth})),
         Err(MultiLineError::NoMatch) =>
             Err(AdvanceError::NoMatch),
         Err(MultiLineError::Unclosed(position)) =>
             Err(AdvanceError::Error(TokenizeError::UnclosedComment { position })),
+        Err(MultiLineError::Nested(position)) =>
+            Err(AdvanceError::Error(TokenizeError::NestedLongBracket { position })),
     }
 }
 
-fn parse_comment<'a>(current: &'a str, position: &SourcePosition) -> Result<(AdvanceResult<'a>, Comment<'a>), AdvanceError> {
-    if let Some(captures) = PATTERN_SINGLE_LINE_COMMENT.captures(current) {
-        let full_capture = captures.get(0).unwrap();
-        let contents = full_capture.as_str();
-        let rest = &current[full_capture.end()..];
-        let new_position = position.next_position(contents);
+fn parse_comment<'a>(current: &'a str, position: &SourcePosition, options: &TokenizerOptions) -> Result<(AdvanceResult<'a>, Comment<'a>), AdvanceError> {
+    if !current.starts_with("--") {
+        return Err(AdvanceError::NoMatch);
+    }
+
+    let result = advance(current, position, current.find('\n').unwrap_or(current.len()), options);
 
-        let comment = Comment::SingleLine {
-            content: contents[2..].into(),
-        };
+    let comment = Comment::SingleLine {
+        content: result.contents[2..].into(),
+    };
 
-        Ok((AdvanceResult {
-            rest,
-            contents,
-            new_position,
-        }, comment))
-    } else {
-        Err(AdvanceError::NoMatch)
-    }
+    Ok((result, comment))
 }
 
 /// Tokenizes a source string completely and returns a [Vec][Vec] of [Tokens][Token].
@@ -497,142 +1821,1179 @@ fn parse_comment<'a>(current: &'a str, position: &SourcePosition) -> Result<(Adv
 /// # Errors
 /// Will return an [UnknownSequence][TokenizeError::UnknownSequence] if it
 /// encounters a sequence of characters that it cannot parse.
-// TODO: Change to returning iterator?
 pub fn tokenize<'a>(source: &'a str) -> Result<Vec<Token<'a>>, TokenizeError> {
-    let mut tokens = Vec::new();
-    let mut current = source;
-    let mut current_position = SourcePosition {
-        line: 1,
-        column: 1,
-        bytes: 0,
-    };
+    tokenize_with_options(source, &TokenizerOptions::default())
+}
 
-    loop {
-        let mut prefix = Vec::new();
+/// Tokenizes a source string like [tokenize], with extensions enabled by
+/// `options`.
+pub fn tokenize_with_options<'a>(source: &'a str, options: &TokenizerOptions) -> Result<Vec<Token<'a>>, TokenizeError> {
+    if let Some(format) = detect_bytecode(source.as_bytes()) {
+        return Err(TokenizeError::PrecompiledBytecode { format });
+    }
 
-        loop {
-            if let Ok(result) = parse_whitespace(current, &current_position) {
-                current = result.rest;
-                current_position = result.new_position;
+    tokenize_from(source, START_POSITION, options)
+}
 
-                prefix.push(TokenPrefix::Whitespace(result.contents.into()));
-            } else if let Ok((result, comment)) = parse_multi_line_comment(current, &current_position) {
-                current = result.rest;
-                current_position = result.new_position;
+/// Tokenizes a source string like [tokenize], but into `tokens`, which is
+/// cleared first. Reusing one buffer for many sources saves growing a new one
+/// for each of them.
+///
+/// The buffer can only hold tokens of sources that outlive it. To reuse one
+/// for sources that come and go, see [Parser][::reusable::Parser].
+///
+/// If tokenizing fails, `tokens` holds the tokens before the error.
+pub fn tokenize_into<'a>(source: &'a str, tokens: &mut Vec<Token<'a>>) -> Result<(), TokenizeError> {
+    tokenize_into_with_options(source, &TokenizerOptions::default(), tokens)
+}
 
-                prefix.push(TokenPrefix::Comment(comment));
-            } else if let Ok((result, comment)) = parse_comment(current, &current_position) {
-                current = result.rest;
-                current_position = result.new_position;
+/// Tokenizes a source string into a buffer like [tokenize_into], with
+/// extensions enabled by `options`.
+pub fn tokenize_into_with_options<'a>(source: &'a str, options: &TokenizerOptions, tokens: &mut Vec<Token<'a>>) -> Result<(), TokenizeError> {
+    tokens.clear();
 
-                prefix.push(TokenPrefix::Comment(comment));
-            } else {
-                break;
-            }
-        }
+    if let Some(format) = detect_bytecode(source.as_bytes()) {
+        return Err(TokenizeError::PrecompiledBytecode { format });
+    }
 
-        if current.is_empty() {
-            if !prefix.is_empty() {
-                tokens.push(Token {
-                    prefix,
-                    kind: TokenKind::EndOfFile,
-                    start_position: current_position.clone(),
-                    end_position: current_position.clone(),
-                });
-            }
+    tokenize_from_into(source, START_POSITION, options, tokens)
+}
 
-            break;
+/// Tokenizes source that hasn't been checked to be UTF-8 yet, like the
+/// contents of a file.
+///
+/// Precompiled bytecode is recognized before the source is checked, so it
+/// gives a [PrecompiledBytecode][TokenizeError::PrecompiledBytecode] error
+/// instead of an [InvalidUtf8][TokenizeError::InvalidUtf8] one.
+pub fn tokenize_bytes<'a>(source: &'a [u8]) -> Result<Vec<Token<'a>>, TokenizeError> {
+    if let Some(format) = detect_bytecode(source) {
+        return Err(TokenizeError::PrecompiledBytecode { format });
+    }
+
+    let source = str::from_utf8(source).map_err(|err| {
+        let valid = str::from_utf8(&source[..err.valid_up_to()]).unwrap();
+
+        TokenizeError::InvalidUtf8 {
+            position: START_POSITION.next_position(valid),
         }
+    })?;
 
-        match tokenize_step(current, &current_position) {
-            Ok((result, token_kind)) => {
-                tokens.push(Token {
-                    prefix,
-                    kind: token_kind,
-                    start_position: current_position.clone(),
-                    end_position: result.new_position.clone(),
-                });
+    tokenize(source)
+}
 
-                current = result.rest;
-                current_position = result.new_position;
-            },
-            Err(AdvanceError::Error(e)) => return Err(e),
-            Err(AdvanceError::NoMatch) => {
-                if !current.is_empty() {
-                    return Err(TokenizeError::UnknownSequence {
-                        position: current_position,
-                    });
-                }
+pub(crate) const START_POSITION: SourcePosition = SourcePosition {
+    line: 1,
+    column: 1,
+    bytes: 0,
+};
+
+/// Some editors start UTF-8 files with this.
+pub(crate) const BYTE_ORDER_MARK: char = '\u{FEFF}';
+
+/// Tokenizes a piece of a larger source that starts at `start`, so that the
+/// positions of the tokens are relative to the larger source.
+pub(crate) fn tokenize_from<'a>(source: &'a str, start: SourcePosition, options: &TokenizerOptions) -> Result<Vec<Token<'a>>, TokenizeError> {
+    // Lua averages a few bytes per token, so this usually saves growing the
+    // buffer over and over.
+    let capacity = (source.len() / 4).min(options.max_tokens.map_or(usize::MAX, |max| max.saturating_add(1)));
+    let mut tokens = Vec::with_capacity(capacity);
+    tokenize_from_into(source, start, options, &mut tokens)?;
+
+    Ok(tokens)
+}
+
+/// Tokenizes like [tokenize_from], appending to `tokens` so that its
+/// allocation can be reused.
+pub(crate) fn tokenize_from_into<'a>(source: &'a str, start: SourcePosition, options: &TokenizerOptions, tokens: &mut Vec<Token<'a>>) -> Result<(), TokenizeError> {
+    for token in TokenIter::new(source, start, options.clone()) {
+        tokens.push(token?);
+    }
+
+    Ok(())
+}
+
+/// Moves the [Whitespace][TokenKind::Whitespace] and
+/// [Comment][TokenKind::Comment] tokens produced with
+/// [TokenizerOptions::emit_trivia] back into the prefixes of the tokens after
+/// them, so that the tokens can be parsed. Trivia at the end goes to an
+/// [EndOfFile][TokenKind::EndOfFile] token.
+///
+/// The result is the same as tokenizing without `emit_trivia`.
+pub fn significant_tokens<'a, I: IntoIterator<Item = Token<'a>>>(tokens: I) -> Vec<Token<'a>> {
+    let mut significant = Vec::new();
+    let mut prefix = Vec::new();
+    let mut end = None;
+
+    for token in tokens {
+        prefix.extend(token.prefix);
+
+        match token.kind {
+            TokenKind::Whitespace(whitespace) => prefix.push(TokenPrefix::Whitespace(whitespace)),
+            TokenKind::Comment(comment) => prefix.push(TokenPrefix::Comment(comment)),
+            kind => {
+                significant.push(Token {
+                    kind,
+                    prefix: mem::take(&mut prefix),
+                    start_position: token.start_position,
+                    end_position: token.end_position,
+                });
             },
         }
+
+        end = Some(token.end_position);
     }
 
-    Ok(tokens)
+    if let Some(end) = end.filter(|_| !prefix.is_empty()) {
+        significant.push(Token {
+            kind: TokenKind::EndOfFile,
+            prefix,
+            start_position: end,
+            end_position: end,
+        });
+    }
+
+    significant
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Tokenizes a source string lazily, one [Token] at a time, so that callers
+/// can stop early without tokenizing the rest of the source.
+///
+/// Collecting the iterator gives the same result as [tokenize]. After the
+/// first error, the iterator only returns `None`.
+pub fn tokenize_iter<'a>(source: &'a str) -> TokenIter<'a> {
+    tokenize_iter_with_options(source, &TokenizerOptions::default())
+}
 
-    fn test_kinds_eq(input: &'static str, expected: Vec<TokenKind<'static>>) {
-        let kinds = tokenize(input).unwrap().iter().map(|v| v.kind.clone()).collect::<Vec<_>>();
-        assert_eq!(kinds, expected);
+/// Tokenizes a source string lazily like [tokenize_iter], with extensions
+/// enabled by `options`.
+pub fn tokenize_iter_with_options<'a>(source: &'a str, options: &TokenizerOptions) -> TokenIter<'a> {
+    let mut iter = TokenIter::new(source, START_POSITION, options.clone());
+
+    if let Some(format) = detect_bytecode(source.as_bytes()) {
+        iter.error = Some(TokenizeError::PrecompiledBytecode { format });
     }
 
-    #[test]
-    fn keyword_vs_identifier() {
-        test_kinds_eq("local", vec![TokenKind::Symbol(Symbol::Local)]);
-        test_kinds_eq("local_", vec![TokenKind::Identifier("local_".into())]);
-        test_kinds_eq("locale", vec![TokenKind::Identifier("locale".into())]);
-        test_kinds_eq("_local", vec![TokenKind::Identifier("_local".into())]);
-        test_kinds_eq("local _", vec![TokenKind::Symbol(Symbol::Local), TokenKind::Identifier("_".into())]);
+    iter
+}
+
+/// Tokenizes a source string like [tokenize], but carries on after errors
+/// instead of stopping at the first one.
+///
+/// After an error, the tokenizer skips ahead to the next whitespace or the
+/// next place a token can start. An unclosed string skips the rest of its
+/// line, and an unclosed comment or long string skips the rest of the
+/// source. The text that was skipped isn't part of any token.
+///
+/// Without errors, the tokens are the same as [tokenize] gives.
+pub fn tokenize_recovering<'a>(source: &'a str) -> (Vec<Token<'a>>, Vec<TokenizeError>) {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+
+    if let Some(format) = detect_bytecode(source.as_bytes()) {
+        errors.push(TokenizeError::PrecompiledBytecode { format });
+        return (tokens, errors);
+    }
+
+    let mut iter = TokenIter::new(source, START_POSITION, TokenizerOptions::default());
+
+    while !iter.finished {
+        match iter.next_token() {
+            Ok(Some(token)) => tokens.push(token),
+            Ok(None) => {},
+            Err(err) => {
+                iter.skip_error(&err);
+                errors.push(err);
+            },
+        }
+    }
+
+    (tokens, errors)
+}
+
+/// The iterator returned by [tokenize_iter].
+#[derive(Debug, Clone)]
+pub struct TokenIter<'a> {
+    current: &'a str,
+    position: SourcePosition,
+    options: TokenizerOptions,
+
+    /// A byte order mark and shebang skipped at the start of the source,
+    /// which go before the first token.
+    header: Vec<TokenPrefix<'a>>,
+
+    /// An error to return before anything else, like for precompiled
+    /// bytecode.
+    error: Option<TokenizeError>,
+    finished: bool,
+
+    /// How many tokens have been returned, not counting one for the end of
+    /// the source.
+    count: usize,
+
+    /// The interpolated strings that the tokenizer is inside the expressions
+    /// of, innermost last.
+    interpolations: Vec<Interpolation>,
+}
+
+/// An interpolated string with an expression that hasn't been closed yet.
+#[derive(Debug, Clone)]
+struct Interpolation {
+    /// Where the string starts.
+    start: SourcePosition,
+
+    /// How many `{` in the expression are still open. A `}` with none open
+    /// ends the expression.
+    braces: usize,
+}
+
+impl<'a> TokenIter<'a> {
+    fn new(source: &'a str, start: SourcePosition, options: TokenizerOptions) -> TokenIter<'a> {
+        let mut current = source;
+        let mut position = start;
+        let mut header = Vec::new();
+
+        // Like Lua, skip a byte order mark, but only at the very start of the
+        // source. Anywhere else it's an unknown sequence.
+        if start.bytes == 0 && current.starts_with(BYTE_ORDER_MARK) {
+            current = &current[BYTE_ORDER_MARK.len_utf8()..];
+            position.bytes += BYTE_ORDER_MARK.len_utf8();
+            header.push(TokenPrefix::ByteOrderMark);
+        }
+
+        // Skip a shebang line, but only at the very start of the source. Lua
+        // skips any first line starting with `#`, but `#t` on its own is a
+        // valid expression, so this requires the `!` too.
+        if start.bytes == 0 && current.starts_with("#!") {
+            let line = &current[..current.find('\n').unwrap_or(current.len())];
+
+            current = &current[line.len()..];
+            position = position.next_position_with_options(line, &options);
+            header.push(TokenPrefix::Shebang(line.into()));
+        }
+
+        let error = match options.max_source_len {
+            Some(max) if source.len() > max => Some(TokenizeError::LimitExceeded {
+                limit: TokenizerLimit::MaxSourceLen(max),
+                position: start,
+            }),
+            _ => None,
+        };
+
+        TokenIter {
+            current,
+            position,
+            options,
+            header,
+            error,
+            finished: false,
+            count: 0,
+            interpolations: Vec::new(),
+        }
+    }
+
+    /// The position of the next token, or of the end of the source once
+    /// every token has been returned.
+    pub fn position(&self) -> SourcePosition {
+        self.position
+    }
+
+    /// Steps over the source that caused `err`, which starts where the
+    /// tokenizer stopped.
+    fn skip_error(&mut self, err: &TokenizeError) {
+        let length = match err {
+            TokenizeError::UnclosedComment { .. } => self.current.len(),
+            TokenizeError::MalformedNumber { text, .. } => text.len(),
+            TokenizeError::NestedLongBracket { .. } => self.current.find("]]").map_or(self.current.len(), |end| end + 2),
+            TokenizeError::InvalidEscape { .. } => closing_quote(self.current).map_or(self.current.len(), |end| end + 1),
+            TokenizeError::UnclosedString { .. } if self.current.starts_with('[') => self.current.len(),
+            TokenizeError::UnclosedString { .. } => self.current.find('\n').unwrap_or(self.current.len()),
+            _ => {
+                let mut length = self.current.chars().next().map_or(0, char::len_utf8);
+
+                while let Some(character) = self.current[length..].chars().next() {
+                    let rest = &self.current[length..];
+
+                    // A token with an error of its own still starts here, and
+                    // is reported separately.
+                    let token_starts = !matches!(tokenize_step(rest, &self.position, &self.options), Err(AdvanceError::NoMatch));
+
+                    if character.is_whitespace() || token_starts {
+                        break;
+                    }
+
+                    length += character.len_utf8();
+                }
+
+                length
+            },
+        };
+
+        self.position = self.position.next_position_with_options(&self.current[..length], &self.options);
+        self.current = &self.current[length..];
+    }
+
+    /// Steps over the whitespace or comment at the start of what's left, if
+    /// there is one.
+    fn next_trivia(&mut self) -> Result<Option<TokenPrefix<'a>>, TokenizeError> {
+        if let Ok(result) = parse_whitespace(self.current, &self.position, &self.options) {
+            self.current = result.rest;
+            self.position = result.new_position;
+
+            return Ok(Some(TokenPrefix::Whitespace(result.contents.into())));
+        }
+
+        // An unclosed block comment is an error, not a line comment that
+        // happens to start with `[[`.
+        let comment = match parse_multi_line_comment(self.current, &self.position, &self.options) {
+            Ok(comment) => comment,
+            Err(AdvanceError::Error(err)) => return Err(err),
+            Err(AdvanceError::NoMatch) => match parse_comment(self.current, &self.position, &self.options) {
+                Ok(comment) => comment,
+                Err(_) => return Ok(None),
+            },
+        };
+
+        let (result, comment) = comment;
+        self.current = result.rest;
+        self.position = result.new_position;
+
+        Ok(Some(TokenPrefix::Comment(comment)))
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, TokenizeError> {
+        let mut prefix = mem::take(&mut self.header);
+
+        loop {
+            let start_position = self.position;
+
+            let trivia = match self.next_trivia() {
+                Ok(trivia) => trivia,
+                Err(err) => return self.skip_or_stop(prefix, err),
+            };
+
+            let kind = match trivia {
+                Some(TokenPrefix::Whitespace(whitespace)) if self.options.emit_trivia => TokenKind::Whitespace(whitespace),
+                Some(TokenPrefix::Comment(comment)) if self.options.emit_trivia => TokenKind::Comment(comment),
+                Some(item) => {
+                    prefix.push(item);
+                    continue;
+                },
+                None => break,
+            };
+
+            return Ok(Some(Token {
+                prefix,
+                kind,
+                start_position,
+                end_position: self.position,
+            }));
+        }
+
+        if self.current.is_empty() {
+            if let Some(interpolation) = self.interpolations.pop() {
+                let err = TokenizeError::UnclosedString {
+                    position: interpolation.start,
+                };
+
+                return self.skip_or_stop(prefix, err);
+            }
+
+            self.finished = true;
+
+            if prefix.is_empty() {
+                return Ok(None);
+            }
+
+            return Ok(Some(Token {
+                prefix,
+                kind: TokenKind::EndOfFile,
+                start_position: self.position,
+                end_position: self.position,
+            }));
+        }
+
+        let resumes_string = self.current.starts_with('}')
+            && self.interpolations.last().is_some_and(|interpolation| interpolation.braces == 0);
+
+        let step = if self.options.interpolated_strings && (self.current.starts_with('`') || resumes_string) {
+            let part = parse_interpolated_string(self.current, &self.position, &self.options);
+
+            // A string that isn't closed on its line can't be resumed.
+            if resumes_string && part.is_err() {
+                self.interpolations.pop();
+            }
+
+            part.map(|(result, part)| (result, TokenKind::InterpolatedString(part)))
+        } else {
+            tokenize_step(self.current, &self.position, &self.options)
+        };
+
+        match step {
+            Ok((result, kind)) => {
+                match &kind {
+                    TokenKind::InterpolatedString(InterpolatedStringPart::Begin { .. }) => {
+                        self.interpolations.push(Interpolation {
+                            start: self.position,
+                            braces: 0,
+                        });
+                    },
+                    TokenKind::InterpolatedString(InterpolatedStringPart::End { .. }) => {
+                        self.interpolations.pop();
+                    },
+                    TokenKind::Symbol(Symbol::LeftBrace) => {
+                        if let Some(interpolation) = self.interpolations.last_mut() {
+                            interpolation.braces += 1;
+                        }
+                    },
+                    TokenKind::Symbol(Symbol::RightBrace) => {
+                        if let Some(interpolation) = self.interpolations.last_mut() {
+                            interpolation.braces -= 1;
+                        }
+                    },
+                    _ => {},
+                }
+
+                let token = Token {
+                    prefix,
+                    kind,
+                    start_position: self.position,
+                    end_position: result.new_position,
+                };
+
+                self.current = result.rest;
+                self.position = result.new_position;
+
+                Ok(Some(token))
+            },
+            Err(AdvanceError::Error(err)) => self.skip_or_stop(prefix, err),
+            Err(AdvanceError::NoMatch) => {
+                let err = if self.current.starts_with(|c: char| !c.is_ascii() && is_xid_continue(c)) {
+                    TokenizeError::NonAsciiIdentifier {
+                        position: self.position,
+                    }
+                } else {
+                    TokenizeError::UnknownSequence {
+                        position: self.position,
+                        sequence: preview(self.current),
+                    }
+                };
+
+                self.skip_or_stop(prefix, err)
+            },
+        }
+    }
+
+    /// Returns `err`, or skips over the source where it starts and turns it
+    /// into an [Error][TokenKind::Error] token, as
+    /// [TokenizerOptions::on_error] says.
+    fn skip_or_stop(&mut self, prefix: Vec<TokenPrefix<'a>>, err: TokenizeError) -> Result<Option<Token<'a>>, TokenizeError> {
+        let first = self.current.chars().next().map_or(0, char::len_utf8);
+
+        let length = match self.options.on_error {
+            OnError::Stop => return Err(err),
+            OnError::SkipChar => first,
+            OnError::SkipToWhitespace => self.current[first..].find(char::is_whitespace).map_or(self.current.len(), |end| first + end),
+        };
+
+        let result = advance(self.current, &self.position, length, &self.options);
+
+        let token = Token {
+            prefix,
+            kind: TokenKind::Error {
+                text: Cow::from(result.contents),
+                error: err,
+            },
+            start_position: self.position,
+            end_position: result.new_position,
+        };
+
+        self.current = result.rest;
+        self.position = result.new_position;
+
+        Ok(Some(token))
+    }
+}
+
+/// The first few characters of `source`, up to the next whitespace, for
+/// showing in errors.
+fn preview(source: &str) -> String {
+    const LENGTH: usize = 16;
+
+    let mut sequence = String::new();
+
+    // Only the characters that are shown are looked at, so that a long run
+    // without whitespace doesn't make every error slow.
+    for (index, c) in source.chars().take_while(|c| !c.is_whitespace()).enumerate() {
+        if index == LENGTH {
+            sequence.push_str("...");
+            break;
+        }
+
+        sequence.push(c);
+    }
+
+    sequence
+}
+
+impl<'a> Iterator for TokenIter<'a> {
+    type Item = Result<Token<'a>, TokenizeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        if let Some(err) = self.error.take() {
+            self.finished = true;
+            return Some(Err(err));
+        }
+
+        match self.next_token() {
+            Ok(Some(token)) => {
+                if token.kind != TokenKind::EndOfFile {
+                    self.count += 1;
+                }
+
+                match self.options.max_tokens {
+                    Some(max) if self.count > max => {
+                        self.finished = true;
+
+                        Some(Err(TokenizeError::LimitExceeded {
+                            limit: TokenizerLimit::MaxTokens(max),
+                            position: token.start_position,
+                        }))
+                    },
+                    _ => Some(Ok(token)),
+                }
+            },
+            Ok(None) => None,
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+impl<'a> FusedIterator for TokenIter<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(text: &'static str) -> TokenKind<'static> {
+        TokenKind::NumberLiteral {
+            text: text.into(),
+            kind: number_kind(text),
+        }
+    }
+
+    fn test_kinds_eq(input: &'static str, expected: Vec<TokenKind<'static>>) {
+        let kinds = tokenize(input).unwrap().iter().map(|v| v.kind.clone()).collect::<Vec<_>>();
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn token_kind_helpers() {
+        let tokens = tokenize("local x = 0x10 + #y -- end").unwrap();
+
+        assert!(tokens[0].is_keyword());
+        assert!(tokens[0].is_symbol(Symbol::Local));
+        assert!(!tokens[4].is_keyword());
+        assert!(tokens[4].is_symbol(Symbol::Plus));
+        assert!(!tokens[4].is_symbol(Symbol::Minus));
+
+        assert!(tokens[1].is_identifier());
+        assert_eq!(tokens[1].as_identifier(), Some("x"));
+        assert_eq!(tokens[0].as_identifier(), None);
+        assert_eq!(tokens[3].as_number(), Some("0x10"));
+        assert_eq!(tokens[1].as_number(), None);
+
+        assert!(tokens.last().unwrap().is_trivia_only());
+        assert!(!tokens[0].is_trivia_only());
+
+        let descriptions: Vec<_> = tokens.iter().map(Token::description).collect();
+        assert_eq!(descriptions, vec![
+            "'local'", "identifier", "'='", "number literal", "'+'", "'#'", "identifier", "end of file",
+        ]);
+        assert_eq!(tokenize("'s'").unwrap()[0].description(), "string literal");
+    }
+
+    #[test]
+    fn keyword_vs_identifier() {
+        test_kinds_eq("local", vec![TokenKind::Symbol(Symbol::Local)]);
+        test_kinds_eq("local_", vec![TokenKind::Identifier("local_".into())]);
+        test_kinds_eq("locale", vec![TokenKind::Identifier("locale".into())]);
+        test_kinds_eq("_local", vec![TokenKind::Identifier("_local".into())]);
+        test_kinds_eq("local _", vec![TokenKind::Symbol(Symbol::Local), TokenKind::Identifier("_".into())]);
+    }
+
+    #[test]
+    fn every_keyword() {
+        let keywords = [
+            "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+            "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+        ];
+
+        for keyword in &keywords {
+            let tokens = tokenize(keyword).unwrap();
+            assert!(matches!(tokens[0].kind, TokenKind::Symbol(symbol) if symbol.to_str() == *keyword), "{}", keyword);
+
+            // Words that only start with a keyword are still names.
+            let longer = format!("{}y", keyword);
+            assert_eq!(tokenize(&longer).unwrap()[0].kind, TokenKind::Identifier(longer.clone().into()));
+        }
+
+        test_kinds_eq("iffy andy _end", vec![
+            TokenKind::Identifier("iffy".into()),
+            TokenKind::Identifier("andy".into()),
+            TokenKind::Identifier("_end".into()),
+        ]);
+
+        // `goto` is only reserved from Lua 5.2 on, so it stays a name that
+        // the parser can recognize by context.
+        test_kinds_eq("goto", vec![TokenKind::Identifier("goto".into())]);
+    }
+
+    #[test]
+    fn number_kinds() {
+        let kind = |source: &str| match tokenize(source).unwrap()[0].kind {
+            TokenKind::NumberLiteral { kind, .. } => kind,
+            ref other => panic!("{:?} is not a number", other),
+        };
+
+        for &integer in &["3", "0x7fffffffffffffff", "0xffffffffffffffffff", "9223372036854775807"] {
+            assert_eq!(kind(integer), NumberKind::Integer, "{}", integer);
+        }
+
+        for &float in &["3.0", "1e1", "1.", ".5", "0x1p4", "0xA.8", "9223372036854775808"] {
+            assert_eq!(kind(float), NumberKind::Float, "{}", float);
+        }
+
+        assert_eq!(tokenize("1e1").unwrap()[0].kind.to_source(), "1e1");
+    }
+
+    #[test]
+    fn luajit_number_suffixes() {
+        let options = TokenizerOptions {
+            luajit_number_suffixes: true,
+            ..TokenizerOptions::default()
+        };
+
+        let kind = |source: &str| match tokenize_with_options(source, &options).unwrap()[0].kind {
+            TokenKind::NumberLiteral { ref text, kind } => {
+                assert_eq!(text, source);
+                kind
+            },
+            ref other => panic!("{:?} is not a number", other),
+        };
+
+        for &int64 in &["12LL", "12ll", "0xffLL", "1lL"] {
+            assert_eq!(kind(int64), NumberKind::Int64, "{}", int64);
+        }
+
+        for &uint64 in &["12ULL", "0xffffULL", "12ull", "1uLl"] {
+            assert_eq!(kind(uint64), NumberKind::UInt64, "{}", uint64);
+        }
+
+        for &imaginary in &["3i", "3I", "0.5i", "1e5i", "0x10i", "9223372036854775808i"] {
+            assert_eq!(kind(imaginary), NumberKind::Imaginary, "{}", imaginary);
+        }
+
+        assert_eq!(kind("12"), NumberKind::Integer);
+        assert_eq!(kind("1.5"), NumberKind::Float);
+
+        for malformed in &["1.5LL", "1e2ULL", "12L", "12UL", "12LLx", "3ii", "3i.", "12LL_"] {
+            assert!(matches!(tokenize_with_options(malformed, &options), Err(TokenizeError::MalformedNumber { .. })), "{}", malformed);
+        }
+
+        assert_eq!(tokenize_with_options("x = 1LL+2i", &options).unwrap().iter().map(|token| token.kind.to_source()).collect::<Vec<_>>(),
+            vec!["x", "=", "1LL", "+", "2i"]);
+
+        // Without the option, they're malformed.
+        for &(suffixed, text) in &[("12LL", "12L"), ("0xffffULL", "0xffffU"), ("3i", "3i")] {
+            assert_eq!(tokenize(suffixed), Err(TokenizeError::MalformedNumber {
+                position: START_POSITION,
+                text: text.to_string(),
+            }), "{}", suffixed);
+        }
     }
 
     #[test]
     fn number_literals() {
-        test_kinds_eq("6", vec![TokenKind::NumberLiteral("6".into())]);
-        test_kinds_eq("0.231e-6", vec![TokenKind::NumberLiteral("0.231e-6".into())]);
-        test_kinds_eq("-123.7", vec![TokenKind::NumberLiteral("-123.7".into())]);
-        test_kinds_eq("0x12AfEE", vec![TokenKind::NumberLiteral("0x12AfEE".into())]);
-        test_kinds_eq("-0x123FFe", vec![TokenKind::NumberLiteral("-0x123FFe".into())]);
-        test_kinds_eq("1023.47e126", vec![TokenKind::NumberLiteral("1023.47e126".into())]);
+        test_kinds_eq("6", vec![number("6")]);
+        test_kinds_eq("0.231e-6", vec![number("0.231e-6")]);
+        test_kinds_eq("1e+5", vec![number("1e+5")]);
+
+        for hex_float in &["0x1p4", "0xA.8", "0x.8p1", "0xAp-2", "0XA.8P+2", "0x1.", "0xA.8p2"] {
+            test_kinds_eq(hex_float, vec![number(hex_float)]);
+        }
+
+        for malformed in &["0x", "0xg", "0x.p1", "3a", "1e", "1e+", "0x1p", "1.5.2", "2_"] {
+            assert_eq!(tokenize(malformed), Err(TokenizeError::MalformedNumber {
+                position: START_POSITION,
+                text: (*malformed).to_string(),
+            }), "{}", malformed);
+        }
+
+        // A number is read as far as it could go, even when the start of it
+        // would make a valid number.
+        assert_eq!(tokenize("x = 12.5e-"), Err(TokenizeError::MalformedNumber {
+            position: SourcePosition { bytes: 4, line: 1, column: 5 },
+            text: "12.5e-".to_string(),
+        }));
+
+        for valid in &["1.", "3.e5", "1.5"] {
+            test_kinds_eq(valid, vec![number(valid)]);
+        }
+
+        test_kinds_eq("1 .. 2", vec![number("1"), TokenKind::Symbol(Symbol::TwoDots), number("2")]);
+
+        // A `..` right after a number is concatenation, not a fraction.
+        test_kinds_eq("1..2", vec![number("1"), TokenKind::Symbol(Symbol::TwoDots), number("2")]);
+        test_kinds_eq("1.5..2.", vec![number("1.5"), TokenKind::Symbol(Symbol::TwoDots), number("2.")]);
+        test_kinds_eq(".5..5", vec![number(".5"), TokenKind::Symbol(Symbol::TwoDots), number("5")]);
+        test_kinds_eq("0xA..1", vec![number("0xA"), TokenKind::Symbol(Symbol::TwoDots), number("1")]);
+        test_kinds_eq("1...", vec![number("1"), TokenKind::Symbol(Symbol::Ellipse)]);
+
+        // Like in Lua, a dot followed by digits is a number, even after a
+        // name.
+        test_kinds_eq("a.5", vec![TokenKind::Identifier("a".into()), number(".5")]);
+
+        // The sign of a number is a separate operator.
+        test_kinds_eq("-123.7", vec![TokenKind::Symbol(Symbol::Minus), number("123.7")]);
+        test_kinds_eq("0x12AfEE", vec![number("0x12AfEE")]);
+        test_kinds_eq("-0x123FFe", vec![TokenKind::Symbol(Symbol::Minus), number("0x123FFe")]);
+        test_kinds_eq("1023.47e126", vec![number("1023.47e126")]);
+
+        // Only ASCII digits make numbers.
+        assert_eq!(tokenize("\u{663}"), Err(TokenizeError::NonAsciiIdentifier { position: START_POSITION }));
     }
 
-    #[test]
-    fn string_literals() {
-        test_kinds_eq("\"\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "".into() })]);
-        test_kinds_eq("\"hello\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "hello".into() })]);
-        test_kinds_eq("\"he\\\"llo\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "he\\\"llo".into() })]);
-        test_kinds_eq("\"he\\nllo\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "he\\nllo".into() })]);
+    #[test]
+    fn string_literals() {
+        test_kinds_eq("\"\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "".into() })]);
+        test_kinds_eq("\"hello\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "hello".into() })]);
+        test_kinds_eq("\"he\\\"llo\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "he\\\"llo".into() })]);
+        test_kinds_eq("\"he\\nllo\"", vec![TokenKind::StringLiteral(StringLiteral::DoubleQuote { raw_content: "he\\nllo".into() })]);
+
+        test_kinds_eq("''", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "".into() })]);
+        test_kinds_eq("'hello'", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "hello".into() })]);
+        test_kinds_eq("'he\\'llo'", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "he\\'llo".into() })]);
+        test_kinds_eq("'he\\nllo'", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "he\\nllo".into() })]);
+
+        assert_eq!(tokenize("\""), Err(TokenizeError::UnclosedString {
+            position: SourcePosition {
+                bytes: 0,
+                line: 1,
+                column: 1,
+            },
+        }));
+
+        assert_eq!(tokenize("\"\n"), Err(TokenizeError::UnclosedString {
+            position: SourcePosition {
+                bytes: 0,
+                line: 1,
+                column: 1,
+            },
+        }));
+
+        assert_eq!(tokenize("\"hello"), Err(TokenizeError::UnclosedString {
+            position: SourcePosition {
+                bytes: 0,
+                line: 1,
+                column: 1,
+            },
+        }));
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let value = |source: &str| match &tokenize(source).unwrap()[0].kind {
+            TokenKind::StringLiteral(literal) => literal.value(),
+            other => panic!("expected a string, got {:?}", other),
+        };
+
+        assert_eq!(value(r#""\a\b\f\n\r\t\v\\\"\'""#), Some(b"\x07\x08\x0c\n\r\t\x0b\\\"'".to_vec()));
+        assert_eq!(value(r#"'\0\65\255\0012'"#), Some(b"\x00A\xff\x012".to_vec()));
+
+        // An escaped newline continues the string, and is kept as it was
+        // written.
+        for source in &["'a\\\nb'", "'a\\\r\nb'", "'a\\\n\rb'"] {
+            assert_eq!(value(source), Some(b"a\nb".to_vec()), "{:?}", source);
+            assert_eq!(tokenize(source).unwrap()[0].to_source(), *source);
+        }
+
+        let tokens = tokenize("x = 'a\\\nb' y").unwrap();
+        assert_eq!(tokens[3].start_position.line, 2);
+
+        // Escapes from any version of Lua are accepted.
+        for source in &[r#""\q""#, r#""\x41""#, r#""\z  ""#, r#""\u{48}""#] {
+            assert!(tokenize(source).is_ok(), "{}", source);
+        }
+
+        assert_eq!(tokenize("local s = 'ok'\nprint(\"a\\300b\")"), Err(TokenizeError::InvalidEscape {
+            position: SourcePosition { bytes: 23, line: 2, column: 9 },
+            sequence: "\\300".to_string(),
+        }));
+
+        assert!(matches!(tokenize("'\\256'"), Err(TokenizeError::InvalidEscape { .. })));
+        assert!(tokenize("'\\2560'").is_err());
+        assert!(tokenize("'\\\\300'").is_ok());
+
+        // Unicode escapes need braces around at most 31 bits of hex digits.
+        assert_eq!(value(r#""\u{48}\u{e9}\u{1F600}""#), Some("Hé😀".as_bytes().to_vec()));
+        assert_eq!(value(r#""\u{D800}\u{7FFFFFFF}""#), Some(b"\xed\xa0\x80\xfd\xbf\xbf\xbf\xbf\xbf".to_vec()));
+        assert_eq!(value(r#""\u{000041}""#), Some(b"A".to_vec()));
+
+        let malformed = [
+            (r#"x("\u{}")"#, r"\u{}"),
+            (r#"x("\u{41")"#, r"\u{41"),
+            (r#"x("\u{80000000}")"#, r"\u{80000000}"),
+            (r#"x('\u{4g}')"#, r"\u{4"),
+        ];
+
+        for &(source, sequence) in &malformed {
+            assert_eq!(tokenize(source), Err(TokenizeError::InvalidEscape {
+                position: SourcePosition { bytes: 3, line: 1, column: 4 },
+                sequence: sequence.to_string(),
+            }), "{}", source);
+        }
+
+        assert_eq!(decode_unicode_escape(r"\u{1F600}"), Some('😀'));
+        assert_eq!(decode_unicode_escape(r"\u{41}"), Some('A'));
+        assert_eq!(decode_unicode_escape(r"\u{D800}"), None);
+        assert_eq!(decode_unicode_escape(r"\u{110000}"), None);
+        assert_eq!(decode_unicode_escape(r"\u{}"), None);
+        assert_eq!(decode_unicode_escape(r"\u{41}x"), None);
+        assert_eq!(decode_unicode_escape(r"\x41"), None);
+
+        let (tokens, errors) = tokenize_recovering("a('\\999', b)");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "invalid escape sequence '\\999' at line 1, column 4");
+    }
+
+    #[test]
+    fn skipping_whitespace_escapes() {
+        let source = "x = \"long \\z\n    string \\z  \r\n\t \x0b\x0c  here\" y";
+        let tokens = tokenize(source).unwrap();
+
+        match &tokens[2].kind {
+            TokenKind::StringLiteral(literal) => assert_eq!(literal.value(), Some(b"long string here".to_vec())),
+            other => panic!("expected a string, got {:?}", other),
+        }
+
+        // The skipped whitespace is part of the string, and the lines it
+        // spans are counted.
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), source);
+        assert_eq!(tokens[2].end_position, SourcePosition { bytes: 41, line: 3, column: 12 });
+        assert_eq!(tokens[3].start_position, SourcePosition { bytes: 42, line: 3, column: 13 });
+
+        // Without `\z`, a newline still leaves the string unclosed.
+        assert_eq!(tokenize("'a \\q\n b'"), Err(TokenizeError::UnclosedString { position: START_POSITION }));
+    }
+
+    #[test]
+    fn decimal_escapes() {
+        let value = |source: &str| match &tokenize(source).unwrap()[0].kind {
+            TokenKind::StringLiteral(literal) => literal.value(),
+            other => panic!("expected a string, got {:?}", other),
+        };
+
+        // At most three digits are read, stopping early at anything else.
+        assert_eq!(value(r#""\0""#), Some(b"\0".to_vec()));
+        assert_eq!(value(r#""\00""#), Some(b"\0".to_vec()));
+        assert_eq!(value(r#""\000""#), Some(b"\0".to_vec()));
+        assert_eq!(value(r#""\0001""#), Some(b"\x001".to_vec()));
+        assert_eq!(value(r#""\065""#), Some(b"A".to_vec()));
+        assert_eq!(value(r#""\6foo""#), Some(b"\x06foo".to_vec()));
+
+        assert_eq!(tokenize(r#"x = "a\999""#), Err(TokenizeError::InvalidEscape {
+            position: SourcePosition { bytes: 6, line: 1, column: 7 },
+            sequence: r"\999".to_string(),
+        }));
+    }
+
+    #[test]
+    fn escapes_follow_the_version() {
+        let check = |source: &str, version: LuaVersion| {
+            let options = TokenizerOptions {
+                version: Some(version),
+                ..TokenizerOptions::default()
+            };
+
+            match tokenize_with_options(source, &options) {
+                Ok(_) => None,
+                Err(TokenizeError::InvalidEscape { position, sequence }) => Some((position.bytes, sequence)),
+                Err(other) => panic!("expected an invalid escape, got {:?}", other),
+            }
+        };
+
+        // 5.1 keeps the character after an unknown escape, so anything goes.
+        for &source in &[r#""\q""#, r#""\xZZ""#, r#""\x4""#, r#""\u{110000}""#, r#""\u{zz}""#] {
+            assert_eq!(check(source, LuaVersion::Lua51), None, "{}", source);
+        }
+
+        // Later versions reject unknown escapes, including ones they don't
+        // have yet.
+        assert_eq!(check(r#"x = "a\qb""#, LuaVersion::Lua52), Some((6, r"\q".to_string())));
+        assert_eq!(check(r#""\é""#, LuaVersion::Lua54), Some((1, r"\é".to_string())));
+        assert_eq!(check(r#""\u{48}""#, LuaVersion::Lua52), Some((1, r"\u".to_string())));
+        assert_eq!(check(r#""\a\b\f\n\r\t\v\\\"\'\z  \65\x41""#, LuaVersion::Lua52), None);
+
+        // `\x` needs exactly two hex digits.
+        for &version in &[LuaVersion::Lua52, LuaVersion::Lua53, LuaVersion::Lua54] {
+            assert_eq!(check(r#""\xZZ""#, version), Some((1, r"\x".to_string())));
+            assert_eq!(check(r#""\x4""#, version), Some((1, r"\x4".to_string())));
+            assert_eq!(check(r#""\x4g""#, version), Some((1, r"\x4".to_string())));
+            assert_eq!(check(r#""\x41F""#, version), None);
+        }
+
+        // 5.3 stops `\u` at the end of Unicode, and 5.4 at 2^31.
+        assert_eq!(check(r#""\u{10FFFF}""#, LuaVersion::Lua53), None);
+        assert_eq!(check(r#""ab\u{110000}""#, LuaVersion::Lua53), Some((3, r"\u{110000}".to_string())));
+        assert_eq!(check(r#""\u{110000}""#, LuaVersion::Lua54), None);
+        assert_eq!(check(r#""\u{80000000}""#, LuaVersion::Lua54), Some((1, r"\u{80000000}".to_string())));
+        assert_eq!(check(r#""\u48""#, LuaVersion::Lua53), Some((1, r"\u".to_string())));
+    }
+
+    #[test]
+    fn long_string_literals() {
+        test_kinds_eq("[==[a ]] b ]=] c]==]", vec![TokenKind::StringLiteral(StringLiteral::LongForm { raw_content: "a ]] b ]=] c".into(), depth: 2 })]);
+        test_kinds_eq("[[]]", vec![TokenKind::StringLiteral(StringLiteral::LongForm { raw_content: "".into(), depth: 0 })]);
+
+        // The newline right after the opening bracket stays in the source, but
+        // isn't part of the value.
+        let tokens = tokenize("[[\nfirst\nsecond]] x").unwrap();
+        match &tokens[0].kind {
+            TokenKind::StringLiteral(literal) => assert_eq!(literal.value(), Some(b"first\nsecond".to_vec())),
+            other => panic!("expected a string, got {:?}", other),
+        }
+
+        // Every line inside the string is counted.
+        assert_eq!(tokens[0].end_position, SourcePosition {
+            bytes: 17,
+            line: 3,
+            column: 9,
+        });
+        assert_eq!(tokens[1].start_position.line, 3);
+
+        assert_eq!(tokenize("x = [=[ open ]]"), Err(TokenizeError::UnclosedString {
+            position: SourcePosition {
+                bytes: 4,
+                line: 1,
+                column: 5,
+            },
+        }));
+    }
+
+    #[test]
+    fn single_line_comments() {
+        let tokens = tokenize("-- header\nlocal x").unwrap();
+
+        assert_eq!(tokens[0].kind, TokenKind::Symbol(Symbol::Local));
+        assert_eq!(tokens[0].prefix, vec![
+            TokenPrefix::Comment(Comment::SingleLine { content: " header".into() }),
+            TokenPrefix::Whitespace("\n".into()),
+        ]);
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), "-- header\nlocal x");
+
+        let tokens = tokenize("local a -- the count\n  b").unwrap();
+
+        assert_eq!(tokens[2].prefix, vec![
+            TokenPrefix::Whitespace(" ".into()),
+            TokenPrefix::Comment(Comment::SingleLine { content: " the count".into() }),
+            TokenPrefix::Whitespace("\n  ".into()),
+        ]);
+        assert_eq!(tokens[2].start_position, SourcePosition {
+            bytes: 23,
+            line: 2,
+            column: 3,
+        });
+
+        // A comment at the end without a newline ends up on the end of file
+        // token.
+        let tokens = tokenize("f() -- done").unwrap();
+        assert_eq!(tokens.last().unwrap().kind, TokenKind::EndOfFile);
+        assert_eq!(tokens.last().unwrap().prefix[1], TokenPrefix::Comment(Comment::SingleLine { content: " done".into() }));
+
+        assert_eq!(tokenize("--").unwrap()[0].prefix, vec![TokenPrefix::Comment(Comment::SingleLine { content: "".into() })]);
+    }
+
+    #[test]
+    fn block_comments() {
+        let tokens = tokenize("--[==[ one ]]\ntwo ]=] ]==] x").unwrap();
+
+        assert_eq!(tokens[0].prefix[0], TokenPrefix::Comment(Comment::MultiLine { content: " one ]]\ntwo ]=] ".into(), depth: 2 }));
+        assert_eq!(tokens[0].kind, TokenKind::Identifier("x".into()));
+        assert_eq!(tokens[0].start_position.line, 2);
+
+        // A line comment that only looks like it might be a block comment.
+        assert_eq!(tokenize("--[= x ]=]\n").unwrap()[0].prefix[0], TokenPrefix::Comment(Comment::SingleLine { content: "[= x ]=]".into() }));
+
+        assert_eq!(tokenize("local a\n  --[=[ open ]]\n"), Err(TokenizeError::UnclosedComment {
+            position: SourcePosition {
+                bytes: 10,
+                line: 2,
+                column: 3,
+            },
+        }));
+
+        assert!(matches!(tokenize("f() --[["), Err(TokenizeError::UnclosedComment { .. })));
+    }
+
+    #[test]
+    fn unclosed_in_a_long_file() {
+        let rest = "print(\"line\") -- ]]\n".repeat(499);
+        let opening = SourcePosition { bytes: 10, line: 1, column: 11 };
+
+        // Errors point to where the string or comment opened, not to where
+        // the tokenizer gave up.
+        let source = format!("local s = \"open\n{}", rest);
+        assert_eq!(tokenize(&source), Err(TokenizeError::UnclosedString { position: opening }));
+
+        let source = format!("local s = [==[open\n{}", rest);
+        assert_eq!(tokenize(&source), Err(TokenizeError::UnclosedString { position: opening }));
+
+        let source = format!("local s = --[=[open\n{}", rest);
+        assert_eq!(tokenize(&source), Err(TokenizeError::UnclosedComment { position: opening }));
+    }
+
+    #[test]
+    fn shebang() {
+        let tokens = tokenize("#!/usr/bin/env lua\nlocal x = #t").unwrap();
+
+        assert_eq!(tokens[0].prefix, vec![
+            TokenPrefix::Shebang("#!/usr/bin/env lua".into()),
+            TokenPrefix::Whitespace("\n".into()),
+        ]);
+        assert_eq!(tokens[0].kind, TokenKind::Symbol(Symbol::Local));
+        assert_eq!(tokens[0].start_position, tokenize("--!/usr/bin/env lu\nlocal").unwrap()[0].start_position);
+        assert_eq!(tokens[0].start_position.line, 2);
+        assert_eq!(tokens[3].kind, TokenKind::Symbol(Symbol::Hash));
+
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), "#!/usr/bin/env lua\nlocal x = #t");
+
+        // Only the first line can be skipped.
+        assert!(matches!(tokenize("\n#!/usr/bin/env lua"), Err(TokenizeError::UnknownSequence { .. })));
+        assert_eq!(tokenize("#t").unwrap()[0].kind, TokenKind::Symbol(Symbol::Hash));
+        assert_eq!(tokenize("#!lua").unwrap()[0].prefix, vec![TokenPrefix::Shebang("#!lua".into())]);
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let tokens = tokenize("\u{FEFF}local x").unwrap();
+
+        assert_eq!(tokens[0].prefix, vec![TokenPrefix::ByteOrderMark]);
+        assert_eq!(tokens[0].start_position, SourcePosition { bytes: 3, line: 1, column: 1 });
+        assert_eq!(tokens[1].start_position, SourcePosition { bytes: 9, line: 1, column: 7 });
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), "\u{FEFF}local x");
+
+        // A shebang can still follow it.
+        let tokens = tokenize("\u{FEFF}#!lua\nx").unwrap();
+        assert_eq!(tokens[0].prefix, vec![
+            TokenPrefix::ByteOrderMark,
+            TokenPrefix::Shebang("#!lua".into()),
+            TokenPrefix::Whitespace("\n".into()),
+        ]);
+
+        // Only the first character can be skipped.
+        assert_eq!(tokenize(" \u{FEFF}x"), Err(TokenizeError::UnknownSequence {
+            position: SourcePosition { bytes: 1, line: 1, column: 2 },
+            sequence: "\u{FEFF}x".into(),
+        }));
+        assert!(matches!(tokenize("\u{FEFF}\u{FEFF}"), Err(TokenizeError::UnknownSequence { .. })));
+        assert!(matches!(tokenize("x\n\u{FEFF}"), Err(TokenizeError::UnknownSequence { .. })));
+
+        let tokens = tokenize("\u{FEFF}").unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::EndOfFile);
+        assert_eq!(tokens[0].prefix, vec![TokenPrefix::ByteOrderMark]);
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let name = |name: &'static str| TokenKind::Identifier(name.into());
+
+        test_kinds_eq("a==b", vec![name("a"), TokenKind::Symbol(Symbol::DoubleEqual), name("b")]);
+        test_kinds_eq("a<=b", vec![name("a"), TokenKind::Symbol(Symbol::LessEqual), name("b")]);
+        test_kinds_eq("a>=b", vec![name("a"), TokenKind::Symbol(Symbol::GreaterEqual), name("b")]);
+        test_kinds_eq("a<b>c", vec![name("a"), TokenKind::Symbol(Symbol::LessThan), name("b"), TokenKind::Symbol(Symbol::GreaterThan), name("c")]);
+        test_kinds_eq("a~=-1", vec![name("a"), TokenKind::Symbol(Symbol::NotEqual), TokenKind::Symbol(Symbol::Minus), number("1")]);
+
+        // The longest operator wins, even when a shorter one could follow it.
+        test_kinds_eq("a===b", vec![name("a"), TokenKind::Symbol(Symbol::DoubleEqual), TokenKind::Symbol(Symbol::Equal), name("b")]);
+        test_kinds_eq("a = =b", vec![name("a"), TokenKind::Symbol(Symbol::Equal), TokenKind::Symbol(Symbol::Equal), name("b")]);
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let name = |name: &'static str| TokenKind::Identifier(name.into());
+
+        test_kinds_eq("a//b", vec![name("a"), TokenKind::Symbol(Symbol::DoubleSlash), name("b")]);
+        test_kinds_eq("a~=~b", vec![name("a"), TokenKind::Symbol(Symbol::NotEqual), TokenKind::Symbol(Symbol::Tilde), name("b")]);
+        test_kinds_eq("1<<2>>3", vec![number("1"), TokenKind::Symbol(Symbol::ShiftLeft), number("2"), TokenKind::Symbol(Symbol::ShiftRight), number("3")]);
+        test_kinds_eq("a&b|c~d", vec![
+            name("a"), TokenKind::Symbol(Symbol::Ampersand), name("b"), TokenKind::Symbol(Symbol::Pipe),
+            name("c"), TokenKind::Symbol(Symbol::Tilde), name("d"),
+        ]);
+        test_kinds_eq("a<<=b", vec![name("a"), TokenKind::Symbol(Symbol::ShiftLeft), TokenKind::Symbol(Symbol::Equal), name("b")]);
+        test_kinds_eq("a///b", vec![name("a"), TokenKind::Symbol(Symbol::DoubleSlash), TokenKind::Symbol(Symbol::Slash), name("b")]);
+    }
+
+    #[test]
+    fn dots() {
+        let name = |name: &'static str| TokenKind::Identifier(name.into());
+
+        test_kinds_eq("a..b", vec![name("a"), TokenKind::Symbol(Symbol::TwoDots), name("b")]);
+        test_kinds_eq("a ...", vec![name("a"), TokenKind::Symbol(Symbol::Ellipse)]);
+        test_kinds_eq("a.b", vec![name("a"), TokenKind::Symbol(Symbol::Dot), name("b")]);
+        test_kinds_eq("a...b", vec![name("a"), TokenKind::Symbol(Symbol::Ellipse), name("b")]);
+
+        // Like Lua, the longest operator is taken first, so four dots are an
+        // ellipsis and a dot. The parser rejects it from there.
+        test_kinds_eq("a....b", vec![name("a"), TokenKind::Symbol(Symbol::Ellipse), TokenKind::Symbol(Symbol::Dot), name("b")]);
 
-        test_kinds_eq("''", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "".into() })]);
-        test_kinds_eq("'hello'", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "hello".into() })]);
-        test_kinds_eq("'he\\'llo'", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "he\\'llo".into() })]);
-        test_kinds_eq("'he\\nllo'", vec![TokenKind::StringLiteral(StringLiteral::SingleQuote { raw_content: "he\\nllo".into() })]);
+        test_kinds_eq("a...5", vec![name("a"), TokenKind::Symbol(Symbol::Ellipse), number("5")]);
+        test_kinds_eq("1 ..2", vec![number("1"), TokenKind::Symbol(Symbol::TwoDots), number("2")]);
+    }
 
-        assert_eq!(tokenize("\""), Err(TokenizeError::UnclosedString {
-            position: SourcePosition {
-                bytes: 0,
-                line: 1,
-                column: 1,
-            },
-        }));
+    #[test]
+    fn every_operator() {
+        let source = "local x = -a + b - c * d / e ^ f % #g .. h == i ~= j < k <= l > m >= n and not o or p; t.u:v(...)";
+        let tokens = tokenize(source).unwrap();
+
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), source);
+
+        let symbols: Vec<_> = tokens.iter()
+            .filter_map(|token| match token.kind {
+                TokenKind::Symbol(symbol) => Some(symbol),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(symbols, vec![
+            Symbol::Local, Symbol::Equal, Symbol::Minus, Symbol::Plus, Symbol::Minus, Symbol::Star, Symbol::Slash,
+            Symbol::Caret, Symbol::Percent, Symbol::Hash, Symbol::TwoDots, Symbol::DoubleEqual, Symbol::NotEqual,
+            Symbol::LessThan, Symbol::LessEqual, Symbol::GreaterThan, Symbol::GreaterEqual, Symbol::And, Symbol::Not,
+            Symbol::Or, Symbol::Semicolon, Symbol::Dot, Symbol::Colon, Symbol::LeftParen, Symbol::Ellipse, Symbol::RightParen,
+        ]);
 
-        assert_eq!(tokenize("\"\n"), Err(TokenizeError::UnclosedString {
-            position: SourcePosition {
-                bytes: 0,
-                line: 1,
-                column: 1,
-            },
-        }));
+        // Without spaces, `-` is still an operator rather than part of a number.
+        test_kinds_eq("a-1", vec![TokenKind::Identifier("a".into()), TokenKind::Symbol(Symbol::Minus), number("1")]);
+    }
 
-        assert_eq!(tokenize("\"hello"), Err(TokenizeError::UnclosedString {
-            position: SourcePosition {
-                bytes: 0,
-                line: 1,
-                column: 1,
-            },
-        }));
+    #[test]
+    fn labels() {
+        let name = |name: &'static str| TokenKind::Identifier(name.into());
+        let symbol = TokenKind::Symbol;
+
+        test_kinds_eq("::continue::", vec![symbol(Symbol::DoubleColon), name("continue"), symbol(Symbol::DoubleColon)]);
+        test_kinds_eq("a:b()::done::", vec![
+            name("a"), symbol(Symbol::Colon), name("b"), symbol(Symbol::LeftParen), symbol(Symbol::RightParen),
+            symbol(Symbol::DoubleColon), name("done"), symbol(Symbol::DoubleColon),
+        ]);
+        test_kinds_eq(":::", vec![symbol(Symbol::DoubleColon), symbol(Symbol::Colon)]);
     }
 
     #[test]
@@ -673,6 +3034,307 @@ mod tests {
         assert_eq!(new_position.column, 5);
     }
 
+    #[test]
+    fn tab_width() {
+        let columns = |source: &str, tab_width: usize| -> Vec<usize> {
+            let options = TokenizerOptions {
+                tab_width,
+                ..TokenizerOptions::default()
+            };
+
+            tokenize_with_options(source, &options)
+                .unwrap()
+                .iter()
+                .map(|token| token.start_position.column)
+                .collect()
+        };
+
+        // Tabs move to the next tab stop, wherever they start.
+        let source = " \t  \tx\ty --\tz\n\t  \tw";
+        assert_eq!(columns(source, 0), vec![6, 8, 5]);
+        assert_eq!(columns(source, 1), columns(source, 0));
+        assert_eq!(columns(source, 4), vec![9, 13, 9]);
+        assert_eq!(columns(source, 8), vec![17, 25, 17]);
+
+        // Tabs inside comments and strings count too.
+        assert_eq!(columns("--[[\t]] '\t' x", 4), vec![12, 19]);
+
+        // Absurd widths stop at the largest column rather than overflowing.
+        assert_eq!(columns("\tx y", usize::MAX), vec![usize::MAX, usize::MAX]);
+
+        assert_eq!(tokenize_with_options(source, &TokenizerOptions::default()), tokenize(source));
+    }
+
+    #[test]
+    fn column_units() {
+        use line_index::LineIndex;
+
+        let source = "--[[é🙂]] x = 'é🙂' y\né = 1";
+
+        let columns = |column_unit: ColumnUnit| -> Vec<usize> {
+            let options = TokenizerOptions {
+                column_unit,
+                unicode_identifiers: true,
+                ..TokenizerOptions::default()
+            };
+
+            tokenize_with_options(source, &options)
+                .unwrap()
+                .iter()
+                .flat_map(|token| vec![token.start_position.column, token.end_position.column])
+                .collect()
+        };
+
+        // `é` is two bytes and one UTF-16 unit, and `🙂` is four bytes and two
+        // UTF-16 units. Every line starts at column 1 whatever the unit.
+        assert_eq!(columns(ColumnUnit::Chars), vec![10, 11, 12, 13, 14, 18, 19, 20, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(columns(ColumnUnit::Bytes), vec![14, 15, 16, 17, 18, 26, 27, 28, 1, 3, 4, 5, 6, 7]);
+        assert_eq!(columns(ColumnUnit::Utf16), vec![11, 12, 13, 14, 15, 20, 21, 22, 1, 2, 3, 4, 5, 6]);
+
+        // The UTF-16 columns are the ones an editor would give.
+        let options = TokenizerOptions {
+            column_unit: ColumnUnit::Utf16,
+            unicode_identifiers: true,
+            ..TokenizerOptions::default()
+        };
+        let index = LineIndex::new(source);
+
+        for token in &tokenize_with_options(source, &options).unwrap() {
+            assert_eq!(token.start_position.column, index.utf16_column(token.start_position.bytes) + 1);
+        }
+
+        assert_eq!(TokenizerOptions::default().column_unit, ColumnUnit::Chars);
+    }
+
+    #[test]
+    fn language_versions() {
+        let kinds = |source: &str, version: Option<LuaVersion>| -> Result<Vec<TokenKind<'static>>, TokenizeError> {
+            let options = TokenizerOptions {
+                version,
+                ..TokenizerOptions::default()
+            };
+
+            let tokens = tokenize_with_options(source, &options)?;
+            Ok(tokens.into_iter().map(|token| token.kind.into_owned()).filter(|kind| *kind != TokenKind::EndOfFile).collect())
+        };
+
+        let name = |name: &'static str| TokenKind::Identifier(name.into());
+        let symbol = TokenKind::Symbol;
+
+        // `goto` is a keyword from 5.2 on, and a name before that or when no
+        // version is picked.
+        assert_eq!(kinds("goto", Some(LuaVersion::Lua51)), Ok(vec![name("goto")]));
+        assert_eq!(kinds("goto", Some(LuaVersion::Lua52)), Ok(vec![symbol(Symbol::Goto)]));
+        assert_eq!(kinds("goto", Some(LuaVersion::Lua54)), Ok(vec![symbol(Symbol::Goto)]));
+        assert_eq!(kinds("goto", None), Ok(vec![name("goto")]));
+
+        // Operators that older versions don't have split into the ones they
+        // do have.
+        assert_eq!(kinds("a // b", Some(LuaVersion::Lua52)), Ok(vec![name("a"), symbol(Symbol::Slash), symbol(Symbol::Slash), name("b")]));
+        assert_eq!(kinds("a // b", Some(LuaVersion::Lua53)), Ok(vec![name("a"), symbol(Symbol::DoubleSlash), name("b")]));
+        assert_eq!(kinds("a << b", Some(LuaVersion::Lua51)), Ok(vec![name("a"), symbol(Symbol::LessThan), symbol(Symbol::LessThan), name("b")]));
+        assert_eq!(kinds("::a::", Some(LuaVersion::Lua51)), Ok(vec![symbol(Symbol::Colon), symbol(Symbol::Colon), name("a"), symbol(Symbol::Colon), symbol(Symbol::Colon)]));
+        assert_eq!(kinds("::a::", Some(LuaVersion::Lua52)), Ok(vec![symbol(Symbol::DoubleColon), name("a"), symbol(Symbol::DoubleColon)]));
+
+        // Bitwise operators are errors before 5.3, but `~=` isn't.
+        for &operator in &["&", "|", "~"] {
+            let source = format!("a {} b", operator);
+
+            assert_eq!(kinds(&source, Some(LuaVersion::Lua52)), Err(TokenizeError::UnknownSequence {
+                position: SourcePosition { bytes: 2, line: 1, column: 3 },
+                sequence: operator.to_string(),
+            }));
+            assert!(kinds(&source, Some(LuaVersion::Lua53)).is_ok());
+        }
+
+        assert_eq!(kinds("a ~= b", Some(LuaVersion::Lua51)), Ok(vec![name("a"), symbol(Symbol::NotEqual), name("b")]));
+
+        // Hex floats came with 5.2.
+        for &source in &["0x1p4", "0x1.8", "0xA.8P-1"] {
+            assert_eq!(kinds(source, Some(LuaVersion::Lua51)), Err(TokenizeError::MalformedNumber {
+                position: START_POSITION,
+                text: source.to_string(),
+            }));
+            assert_eq!(kinds(source, Some(LuaVersion::Lua52)), Ok(vec![number(source)]));
+        }
+
+        assert_eq!(kinds("0xff", Some(LuaVersion::Lua51)), Ok(vec![number("0xff")]));
+
+        // With no version, everything is accepted.
+        let source = "a // b & c | d ~ e << f >> g ::h:: 0x1p4";
+        assert_eq!(kinds(source, None), kinds(source, Some(LuaVersion::Lua54)));
+        assert_eq!(tokenize_with_options(source, &TokenizerOptions::default()), tokenize(source));
+    }
+
+    #[test]
+    fn nested_long_brackets() {
+        let lua51 = TokenizerOptions {
+            version: Some(LuaVersion::Lua51),
+            ..TokenizerOptions::default()
+        };
+
+        // From 5.2 on, the first `]]` closes the string, and the `]]` after it
+        // is left for the parser to reject.
+        let tokens = tokenize("[[ [[ ]] ]]").unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::StringLiteral(StringLiteral::LongForm { raw_content: " [[ ".into(), depth: 0 }));
+        assert_eq!(tokens[1].kind, TokenKind::Symbol(Symbol::RightBracket));
+        assert_eq!(tokens[2].kind, TokenKind::Symbol(Symbol::RightBracket));
+
+        // 5.1 rejects the inner `[[`, in strings and comments alike, and
+        // whether or not the outer one is closed.
+        for &(source, bytes) in &[("[[ [[ ]] ]]", 3), ("--[[ [[ ]] ]]", 5), ("x = [[a [[ b", 8)] {
+            let err = tokenize_with_options(source, &lua51).unwrap_err();
+
+            assert!(matches!(err, TokenizeError::NestedLongBracket { .. }), "{}", source);
+            assert_eq!(err.position().unwrap().bytes, bytes, "{}", source);
+        }
+
+        assert_eq!(tokenize_with_options("x = [[a\n [[ b]]", &lua51), Err(TokenizeError::NestedLongBracket {
+            position: SourcePosition { bytes: 9, line: 2, column: 2 },
+        }));
+
+        // Brackets with `=` in them don't nest in any version.
+        for &source in &["[[ [=[ ]=] ]]", "[=[ [[ ]=]", "[==[ [==[ ]==]", "[[ ]=] [ [ ]]"] {
+            assert_eq!(tokenize_with_options(source, &lua51), tokenize(source), "{}", source);
+            assert_eq!(tokenize(source).unwrap().len(), 1, "{}", source);
+        }
+
+        // Recovering skips to where the string would have ended in 5.2.
+        let mut iter = TokenIter::new("a = [[ [[ ]] b = 1", START_POSITION, lua51);
+        let err = iter.next_token().and_then(|_| iter.next_token()).and_then(|_| iter.next_token()).unwrap_err();
+        iter.skip_error(&err);
+        assert_eq!(iter.next_token().unwrap().unwrap().kind, TokenKind::Identifier("b".into()));
+    }
+
+    #[test]
+    fn interpolated_strings() {
+        let options = TokenizerOptions {
+            interpolated_strings: true,
+            ..TokenizerOptions::default()
+        };
+
+        let kinds = |source: &str| -> Result<Vec<TokenKind<'static>>, TokenizeError> {
+            let tokens = tokenize_with_options(source, &options)?;
+            assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), source);
+
+            Ok(tokens.into_iter().map(|token| token.kind.into_owned()).collect())
+        };
+
+        let name = |name: &'static str| TokenKind::Identifier(name.into());
+        let symbol = TokenKind::Symbol;
+        let simple = |text: &'static str| TokenKind::InterpolatedString(InterpolatedStringPart::Simple { raw_content: text.into() });
+        let begin = |text: &'static str| TokenKind::InterpolatedString(InterpolatedStringPart::Begin { raw_content: text.into() });
+        let middle = |text: &'static str| TokenKind::InterpolatedString(InterpolatedStringPart::Middle { raw_content: text.into() });
+        let end = |text: &'static str| TokenKind::InterpolatedString(InterpolatedStringPart::End { raw_content: text.into() });
+
+        assert_eq!(kinds("`hello`"), Ok(vec![simple("hello")]));
+        assert_eq!(kinds("`hello {name}!`"), Ok(vec![begin("hello "), name("name"), end("!")]));
+        assert_eq!(kinds("`{a} and {b}`"), Ok(vec![
+            begin(""), name("a"), middle(" and "), name("b"), end(""),
+        ]));
+
+        // Braces inside an expression have to balance before it ends, and
+        // strings can be nested.
+        assert_eq!(kinds("`{f({1, 2})}`"), Ok(vec![
+            begin(""), name("f"), symbol(Symbol::LeftParen), symbol(Symbol::LeftBrace), number("1"),
+            symbol(Symbol::Comma), number("2"), symbol(Symbol::RightBrace), symbol(Symbol::RightParen), end(""),
+        ]));
+        assert_eq!(kinds("`a{`b{c}`}d` {}"), Ok(vec![
+            begin("a"), begin("b"), name("c"), end(""), end("d"),
+            symbol(Symbol::LeftBrace), symbol(Symbol::RightBrace),
+        ]));
+
+        // Escaped braces and backticks don't end the text.
+        assert_eq!(kinds(r"`\{x\}\``"), Ok(vec![simple(r"\{x\}\`")]));
+
+        // Unclosed text is an error where it starts, and an unclosed
+        // expression is an error where its string starts.
+        let unclosed = |bytes: usize, column: usize| Err(TokenizeError::UnclosedString {
+            position: SourcePosition { bytes, line: 1, column },
+        });
+
+        assert_eq!(kinds("x = `abc"), unclosed(4, 5));
+        assert_eq!(kinds("x = `a{b}c"), unclosed(8, 9));
+        assert_eq!(kinds("x = `a{b"), unclosed(4, 5));
+        assert_eq!(kinds("x = `a{{b}"), unclosed(4, 5));
+        assert_eq!(kinds("`a\nb`"), unclosed(0, 1));
+
+        // Without the option, backticks aren't anything.
+        assert_eq!(tokenize("`a`"), Err(TokenizeError::UnknownSequence {
+            position: START_POSITION,
+            sequence: "`a`".to_string(),
+        }));
+    }
+
+    #[test]
+    fn byte_offsets_slice_the_source() {
+        let source = "#!/usr/bin/env lua\nlocal s = \"héllo\" --[[ ü ]] .. [==[\nlong]==]\n\tf(s, 0x1p4) -- end\n";
+        let tokens = tokenize(source).unwrap();
+        let mut previous_end = 0;
+
+        for token in &tokens {
+            let prefix: String = token.prefix.iter().map(TokenPrefix::to_source).collect();
+
+            assert_eq!(&source[previous_end..token.start_position.bytes], prefix);
+            assert_eq!(&source[token.start_position.bytes..token.end_position.bytes], token.kind.to_source());
+
+            previous_end = token.end_position.bytes;
+        }
+
+        assert_eq!(previous_end, source.len());
+    }
+
+    #[test]
+    fn tokenizing_into_a_buffer() {
+        let first = "local a = {1, 2} -- done";
+        let second = "f(\"x\")\n";
+        let mut tokens = Vec::new();
+
+        tokenize_into(first, &mut tokens).unwrap();
+        assert_eq!(tokens, tokenize(first).unwrap());
+
+        // The buffer is cleared rather than appended to.
+        let capacity = tokens.capacity();
+        tokenize_into(second, &mut tokens).unwrap();
+        assert_eq!(tokens, tokenize(second).unwrap());
+        assert_eq!(tokens.capacity(), capacity);
+
+        tokenize_into(first, &mut tokens).unwrap();
+        assert_eq!(tokens, tokenize(first).unwrap());
+
+        assert_eq!(tokenize_into("a $", &mut tokens), Err(tokenize("a $").unwrap_err()));
+        assert_eq!(tokens, vec![tokenize("a").unwrap()[0].clone()]);
+
+        assert!(matches!(tokenize_into("\x1bLuaS", &mut tokens), Err(TokenizeError::PrecompiledBytecode { .. })));
+        assert!(tokens.is_empty());
+    }
+
+    #[test]
+    fn lazy_tokenizing() {
+        let source = "#!/usr/bin/env lua\nlocal function f() end\nf(1) -- done\n";
+        let collected: Result<Vec<_>, _> = tokenize_iter(source).collect();
+        assert_eq!(collected, tokenize(source));
+
+        // Stopping early leaves the rest of the source alone.
+        let mut iter = tokenize_iter(source);
+        let first = iter.next().unwrap().unwrap();
+        assert_eq!(first.kind, TokenKind::Symbol(Symbol::Local));
+        assert_eq!(iter.position(), SourcePosition { bytes: 24, line: 2, column: 6 });
+
+        // The iterator stops for good after an error.
+        let mut iter = tokenize_iter("f() $ g()");
+        assert_eq!(iter.by_ref().take(3).count(), 3);
+        assert_eq!(iter.next(), Some(Err(TokenizeError::UnknownSequence {
+            position: SourcePosition { bytes: 4, line: 1, column: 5 },
+            sequence: "$".to_string(),
+        })));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next(), None);
+
+        assert!(matches!(tokenize_iter("\x1bLua").collect::<Vec<_>>()[..], [Err(TokenizeError::PrecompiledBytecode { .. })]));
+        assert_eq!(tokenize_iter("").count(), 0);
+    }
+
     #[test]
     fn source_tracking() {
         let input = "local\n   test foo\n     bar";
@@ -742,4 +3404,453 @@ mod tests {
             }
         ]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn display() {
+        let options = TokenizerOptions {
+            interpolated_strings: true,
+            ..TokenizerOptions::default()
+        };
+
+        let rendered: Vec<String> = tokenize_with_options("end foo 3.14 0x10 'a' [[b]] ~= `c{d}e`", &options)
+            .unwrap()
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+
+        assert_eq!(rendered, vec![
+            "'end'",
+            "identifier 'foo'",
+            "number '3.14'",
+            "number '0x10'",
+            "string 'a'",
+            "string [[b]]",
+            "'~='",
+            "interpolated string `c{",
+            "identifier 'd'",
+            "interpolated string }e`",
+        ]);
+
+        assert_eq!(TokenKind::EndOfFile.to_string(), "end of file");
+    }
+
+    #[test]
+    fn keywords() {
+        let keywords = [
+            Symbol::And, Symbol::Break, Symbol::Do, Symbol::Else, Symbol::ElseIf, Symbol::End,
+            Symbol::False, Symbol::For, Symbol::Function, Symbol::Goto, Symbol::If, Symbol::In,
+            Symbol::Local, Symbol::Nil, Symbol::Not, Symbol::Or, Symbol::Repeat, Symbol::Return,
+            Symbol::Then, Symbol::True, Symbol::Until, Symbol::While,
+        ];
+
+        let kinds = |source: &str| -> Vec<TokenKind<'static>> {
+            tokenize(source).unwrap().into_iter().map(|token| token.kind.into_owned()).collect()
+        };
+
+        let is_name = |name: &str| {
+            assert_eq!(Symbol::from_keyword(name), None, "{}", name);
+            assert_eq!(kinds(name), vec![TokenKind::Identifier(name.to_string().into())]);
+        };
+
+        for &keyword in &keywords {
+            let spelling = keyword.to_str();
+            assert_eq!(Symbol::from_keyword(spelling), Some(keyword));
+
+            if keyword != Symbol::Goto {
+                assert_eq!(kinds(spelling), vec![TokenKind::Symbol(keyword)]);
+            }
+
+            is_name(&format!("{}x", spelling));
+            is_name(&format!("_{}", spelling));
+            is_name(&spelling.to_uppercase());
+            is_name(&spelling[..spelling.len() - 1]);
+        }
+
+        for near in &["locale", "functions", "nilx", "True", "elsif", "end_", "iff", "n"] {
+            is_name(near);
+        }
+
+        assert_eq!(Symbol::from_keyword("+"), None);
+        assert_eq!(Symbol::from_keyword(""), None);
+    }
+
+    #[test]
+    fn limits() {
+        let source = "a ".repeat(512 * 1024);
+
+        let options = TokenizerOptions {
+            max_tokens: Some(1000),
+            ..TokenizerOptions::default()
+        };
+
+        assert_eq!(tokenize_with_options(&source, &options), Err(TokenizeError::LimitExceeded {
+            limit: TokenizerLimit::MaxTokens(1000),
+            position: SourcePosition { bytes: 2000, line: 1, column: 2001 },
+        }));
+
+        let tokens: Vec<_> = tokenize_iter_with_options(&source, &options).collect();
+        assert_eq!(tokens.len(), 1001);
+        assert!(tokens[..1000].iter().all(Result::is_ok));
+
+        let options = TokenizerOptions {
+            max_source_len: Some(1024 * 1024 - 1),
+            ..TokenizerOptions::default()
+        };
+
+        assert_eq!(tokenize_with_options(&source, &options), Err(TokenizeError::LimitExceeded {
+            limit: TokenizerLimit::MaxSourceLen(1024 * 1024 - 1),
+            position: START_POSITION,
+        }));
+
+        // Sources right at the limits are fine.
+        let options = TokenizerOptions {
+            max_source_len: Some(4),
+            max_tokens: Some(2),
+            ..TokenizerOptions::default()
+        };
+
+        assert_eq!(tokenize_with_options("a b ", &options).unwrap().len(), 3);
+        assert!(tokenize_with_options("a b c", &options).is_err());
+        assert!(tokenize("a ".repeat(1000).as_str()).is_ok());
+    }
+
+    #[test]
+    fn skipping_errors() {
+        let skip = |on_error: OnError| TokenizerOptions {
+            on_error,
+            ..TokenizerOptions::default()
+        };
+
+        let source = "local x = $ + 1\ny = 2";
+        assert!(tokenize_with_options(source, &skip(OnError::Stop)).is_err());
+
+        let tokens = tokenize_with_options(source, &skip(OnError::SkipChar)).unwrap();
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), source);
+        assert_eq!(tokens[3].kind, TokenKind::Error {
+            text: "$".into(),
+            error: TokenizeError::UnknownSequence {
+                position: SourcePosition { bytes: 10, line: 1, column: 11 },
+                sequence: "$".to_string(),
+            },
+        });
+        assert_eq!(tokens[3].prefix, vec![TokenPrefix::Whitespace(" ".into())]);
+
+        // Everything after the skipped text is where it would be without it.
+        let clean = tokenize("local x = _ + 1\ny = 2").unwrap();
+        assert_eq!(tokens.len(), clean.len());
+        for (token, clean) in tokens.iter().zip(&clean).skip(4) {
+            assert_eq!(token, clean);
+        }
+
+        // Skipping a character can leave more errors behind, where skipping to
+        // whitespace doesn't.
+        let source = "a = $$$ b\n\tc = 'open\nd";
+        let options = TokenizerOptions {
+            tab_width: 4,
+            ..skip(OnError::SkipChar)
+        };
+
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        let errors: Vec<_> = tokens.iter().filter(|token| matches!(token.kind, TokenKind::Error { .. })).map(|token| token.kind.to_source()).collect();
+        assert_eq!(errors, vec!["$", "$", "$", "'"]);
+
+        let d = tokens.iter().find(|token| token.kind == TokenKind::Identifier("d".into())).unwrap();
+        assert_eq!(d.start_position, SourcePosition { bytes: 21, line: 3, column: 1 });
+        let open = tokens.iter().find(|token| token.kind == TokenKind::Identifier("open".into())).unwrap();
+        assert_eq!(open.start_position, SourcePosition { bytes: 16, line: 2, column: 10 });
+
+        let options = TokenizerOptions {
+            tab_width: 4,
+            ..skip(OnError::SkipToWhitespace)
+        };
+
+        let tokens = tokenize_with_options(source, &options).unwrap();
+        let errors: Vec<_> = tokens.iter().filter(|token| matches!(token.kind, TokenKind::Error { .. })).map(|token| token.kind.to_source()).collect();
+        assert_eq!(errors, vec!["$$$", "'open"]);
+        assert_eq!(tokens.last().unwrap().start_position, SourcePosition { bytes: 21, line: 3, column: 1 });
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), source);
+
+        // An unclosed comment only gives up its opening, and the rest of it is
+        // tokenized as code.
+        let tokens = tokenize_with_options("x --[[ y", &skip(OnError::SkipToWhitespace)).unwrap();
+        assert_eq!(tokens.iter().map(|token| token.kind.to_string()).collect::<Vec<_>>(), vec!["identifier 'x'", "invalid text '--[['", "identifier 'y'"]);
+
+        // Errors about the source as a whole still stop the tokenizer.
+        let options = TokenizerOptions {
+            max_tokens: Some(1),
+            ..skip(OnError::SkipChar)
+        };
+
+        assert!(matches!(tokenize_with_options("$ $", &options), Err(TokenizeError::LimitExceeded { .. })));
+    }
+
+    #[test]
+    fn emitting_trivia() {
+        let options = TokenizerOptions {
+            emit_trivia: true,
+            ..TokenizerOptions::default()
+        };
+
+        let source = "#!lua\nlocal x = 1 -- one\n--[[ two ]]\treturn x\n";
+        let tokens = tokenize_with_options(source, &options).unwrap();
+
+        assert_eq!(tokens[0].prefix, vec![TokenPrefix::Shebang("#!lua".into())]);
+        assert_eq!(tokens[0].kind, TokenKind::Whitespace("\n".into()));
+        assert!(tokens[1..].iter().all(|token| token.prefix.is_empty()));
+
+        let kinds: Vec<String> = tokens.iter().map(|token| token.kind.to_source()).collect();
+        assert_eq!(kinds, vec!["\n", "local", " ", "x", " ", "=", " ", "1", " ", "-- one", "\n", "--[[ two ]]", "\t", "return", " ", "x", "\n"]);
+
+        assert_eq!(tokens[9].kind, TokenKind::Comment(Comment::SingleLine { content: " one".into() }));
+        assert!(tokens[9].is_trivia());
+        assert_eq!(tokens[9].start_position, SourcePosition { bytes: 18, line: 2, column: 13 });
+        assert_eq!(tokens[9].end_position, SourcePosition { bytes: 24, line: 2, column: 19 });
+        assert_eq!(tokens[10].end_position.line, 3);
+
+        assert_eq!(tokens.iter().map(Token::to_source).collect::<String>(), source);
+        assert_eq!(significant_tokens(tokens), tokenize(source).unwrap());
+
+        for source in &["", "x", "  ", "-- c", "x -- c\n", "\u{FEFF} x"] {
+            let tokens = tokenize_with_options(source, &options).unwrap();
+            assert_eq!(significant_tokens(tokens), tokenize(source).unwrap(), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn columns_after_whitespace() {
+        use line_index::LineIndex;
+
+        for prefix in &["\n", "\n  ", "  \n", "\n\n\t", "\r\n ", " \t\r\n\r\n  "] {
+            for body in &["x", "local x = 1", "-- c\n  y", "'s'\n  [[a\nb]] z"] {
+                let source = format!("{}{}", prefix, body);
+                let index = LineIndex::new(&source);
+
+                for token in &tokenize(&source).unwrap() {
+                    let position = token.start_position;
+
+                    // The line and column lead to the same place as the byte
+                    // offset, which is where the token's text is.
+                    assert_eq!(index.offset_of(position.line, position.column), Some(position.bytes), "{:?}", source);
+                    assert!(source[position.bytes..].starts_with(&token.kind.to_source()), "{:?}", source);
+                }
+            }
+        }
+
+        let tokens = tokenize("\n\n\tx").unwrap();
+        assert_eq!(tokens[0].start_position, SourcePosition { bytes: 3, line: 3, column: 2 });
+    }
+
+    #[test]
+    fn source_tracking_with_crlf() {
+        let input = "local\r\n   test foo\r\n     bar";
+        let tokenized = tokenize(input).unwrap();
+
+        // Lines and columns are the same as with `\n`, and only the byte
+        // offsets move.
+        let lines_and_columns = |tokens: &[Token]| -> Vec<_> {
+            tokens.iter()
+                .map(|token| (token.start_position.line, token.start_position.column, token.end_position.line, token.end_position.column))
+                .collect()
+        };
+
+        let lf = tokenize("local\n   test foo\n     bar").unwrap();
+        assert_eq!(lines_and_columns(&tokenized), lines_and_columns(&lf));
+        assert_eq!(lines_and_columns(&tokenized), vec![(1, 1, 1, 6), (2, 4, 2, 8), (2, 9, 2, 12), (3, 6, 3, 9)]);
+
+        assert_eq!(tokenized[1].prefix, vec![TokenPrefix::Whitespace("\r\n   ".into())]);
+        assert_eq!(tokenized[1].start_position.bytes, 10);
+        assert_eq!(tokenized[3].start_position.bytes, 25);
+
+        // A line break at the very end of what's consumed still starts the
+        // next line at column 1.
+        let position = START_POSITION.next_position("local\r\n");
+        assert_eq!((position.line, position.column), (2, 1));
+
+        // Line breaks inside comments and strings count once too.
+        let tokens = tokenize("--[[a\r\nb]] x = [[\r\n]]\r\ny").unwrap();
+        assert_eq!((tokens[0].start_position.line, tokens[0].start_position.column), (2, 5));
+        assert_eq!((tokens[3].start_position.line, tokens[3].start_position.column), (4, 1));
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let source = "local 名前 = f\u{e9}e";
+
+        let options = TokenizerOptions {
+            unicode_identifiers: true,
+            ..TokenizerOptions::default()
+        };
+        let tokens = tokenize_with_options(source, &options).unwrap();
+
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind.clone()).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::Symbol(Symbol::Local),
+            TokenKind::Identifier("名前".into()),
+            TokenKind::Symbol(Symbol::Equal),
+            TokenKind::Identifier("f\u{e9}e".into()),
+        ]);
+
+        // Columns count characters, not bytes.
+        assert_eq!(tokens[1].end_position.column, 9);
+        assert_eq!(tokens[2].start_position.column, 10);
+        assert_eq!(tokens[2].start_position.bytes, 13);
+
+        // Digits continue identifiers but can't start them.
+        let tokens = tokenize_with_options("_\u{663}", &options).unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::Identifier("_\u{663}".into()));
+        assert!(tokenize_with_options("\u{663}", &options).is_err());
+
+        assert_eq!(tokenize(source), Err(TokenizeError::NonAsciiIdentifier {
+            position: SourcePosition {
+                bytes: 6,
+                line: 1,
+                column: 7,
+            },
+        }));
+
+        assert!(matches!(tokenize("local f\u{e9}e"), Err(TokenizeError::NonAsciiIdentifier { .. })));
+        assert!(matches!(tokenize("local x = \u{2192}"), Err(TokenizeError::UnknownSequence { .. })));
+    }
+
+    #[test]
+    fn precompiled_bytecode() {
+        let lua51 = b"\x1bLuaQ\x00\x01\x04\x08\x04\x08\x00";
+        assert_eq!(tokenize_bytes(lua51), Err(TokenizeError::PrecompiledBytecode {
+            format: BytecodeFormat::Lua { version: Some((5, 1)) },
+        }));
+
+        let lua54 = b"\x1bLuaT\x00\x19\x93\r\n\x1a\n\x04\x08\x08";
+        let err = tokenize_bytes(lua54).unwrap_err();
+        assert_eq!(err.to_string(), "input appears to be precompiled Lua 5.4 bytecode, not source");
+
+        let luajit = b"\x1bLJ\x02\x02\x8e\x01\x02";
+        assert_eq!(tokenize_bytes(luajit), Err(TokenizeError::PrecompiledBytecode {
+            format: BytecodeFormat::LuaJit { version: Some(2) },
+        }));
+
+        let luau = b"\x05\x03\x02\x05print\x05hello";
+        assert_eq!(tokenize_bytes(luau), Err(TokenizeError::PrecompiledBytecode {
+            format: BytecodeFormat::Luau { version: 5 },
+        }));
+
+        // Headers that happen to be valid UTF-8 are caught by the string
+        // entrypoint as well.
+        assert!(matches!(tokenize("\x1bLuaS\x00"), Err(TokenizeError::PrecompiledBytecode { .. })));
+    }
+
+    #[test]
+    fn source_starting_with_escape() {
+        assert_eq!(tokenize_bytes(b"\x1b[0mprint()"), Err(TokenizeError::UnknownSequence {
+            position: START_POSITION,
+            sequence: "\x1b[0mprint()".to_string(),
+        }));
+
+        match tokenize_bytes(b"local x\n\xff") {
+            Err(TokenizeError::InvalidUtf8 { position }) => assert_eq!((position.bytes, position.line), (8, 2)),
+            other => panic!("expected invalid UTF-8, got {:?}", other),
+        }
+
+        assert_eq!(tokenize_bytes(b"print()").unwrap(), tokenize("print()").unwrap());
+    }
+
+    #[test]
+    fn recovering_from_errors() {
+        let source = "local a = 1 $$$ b\nprint(a, @@) -- done\n";
+        let (tokens, errors) = tokenize_recovering(source);
+
+        let names: Vec<_> = tokens.iter().map(|token| token.kind.to_source()).collect();
+        assert_eq!(names, vec!["local", "a", "=", "1", "b", "print", "(", "a", ",", ")", ""]);
+
+        assert_eq!(errors, vec![
+            TokenizeError::UnknownSequence {
+                position: SourcePosition { bytes: 12, line: 1, column: 13 },
+                sequence: "$$$".to_string(),
+            },
+            TokenizeError::UnknownSequence {
+                position: SourcePosition { bytes: 27, line: 2, column: 10 },
+                sequence: "@@)".to_string(),
+            },
+        ]);
+
+        // Tokens after an error keep their real positions.
+        assert_eq!(tokens[4].start_position, SourcePosition { bytes: 16, line: 1, column: 17 });
+        assert_eq!(tokens[9].start_position.bytes, 29);
+
+        // An unclosed string only takes the rest of its line with it, and a
+        // malformed number takes all of itself.
+        let (tokens, errors) = tokenize_recovering("x = 'open\ny = 3a + 1");
+        assert_eq!(tokens.iter().map(|token| token.kind.to_source()).collect::<Vec<_>>(), vec!["x", "=", "y", "=", "+", "1"]);
+        assert!(matches!(errors[..], [TokenizeError::UnclosedString { .. }, TokenizeError::MalformedNumber { .. }]));
+
+        let (tokens, errors) = tokenize_recovering("f() --[[ never closed\ng()");
+        assert_eq!(tokens.len(), 3);
+        assert!(matches!(errors[..], [TokenizeError::UnclosedComment { .. }]));
+
+        for source in &["", "local a = 1 -- hi\n", "#!/bin/lua\nprint('x')", "\x1bLua"] {
+            let (tokens, errors) = tokenize_recovering(source);
+
+            match tokenize(source) {
+                Ok(expected) => assert_eq!((tokens, errors), (expected, Vec::new())),
+                Err(err) => assert_eq!((tokens, errors), (Vec::new(), vec![err])),
+            }
+        }
+    }
+
+    #[test]
+    fn error_messages() {
+        let message = |source| tokenize(source).unwrap_err().to_string();
+
+        assert_eq!(tokenize("a = 'b").unwrap_err().position(), Some(SourcePosition { bytes: 4, line: 1, column: 5 }));
+        assert_eq!(tokenize("\x1bLua").unwrap_err().position(), None);
+
+        assert_eq!(message("local a = 1\n\n\nprint(a) $$$ b"), "unknown sequence starting at line 4, column 10: '$$$'");
+        assert_eq!(message("\u{a7}\u{a7}\u{a7}"), "unknown sequence starting at line 1, column 1: '\u{a7}\u{a7}\u{a7}'");
+        assert_eq!(message("$0123456789abcdefghij"), "unknown sequence starting at line 1, column 1: '$0123456789abcde...'");
+        assert_eq!(message("x = 'open"), "unclosed string starting at line 1, column 5");
+        assert_eq!(message("--[[ open"), "unclosed comment starting at line 1, column 1");
+        assert_eq!(message("3a"), "malformed number '3a' at line 1, column 1");
+
+        // Errors can be passed along with `?`.
+        fn count(source: &str) -> Result<usize, Box<dyn error::Error>> {
+            Ok(tokenize(source)?.len())
+        }
+
+        assert_eq!(count("a b").unwrap(), 2);
+        assert_eq!(count("$").unwrap_err().to_string(), "unknown sequence starting at line 1, column 1: '$'");
+    }
+
+    fn round_trip(value: &[u8], style: StringStyle) -> String {
+        let source = escape_for_lua(value, style);
+        let tokens = tokenize(&source).unwrap();
+
+        match &tokens[0].kind {
+            TokenKind::StringLiteral(literal) => assert_eq!(literal.value().as_deref(), Some(value), "source was {}", source),
+            other => panic!("expected a string, got {:?}", other),
+        }
+
+        source
+    }
+
+    #[test]
+    fn escaping_strings() {
+        assert_eq!(round_trip(b"plain text", StringStyle::DoubleQuote), "\"plain text\"");
+        assert_eq!(round_trip(b"say \"hi\" it's", StringStyle::DoubleQuote), "\"say \\\"hi\\\" it's\"");
+        assert_eq!(round_trip(b"say \"hi\" it's", StringStyle::SingleQuote), "'say \"hi\" it\\'s'");
+        assert_eq!(round_trip(b"a\\b\nc\x001", StringStyle::DoubleQuote), "\"a\\\\b\\nc\\0001\"");
+        assert_eq!(round_trip(b"\xff\xfe caf\xc3\xa9", StringStyle::SingleQuote), "'\\255\\254 caf\u{e9}'");
+    }
+
+    #[test]
+    fn escaping_long_strings() {
+        assert_eq!(round_trip(b"two\nlines", StringStyle::LongForm), "[[two\nlines]]");
+        assert_eq!(round_trip(b"a]]b", StringStyle::LongForm), "[=[a]]b]=]");
+        assert_eq!(round_trip(b"a]]b]=]", StringStyle::LongForm), "[==[a]]b]=]]==]");
+        assert_eq!(round_trip(b"ends with ]", StringStyle::LongForm), "[=[ends with ]]=]");
+        assert_eq!(round_trip(b"[[nested]", StringStyle::LongForm), "[=[[[nested]]=]");
+        assert_eq!(round_trip(b"\nstarts with a newline", StringStyle::LongForm), "[[\n\nstarts with a newline]]");
+
+        // Values long strings can't hold fall back to double quotes.
+        assert_eq!(round_trip(b"carriage\rreturn", StringStyle::LongForm), "\"carriage\\rreturn\"");
+        assert_eq!(round_trip(b"\x80", StringStyle::LongForm), "\"\\128\"");
+    }
+}
@@ -0,0 +1,38 @@
+//! The versions of Lua that parts of the crate can follow the semantics of.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LuaVersion {
+    #[default]
+    Lua51,
+    Lua52,
+    Lua53,
+    Lua54,
+}
+
+impl LuaVersion {
+    /// Every version, oldest first.
+    pub const ALL: [LuaVersion; 4] = [LuaVersion::Lua51, LuaVersion::Lua52, LuaVersion::Lua53, LuaVersion::Lua54];
+
+    /// Whether the version has a separate integer subtype for numbers.
+    pub fn has_integers(&self) -> bool {
+        match *self {
+            LuaVersion::Lua51 | LuaVersion::Lua52 => false,
+            LuaVersion::Lua53 | LuaVersion::Lua54 => true,
+        }
+    }
+}
+
+impl fmt::Display for LuaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            LuaVersion::Lua51 => "5.1",
+            LuaVersion::Lua52 => "5.2",
+            LuaVersion::Lua53 => "5.3",
+            LuaVersion::Lua54 => "5.4",
+        };
+
+        f.write_str(name)
+    }
+}
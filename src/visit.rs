@@ -0,0 +1,175 @@
+//! Helpers for walking over every node of a given kind in an AST.
+
+use ast::*;
+
+/// A reference to either kind of node that [for_each_node] walks over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Node<'c, 'a: 'c> {
+    Statement(&'c Statement<'a>),
+    Expression(&'c Expression<'a>),
+}
+
+/// Calls `callback` with every statement and expression in the chunk,
+/// including nested chunks, in source order with outer nodes first.
+pub fn for_each_node<'c, 'a: 'c, F: FnMut(Node<'c, 'a>)>(chunk: &'c Chunk<'a>, callback: &mut F) {
+    for statement in &chunk.statements {
+        visit_statement(statement, callback);
+    }
+}
+
+/// Calls `callback` with `statement` and every statement and expression nested
+/// inside of it, in source order with outer nodes first.
+pub fn visit_statement<'c, 'a: 'c, F: FnMut(Node<'c, 'a>)>(statement: &'c Statement<'a>, callback: &mut F) {
+    callback(Node::Statement(statement));
+    walk_statement(statement, true, callback);
+}
+
+/// Calls `callback` with every expression that belongs to `statement` itself,
+/// skipping over the statements in any of its bodies.
+pub fn visit_own_expressions<'c, 'a: 'c, F: FnMut(&'c Expression<'a>)>(statement: &'c Statement<'a>, callback: &mut F) {
+    walk_statement(statement, false, &mut |node| {
+        if let Node::Expression(expression) = node {
+            callback(expression);
+        }
+    });
+}
+
+/// Calls `callback` with every expression in the chunk, including nested
+/// chunks, outer expressions first.
+pub fn for_each_expression<'c, 'a: 'c, F: FnMut(&'c Expression<'a>)>(chunk: &'c Chunk<'a>, callback: &mut F) {
+    for_each_node(chunk, &mut |node| {
+        if let Node::Expression(expression) = node {
+            callback(expression);
+        }
+    });
+}
+
+/// Calls `callback` with every statement in the chunk, including statements in
+/// nested chunks, outer statements first.
+pub fn for_each_statement<'c, 'a: 'c, F: FnMut(&'c Statement<'a>)>(chunk: &'c Chunk<'a>, callback: &mut F) {
+    for_each_node(chunk, &mut |node| {
+        if let Node::Statement(statement) = node {
+            callback(statement);
+        }
+    });
+}
+
+/// Calls `callback` with `expression` and every expression nested inside of it,
+/// outer expressions first.
+pub fn visit_expression<'c, 'a: 'c, F: FnMut(&'c Expression<'a>)>(expression: &'c Expression<'a>, callback: &mut F) {
+    visit_node(expression, &mut |node| {
+        if let Node::Expression(expression) = node {
+            callback(expression);
+        }
+    });
+}
+
+/// Visits the nodes nested inside of `statement`, descending into its bodies
+/// only when `nested` is set.
+fn walk_statement<'c, 'a: 'c, F: FnMut(Node<'c, 'a>)>(statement: &'c Statement<'a>, nested: bool, callback: &mut F) {
+    let visit_body = |body: &'c Chunk<'a>, callback: &mut F| {
+        if nested {
+            for_each_node(body, callback);
+        }
+    };
+
+    match statement {
+        Statement::Assignment(assignment) => {
+            for target in &assignment.targets {
+                visit_target(target, callback);
+            }
+
+            visit_expressions(&assignment.values, callback);
+        },
+        Statement::LocalAssignment(assignment) => visit_expressions(&assignment.values, callback),
+        Statement::FunctionCall(call) => {
+            visit_node(&call.name_expression, callback);
+            visit_expressions(&call.arguments, callback);
+        },
+        Statement::NumericFor(numeric_for) => {
+            visit_node(&numeric_for.start, callback);
+            visit_node(&numeric_for.end, callback);
+
+            if let Some(step) = &numeric_for.step {
+                visit_node(step, callback);
+            }
+
+            visit_body(&numeric_for.body, callback);
+        },
+        Statement::GenericFor(generic_for) => {
+            visit_expressions(&generic_for.item_source, callback);
+            visit_body(&generic_for.body, callback);
+        },
+        Statement::IfStatement(if_statement) => {
+            visit_node(&if_statement.condition, callback);
+            visit_body(&if_statement.body, callback);
+
+            for (condition, body) in &if_statement.else_if_branches {
+                visit_node(condition, callback);
+                visit_body(body, callback);
+            }
+
+            if let Some(body) = &if_statement.else_branch {
+                visit_body(body, callback);
+            }
+        },
+        Statement::WhileLoop(while_loop) => {
+            visit_node(&while_loop.condition, callback);
+            visit_body(&while_loop.body, callback);
+        },
+        Statement::RepeatLoop(repeat_loop) => {
+            visit_body(&repeat_loop.body, callback);
+            visit_node(&repeat_loop.condition, callback);
+        },
+        Statement::FunctionDeclaration(declaration) => visit_body(&declaration.body, callback),
+        Statement::Do(body) => visit_body(body, callback),
+        Statement::Return(return_statement) => visit_expressions(&return_statement.values, callback),
+        Statement::Break => {},
+    }
+}
+
+
+fn visit_target<'c, 'a: 'c, F: FnMut(Node<'c, 'a>)>(target: &'c AssignmentTarget<'a>, callback: &mut F) {
+    match target {
+        AssignmentTarget::Name(_) => {},
+        AssignmentTarget::FieldAccess { base, .. } => visit_node(base, callback),
+        AssignmentTarget::IndexAccess { base, index } => {
+            visit_node(base, callback);
+            visit_node(index, callback);
+        },
+    }
+}
+
+fn visit_expressions<'c, 'a: 'c, F: FnMut(Node<'c, 'a>)>(expressions: &'c [Expression<'a>], callback: &mut F) {
+    for expression in expressions {
+        visit_node(expression, callback);
+    }
+}
+
+fn visit_node<'c, 'a: 'c, F: FnMut(Node<'c, 'a>)>(expression: &'c Expression<'a>, callback: &mut F) {
+    callback(Node::Expression(expression));
+
+    match expression {
+        Expression::Table(table) => {
+            for (key, value) in &table.items {
+                if let Some(TableKey::Expression(key)) = key {
+                    visit_node(key, callback);
+                }
+
+                visit_node(value, callback);
+            }
+        },
+        Expression::FunctionCall(call) => {
+            visit_node(&call.name_expression, callback);
+            visit_expressions(&call.arguments, callback);
+        },
+        Expression::Var(target) => visit_target(target, callback),
+        Expression::ParenExpression(inner) => visit_node(inner, callback),
+        Expression::UnaryOp(op) => visit_node(&op.argument, callback),
+        Expression::BinaryOp(op) => {
+            visit_node(&op.left, callback);
+            visit_node(&op.right, callback);
+        },
+        _ => {},
+    }
+}
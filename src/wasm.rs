@@ -0,0 +1,83 @@
+//! A string-in, string-out entrypoint for using the parser from JavaScript.
+//!
+//! This module doesn't depend on wasm-bindgen itself so that the crate stays
+//! buildable everywhere. A playground can wrap it in a few lines:
+//!
+//! ```ignore
+//! #[wasm_bindgen]
+//! pub fn parse(source: &str, dialect: &str) -> String {
+//!     mab::wasm::parse_to_json(source, dialect)
+//! }
+//! ```
+
+use serde_json;
+
+use parser::parse_from_tokens;
+use tokenizer::{tokenize_with_options, TokenizerOptions};
+use version::LuaVersion;
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Output<T: ::serde::Serialize> {
+    Ast(T),
+    Error(String),
+}
+
+/// Parses `source` and returns its AST as JSON, in the form `{"ast": ...}`, or
+/// `{"error": "..."}` if it couldn't be parsed.
+///
+/// `dialect` names the version of Lua to parse, like `lua51`, `lua52`,
+/// `lua53`, or `lua54`. An empty string accepts everything any of them do.
+pub fn parse_to_json(source: &str, dialect: &str) -> String {
+    let output = match parse_dialect(dialect) {
+        Some(version) => match tokenize_with_options(source, &TokenizerOptions { version, ..TokenizerOptions::default() }) {
+            Ok(tokens) => match parse_from_tokens(&tokens) {
+                Ok(chunk) => serde_json::to_string(&Output::Ast(chunk)),
+                Err(message) => serde_json::to_string(&Output::Error::<()>(message)),
+            },
+            Err(err) => serde_json::to_string(&Output::Error::<()>(err.to_string())),
+        },
+        None => serde_json::to_string(&Output::Error::<()>(format!("unknown dialect '{}'", dialect))),
+    };
+
+    output.expect("AST could not be serialized")
+}
+
+fn parse_dialect(dialect: &str) -> Option<Option<LuaVersion>> {
+    match dialect {
+        "" => Some(None),
+        "lua51" => Some(Some(LuaVersion::Lua51)),
+        "lua52" => Some(Some(LuaVersion::Lua52)),
+        "lua53" => Some(Some(LuaVersion::Lua53)),
+        "lua54" => Some(Some(LuaVersion::Lua54)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+
+    #[test]
+    fn ast_output() {
+        let output: Value = serde_json::from_str(&parse_to_json("local x = 1", "lua51")).unwrap();
+
+        let statements = &output["ast"]["statements"];
+        assert_eq!(statements.as_array().unwrap().len(), 1);
+        assert_eq!(statements[0]["LocalAssignment"]["names"][0], "x");
+    }
+
+    #[test]
+    fn error_output() {
+        let output: Value = serde_json::from_str(&parse_to_json("local = 1", "")).unwrap();
+        assert!(output["error"].is_string());
+
+        let output: Value = serde_json::from_str(&parse_to_json("local x", "lua99")).unwrap();
+        assert_eq!(output["error"], "unknown dialect 'lua99'");
+
+        let output: Value = serde_json::from_str(&parse_to_json("local x = a & b", "lua51")).unwrap();
+        assert!(output["error"].as_str().unwrap().starts_with("unknown sequence"));
+    }
+}
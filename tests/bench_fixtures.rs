@@ -0,0 +1,62 @@
+extern crate mab;
+
+#[path = "../benches/fixtures/mod.rs"]
+mod fixtures;
+
+use mab::line_index::LineIndex;
+use mab::{tokenize, tokenize_iter, tokenize_recovering};
+
+/// Checks that `source` tokenizes without errors, the same way through every
+/// entrypoint, into tokens that give back the source and know where they
+/// are.
+fn check(source: &str) {
+    let tokens = tokenize(source).unwrap();
+    let reproduced: String = tokens.iter().map(|token| token.to_source()).collect();
+    assert_eq!(reproduced, source);
+
+    let index = LineIndex::new(source);
+
+    for token in &tokens {
+        assert_eq!(index.position_of(token.start_position.bytes), token.start_position);
+        assert_eq!(index.position_of(token.end_position.bytes), token.end_position);
+        assert_eq!(&source[token.start_position.bytes..token.end_position.bytes], token.kind.to_source());
+    }
+
+    assert_eq!(tokenize_iter(source).collect::<Result<Vec<_>, _>>().unwrap(), tokens);
+    assert_eq!(tokenize_recovering(source), (tokens, Vec::new()));
+}
+
+#[test]
+fn small() {
+    check(fixtures::SMALL);
+}
+
+#[test]
+fn module() {
+    let source = fixtures::module();
+    assert!(source.len() >= 100 * 1024);
+
+    check(&source);
+}
+
+#[test]
+fn keywords() {
+    let source = fixtures::keywords();
+    let tokens = tokenize(&source).unwrap();
+
+    let pairs = source.lines().count() / 2;
+
+    // Without a version, `goto` is a name.
+    assert_eq!(tokens.iter().filter(|token| token.is_keyword()).count(), pairs * 21);
+    assert_eq!(tokens.iter().filter(|token| token.is_identifier()).count(), pairs * 23);
+
+    check(&source);
+}
+
+#[test]
+fn pathological() {
+    let source = fixtures::pathological();
+    assert!(!source.contains('\n'));
+
+    check(&source);
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "fs")]
+
+extern crate mab;
+
+use std::env;
+use std::fs;
+
+use mab::corpus::{run_corpus, run_corpus_with, CorpusFailure, CorpusMode};
+
+#[test]
+fn fixtures_ast() {
+    run_corpus("fixtures", CorpusMode::Ast).unwrap().assert_passed();
+}
+
+#[test]
+fn fixtures_tokens() {
+    run_corpus("fixtures", CorpusMode::Tokens).unwrap().assert_passed();
+}
+
+#[test]
+fn blessing_and_drift() {
+    let dir = env::temp_dir().join(format!("mab-corpus-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("call.lua"), "print(1)\n").unwrap();
+
+    // Without an expectation, the file fails until it's blessed.
+    let report = run_corpus_with(&dir, CorpusMode::Ast, false).unwrap();
+    assert!(matches!(report.failures[..], [CorpusFailure::Missing { .. }]));
+
+    let report = run_corpus_with(&dir, CorpusMode::Ast, true).unwrap();
+    assert_eq!(report.blessed, vec![dir.join("call.lua")]);
+    assert_eq!(fs::read_to_string(dir.join("call.ast.sexp")).unwrap(), "(chunk\n  (call print 1))\n");
+
+    // Reformatting an expectation doesn't matter.
+    fs::write(dir.join("call.ast.sexp"), "(chunk (call print 1))").unwrap();
+    assert!(run_corpus_with(&dir, CorpusMode::Ast, false).unwrap().is_success());
+
+    // Changing the source does, and the diff says how.
+    fs::write(dir.join("call.lua"), "print(2)\n").unwrap();
+    let report = run_corpus_with(&dir, CorpusMode::Ast, false).unwrap();
+    assert!(!report.is_success());
+
+    let text = report.to_string();
+    assert!(text.contains("- (chunk (call print 1))"), "{}", text);
+    assert!(text.contains("+ (chunk\n+   (call print 2))"), "{}", text);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
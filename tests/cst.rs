@@ -0,0 +1,29 @@
+extern crate mab;
+
+use std::fs::{File, read_dir};
+use std::io::Read;
+
+use mab::{tokenize, parse_from_tokens};
+use mab::cst::SyntaxTree;
+
+#[test]
+fn cst_matches_direct_parsing() {
+    for entry in read_dir("parse_examples/source").unwrap() {
+        let path = entry.unwrap().path();
+
+        let mut contents = String::new();
+        File::open(&path)
+            .expect("Unable to open file!")
+            .read_to_string(&mut contents)
+            .expect("Unable to read from file!");
+
+        let tokens = tokenize(&contents).unwrap();
+        let tree = match SyntaxTree::parse(&tokens) {
+            Ok(tree) => tree,
+            Err(err) => panic!("Failed to build syntax tree for {}: {}", path.display(), err),
+        };
+
+        assert_eq!(tree.source(), contents, "source of {} was not preserved", path.display());
+        assert_eq!(tree.to_ast().unwrap(), parse_from_tokens(&tokens).unwrap(), "AST of {} differs", path.display());
+    }
+}
@@ -0,0 +1,56 @@
+extern crate proptest;
+extern crate mab;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use mab::{escape_for_lua, tokenize, StringStyle, TokenKind};
+
+fn decode(source: &str) -> Option<Vec<u8>> {
+    let tokens = tokenize(source).ok()?;
+
+    match &tokens[..] {
+        [token] => match &token.kind {
+            TokenKind::StringLiteral(literal) => literal.value(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn style() -> impl Strategy<Value = StringStyle> {
+    prop_oneof![
+        Just(StringStyle::DoubleQuote),
+        Just(StringStyle::SingleQuote),
+        Just(StringStyle::LongForm),
+    ]
+}
+
+/// Bytes weighted towards the ones that need care: quotes, backslashes,
+/// brackets, newlines and digits after escapes.
+fn tricky_bytes() -> impl Strategy<Value = Vec<u8>> {
+    vec(prop_oneof![
+        prop::sample::select(b"\"'\\[]=\n\r\t\x001".to_vec()),
+        any::<u8>(),
+    ], 0..64)
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_bytes_round_trip(value in vec(any::<u8>(), 0..64), style in style()) {
+        let source = escape_for_lua(&value, style);
+        prop_assert_eq!(decode(&source), Some(value), "source was {}", source);
+    }
+
+    #[test]
+    fn tricky_bytes_round_trip(value in tricky_bytes(), style in style()) {
+        let source = escape_for_lua(&value, style);
+        prop_assert_eq!(decode(&source), Some(value), "source was {}", source);
+    }
+
+    #[test]
+    fn text_round_trips(value in "\\PC*", style in style()) {
+        let source = escape_for_lua(value.as_bytes(), style);
+        prop_assert_eq!(decode(&source), Some(value.into_bytes()), "source was {}", source);
+    }
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "ffi")]
+
+extern crate mab;
+extern crate serde_json;
+
+use std::ptr;
+use std::slice;
+use std::str;
+
+use mab::ffi::*;
+
+/// Copies a string out of a handle the way a C caller would.
+unsafe fn read_string(pointer: *const u8, length: usize) -> Option<String> {
+    if pointer.is_null() {
+        None
+    } else {
+        Some(str::from_utf8(slice::from_raw_parts(pointer, length)).unwrap().to_string())
+    }
+}
+
+#[test]
+fn successful_parse() {
+    let source = b"local x = 1";
+
+    unsafe {
+        let result = mab_parse(source.as_ptr(), source.len());
+        assert!(!result.is_null());
+        assert!(mab_result_success(result));
+
+        let mut length = 99;
+        assert!(mab_result_error(result, &mut length).is_null());
+        assert_eq!(length, 0);
+
+        let pointer = mab_result_ast_json(result, &mut length);
+        let json: serde_json::Value = serde_json::from_str(&read_string(pointer, length).unwrap()).unwrap();
+        assert_eq!(json["statements"][0]["LocalAssignment"]["names"][0], "x");
+
+        mab_result_free(result);
+    }
+}
+
+#[test]
+fn failed_parse() {
+    let source = b"local = 1";
+
+    unsafe {
+        let result = mab_parse(source.as_ptr(), source.len());
+        assert!(!mab_result_success(result));
+
+        let mut length = 0;
+        let error = read_string(mab_result_error(result, &mut length), length);
+        assert!(error.is_some());
+        assert!(mab_result_ast_json(result, ptr::null_mut()).is_null());
+
+        mab_result_free(result);
+    }
+}
+
+#[test]
+fn invalid_input() {
+    let source = [0xff, 0xfe];
+
+    unsafe {
+        let result = mab_parse(source.as_ptr(), source.len());
+
+        let mut length = 0;
+        let error = read_string(mab_result_error(result, &mut length), length).unwrap();
+        assert!(error.starts_with("source is not valid UTF-8"));
+
+        mab_result_free(result);
+
+        // An empty buffer doesn't need a valid pointer.
+        let result = mab_parse(ptr::null(), 0);
+        assert!(mab_result_success(result));
+        mab_result_free(result);
+
+        // Null handles are tolerated everywhere.
+        assert!(!mab_result_success(ptr::null()));
+        assert!(mab_result_ast_json(ptr::null(), ptr::null_mut()).is_null());
+        mab_result_free(ptr::null_mut());
+    }
+}
@@ -0,0 +1,82 @@
+extern crate mab;
+
+use mab::ast::{Chunk, Statement};
+use mab::lint::{lint, DiagnosticSink, LintConfig, LintContext, Registry, Rule, Severity};
+use mab::tokenize;
+
+/// A rule that lives outside of the crate and only uses its public API.
+struct TooManyStatements;
+
+impl Rule for TooManyStatements {
+    fn name(&self) -> &str {
+        "too-many-statements"
+    }
+
+    fn code(&self) -> &str {
+        "X001"
+    }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, context: &LintContext, sink: &mut DiagnosticSink) {
+        let limit = match context.config.get("limit") {
+            Some(mab::lint::ConfigValue::Number(limit)) => *limit as usize,
+            _ => 2,
+        };
+
+        fn count(chunk: &Chunk) -> usize {
+            chunk.statements.iter().map(|statement| match statement {
+                Statement::FunctionDeclaration(declaration) => 1 + count(&declaration.body),
+                _ => 1,
+            }).sum()
+        }
+
+        let total = count(context.chunk);
+
+        if total > limit {
+            sink.report(format!("{} statements is more than the limit of {}", total, limit));
+        }
+    }
+}
+
+#[test]
+fn custom_rule() {
+    let source = "local a = 1\nfunction f()\n\tprint(a)\nend\n";
+    let tokens = tokenize(source).unwrap();
+
+    let mut registry = Registry::new();
+    registry.register(Box::new(TooManyStatements));
+
+    let diagnostics = lint(&tokens, &registry, &LintConfig::default()).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, "X001");
+    assert_eq!(diagnostics[0].severity, Severity::Error);
+    assert_eq!(diagnostics[0].message, "3 statements is more than the limit of 2");
+
+    let mut config = LintConfig::default();
+    config.rule_mut("too-many-statements").values.insert(
+        "limit".to_string(),
+        mab::lint::ConfigValue::Number(3.0),
+    );
+
+    assert!(lint(&tokens, &registry, &config).unwrap().is_empty());
+}
+
+#[test]
+fn custom_rule_alongside_builtins() {
+    let source = "local unused = 1\nprint(2)";
+    let tokens = tokenize(source).unwrap();
+
+    let mut registry = Registry::with_builtin_rules();
+    registry.register(Box::new(TooManyStatements));
+
+    let codes: Vec<_> = lint(&tokens, &registry, &LintConfig::default())
+        .unwrap()
+        .into_iter()
+        .map(|diagnostic| diagnostic.code)
+        .collect();
+
+    assert_eq!(codes, vec!["L002".to_string()]);
+}
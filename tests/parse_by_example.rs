@@ -127,4 +127,30 @@ fn parse_by_example() {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[test]
+fn tokens_reproduce_source() {
+    let sources = read_dir("parse_examples/source").unwrap().chain(read_dir("fixtures").unwrap());
+
+    for entry in sources {
+        let entry_path = entry.unwrap().path();
+
+        if entry_path.extension().unwrap() != "lua" {
+            continue;
+        }
+
+        let mut contents = String::new();
+        File::open(&entry_path).unwrap().read_to_string(&mut contents).unwrap();
+
+        let tokens = tokenize(&contents).unwrap();
+
+        // The whitespace and comments before each token, then the token
+        // exactly as it was written, make up the whole file.
+        let detokenized: String = tokens.iter().map(Token::to_source).collect();
+        assert_eq!(detokenized, contents, "{} didn't round trip", entry_path.display());
+
+        for token in &tokens {
+            assert_eq!(token.kind.to_source(), &contents[token.start_position.bytes..token.end_position.bytes]);
+        }
+    }
+}
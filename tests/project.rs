@@ -0,0 +1,156 @@
+#![cfg(feature = "fs")]
+
+extern crate mab;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use mab::project::{parse_files, parse_project, FileError, ProjectOptions};
+
+struct TempProject {
+    root: PathBuf,
+}
+
+impl TempProject {
+    fn new(name: &str) -> TempProject {
+        let mut root = env::temp_dir();
+        root.push(format!("mab-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        TempProject { root }
+    }
+
+    fn write(&self, relative_path: &str, contents: &str) -> PathBuf {
+        let path = self.root.join(relative_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let mut file = File::create(&path).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+
+        path
+    }
+
+    fn path(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Drop for TempProject {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn make_fixture(name: &str) -> TempProject {
+    let project = TempProject::new(name);
+    project.write("main.lua", "local x = 5\nprint(x)\n");
+    project.write("lib/util.lua", "function util.add(a, b)\nend\n");
+    project.write("lib/broken.lua", "local = 5\n");
+    project.write("lib/unclosed.lua", "local x = \"hello\n");
+    project.write("notes.txt", "not lua at all");
+    project.write("other.luau", "local y = 1");
+    project
+}
+
+#[test]
+fn parses_every_lua_file() {
+    let project = make_fixture("parses-every-lua-file");
+    let result = parse_project(project.path(), &ProjectOptions::default()).unwrap();
+
+    let paths: Vec<_> = result.iter()
+        .map(|(path, _)| path.strip_prefix(project.path()).unwrap().to_path_buf())
+        .collect();
+
+    assert_eq!(paths, vec![
+        PathBuf::from("lib/broken.lua"),
+        PathBuf::from("lib/unclosed.lua"),
+        PathBuf::from("lib/util.lua"),
+        PathBuf::from("main.lua"),
+    ]);
+
+    assert!(result.get(project.path().join("main.lua")).unwrap().is_ok());
+    assert!(result.get(project.path().join("lib/util.lua")).unwrap().is_ok());
+
+    match result.get(project.path().join("lib/broken.lua")).unwrap() {
+        Err(FileError::Parse(_)) => {},
+        other => panic!("Expected a parse error, got {:?}", other),
+    }
+
+    match result.get(project.path().join("lib/unclosed.lua")).unwrap() {
+        Err(FileError::Tokenize(_)) => {},
+        other => panic!("Expected a tokenize error, got {:?}", other),
+    }
+
+    assert_eq!(result.errors().count(), 2);
+}
+
+#[test]
+fn respects_extensions() {
+    let project = make_fixture("respects-extensions");
+    let options = ProjectOptions {
+        extensions: vec!["luau".to_string()],
+        ..ProjectOptions::default()
+    };
+
+    let result = parse_project(project.path(), &options).unwrap();
+
+    assert_eq!(result.len(), 1);
+    assert!(result.get(project.path().join("other.luau")).unwrap().is_ok());
+}
+
+#[test]
+fn parallel_matches_sequential() {
+    let project = make_fixture("parallel-matches-sequential");
+
+    let sequential = parse_project(project.path(), &ProjectOptions::default()).unwrap();
+    let parallel = parse_project(project.path(), &ProjectOptions {
+        parallel: true,
+        ..ProjectOptions::default()
+    }).unwrap();
+
+    let sequential_paths: Vec<_> = sequential.iter().map(|(path, _)| path.clone()).collect();
+    let parallel_paths: Vec<_> = parallel.iter().map(|(path, _)| path.clone()).collect();
+    assert_eq!(sequential_paths, parallel_paths);
+
+    for (path, result) in &sequential {
+        match (result, parallel.get(path).unwrap()) {
+            (Ok(a), Ok(b)) => assert_eq!(a, b),
+            (Err(_), Err(_)) => {},
+            _ => panic!("Sequential and parallel results differ for {}", path.display()),
+        }
+    }
+}
+
+#[test]
+fn unreadable_files_are_recorded() {
+    let project = make_fixture("unreadable-files-are-recorded");
+    let missing = project.path().join("missing.lua");
+    let present = project.path().join("main.lua");
+
+    let result = parse_files(vec![missing.clone(), present.clone()], &ProjectOptions::default());
+
+    assert!(result.get(&present).unwrap().is_ok());
+
+    match result.get(&missing).unwrap() {
+        Err(FileError::Io(_)) => {},
+        other => panic!("Expected an IO error, got {:?}", other),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_directories_are_not_followed() {
+    use std::os::unix::fs::symlink;
+
+    let project = make_fixture("symlinked-directories-are-not-followed");
+    symlink(project.path(), project.path().join("lib/loop")).unwrap();
+
+    let result = parse_project(project.path(), &ProjectOptions::default()).unwrap();
+
+    assert_eq!(result.len(), 4);
+    assert!(result.iter().all(|(path, _)| !path.starts_with(project.path().join("lib/loop"))));
+}
@@ -0,0 +1,88 @@
+extern crate mab;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use mab::reusable::{Parser, ParserOptions};
+use mab::{parse_from_tokens, tokenize_into};
+
+/// Counts every allocation made by each thread of this test binary, so that
+/// tests running at the same time don't count each other's.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    // The count is already gone while a thread is shutting down.
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        System.dealloc(pointer, layout)
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(pointer, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_during<F: FnOnce()>(callback: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    callback();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn second_parse_allocates_less() {
+    let source = "local a, b, c = 1, 2, 3\nprint(a, b, c)\nwhile a do\n\tf(a, {b, c})\nend\n";
+
+    let mut parser = Parser::new(ParserOptions::default());
+    let mut statements = Vec::new();
+
+    let first = allocations_during(|| {
+        statements.push(parser.parse_with(source, |chunk| chunk.statements.len()).unwrap());
+    });
+
+    let second = allocations_during(|| {
+        statements.push(parser.parse_with(source, |chunk| chunk.statements.len()).unwrap());
+    });
+
+    assert_eq!(statements, vec![3, 3]);
+    assert!(second < first, "first parse made {} allocations, second made {}", first, second);
+}
+
+#[test]
+fn reused_buffer_costs_no_allocations() {
+    let source = "local a, b, c = 1, 2, 3\nprint(a, b, c)\nwhile a do\n\tf(a, {b, c})\nend\n";
+
+    let mut parser = Parser::new(ParserOptions::default());
+    parser.parse_with(source, |_| ()).unwrap();
+
+    // Tokenizing into a buffer that's already big enough and parsing the
+    // tokens, which is all a warmed up parser should allocate for.
+    let mut tokens = Vec::new();
+    tokenize_into(source, &mut tokens).unwrap();
+
+    let expected = allocations_during(|| {
+        tokenize_into(source, &mut tokens).unwrap();
+        parse_from_tokens(&tokens).unwrap();
+    });
+
+    let second = allocations_during(|| {
+        parser.parse_with(source, |_| ()).unwrap();
+    });
+
+    assert_eq!(second, expected);
+}
@@ -0,0 +1,122 @@
+extern crate mab;
+
+use std::fs::{File, read_dir};
+use std::io::Read;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use mab::{tokenize, parse_from_tokens, Token};
+
+const CASES_PER_SOURCE: usize = 200;
+const CASE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A small xorshift generator so failures can be reproduced from the seed in
+/// the panic message.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+fn read_sources() -> Vec<String> {
+    let mut sources = Vec::new();
+
+    for directory in &["parse_examples/source", "parse_examples/should_not_parse"] {
+        for entry in read_dir(directory).unwrap() {
+            let mut contents = String::new();
+            File::open(entry.unwrap().path())
+                .expect("Unable to open file!")
+                .read_to_string(&mut contents)
+                .expect("Unable to read from file!");
+
+            sources.push(contents);
+        }
+    }
+
+    sources
+}
+
+/// Builds a token stream the tokenizer would never produce: tokens are
+/// reordered, repeated and dropped, so positions are out of order and
+/// end-of-file markers can show up anywhere.
+fn scramble<'a>(tokens: &[Token<'a>], pool: &[Token<'a>], rng: &mut Rng) -> Vec<Token<'a>> {
+    let mut scrambled = tokens.to_vec();
+
+    match rng.below(3) {
+        0 => {
+            for i in (1..scrambled.len()).rev() {
+                let j = rng.below(i + 1);
+                scrambled.swap(i, j);
+            }
+        },
+        1 => {
+            for _ in 0..1 + rng.below(4) {
+                if scrambled.is_empty() {
+                    break;
+                }
+
+                let i = rng.below(scrambled.len());
+                let j = rng.below(scrambled.len());
+                scrambled.swap(i, j);
+
+                let k = rng.below(scrambled.len());
+                scrambled.remove(k);
+            }
+        },
+        _ => {
+            let length = rng.below(64);
+            scrambled = (0..length).map(|_| pool[rng.below(pool.len())].clone()).collect();
+        },
+    }
+
+    scrambled
+}
+
+#[test]
+fn shuffled_tokens_terminate() {
+    let (sender, receiver) = mpsc::channel();
+
+    let worker = thread::spawn(move || {
+        let sources = read_sources();
+        let token_lists: Vec<Vec<Token>> = sources.iter()
+            .filter_map(|source| tokenize(source).ok())
+            .collect();
+        let pool: Vec<Token> = token_lists.iter().flat_map(|tokens| tokens.iter().cloned()).collect();
+
+        for (index, tokens) in token_lists.iter().enumerate() {
+            for case in 0..CASES_PER_SOURCE {
+                let seed = ((index as u64) << 32 | case as u64) + 1;
+                sender.send(seed).unwrap();
+
+                let scrambled = scramble(tokens, &pool, &mut Rng(seed));
+                let _ = parse_from_tokens(&scrambled);
+            }
+        }
+    });
+
+    let mut last_seed = None;
+
+    loop {
+        match receiver.recv_timeout(CASE_TIMEOUT) {
+            Ok(seed) => last_seed = Some(seed),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                panic!("Parsing did not finish within {:?} for seed {:?}", CASE_TIMEOUT, last_seed);
+            },
+        }
+    }
+
+    if worker.join().is_err() {
+        panic!("The parser panicked for seed {:?}", last_seed);
+    }
+}
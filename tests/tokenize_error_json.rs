@@ -0,0 +1,42 @@
+extern crate mab;
+extern crate serde_json;
+
+use mab::{tokenize, tokenize_recovering, TokenizeError};
+
+fn round_trip(err: &TokenizeError) -> TokenizeError {
+    let json = serde_json::to_string(err).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+#[test]
+fn errors_round_trip() {
+    let (_, errors) = tokenize_recovering("local s = 'open\nx = 0x\ny = $ .. '\\300'\n--[[ never closed");
+    assert!(matches!(errors[..], [
+        TokenizeError::UnclosedString { .. },
+        TokenizeError::MalformedNumber { .. },
+        TokenizeError::UnknownSequence { .. },
+        TokenizeError::InvalidEscape { .. },
+        TokenizeError::UnclosedComment { .. },
+    ]));
+
+    for err in &errors {
+        assert_eq!(&round_trip(err), err);
+    }
+
+    let bytecode = tokenize("\x1bLuaS").unwrap_err();
+    assert!(matches!(bytecode, TokenizeError::PrecompiledBytecode { .. }));
+    assert_eq!(round_trip(&bytecode), bytecode);
+}
+
+#[test]
+fn error_json() {
+    let err = tokenize("x = 1 $").unwrap_err();
+    let json: serde_json::Value = serde_json::to_value(&err).unwrap();
+
+    assert_eq!(json, serde_json::json!({
+        "UnknownSequence": {
+            "position": { "bytes": 6, "line": 1, "column": 7 },
+            "sequence": "$",
+        },
+    }));
+}
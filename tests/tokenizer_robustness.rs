@@ -0,0 +1,163 @@
+extern crate proptest;
+extern crate mab;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use mab::version::LuaVersion;
+use mab::{significant_tokens, tokenize_recovering, tokenize_with_options, ColumnUnit, OnError, SourcePosition, TokenizeError, TokenizerOptions};
+
+const START: SourcePosition = SourcePosition {
+    bytes: 0,
+    line: 1,
+    column: 1,
+};
+
+/// Checks that errors, in the order they were found, point at places in the
+/// source, and that their lines and columns agree with their byte offsets.
+fn check_errors(source: &str, errors: &[TokenizeError], options: &TokenizerOptions) {
+    let mut expected = START;
+
+    for err in errors {
+        let position = match err.position() {
+            Some(position) => position,
+            None => continue,
+        };
+
+        assert!(source.is_char_boundary(position.bytes), "{:?} is outside of {:?}", err, source);
+
+        // A byte order mark at the start doesn't take up a column.
+        if expected.bytes == 0 && position.bytes > 0 && source.starts_with('\u{FEFF}') {
+            expected.bytes = 3;
+        }
+        assert!(position.bytes >= expected.bytes, "{:?} is out of order", err);
+
+        expected = expected.next_position_with_options(&source[expected.bytes..position.bytes], options);
+        assert_eq!(position, expected, "{:?}", err);
+    }
+}
+
+/// Tokenizes `source` every way there is, which shouldn't panic, and checks
+/// that the tokens or errors describe the source.
+fn check(source: &str, options: &TokenizerOptions) {
+    match tokenize_with_options(source, options) {
+        Ok(tokens) => {
+            let reproduced: String = tokens.iter().map(|token| token.to_source()).collect();
+            assert_eq!(reproduced, source);
+
+            for token in &tokens {
+                assert!(token.start_position.bytes <= token.end_position.bytes);
+                assert!(token.end_position.bytes <= source.len());
+            }
+
+            if options.emit_trivia {
+                let without = TokenizerOptions {
+                    emit_trivia: false,
+                    ..options.clone()
+                };
+
+                assert_eq!(Ok(significant_tokens(tokens)), tokenize_with_options(source, &without));
+            }
+        },
+        Err(err) => check_errors(source, &[err], options),
+    }
+
+    let (_, errors) = tokenize_recovering(source);
+    check_errors(source, &errors, &TokenizerOptions::default());
+}
+
+fn options() -> impl Strategy<Value = TokenizerOptions> {
+    let version = prop_oneof![
+        Just(None),
+        Just(Some(LuaVersion::Lua51)),
+        Just(Some(LuaVersion::Lua52)),
+        Just(Some(LuaVersion::Lua53)),
+        Just(Some(LuaVersion::Lua54)),
+    ];
+
+    let flags = (any::<bool>(), any::<bool>(), any::<bool>(), any::<bool>());
+
+    let limits = (prop::option::of(0..256usize), prop::option::of(0..64usize));
+
+    let on_error = prop_oneof![
+        Just(OnError::Stop),
+        Just(OnError::SkipChar),
+        Just(OnError::SkipToWhitespace),
+    ];
+
+    let column_unit = prop_oneof![
+        Just(ColumnUnit::Bytes),
+        Just(ColumnUnit::Chars),
+        Just(ColumnUnit::Utf16),
+    ];
+
+    (flags, limits, (0..9usize, column_unit), version, on_error).prop_map(|((unicode_identifiers, interpolated_strings, luajit_number_suffixes, emit_trivia), (max_source_len, max_tokens), (tab_width, column_unit), version, on_error)| {
+        TokenizerOptions {
+            unicode_identifiers,
+            interpolated_strings,
+            luajit_number_suffixes,
+            emit_trivia,
+            max_source_len,
+            max_tokens,
+            tab_width,
+            column_unit,
+            version,
+            on_error,
+        }
+    })
+}
+
+/// Bytes weighted towards the ones that start or end tokens, read as UTF-8
+/// with invalid sequences replaced.
+fn tricky_source() -> impl Strategy<Value = String> {
+    vec(prop_oneof![
+        prop::sample::select(b"\"'`\\[]={}()-.:~<>/#!0159xXeEpPuUlLiz \n\r\t\x0b".to_vec()),
+        any::<u8>(),
+    ], 0..128).prop_map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+proptest! {
+    #[test]
+    fn tricky_sources_never_panic(source in tricky_source(), options in options()) {
+        check(&source, &options);
+    }
+
+    #[test]
+    fn any_text_never_panics(source in "\\PC*", options in options()) {
+        check(&source, &options);
+    }
+}
+
+#[test]
+fn huge_inputs() {
+    let all = TokenizerOptions {
+        unicode_identifiers: true,
+        interpolated_strings: true,
+        luajit_number_suffixes: true,
+        emit_trivia: true,
+        tab_width: 4,
+        version: None,
+        ..TokenizerOptions::default()
+    };
+
+    let braces = "{".repeat(1_000_000);
+    check(&braces, &all);
+
+    let line = format!("x = \"{}\" .. [[{}", "a".repeat(1_000_000), "b".repeat(1_000_000));
+    check(&line, &all);
+
+    let strings = "`{".repeat(100_000);
+    check(&strings, &all);
+
+    let replacements = String::from_utf8_lossy(&[0xff; 10_000]).into_owned();
+    check(&replacements, &all);
+
+    // Errors one after another, with nothing to stop skipping at, used to
+    // take quadratic time to recover from.
+    let unclosed = format!("${}", "[[".repeat(100_000));
+    check(&unclosed, &all);
+
+    let unknown = "`{".repeat(100_000);
+    let (tokens, errors) = tokenize_recovering(&unknown);
+    assert_eq!((tokens.len(), errors.len()), (100_000, 100_000));
+}